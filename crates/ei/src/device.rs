@@ -0,0 +1,172 @@
+//! Emulating input on an `ei_device`, which has to be bracketed by `start_emulating` and
+//! `stop_emulating` and have its events grouped into frames.
+
+use crate::{Arg, Connection};
+use rustix::time::{clock_gettime, ClockId};
+
+const REQUEST_START_EMULATING: u32 = 1;
+const REQUEST_STOP_EMULATING: u32 = 2;
+const REQUEST_FRAME: u32 = 3;
+
+/// An `ei_device` of a sender client, which numbers its emulation sequences.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Device {
+    id: u64,
+    sequence: u32,
+}
+
+impl Device {
+    pub fn new(id: u64) -> Device {
+        Device { id, sequence: 0 }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sends `ei_device.start_emulating` with the next sequence number, returning a guard
+    /// that sends `ei_device.stop_emulating` once it's dropped.
+    ///
+    /// `last_serial` is the serial of the last event the EIS implementation sent, which every
+    /// request of the sequence is sent with.
+    pub fn start_emulating<'a, C: AsMut<Connection>>(
+        &mut self,
+        conn: &'a mut C,
+        last_serial: u32,
+    ) -> Emulating<'a, C> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        let args = [Arg::Uint32(last_serial), Arg::Uint32(sequence)];
+        send(conn.as_mut(), self.id, REQUEST_START_EMULATING, &args);
+        Emulating {
+            conn,
+            device: self.id,
+            last_serial,
+            pending: false,
+        }
+    }
+}
+
+/// An emulation sequence on a device, ended when this is dropped.
+///
+/// Events are sent through [`Emulating::conn`], and are grouped into a frame by
+/// [`Emulating::frame`]. Events that haven't been are framed before the sequence ends.
+#[derive(Debug)]
+pub struct Emulating<'a, C: AsMut<Connection>> {
+    conn: &'a mut C,
+    device: u64,
+    last_serial: u32,
+    /// Whether events were sent since the last frame.
+    pending: bool,
+}
+
+impl<C: AsMut<Connection>> Emulating<'_, C> {
+    /// Returns the connection, to send events of the device's interfaces on.
+    pub fn conn(&mut self) -> &mut C {
+        self.pending = true;
+        self.conn
+    }
+
+    /// Sends `ei_device.frame` for the events sent since the last one, timestamped with the
+    /// current time, if there were any.
+    pub fn frame(&mut self) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+        let time = clock_gettime(ClockId::Monotonic);
+        let timestamp = time.tv_sec as u64 * 1_000_000 + time.tv_nsec as u64 / 1_000;
+        let args = [Arg::Uint32(self.last_serial), Arg::Uint64(timestamp)];
+        send(self.conn.as_mut(), self.device, REQUEST_FRAME, &args);
+    }
+}
+
+impl<C: AsMut<Connection>> Drop for Emulating<'_, C> {
+    fn drop(&mut self) {
+        self.frame();
+        let args = [Arg::Uint32(self.last_serial)];
+        send(
+            self.conn.as_mut(),
+            self.device,
+            REQUEST_STOP_EMULATING,
+            &args,
+        );
+    }
+}
+
+fn send(conn: &mut Connection, device: u64, opcode: u32, args: &[Arg<'_>]) {
+    let name = match opcode {
+        REQUEST_START_EMULATING => "StartEmulating",
+        REQUEST_STOP_EMULATING => "StopEmulating",
+        _ => "Frame",
+    };
+    conn.trace(true, "ei_device", device, &format_args!("{name}{args:?}"));
+    conn.write_message(device, opcode, args, []);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+    use rustix::net::{socketpair, AddressFamily, SocketFlags, SocketType};
+
+    struct Conn(Connection);
+
+    impl AsMut<Connection> for Conn {
+        fn as_mut(&mut self) -> &mut Connection {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn emulating() {
+        let (client, server) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let (mut client, mut server) = (Conn(Connection::new(client)), Connection::new(server));
+
+        let mut device = Device::new(5);
+        for _ in 0..2 {
+            let mut emulating = device.start_emulating(&mut client, 7);
+            // Nothing was sent, so there's nothing to frame.
+            emulating.frame();
+            emulating.conn().0.write_message(6, 1, &[], []);
+            emulating.frame();
+            emulating.conn().0.write_message(6, 1, &[], []);
+        }
+        client.0.flush_blocking().unwrap();
+
+        server.read_blocking().unwrap();
+        let decode = |mut msg: Message<'_>| {
+            let (object, opcode) = (msg.object(), msg.opcode());
+            if object != 5 {
+                return Some((object, opcode, None, None));
+            }
+            let serial = msg.read_uint32()?;
+            let sequence = match opcode {
+                REQUEST_START_EMULATING => Some(msg.read_uint32()?),
+                _ => None,
+            };
+            Some((object, opcode, Some(serial), sequence))
+        };
+        let mut messages = Vec::new();
+        while let Some(message) = server.read_message(decode) {
+            messages.push(message);
+        }
+        let sequence = |sequence| {
+            [
+                (5, REQUEST_START_EMULATING, Some(7), Some(sequence)),
+                (6, 1, None, None),
+                (5, REQUEST_FRAME, Some(7), None),
+                (6, 1, None, None),
+                (5, REQUEST_FRAME, Some(7), None),
+                (5, REQUEST_STOP_EMULATING, Some(7), None),
+            ]
+        };
+        assert_eq!(messages, [sequence(0), sequence(1)].concat());
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// Why the EIS implementation disconnected the client, the `ei_connection.disconnect_reason`
+/// entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client was disconnected on purpose, such as when the user revoked its access.
+    Disconnected,
+    /// An error on the EIS implementation's side.
+    Error,
+    /// The client sent a request meant for the other context type.
+    Mode,
+    /// The client violated the protocol.
+    Protocol,
+    /// The client sent an invalid value.
+    Value,
+    /// An error on the transport layer.
+    Transport,
+    /// A reason newer than this crate.
+    Unknown(u32),
+}
+
+impl DisconnectReason {
+    pub fn from_raw(reason: u32) -> DisconnectReason {
+        match reason {
+            0 => DisconnectReason::Disconnected,
+            1 => DisconnectReason::Error,
+            2 => DisconnectReason::Mode,
+            3 => DisconnectReason::Protocol,
+            4 => DisconnectReason::Value,
+            5 => DisconnectReason::Transport,
+            _ => DisconnectReason::Unknown(reason),
+        }
+    }
+
+    /// Whether the client caused the disconnection, so connecting again and doing the same
+    /// thing would fail the same way.
+    pub fn is_client_error(self) -> bool {
+        matches!(
+            self,
+            DisconnectReason::Mode | DisconnectReason::Protocol | DisconnectReason::Value
+        )
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Disconnected => write!(f, "disconnected on purpose"),
+            DisconnectReason::Error => write!(f, "an error in the EIS implementation"),
+            DisconnectReason::Mode => write!(f, "a request for the wrong context type"),
+            DisconnectReason::Protocol => write!(f, "a protocol violation"),
+            DisconnectReason::Value => write!(f, "an invalid value"),
+            DisconnectReason::Transport => write!(f, "a transport error"),
+            DisconnectReason::Unknown(reason) => write!(f, "unknown reason {reason}"),
+        }
+    }
+}
+
+/// The contents of `ei_connection.disconnected`, as an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disconnected {
+    pub reason: DisconnectReason,
+    /// The EIS implementation's explanation, meant for debugging.
+    pub explanation: Option<String>,
+}
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the EIS implementation disconnected the client: {}",
+            self.reason
+        )?;
+        if let Some(explanation) = &self.explanation {
+            write!(f, " ({explanation})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Disconnected {}
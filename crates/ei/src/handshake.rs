@@ -0,0 +1,291 @@
+//! The `ei_handshake` exchange that starts every connection, done at the wire level so it
+//! doesn't depend on any generated bindings.
+
+use crate::{Arg, Connection, Message};
+use rustix::io::Errno;
+use std::{collections::HashMap, fmt};
+
+/// The `ei_handshake` object's id, which is fixed.
+const HANDSHAKE: u64 = 0;
+
+/// The newest `ei_handshake` version this client implements.
+const HANDSHAKE_VERSION: u32 = 1;
+
+const REQUEST_HANDSHAKE_VERSION: u32 = 0;
+const REQUEST_FINISH: u32 = 1;
+const REQUEST_CONTEXT_TYPE: u32 = 2;
+const REQUEST_NAME: u32 = 3;
+const REQUEST_INTERFACE_VERSION: u32 = 4;
+
+const EVENT_HANDSHAKE_VERSION: u32 = 0;
+const EVENT_INTERFACE_VERSION: u32 = 1;
+const EVENT_CONNECTION: u32 = 2;
+
+/// The `ei_handshake.context_type` entries.
+pub const CONTEXT_TYPE_RECEIVER: u32 = 1;
+pub const CONTEXT_TYPE_SENDER: u32 = 2;
+
+/// An error during the handshake.
+#[derive(Debug)]
+pub enum Error {
+    Io(Errno),
+    /// The EIS implementation closed the connection, usually because it rejected the client.
+    Closed,
+    /// The EIS implementation sent something other than an `ei_handshake` event.
+    Protocol,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::Closed => write!(f, "the EIS implementation closed the connection"),
+            Error::Protocol => write!(f, "unexpected message during the ei handshake"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Errno> for Error {
+    fn from(e: Errno) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// What the client tells the EIS implementation about itself during the handshake.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    context_type: u32,
+    name: String,
+    interfaces: Vec<(String, u32)>,
+}
+
+/// A connection that finished its handshake.
+#[derive(Debug, Clone)]
+pub struct Connected {
+    /// The id of the `ei_connection` object.
+    pub connection: u64,
+    /// The serial of the `ei_handshake.connection` event.
+    pub serial: u32,
+    /// The version of the `ei_connection` object.
+    pub version: u32,
+    /// The versions of each interface the EIS implementation agreed to, by name.
+    pub interfaces: HashMap<String, u32>,
+}
+
+impl Connected {
+    /// Returns the version of `interface` in use, or `None` if the EIS implementation doesn't
+    /// support it.
+    pub fn version(&self, interface: &str) -> Option<u32> {
+        self.interfaces.get(interface).copied()
+    }
+}
+
+#[derive(Debug)]
+enum Event {
+    HandshakeVersion(u32),
+    InterfaceVersion(String, u32),
+    Connection {
+        serial: u32,
+        connection: u64,
+        version: u32,
+    },
+}
+
+impl Handshake {
+    /// A handshake for a client of `context_type` called `name`.
+    pub fn new(context_type: u32, name: impl Into<String>) -> Handshake {
+        Handshake {
+            context_type,
+            name: name.into(),
+            interfaces: Vec::new(),
+        }
+    }
+
+    /// Announces support for `interface` up to `version`.
+    pub fn interface(mut self, interface: impl Into<String>, version: u32) -> Handshake {
+        self.interfaces.push((interface.into(), version));
+        self
+    }
+
+    /// Does the handshake on a new connection, blocking until the EIS implementation creates
+    /// the `ei_connection`.
+    ///
+    /// Messages the EIS implementation sends after that are left on `conn` to be read.
+    pub fn run(&self, conn: &mut Connection) -> Result<Connected, Error> {
+        let mut interfaces = HashMap::new();
+        loop {
+            while let Some(event) = conn.read_message(|msg| Some(decode(msg))) {
+                let event = event.ok_or(Error::Protocol)?;
+                conn.trace(false, "ei_handshake", HANDSHAKE, &event);
+                match event {
+                    Event::HandshakeVersion(version) => {
+                        self.send(conn, version.min(HANDSHAKE_VERSION));
+                        conn.flush_blocking()?;
+                    }
+                    Event::InterfaceVersion(name, version) => {
+                        interfaces.insert(name, version);
+                    }
+                    Event::Connection {
+                        serial,
+                        connection,
+                        version,
+                    } => {
+                        conn.connection = Some(connection);
+                        return Ok(Connected {
+                            connection,
+                            serial,
+                            version,
+                            interfaces,
+                        });
+                    }
+                }
+            }
+            if !conn.read_blocking()? {
+                return Err(Error::Closed);
+            }
+        }
+    }
+
+    fn send(&self, conn: &mut Connection, version: u32) {
+        let mut send = |opcode, args: &[Arg<'_>], name| {
+            conn.trace(
+                true,
+                "ei_handshake",
+                HANDSHAKE,
+                &format_args!("{name}{args:?}"),
+            );
+            conn.write_message(HANDSHAKE, opcode, args, []);
+        };
+        send(
+            REQUEST_HANDSHAKE_VERSION,
+            &[Arg::Uint32(version)],
+            "HandshakeVersion",
+        );
+        send(
+            REQUEST_CONTEXT_TYPE,
+            &[Arg::Uint32(self.context_type)],
+            "ContextType",
+        );
+        send(REQUEST_NAME, &[Arg::String(Some(&self.name))], "Name");
+        for (interface, version) in &self.interfaces {
+            send(
+                REQUEST_INTERFACE_VERSION,
+                &[Arg::String(Some(interface)), Arg::Uint32(*version)],
+                "InterfaceVersion",
+            );
+        }
+        send(REQUEST_FINISH, &[], "Finish");
+    }
+}
+
+/// Decodes an `ei_handshake` event, returning `None` for anything else.
+fn decode(mut msg: Message<'_>) -> Option<Event> {
+    if msg.object() != HANDSHAKE {
+        return None;
+    }
+    Some(match msg.opcode() {
+        EVENT_HANDSHAKE_VERSION => Event::HandshakeVersion(msg.read_uint32()?),
+        EVENT_INTERFACE_VERSION => Event::InterfaceVersion(msg.read_string()??, msg.read_uint32()?),
+        EVENT_CONNECTION => Event::Connection {
+            serial: msg.read_uint32()?,
+            connection: msg.read_uint64()?,
+            version: msg.read_uint32()?,
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustix::net::{socketpair, AddressFamily, SocketFlags, SocketType};
+
+    /// A request the client sent, as its opcode and arguments.
+    #[derive(Debug, PartialEq)]
+    enum Request {
+        Uint32(u32, u32),
+        String(u32, String),
+        InterfaceVersion(String, u32),
+        Finish,
+    }
+
+    fn decode_request(mut msg: Message<'_>) -> Option<Request> {
+        assert_eq!(msg.object(), HANDSHAKE);
+        Some(match msg.opcode() {
+            REQUEST_HANDSHAKE_VERSION | REQUEST_CONTEXT_TYPE => {
+                Request::Uint32(msg.opcode(), msg.read_uint32()?)
+            }
+            REQUEST_NAME => Request::String(msg.opcode(), msg.read_string()??),
+            REQUEST_INTERFACE_VERSION => {
+                Request::InterfaceVersion(msg.read_string()??, msg.read_uint32()?)
+            }
+            REQUEST_FINISH => Request::Finish,
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn handshake() {
+        let (client, server) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let (mut client, mut server) = (Connection::new(client), Connection::new(server));
+
+        // The server sends everything up front, which a real one would only do after `finish`.
+        server.write_message(HANDSHAKE, EVENT_HANDSHAKE_VERSION, &[Arg::Uint32(3)], []);
+        let interface_version = [Arg::String(Some("ei_seat")), Arg::Uint32(1)];
+        server.write_message(HANDSHAKE, EVENT_INTERFACE_VERSION, &interface_version, []);
+        let connection = [
+            Arg::Uint32(7),
+            Arg::Uint64(0xff00_0000_0000_0000),
+            Arg::Uint32(1),
+        ];
+        server.write_message(HANDSHAKE, EVENT_CONNECTION, &connection, []);
+        // Sent after the handshake, so it's left for the client to read.
+        server.write_message(0xff00_0000_0000_0000, 0, &[], []);
+        server.flush_blocking().unwrap();
+
+        let connected = Handshake::new(CONTEXT_TYPE_SENDER, "test")
+            .interface("ei_seat", 2)
+            .interface("ei_device", 1)
+            .run(&mut client)
+            .unwrap();
+        assert_eq!(connected.connection, 0xff00_0000_0000_0000);
+        assert_eq!(connected.serial, 7);
+        assert_eq!(connected.version, 1);
+        assert_eq!(connected.version("ei_seat"), Some(1));
+        assert_eq!(connected.version("ei_device"), None);
+        let next = client.read_message(|msg| Some(msg.object()));
+        assert_eq!(next, Some(0xff00_0000_0000_0000));
+
+        server.read_blocking().unwrap();
+        let mut requests = Vec::new();
+        while let Some(request) = server.read_message(decode_request) {
+            requests.push(request);
+        }
+        assert_eq!(
+            requests,
+            [
+                Request::Uint32(REQUEST_HANDSHAKE_VERSION, HANDSHAKE_VERSION),
+                Request::Uint32(REQUEST_CONTEXT_TYPE, CONTEXT_TYPE_SENDER),
+                Request::String(REQUEST_NAME, "test".into()),
+                Request::InterfaceVersion("ei_seat".into(), 2),
+                Request::InterfaceVersion("ei_device".into(), 1),
+                Request::Finish,
+            ],
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Typing with an `ei_keyboard`, which takes evdev key codes, by finding the keys that produce
+//! a keysym in the device's keymap.
+
+use rustix::fd::{IntoRawFd, OwnedFd};
+use xkbcommon::xkb;
+
+/// The `xkb` entry of `ei_keyboard.keymap_type`.
+pub const KEYMAP_TYPE_XKB: u32 = 1;
+
+/// The `ei_keyboard.key_state` entries.
+pub const KEY_STATE_RELEASED: u32 = 0;
+pub const KEY_STATE_PRESS: u32 = 1;
+
+/// Keysyms of the keys that are held to reach other levels of a key, in the order they're
+/// tried.
+const LEVEL_KEYSYMS: &[xkb::Keysym] = &[
+    xkb::KEY_Shift_L,
+    xkb::KEY_Shift_R,
+    xkb::KEY_ISO_Level3_Shift,
+    xkb::KEY_ISO_Level5_Shift,
+];
+
+/// Evdev key codes are offset by 8 from XKB keycodes.
+const EVDEV_OFFSET: xkb::Keycode = 8;
+
+/// An `ei_keyboard`'s keymap and the modifiers the EIS implementation last sent for it.
+pub struct Keyboard {
+    keymap: xkb::Keymap,
+    locked_mods: xkb::ModMask,
+    group: xkb::LayoutIndex,
+}
+
+impl Keyboard {
+    /// Compiles the keymap from an `ei_keyboard.keymap` event, returning `None` if its type
+    /// isn't XKB or it doesn't compile.
+    pub fn from_keymap(keymap_type: u32, fd: OwnedFd, size: u32) -> Option<Keyboard> {
+        if keymap_type != KEYMAP_TYPE_XKB || size == 0 {
+            return None;
+        }
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        // The keymap takes ownership of the fd, and closes it once it's mapped.
+        let keymap = unsafe {
+            xkb::Keymap::new_from_fd(
+                &context,
+                fd.into_raw_fd(),
+                size as usize,
+                xkb::KEYMAP_FORMAT_TEXT_V1,
+                xkb::COMPILE_NO_FLAGS,
+            )
+        };
+        Some(Keyboard::new(keymap.ok()??))
+    }
+
+    pub fn new(keymap: xkb::Keymap) -> Keyboard {
+        Keyboard {
+            keymap,
+            locked_mods: 0,
+            group: 0,
+        }
+    }
+
+    pub fn keymap(&self) -> &xkb::Keymap {
+        &self.keymap
+    }
+
+    /// Handles `ei_keyboard.modifiers`. Only the locked modifiers and the group matter for
+    /// typing, since the keys held for the others are released after each keysym.
+    pub fn update_modifiers(&mut self, _depressed: u32, locked: u32, _latched: u32, group: u32) {
+        self.locked_mods = locked;
+        self.group = group;
+    }
+
+    /// Returns the evdev codes of the keys to press, in order, to type `keysym`: the key that
+    /// produces it, after a key reaching its level if it isn't the first.
+    pub fn keys_for(&self, keysym: xkb::Keysym) -> Option<Vec<u32>> {
+        let level_keys: Vec<xkb::Keycode> = self
+            .keycodes()
+            .filter(|&key| {
+                let syms = self.keymap.key_get_syms_by_level(key, 0, 0);
+                syms.len() == 1 && LEVEL_KEYSYMS.contains(&syms[0])
+            })
+            .collect();
+        let held = [None].into_iter().chain(level_keys.into_iter().map(Some));
+        for level_key in held {
+            let mut state = xkb::State::new(&self.keymap);
+            state.update_mask(0, 0, self.locked_mods, 0, 0, self.group);
+            if let Some(level_key) = level_key {
+                state.update_key(level_key, xkb::KeyDirection::Down);
+            }
+            if let Some(key) = self
+                .keycodes()
+                .find(|&key| Some(key) != level_key && state.key_get_one_sym(key) == keysym)
+            {
+                let keys = level_key.into_iter().chain([key]);
+                return Some(keys.map(|key| key - EVDEV_OFFSET).collect());
+            }
+        }
+        None
+    }
+
+    /// Types `keysym`, calling `key` with each evdev code and `ei_keyboard.key_state` to send,
+    /// pressing the keys in order and releasing them in reverse. Returns `false` without
+    /// calling it if no keys produce `keysym`.
+    pub fn type_keysym(&self, keysym: xkb::Keysym, mut key: impl FnMut(u32, u32)) -> bool {
+        let Some(keys) = self.keys_for(keysym) else {
+            return false;
+        };
+        for &code in &keys {
+            key(code, KEY_STATE_PRESS);
+        }
+        for &code in keys.iter().rev() {
+            key(code, KEY_STATE_RELEASED);
+        }
+        true
+    }
+
+    /// The keycodes with evdev codes, which are the ones an `ei_keyboard` can press.
+    fn keycodes(&self) -> impl Iterator<Item = xkb::Keycode> {
+        self.keymap.min_keycode().max(EVDEV_OFFSET)..=self.keymap.max_keycode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYMAP: &str = r#"
+        xkb_keymap {
+            xkb_keycodes "test" {
+                minimum = 8;
+                maximum = 255;
+                <AC01> = 38;
+                <LFSH> = 50;
+                <CAPS> = 66;
+            };
+            xkb_types "test" {
+                type "ONE_LEVEL" {
+                    modifiers = none;
+                    level_name[Level1] = "Any";
+                };
+                type "ALPHABETIC" {
+                    modifiers = Shift + Lock;
+                    map[Shift] = Level2;
+                    map[Lock] = Level2;
+                    level_name[Level1] = "Base";
+                    level_name[Level2] = "Caps";
+                };
+            };
+            xkb_compatibility "test" {
+                interpret Shift_L { action = SetMods(modifiers = Shift); };
+                interpret Caps_Lock { action = LockMods(modifiers = Lock); };
+            };
+            xkb_symbols "test" {
+                key <AC01> { type = "ALPHABETIC", [ a, A ] };
+                key <LFSH> { [ Shift_L ] };
+                key <CAPS> { [ Caps_Lock ] };
+                modifier_map Shift { <LFSH> };
+                modifier_map Lock { <CAPS> };
+            };
+        };
+    "#;
+
+    fn keyboard() -> Keyboard {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            KEYMAP.to_owned(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::COMPILE_NO_FLAGS,
+        )
+        .unwrap();
+        Keyboard::new(keymap)
+    }
+
+    #[test]
+    fn keys_for() {
+        let mut keyboard = keyboard();
+        assert_eq!(keyboard.keys_for(xkb::KEY_a), Some(vec![30]));
+        assert_eq!(keyboard.keys_for(xkb::KEY_A), Some(vec![42, 30]));
+        assert_eq!(keyboard.keys_for(xkb::KEY_b), None);
+
+        // With caps lock on, shift is needed for lowercase instead.
+        let lock = 1 << keyboard.keymap().mod_get_index(xkb::MOD_NAME_CAPS);
+        keyboard.update_modifiers(0, lock, 0, 0);
+        assert_eq!(keyboard.keys_for(xkb::KEY_A), Some(vec![30]));
+        assert_eq!(keyboard.keys_for(xkb::KEY_a), Some(vec![42, 30]));
+    }
+
+    #[test]
+    fn type_keysym() {
+        let keyboard = keyboard();
+        let mut sent = Vec::new();
+        assert!(keyboard.type_keysym(xkb::KEY_A, |key, state| sent.push((key, state))));
+        assert_eq!(
+            sent,
+            [
+                (42, KEY_STATE_PRESS),
+                (30, KEY_STATE_PRESS),
+                (30, KEY_STATE_RELEASED),
+                (42, KEY_STATE_RELEASED),
+            ],
+        );
+        assert!(!keyboard.type_keysym(xkb::KEY_b, |_, _| unreachable!()));
+    }
+}
@@ -1,5 +1,19 @@
 pub extern crate rustix;
 
+mod device;
+mod disconnect;
+pub mod handshake;
+#[cfg(feature = "xkb")]
+pub mod keyboard;
+#[cfg(feature = "portal")]
+pub mod portal;
+#[cfg(any(test, feature = "server"))]
+pub mod server;
+
+pub use device::{Device, Emulating};
+pub use disconnect::{DisconnectReason, Disconnected};
+pub use handshake::Handshake;
+
 use circbuf::CircBuf;
 use rustix::{
     cmsg_space,
@@ -14,11 +28,25 @@ use rustix::{
 };
 use std::{
     collections::VecDeque,
+    ffi::OsString,
     fmt::Debug,
     io::{self, IoSlice, IoSliceMut, Read, Write},
     os::unix::prelude::OsStringExt,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+const CONNECTION_EVENT_PING: u32 = 3;
+const PINGPONG_REQUEST_DONE: u32 = 0;
+
+/// Connects to the socket of an EIS implementation at `path`, regardless of the environment.
+pub fn connect_to_path(path: impl AsRef<Path>) -> Result<OwnedFd, Errno> {
+    let fd = rustix::net::socket(AddressFamily::UNIX, SocketType::STREAM, None)?;
+    let addr = SocketAddrUnix::new(path.as_ref())?;
+    connect_unix(&fd, &addr)?;
+    Ok(fd)
+}
+
 pub fn client_socket_from_env() -> Result<Option<OwnedFd>, Errno> {
     fn socket_path_from_wayland_display_env() -> Option<Vec<u8>> {
         let display = std::env::var_os("LIBEI_SOCKET")?;
@@ -27,9 +55,7 @@ pub fn client_socket_from_env() -> Result<Option<OwnedFd>, Errno> {
             return Some(display);
         }
         let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") else {
-            eprintln!(
-                "warning: LIBEI_SOCKET was not an absolute path and XDG_RUNTIME_PATH is unset"
-            );
+            log::warn!("LIBEI_SOCKET was not an absolute path and XDG_RUNTIME_PATH is unset");
             return None;
         };
         let mut path = runtime_dir.into_vec();
@@ -38,15 +64,8 @@ pub fn client_socket_from_env() -> Result<Option<OwnedFd>, Errno> {
         Some(path)
     }
 
-    fn socket_fd_from_socket_path(path: Vec<u8>) -> Result<OwnedFd, Errno> {
-        let fd = rustix::net::socket(AddressFamily::UNIX, SocketType::STREAM, None)?;
-        let addr = SocketAddrUnix::new(path)?;
-        connect_unix(&fd, &addr)?;
-        Ok(fd)
-    }
-
     socket_path_from_wayland_display_env()
-        .map(socket_fd_from_socket_path)
+        .map(|path| connect_to_path(OsString::from_vec(path)))
         .transpose()
 }
 
@@ -58,7 +77,9 @@ fn read_from_socket<'fds>(
     let mut cmsg_data = vec![0; cmsg_space!(ScmRights(32))];
     let mut ctl = RecvAncillaryBuffer::new(&mut cmsg_data);
     let [first_half, second_half] = buf.get_avail();
-    let rustix::net::RecvMsgReturn { bytes: n, .. } = recvmsg(
+    let rustix::net::RecvMsgReturn {
+        bytes: n, flags, ..
+    } = recvmsg(
         &socket,
         &mut [IoSliceMut::new(first_half), IoSliceMut::new(second_half)],
         &mut ctl,
@@ -71,6 +92,10 @@ fn read_from_socket<'fds>(
         };
         fds.extend(fd_iter);
     }
+    // `MSG_CTRUNC`, which rustix doesn't have a name for.
+    if flags.contains(RecvFlags::from_bits_retain(0x8)) {
+        log::warn!("more than 32 file descriptors were received at once, so some were lost");
+    }
     Ok(n > 0)
 }
 
@@ -87,7 +112,8 @@ fn write_to_socket(
         &socket,
         &[IoSlice::new(first_half), IoSlice::new(second_half)],
         &mut ctl,
-        SendFlags::DONTWAIT,
+        // Otherwise writing to a closed socket raises `SIGPIPE`, which kills the process.
+        SendFlags::DONTWAIT | SendFlags::NOSIGNAL,
     )?;
     buf.advance_read_raw(n);
     Ok(n > 0)
@@ -100,6 +126,9 @@ pub struct Connection {
     write_buf: CircBuf,
     read_fds: VecDeque<OwnedFd>,
     write_fds: VecDeque<OwnedFd>,
+    /// The id of the `ei_connection` object once the handshake is done, whose pings are
+    /// answered while reading.
+    connection: Option<u64>,
 }
 
 impl AsFd for Connection {
@@ -116,9 +145,32 @@ impl Connection {
             read_buf: CircBuf::new(),
             read_fds: VecDeque::new(),
             write_fds: VecDeque::new(),
+            connection: None,
         }
     }
 
+    /// Logs a message sent or received on `object` of `interface` at the trace level, under the
+    /// `ei::wire` target, with a timestamp in the format libwayland uses.
+    ///
+    /// The connection only sees arguments, so whoever decodes or encodes the message calls
+    /// this with it.
+    pub fn trace(&self, sent: bool, interface: &str, object: u64, message: &dyn Debug) {
+        if !log::log_enabled!(target: "ei::wire", log::Level::Trace) {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        log::trace!(
+            target: "ei::wire",
+            "[{:7}.{:03}] {}{interface}@{object}: {message:?}",
+            (time / 1000) as u32,
+            time % 1000,
+            if sent { " -> " } else { "" },
+        );
+    }
+
     pub fn flush_nonblocking(&mut self) -> Result<bool, Errno> {
         if self.write_buf.is_empty() {
             return Ok(true);
@@ -217,7 +269,7 @@ impl Connection {
                     self.write_buf.write_all(&zeros[0..padding_len]).unwrap();
                 }
                 Arg::Array(s) => {
-                    let s_len = u32::try_from(s.len() + 1).unwrap();
+                    let s_len = u32::try_from(s.len()).unwrap();
                     self.write_buf.write_all(&s_len.to_ne_bytes()).unwrap();
                     self.write_buf.write_all(s).unwrap();
                     let padding_len = (s.len() + 3) / 4 * 4 - s.len();
@@ -228,33 +280,61 @@ impl Connection {
         }
     }
 
+    /// Decodes the next message with `decoder`, if a whole one has been read.
+    ///
+    /// `ei_connection.ping` is answered here instead of being passed to `decoder`, so clients
+    /// can't forget to.
     pub fn read_message<F, Msg>(&mut self, decoder: F) -> Option<Msg>
     where
         for<'a> F: Fn(Message<'a>) -> Option<Msg>,
     {
-        if self.read_buf.len() < 4 {
-            return None;
-        }
-        let mut buf = [0u8; 16];
-        self.read_buf.reader_peek().read_exact(&mut buf).unwrap();
-        let obj = u64::from_ne_bytes(buf[0..8].try_into().unwrap());
-        let size = u32::from_ne_bytes(buf[8..12].try_into().unwrap());
-        let op = u32::from_ne_bytes(buf[12..16].try_into().unwrap());
-        if self.read_buf.len() < usize::try_from(size).unwrap() {
-            return None;
+        loop {
+            if self.read_buf.len() < 4 {
+                return None;
+            }
+            let mut buf = [0u8; 16];
+            self.read_buf.reader_peek().read_exact(&mut buf).unwrap();
+            let obj = u64::from_ne_bytes(buf[0..8].try_into().unwrap());
+            let size = u32::from_ne_bytes(buf[8..12].try_into().unwrap());
+            let op = u32::from_ne_bytes(buf[12..16].try_into().unwrap());
+            if self.read_buf.len() < usize::try_from(size).unwrap() {
+                return None;
+            }
+            let buf_bytes = self.read_buf.get_bytes_upto_size(size as usize);
+            let mut data = SplitSlice(buf_bytes);
+            data.advance(16);
+            let mut msg = Message {
+                object: obj,
+                opcode: op,
+                data,
+                fds: &mut self.read_fds,
+            };
+            if Some(obj) == self.connection && op == CONNECTION_EVENT_PING {
+                let ping = msg.read_uint64().zip(msg.read_uint32());
+                self.read_buf.advance_read_raw(size as usize);
+                if let Some((ping, version)) = ping {
+                    self.pong(obj, ping, version);
+                }
+                continue;
+            }
+            let msg = decoder(msg).expect("decoder failed!");
+            self.read_buf.advance_read_raw(size as usize);
+            return Some(msg);
         }
-        let buf_bytes = self.read_buf.get_bytes_upto_size(size as usize);
-        let mut data = SplitSlice(buf_bytes);
-        data.advance(16);
-        let msg = decoder(Message {
-            object: obj,
-            opcode: op,
-            data,
-            fds: &mut self.read_fds,
-        })
-        .expect("decoder failed!");
-        self.read_buf.advance_read_raw(size as usize);
-        Some(msg)
+    }
+
+    /// Answers an `ei_connection.ping`, which destroys the `ei_pingpong` it created.
+    fn pong(&mut self, connection: u64, ping: u64, version: u32) {
+        let args = [Arg::Uint64(ping), Arg::Uint32(version)];
+        self.trace(
+            false,
+            "ei_connection",
+            connection,
+            &format_args!("Ping{args:?}"),
+        );
+        let args = [Arg::Uint64(0)];
+        self.trace(true, "ei_pingpong", ping, &format_args!("Done{args:?}"));
+        self.write_message(ping, PINGPONG_REQUEST_DONE, &args, []);
     }
 }
 
@@ -324,21 +404,36 @@ impl<'a> Message<'a> {
         self.read_uint32().map(f32::from_bits)
     }
 
+    /// Reads a string argument, which fails to decode if it isn't UTF-8.
     pub fn read_string(&mut self) -> Option<Option<String>> {
+        match self.read_string_bytes()? {
+            Some(bytes) => Some(Some(String::from_utf8(bytes).ok()?)),
+            None => Some(None),
+        }
+    }
+
+    /// Reads a string argument, replacing anything that isn't UTF-8 with U+FFFD.
+    pub fn read_string_lossy(&mut self) -> Option<Option<String>> {
+        let bytes = self.read_string_bytes()?;
+        Some(bytes.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    /// Reads a string argument as the bytes before its terminating nul, whatever they are.
+    pub fn read_string_bytes(&mut self) -> Option<Option<Vec<u8>>> {
         let length = self.read_uint32()?;
         if length == 0 {
             Some(None)
         } else {
-            let mut buf = vec![0u8; usize::try_from((length + 3) / 4 * 4).unwrap()];
+            let mut buf = vec![0u8; usize::try_from(length.div_ceil(4) * 4).unwrap()];
             self.data.read_exact(&mut buf).ok()?;
             buf.truncate(usize::try_from(length - 1).unwrap());
-            Some(Some(String::from_utf8(buf).unwrap()))
+            Some(Some(buf))
         }
     }
 
     pub fn read_array(&mut self) -> Option<Vec<u8>> {
         let length = self.read_uint32()?;
-        let mut buf = vec![0u8; usize::try_from(length / 4 * 4).unwrap()];
+        let mut buf = vec![0u8; usize::try_from(length.div_ceil(4) * 4).unwrap()];
         self.data.read_exact(&mut buf).ok()?;
         buf.truncate(usize::try_from(length).unwrap());
         Some(buf)
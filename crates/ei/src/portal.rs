@@ -0,0 +1,1036 @@
+//! Getting a connection to an EIS implementation from the RemoteDesktop portal, which is how
+//! sandboxed or unprivileged clients get one on desktops like GNOME and KDE.
+//!
+//! This talks to the portal over the D-Bus session bus with a minimal client of its own, which
+//! only knows the parts of D-Bus the portal needs.
+
+use rustix::{
+    cmsg_space,
+    fd::OwnedFd,
+    io::Errno,
+    net::{
+        connect_unix, recvmsg, send, AddressFamily, RecvAncillaryBuffer, RecvAncillaryMessage,
+        RecvFlags, SendFlags, SocketAddrUnix, SocketType,
+    },
+};
+use std::{collections::VecDeque, fmt, io::IoSliceMut, os::unix::ffi::OsStrExt, path::Path};
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const REMOTE_DESKTOP: &str = "org.freedesktop.portal.RemoteDesktop";
+
+/// The device types to ask for, keyboard and pointer.
+const DEVICE_TYPES: u32 = 1 | 2;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+/// An error getting a connection from the portal.
+#[derive(Debug)]
+pub enum Error {
+    Io(Errno),
+    /// No session bus address was found in the environment.
+    NoBus,
+    /// The session bus rejected authentication, or doesn't support passing file descriptors.
+    Auth,
+    /// The session bus closed the connection.
+    Closed,
+    /// The bus or the portal sent a message this client couldn't make sense of.
+    Malformed,
+    /// A method call failed, such as because there's no portal with remote desktop support.
+    Call {
+        name: String,
+        message: String,
+    },
+    /// The user declined to allow remote control.
+    Cancelled,
+    /// The portal failed the request for some other reason.
+    Failed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::NoBus => write!(f, "no D-Bus session bus address is set"),
+            Error::Auth => write!(f, "the session bus rejected authentication"),
+            Error::Closed => write!(f, "the session bus closed the connection"),
+            Error::Malformed => write!(f, "malformed D-Bus message"),
+            Error::Call { name, message } => write!(f, "{name}: {message}"),
+            Error::Cancelled => write!(f, "remote control was not allowed"),
+            Error::Failed => write!(f, "the remote desktop portal failed the request"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Errno> for Error {
+    fn from(e: Errno) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// A remote desktop session, which the portal ends once this is dropped.
+///
+/// The connection to the EIS implementation only works as long as the session exists.
+#[derive(Debug)]
+pub struct Session {
+    _bus: Bus,
+}
+
+/// Asks the portal for a remote desktop session with keyboard and pointer devices, and returns
+/// a socket connected to the EIS implementation for it.
+///
+/// Starting the session can show the user a dialog, so this blocks until they answer it.
+pub fn connect() -> Result<(OwnedFd, Session), Error> {
+    let mut bus = Bus::connect_session()?;
+    bus.call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "AddMatch",
+        "s",
+        |w| w.str("type='signal',interface='org.freedesktop.portal.Request',member='Response'"),
+    )?;
+    let results = bus.request("CreateSession", "a{sv}", |w, token| {
+        w.dict(|w| {
+            w.entry("handle_token", "s", |w| w.str(token));
+            w.entry("session_handle_token", "s", |w| w.str(token));
+        })
+    })?;
+    let session = results
+        .into_iter()
+        .find_map(|(key, value)| match value {
+            Value::Str(handle) if key == "session_handle" => Some(handle),
+            _ => None,
+        })
+        .ok_or(Error::Malformed)?;
+    bus.request("SelectDevices", "oa{sv}", |w, token| {
+        w.str(&session);
+        w.dict(|w| {
+            w.entry("handle_token", "s", |w| w.str(token));
+            w.entry("types", "u", |w| w.u32(DEVICE_TYPES));
+        })
+    })?;
+    bus.request("Start", "osa{sv}", |w, token| {
+        w.str(&session);
+        // There's no window to show the dialog for.
+        w.str("");
+        w.dict(|w| w.entry("handle_token", "s", |w| w.str(token)))
+    })?;
+    let mut reply = bus.call(
+        DESTINATION,
+        PATH,
+        REMOTE_DESKTOP,
+        "ConnectToEIS",
+        "oa{sv}",
+        |w| {
+            w.str(&session);
+            w.dict(|_| {});
+        },
+    )?;
+    if reply.signature != "h" {
+        return Err(Error::Malformed);
+    }
+    let index = Reader::new(&reply.body, reply.big_endian).u32()?;
+    let index = usize::try_from(index).unwrap();
+    if index >= reply.fds.len() {
+        return Err(Error::Malformed);
+    }
+    let fd = reply.fds.swap_remove(index);
+    Ok((fd, Session { _bus: bus }))
+}
+
+/// A connection to a message bus.
+#[derive(Debug)]
+struct Bus {
+    socket: OwnedFd,
+    buf: Vec<u8>,
+    fds: VecDeque<OwnedFd>,
+    serial: u32,
+    /// The `Response` signals that arrived while waiting for a method's reply.
+    responses: Vec<Incoming>,
+    /// The bus's name for this connection, which the portal's request paths include.
+    unique_name: String,
+}
+
+impl Bus {
+    fn connect_session() -> Result<Bus, Error> {
+        let socket = connect_to_session_bus()?;
+        authenticate(&socket)?;
+        let mut bus = Bus {
+            socket,
+            buf: Vec::new(),
+            fds: VecDeque::new(),
+            serial: 0,
+            responses: Vec::new(),
+            unique_name: String::new(),
+        };
+        let reply = bus.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            "",
+            |_| {},
+        )?;
+        bus.unique_name = Reader::new(&reply.body, reply.big_endian).str()?;
+        Ok(bus)
+    }
+
+    /// Calls a method and waits for its reply, keeping any `Response` signal that arrives
+    /// first.
+    fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        body: impl FnOnce(&mut Writer),
+    ) -> Result<Incoming, Error> {
+        self.serial += 1;
+        let serial = self.serial;
+        let mut w = Writer::default();
+        body(&mut w);
+        let message = method_call(
+            serial,
+            [destination, path, interface, member],
+            signature,
+            &w.buf,
+        );
+        send_all(&self.socket, &message)?;
+        loop {
+            let msg = self.read_message()?;
+            match msg.kind {
+                METHOD_RETURN if msg.reply_serial == Some(serial) => return Ok(msg),
+                ERROR if msg.reply_serial == Some(serial) => {
+                    let message = match msg.signature.starts_with('s') {
+                        true => Reader::new(&msg.body, msg.big_endian).str()?,
+                        false => String::new(),
+                    };
+                    let name = msg.error_name.unwrap_or_default();
+                    return Err(Error::Call { name, message });
+                }
+                SIGNAL if msg.member.as_deref() == Some("Response") => self.responses.push(msg),
+                _ => {}
+            }
+        }
+    }
+
+    /// Calls a RemoteDesktop method that answers with a `Response` signal on a request object,
+    /// and returns the results from it.
+    ///
+    /// `body` gets the token for the request's path, which it passes as `handle_token`.
+    fn request(
+        &mut self,
+        method: &str,
+        signature: &str,
+        body: impl FnOnce(&mut Writer, &str),
+    ) -> Result<Vec<(String, Value)>, Error> {
+        let token = format!("ei{}", self.serial + 1);
+        // The portal makes the path from the token, so a response that arrives before the
+        // method's reply can be recognized.
+        let sender = self.unique_name.trim_start_matches(':').replace('.', "_");
+        let handle = format!("{PATH}/request/{sender}/{token}");
+        self.call(DESTINATION, PATH, REMOTE_DESKTOP, method, signature, |w| {
+            body(w, &token)
+        })?;
+        let msg = loop {
+            if let Some(i) = self
+                .responses
+                .iter()
+                .position(|msg| msg.path.as_deref() == Some(&*handle))
+            {
+                break self.responses.swap_remove(i);
+            }
+            let msg = self.read_message()?;
+            if msg.kind == SIGNAL && msg.member.as_deref() == Some("Response") {
+                self.responses.push(msg);
+            }
+        };
+        if msg.signature != "ua{sv}" {
+            return Err(Error::Malformed);
+        }
+        let mut r = Reader::new(&msg.body, msg.big_endian);
+        match r.u32()? {
+            0 => {}
+            1 => return Err(Error::Cancelled),
+            _ => return Err(Error::Failed),
+        }
+        r.dict()
+    }
+
+    fn read_message(&mut self) -> Result<Incoming, Error> {
+        loop {
+            if let Some(len) = message_len(&self.buf)? {
+                if self.buf.len() >= len {
+                    let bytes = self.buf.drain(..len).collect::<Vec<_>>();
+                    return parse_message(&bytes, &mut self.fds);
+                }
+            }
+            let mut chunk = [0; 4096];
+            let mut cmsg_data = [0; cmsg_space!(ScmRights(16))];
+            let mut ctl = RecvAncillaryBuffer::new(&mut cmsg_data);
+            let n = match recvmsg(
+                &self.socket,
+                &mut [IoSliceMut::new(&mut chunk)],
+                &mut ctl,
+                RecvFlags::CMSG_CLOEXEC,
+            ) {
+                Ok(msg) => msg.bytes,
+                Err(Errno::INTR) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for msg in ctl.drain() {
+                if let RecvAncillaryMessage::ScmRights(fds) = msg {
+                    self.fds.extend(fds);
+                }
+            }
+            if n == 0 {
+                return Err(Error::Closed);
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Connects to the first unix socket in `DBUS_SESSION_BUS_ADDRESS`, or to the one in
+/// `XDG_RUNTIME_DIR` without it.
+fn connect_to_session_bus() -> Result<OwnedFd, Error> {
+    let addr = match std::env::var_os("DBUS_SESSION_BUS_ADDRESS") {
+        Some(address) => address
+            .as_bytes()
+            .split(|&b| b == b';')
+            .find_map(parse_address)
+            .ok_or(Error::NoBus)?,
+        None => {
+            let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").ok_or(Error::NoBus)?;
+            SocketAddrUnix::new(Path::new(&runtime_dir).join("bus"))?
+        }
+    };
+    let fd = rustix::net::socket(AddressFamily::UNIX, SocketType::STREAM, None)?;
+    connect_unix(&fd, &addr)?;
+    Ok(fd)
+}
+
+/// Parses an address like `unix:path=/run/user/1000/bus`, returning `None` for other
+/// transports.
+fn parse_address(address: &[u8]) -> Option<SocketAddrUnix> {
+    let params = address.strip_prefix(b"unix:")?;
+    params.split(|&b| b == b',').find_map(|param| {
+        if let Some(path) = param.strip_prefix(b"path=") {
+            SocketAddrUnix::new(std::ffi::OsStr::from_bytes(&unescape(path)?)).ok()
+        } else if let Some(name) = param.strip_prefix(b"abstract=") {
+            SocketAddrUnix::new_abstract_name(&unescape(name)?).ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Decodes the `%xx` escapes in an address value.
+fn unescape(value: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.iter();
+    while let Some(&b) = bytes.next() {
+        if b == b'%' {
+            let hex = [*bytes.next()?, *bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    Some(out)
+}
+
+/// Authenticates as the user the process runs as, and asks to pass file descriptors.
+fn authenticate(socket: &OwnedFd) -> Result<(), Error> {
+    let uid = rustix::process::getuid().as_raw().to_string();
+    let hex = uid.bytes().map(|b| format!("{b:02x}")).collect::<String>();
+    let commands = [
+        format!("\0AUTH EXTERNAL {hex}\r\n"),
+        "NEGOTIATE_UNIX_FD\r\n".to_owned(),
+    ];
+    for (command, expected) in commands.iter().zip(["OK ", "AGREE_UNIX_FD"]) {
+        send_all(socket, command.as_bytes())?;
+        if !read_line(socket)?.starts_with(expected) {
+            return Err(Error::Auth);
+        }
+    }
+    send_all(socket, b"BEGIN\r\n")
+}
+
+/// Sends all of `bytes`, which a stream socket can take only part of at a time.
+fn send_all(socket: &OwnedFd, mut bytes: &[u8]) -> Result<(), Error> {
+    while !bytes.is_empty() {
+        match send(socket, bytes, SendFlags::NOSIGNAL) {
+            Ok(0) => return Err(Error::Closed),
+            Ok(n) => bytes = &bytes[n..],
+            Err(Errno::INTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Reads one line of the authentication protocol, which the bus only sends in answer to a
+/// command, so there's nothing after it to read too much of.
+fn read_line(socket: &OwnedFd) -> Result<String, Error> {
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        let mut byte = [0];
+        if rustix::io::read(socket, &mut byte)? == 0 {
+            return Err(Error::Closed);
+        }
+        line.push(byte[0]);
+        if line.len() > 512 {
+            return Err(Error::Auth);
+        }
+    }
+    String::from_utf8(line).map_err(|_| Error::Auth)
+}
+
+/// Encodes a method call to `[destination, path, interface, member]`.
+fn method_call(serial: u32, fields: [&str; 4], signature: &str, body: &[u8]) -> Vec<u8> {
+    let [destination, path, interface, member] = fields;
+    let mut w = Writer::default();
+    w.buf.extend_from_slice(&[b'l', METHOD_CALL, 0, 1]);
+    w.u32(u32::try_from(body.len()).unwrap());
+    w.u32(serial);
+    w.array(8, |w| {
+        w.field(1, "o", |w| w.str(path));
+        w.field(6, "s", |w| w.str(destination));
+        w.field(2, "s", |w| w.str(interface));
+        w.field(3, "s", |w| w.str(member));
+        if !signature.is_empty() {
+            w.field(8, "g", |w| w.signature(signature));
+        }
+    });
+    w.align(8);
+    w.buf.extend_from_slice(body);
+    w.buf
+}
+
+/// Returns the length of the message at the start of `buf`, once enough of it has arrived to
+/// tell.
+fn message_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+    let mut r = Reader::new(buf, buf[0] == b'B');
+    r.pos = 4;
+    let body_len = usize::try_from(r.u32()?).unwrap();
+    r.pos = 12;
+    let fields_len = usize::try_from(r.u32()?).unwrap();
+    // The most the specification allows.
+    if body_len + fields_len > 1 << 27 {
+        return Err(Error::Malformed);
+    }
+    Ok(Some((16 + fields_len).next_multiple_of(8) + body_len))
+}
+
+/// A message from the bus, with what's needed from its header.
+#[derive(Debug)]
+struct Incoming {
+    kind: u8,
+    big_endian: bool,
+    reply_serial: Option<u32>,
+    path: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+    fds: Vec<OwnedFd>,
+}
+
+/// Decodes a complete message, taking the file descriptors it has from `fds`.
+fn parse_message(bytes: &[u8], fds: &mut VecDeque<OwnedFd>) -> Result<Incoming, Error> {
+    let (big_endian, kind) = match *bytes {
+        [b'l', kind, ..] => (false, kind),
+        [b'B', kind, ..] => (true, kind),
+        _ => return Err(Error::Malformed),
+    };
+    let mut msg = Incoming {
+        kind,
+        big_endian,
+        reply_serial: None,
+        path: None,
+        member: None,
+        error_name: None,
+        signature: String::new(),
+        body: Vec::new(),
+        fds: Vec::new(),
+    };
+    let mut fd_count = 0;
+    let mut r = Reader::new(bytes, big_endian);
+    r.pos = 12;
+    let fields_end = usize::try_from(r.u32()?).unwrap() + 16;
+    while r.pos < fields_end {
+        r.align(8)?;
+        let code = r.u8()?;
+        let signature = r.signature()?;
+        match (code, &*signature) {
+            (1, "o") => msg.path = Some(r.str()?),
+            (3, "s") => msg.member = Some(r.str()?),
+            (4, "s") => msg.error_name = Some(r.str()?),
+            (5, "u") => msg.reply_serial = Some(r.u32()?),
+            (8, "g") => msg.signature = r.signature()?,
+            (9, "u") => fd_count = r.u32()?,
+            _ => r.skip(signature.as_bytes())?,
+        }
+    }
+    r.align(8)?;
+    msg.body = bytes[r.pos..].to_vec();
+    for _ in 0..fd_count {
+        msg.fds.push(fds.pop_front().ok_or(Error::Malformed)?);
+    }
+    Ok(msg)
+}
+
+/// A value in the results of a portal request, as far as they're needed.
+#[derive(Debug)]
+enum Value {
+    Str(String),
+    Other,
+}
+
+/// Encodes values in D-Bus format, little-endian unless `big_endian` is set.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Writer {
+    fn u32_bytes(&self, v: u32) -> [u8; 4] {
+        match self.big_endian {
+            true => v.to_be_bytes(),
+            false => v.to_le_bytes(),
+        }
+    }
+
+    fn align(&mut self, alignment: usize) {
+        let len = self.buf.len().next_multiple_of(alignment);
+        self.buf.resize(len, 0);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        let bytes = self.u32_bytes(v);
+        self.buf.extend_from_slice(&bytes);
+    }
+
+    /// Writes a string or an object path.
+    fn str(&mut self, s: &str) {
+        self.u32(u32::try_from(s.len()).unwrap());
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.buf.push(u8::try_from(s.len()).unwrap());
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Writes an array whose elements have `alignment`.
+    fn array(&mut self, alignment: usize, elements: impl FnOnce(&mut Writer)) {
+        self.u32(0);
+        let len_pos = self.buf.len() - 4;
+        // The length doesn't include the padding before the first element.
+        self.align(alignment);
+        let start = self.buf.len();
+        elements(self);
+        let len = u32::try_from(self.buf.len() - start).unwrap();
+        let bytes = self.u32_bytes(len);
+        self.buf[len_pos..len_pos + 4].copy_from_slice(&bytes);
+    }
+
+    /// Writes an `a{sv}` dictionary.
+    fn dict(&mut self, entries: impl FnOnce(&mut Writer)) {
+        self.array(8, entries);
+    }
+
+    /// Writes an entry of an `a{sv}` dictionary, with a value of type `signature`.
+    fn entry(&mut self, key: &str, signature: &str, value: impl FnOnce(&mut Writer)) {
+        self.align(8);
+        self.str(key);
+        self.signature(signature);
+        value(self);
+    }
+
+    /// Writes a header field, which is a `(yv)` struct.
+    fn field(&mut self, code: u8, signature: &str, value: impl FnOnce(&mut Writer)) {
+        self.align(8);
+        self.buf.push(code);
+        self.signature(signature);
+        value(self);
+    }
+}
+
+/// Decodes values in D-Bus format, returning [`Error::Malformed`] for anything out of bounds.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+    /// How many variants deep the value being read is.
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], big_endian: bool) -> Reader<'a> {
+        Reader {
+            data,
+            pos: 0,
+            big_endian,
+            depth: 0,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.checked_add(len).ok_or(Error::Malformed)?)
+            .ok_or(Error::Malformed)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<(), Error> {
+        let padding = self.pos.next_multiple_of(alignment) - self.pos;
+        self.take(padding).map(drop)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.align(4)?;
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    /// Reads a string or an object path.
+    fn str(&mut self) -> Result<String, Error> {
+        let len = usize::try_from(self.u32()?).unwrap();
+        let s = self.take(len)?.to_vec();
+        self.take(1)?;
+        String::from_utf8(s).map_err(|_| Error::Malformed)
+    }
+
+    fn signature(&mut self) -> Result<String, Error> {
+        let len = usize::from(self.u8()?);
+        let s = self.take(len)?.to_vec();
+        self.take(1)?;
+        String::from_utf8(s).map_err(|_| Error::Malformed)
+    }
+
+    /// Reads an `a{sv}` dictionary, keeping string and object path values.
+    fn dict(&mut self) -> Result<Vec<(String, Value)>, Error> {
+        let len = usize::try_from(self.u32()?).unwrap();
+        self.align(8)?;
+        let end = self.pos + len;
+        let mut entries = Vec::new();
+        while self.pos < end {
+            self.align(8)?;
+            let key = self.str()?;
+            let signature = self.signature()?;
+            let value = match &*signature {
+                "s" | "o" => Value::Str(self.str()?),
+                _ => {
+                    self.skip(signature.as_bytes())?;
+                    Value::Other
+                }
+            };
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    /// Skips values of the complete types in `signature`.
+    fn skip(&mut self, mut signature: &[u8]) -> Result<(), Error> {
+        while !signature.is_empty() {
+            let len = type_len(signature).ok_or(Error::Malformed)?;
+            self.skip_one(&signature[..len])?;
+            signature = &signature[len..];
+        }
+        Ok(())
+    }
+
+    /// Skips a value of the single complete type `ty`.
+    fn skip_one(&mut self, ty: &[u8]) -> Result<(), Error> {
+        match ty[0] {
+            b'y' => self.take(1).map(drop),
+            b'n' | b'q' => self.align(2).and_then(|()| self.take(2).map(drop)),
+            b'b' | b'i' | b'u' | b'h' => self.u32().map(drop),
+            b'x' | b't' | b'd' => self.align(8).and_then(|()| self.take(8).map(drop)),
+            b's' | b'o' => self.str().map(drop),
+            b'g' => self.signature().map(drop),
+            b'v' => {
+                // Signatures limit how deep arrays and structs go, but variants can nest
+                // without end, so they're limited to the depth the specification allows.
+                if self.depth == 64 {
+                    return Err(Error::Malformed);
+                }
+                let signature = self.signature()?;
+                self.depth += 1;
+                let result = self.skip(signature.as_bytes());
+                self.depth -= 1;
+                result
+            }
+            b'a' => {
+                let len = usize::try_from(self.u32()?).unwrap();
+                self.align(alignment(ty[1]))?;
+                self.take(len).map(drop)
+            }
+            b'(' | b'{' => {
+                self.align(8)?;
+                self.skip(&ty[1..ty.len() - 1])
+            }
+            _ => Err(Error::Malformed),
+        }
+    }
+}
+
+/// Returns the length of the complete type at the start of `signature`.
+fn type_len(signature: &[u8]) -> Option<usize> {
+    match signature.first()? {
+        b'a' => Some(1 + type_len(&signature[1..])?),
+        open @ (b'(' | b'{') => {
+            let close = if *open == b'(' { b')' } else { b'}' };
+            let mut len = 1;
+            while *signature.get(len)? != close {
+                len += type_len(&signature[len..])?;
+            }
+            Some(len + 1)
+        }
+        b')' | b'}' => None,
+        _ => Some(1),
+    }
+}
+
+/// Returns the alignment of values of the type that starts with `code`.
+fn alignment(code: u8) -> usize {
+    match code {
+        b'y' | b'g' | b'v' => 1,
+        b'n' | b'q' => 2,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let mut w = Writer::default();
+        w.str("/org/freedesktop/portal/desktop/session/1_42/ei1");
+        w.dict(|w| {
+            w.entry("handle_token", "s", |w| w.str("ei2"));
+            w.entry("types", "u", |w| w.u32(DEVICE_TYPES));
+        });
+        let fields = [DESTINATION, PATH, REMOTE_DESKTOP, "SelectDevices"];
+        let bytes = method_call(7, fields, "oa{sv}", &w.buf);
+        assert_eq!(message_len(&bytes).unwrap(), Some(bytes.len()));
+        let msg = parse_message(&bytes, &mut VecDeque::new()).unwrap();
+        assert_eq!(msg.kind, METHOD_CALL);
+        assert_eq!(msg.path.as_deref(), Some(PATH));
+        assert_eq!(msg.member.as_deref(), Some("SelectDevices"));
+        assert_eq!(msg.signature, "oa{sv}");
+        let mut r = Reader::new(&msg.body, false);
+        assert_eq!(
+            r.str().unwrap(),
+            "/org/freedesktop/portal/desktop/session/1_42/ei1"
+        );
+        let entries = r.dict().unwrap();
+        assert!(matches!(&entries[..], [
+            (handle_token, Value::Str(token)),
+            (types, Value::Other),
+        ] if handle_token == "handle_token" && token == "ei2" && types == "types"));
+        assert_eq!(r.pos, msg.body.len());
+    }
+
+    #[test]
+    fn parse_addresses() {
+        assert!(parse_address(b"unix:path=/run/user/1000/bus").is_some());
+        assert!(parse_address(b"unix:abstract=/tmp/dbus-x%2cy,guid=0123").is_some());
+        assert!(parse_address(b"tcp:host=localhost,port=1234").is_none());
+        assert_eq!(unescape(b"a%2cb%2F").unwrap(), b"a,b/");
+        assert_eq!(type_len(b"a{sv}u"), Some(5));
+        assert_eq!(type_len(b"(ia(su))"), Some(8));
+    }
+
+    const HANDLE: &str = "/org/freedesktop/portal/desktop/request/1_42/ei1";
+    const SESSION: &str = "/org/freedesktop/portal/desktop/session/1_42/ei1";
+
+    /// Encodes a message like the bus would send, with the header fields `fields` writes.
+    fn encode(
+        kind: u8,
+        big_endian: bool,
+        fields: impl FnOnce(&mut Writer),
+        signature: &str,
+        body: impl FnOnce(&mut Writer),
+    ) -> Vec<u8> {
+        let mut b = Writer {
+            big_endian,
+            ..Writer::default()
+        };
+        body(&mut b);
+        let mut w = Writer {
+            big_endian,
+            ..Writer::default()
+        };
+        let endianness = if big_endian { b'B' } else { b'l' };
+        w.buf.extend_from_slice(&[endianness, kind, 0, 1]);
+        w.u32(u32::try_from(b.buf.len()).unwrap());
+        w.u32(1);
+        w.array(8, |w| {
+            fields(w);
+            w.field(8, "g", |w| w.signature(signature));
+        });
+        w.align(8);
+        w.buf.extend_from_slice(&b.buf);
+        w.buf
+    }
+
+    fn method_return(reply_serial: u32, big_endian: bool) -> Vec<u8> {
+        encode(
+            METHOD_RETURN,
+            big_endian,
+            |w| w.field(5, "u", |w| w.u32(reply_serial)),
+            "o",
+            |w| w.str(HANDLE),
+        )
+    }
+
+    fn response(path: &str, code: u32, big_endian: bool) -> Vec<u8> {
+        encode(
+            SIGNAL,
+            big_endian,
+            |w| {
+                w.field(1, "o", |w| w.str(path));
+                w.field(3, "s", |w| w.str("Response"));
+            },
+            "ua{sv}",
+            |w| {
+                w.u32(code);
+                w.dict(|w| w.entry("session_handle", "o", |w| w.str(SESSION)));
+            },
+        )
+    }
+
+    /// Returns a bus whose other end has already sent `messages`, along with that end.
+    fn bus_with(messages: &[Vec<u8>]) -> (Bus, OwnedFd) {
+        let (socket, peer) = rustix::net::socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        for message in messages {
+            send_all(&peer, message).unwrap();
+        }
+        let bus = Bus {
+            socket,
+            buf: Vec::new(),
+            fds: VecDeque::new(),
+            serial: 0,
+            responses: Vec::new(),
+            unique_name: ":1.42".to_owned(),
+        };
+        (bus, peer)
+    }
+
+    fn create_session(bus: &mut Bus) -> Result<Vec<(String, Value)>, Error> {
+        bus.request("CreateSession", "a{sv}", |w, token| {
+            w.dict(|w| w.entry("handle_token", "s", |w| w.str(token)))
+        })
+    }
+
+    #[test]
+    fn response_before_reply() {
+        let other = "/org/freedesktop/portal/desktop/request/1_42/ei9";
+        let (mut bus, _peer) = bus_with(&[
+            response(other, 1, false),
+            response(HANDLE, 0, false),
+            method_return(1, false),
+        ]);
+        let results = create_session(&mut bus).unwrap();
+        assert!(matches!(&results[..], [(key, Value::Str(session))]
+            if key == "session_handle" && session == SESSION));
+        // The response for another request is kept for it.
+        assert_eq!(bus.responses.len(), 1);
+        assert_eq!(bus.responses[0].path.as_deref(), Some(other));
+    }
+
+    #[test]
+    fn big_endian_messages() {
+        let (mut bus, _peer) = bus_with(&[method_return(1, true), response(HANDLE, 0, true)]);
+        let results = create_session(&mut bus).unwrap();
+        assert!(matches!(&results[..], [(key, Value::Str(session))]
+            if key == "session_handle" && session == SESSION));
+    }
+
+    #[test]
+    fn response_codes() {
+        let (mut bus, _peer) = bus_with(&[method_return(1, false), response(HANDLE, 1, false)]);
+        assert!(matches!(create_session(&mut bus), Err(Error::Cancelled)));
+        let (mut bus, _peer) = bus_with(&[method_return(1, false), response(HANDLE, 2, false)]);
+        assert!(matches!(create_session(&mut bus), Err(Error::Failed)));
+    }
+
+    #[test]
+    fn error_reply() {
+        let error = encode(
+            ERROR,
+            false,
+            |w| {
+                w.field(4, "s", |w| {
+                    w.str("org.freedesktop.DBus.Error.UnknownMethod")
+                });
+                w.field(5, "u", |w| w.u32(1));
+            },
+            "s",
+            |w| w.str("No such interface"),
+        );
+        let (mut bus, _peer) = bus_with(&[error]);
+        assert!(
+            matches!(create_session(&mut bus), Err(Error::Call { name, message })
+            if name == "org.freedesktop.DBus.Error.UnknownMethod"
+                && message == "No such interface")
+        );
+    }
+
+    #[test]
+    fn truncated_reply() {
+        let reply = method_return(1, false);
+        let (mut bus, peer) = bus_with(&[reply[..reply.len() - 3].to_vec()]);
+        rustix::net::shutdown(&peer, rustix::net::Shutdown::Write).unwrap();
+        assert!(matches!(create_session(&mut bus), Err(Error::Closed)));
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let reply = method_return(1, false);
+        assert_eq!(message_len(&reply[..15]).unwrap(), None);
+        assert!(matches!(
+            parse_message(&[], &mut VecDeque::new()),
+            Err(Error::Malformed)
+        ));
+
+        let mut bad_endianness = reply.clone();
+        bad_endianness[0] = b'x';
+        assert!(matches!(
+            parse_message(&bad_endianness, &mut VecDeque::new()),
+            Err(Error::Malformed)
+        ));
+
+        // Header fields that run past the end of the message.
+        let mut long_fields = reply.clone();
+        long_fields[12..16].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(matches!(
+            parse_message(&long_fields, &mut VecDeque::new()),
+            Err(Error::Malformed)
+        ));
+
+        let mut huge_body = reply.clone();
+        huge_body[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(message_len(&huge_body), Err(Error::Malformed)));
+
+        // A message that says it passes a file descriptor, without one.
+        let fd_reply = encode(
+            METHOD_RETURN,
+            false,
+            |w| {
+                w.field(5, "u", |w| w.u32(1));
+                w.field(9, "u", |w| w.u32(1));
+            },
+            "h",
+            |w| w.u32(0),
+        );
+        assert!(matches!(
+            parse_message(&fd_reply, &mut VecDeque::new()),
+            Err(Error::Malformed)
+        ));
+
+        // A dictionary that's cut off in the middle of an entry.
+        let mut w = Writer::default();
+        w.dict(|w| w.entry("session_handle", "o", |w| w.str(SESSION)));
+        let len = w.buf.len();
+        assert!(matches!(
+            Reader::new(&w.buf[..len - 4], false).dict(),
+            Err(Error::Malformed)
+        ));
+        let mut bad_signature = w.buf.clone();
+        bad_signature[28] = b'}';
+        assert!(matches!(
+            Reader::new(&bad_signature, false).dict(),
+            Err(Error::Malformed)
+        ));
+
+        // Variants nested too deeply to skip without risking the stack.
+        let nested = |depth| {
+            let mut data = [1, b'v', 0].repeat(depth);
+            data.extend_from_slice(&[1, b'y', 0, 7]);
+            data
+        };
+        assert!(Reader::new(&nested(10), false).skip(b"v").is_ok());
+        assert!(matches!(
+            Reader::new(&nested(100), false).skip(b"v"),
+            Err(Error::Malformed)
+        ));
+    }
+
+    #[test]
+    fn authentication() {
+        let (socket, peer) = rustix::net::socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        send_all(&peer, b"OK 0123456789abcdef\r\nAGREE_UNIX_FD\r\n").unwrap();
+        authenticate(&socket).unwrap();
+        drop(socket);
+        let mut sent = Vec::new();
+        let mut chunk = [0; 256];
+        loop {
+            match rustix::io::read(&peer, &mut chunk).unwrap() {
+                0 => break,
+                n => sent.extend_from_slice(&chunk[..n]),
+            }
+        }
+        let sent = String::from_utf8(sent).unwrap();
+        assert!(sent.starts_with("\0AUTH EXTERNAL "));
+        assert!(sent.ends_with("\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n"));
+
+        let (socket, peer) = rustix::net::socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            rustix::net::SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        send_all(&peer, b"OK 0123456789abcdef\r\nERROR\r\n").unwrap();
+        assert!(matches!(authenticate(&socket), Err(Error::Auth)));
+    }
+}
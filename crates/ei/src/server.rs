@@ -0,0 +1,582 @@
+//! A minimal EIS implementation, for testing sender clients without a compositor.
+//!
+//! It accepts one client, advertises a seat with every capability the client supports, gives
+//! it a device with the capabilities it binds, and records the input it emulates.
+
+use crate::{Arg, Connection, Message};
+use rustix::io::Errno;
+use std::{collections::HashMap, fmt};
+
+/// Objects created by the EIS implementation have ids from here on.
+const FIRST_SERVER_ID: u64 = 0xff00_0000_0000_0000;
+
+const HANDSHAKE: u64 = 0;
+
+/// The interfaces this implementation supports, and the newest version of each.
+const INTERFACES: &[(&str, u32)] = &[
+    ("ei_handshake", 1),
+    ("ei_connection", 1),
+    ("ei_callback", 1),
+    ("ei_pingpong", 1),
+    ("ei_seat", 1),
+    ("ei_device", 2),
+    ("ei_pointer", 1),
+    ("ei_pointer_absolute", 1),
+    ("ei_scroll", 1),
+    ("ei_button", 1),
+    ("ei_keyboard", 1),
+];
+
+/// The interfaces a device can have. The seat's capability for each is the bit at its index.
+const CAPABILITIES: &[&str] = &[
+    "ei_pointer",
+    "ei_pointer_absolute",
+    "ei_scroll",
+    "ei_button",
+    "ei_keyboard",
+];
+
+/// The `virtual` entry of `ei_device.device_type`.
+const DEVICE_TYPE_VIRTUAL: u32 = 1;
+
+/// An error while serving the client.
+#[derive(Debug)]
+pub enum Error {
+    Io(Errno),
+    /// The client closed the connection during the handshake.
+    Closed,
+    /// The client sent something this implementation doesn't understand.
+    Protocol(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::Closed => write!(f, "the client closed the connection"),
+            Error::Protocol(reason) => write!(f, "protocol error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Errno> for Error {
+    fn from(e: Errno) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// A rectangle of the logical screen that absolute pointer motion is mapped to, as sent in
+/// `ei_device.region`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub scale: f32,
+}
+
+/// Something the client did, in the order it did it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The client bound the seat, and was given a device with these interfaces.
+    Bound {
+        interfaces: Vec<&'static str>,
+    },
+    StartEmulating {
+        sequence: u32,
+    },
+    StopEmulating,
+    Frame {
+        timestamp: u64,
+    },
+    MotionRelative {
+        x: f32,
+        y: f32,
+    },
+    MotionAbsolute {
+        x: f32,
+        y: f32,
+    },
+    Button {
+        button: u32,
+        state: u32,
+    },
+    Scroll {
+        x: f32,
+        y: f32,
+    },
+    ScrollDiscrete {
+        x: i32,
+        y: i32,
+    },
+    ScrollStop {
+        x: u32,
+        y: u32,
+        is_cancel: u32,
+    },
+    Key {
+        key: u32,
+        state: u32,
+    },
+    /// The client answered a ping.
+    Pong,
+}
+
+/// A request from the client, decoded according to the interface of its object.
+#[derive(Debug)]
+enum Request {
+    HandshakeVersion(u32),
+    ContextType(u32),
+    Name(String),
+    InterfaceVersion(String, u32),
+    Finish,
+    Sync(u64),
+    Disconnect,
+    Bind(u64),
+    Release,
+    Event(Event),
+    Pong,
+    /// A request on an object that doesn't exist, which is ignored after telling the client.
+    UnknownObject,
+}
+
+/// The EIS side of a connection to one sender client.
+#[derive(Debug)]
+pub struct Server {
+    conn: Connection,
+    serial: u32,
+    next_id: u64,
+    /// The interface of each live object, by id.
+    objects: HashMap<u64, &'static str>,
+    /// The versions agreed on during the handshake.
+    versions: HashMap<&'static str, u32>,
+    connection: u64,
+    regions: Vec<Region>,
+    name: Option<String>,
+    events: Vec<Event>,
+}
+
+impl Server {
+    pub fn new(conn: Connection) -> Server {
+        Server {
+            conn,
+            serial: 0,
+            next_id: FIRST_SERVER_ID,
+            objects: HashMap::from([(HANDSHAKE, "ei_handshake")]),
+            versions: HashMap::new(),
+            connection: 0,
+            regions: Vec::new(),
+            name: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Adds a region to the devices given to the client.
+    pub fn region(mut self, region: Region) -> Server {
+        self.regions.push(region);
+        self
+    }
+
+    /// The name the client gave in the handshake.
+    pub fn client_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Does the handshake, blocking until the client finishes it, then advertises the seat.
+    pub fn accept(&mut self) -> Result<(), Error> {
+        self.send(HANDSHAKE, 0, "HandshakeVersion", &[Arg::Uint32(1)]);
+        self.conn.flush_blocking()?;
+        let mut client_versions = HashMap::new();
+        'handshake: loop {
+            if !self.conn.read_blocking()? {
+                return Err(Error::Closed);
+            }
+            while let Some((object, request)) = self.read_request() {
+                if object != HANDSHAKE {
+                    return Err(Error::Protocol("request before the handshake finished"));
+                }
+                match request? {
+                    Request::HandshakeVersion(0) => {
+                        return Err(Error::Protocol("handshake version 0"));
+                    }
+                    Request::HandshakeVersion(_) => {}
+                    Request::ContextType(context_type) => {
+                        if context_type != crate::handshake::CONTEXT_TYPE_SENDER {
+                            return Err(Error::Protocol("only sender clients are supported"));
+                        }
+                    }
+                    Request::Name(name) => self.name = Some(name),
+                    Request::InterfaceVersion(name, version) => {
+                        client_versions.insert(name, version);
+                    }
+                    Request::Finish => break 'handshake,
+                    _ => return Err(Error::Protocol("unexpected request")),
+                }
+            }
+        }
+
+        for &(interface, version) in INTERFACES {
+            let Some(&client_version) = client_versions.get(interface) else {
+                continue;
+            };
+            let version = version.min(client_version);
+            self.versions.insert(interface, version);
+            let args = [Arg::String(Some(interface)), Arg::Uint32(version)];
+            self.send(HANDSHAKE, 1, "InterfaceVersion", &args);
+        }
+        let connection = self.create("ei_connection");
+        self.connection = connection;
+        let serial = self.next_serial();
+        let args = [
+            Arg::Uint32(serial),
+            Arg::Uint64(connection),
+            Arg::Uint32(self.version("ei_connection")),
+        ];
+        self.send(HANDSHAKE, 2, "Connection", &args);
+        self.objects.remove(&HANDSHAKE);
+
+        let seat = self.create("ei_seat");
+        let args = [Arg::Uint64(seat), Arg::Uint32(self.version("ei_seat"))];
+        self.send(connection, 1, "Seat", &args);
+        self.send(seat, 1, "Name", &[Arg::String(Some("default"))]);
+        for (i, &interface) in CAPABILITIES.iter().enumerate() {
+            if self.versions.contains_key(interface) {
+                let args = [Arg::Uint64(1 << i), Arg::String(Some(interface))];
+                self.send(seat, 2, "Capability", &args);
+            }
+        }
+        self.send(seat, 3, "Done", &[]);
+        self.conn.flush_blocking()?;
+        Ok(())
+    }
+
+    /// Blocks until the client sends something, and handles it. Returns `false` once the
+    /// client disconnects.
+    pub fn dispatch(&mut self) -> Result<bool, Error> {
+        if !self.conn.read_blocking()? {
+            return Ok(false);
+        }
+        while let Some((object, request)) = self.read_request() {
+            match request? {
+                Request::Sync(callback) => {
+                    self.objects.insert(callback, "ei_callback");
+                    self.send(callback, 0, "Done", &[Arg::Uint64(0)]);
+                    self.objects.remove(&callback);
+                }
+                Request::Disconnect => return Ok(false),
+                Request::Bind(capabilities) => self.add_device(object, capabilities),
+                Request::Release => {
+                    let serial = self.next_serial();
+                    self.send(object, 0, "Destroyed", &[Arg::Uint32(serial)]);
+                    self.objects.remove(&object);
+                }
+                Request::Event(event) => self.events.push(event),
+                Request::Pong => {
+                    self.objects.remove(&object);
+                    self.events.push(Event::Pong);
+                }
+                Request::UnknownObject => {
+                    let args = [Arg::Uint32(self.serial), Arg::Uint64(object)];
+                    self.send(self.connection, 2, "InvalidObject", &args);
+                }
+                _ => return Err(Error::Protocol("handshake request after the handshake")),
+            }
+        }
+        self.conn.flush_blocking()?;
+        Ok(true)
+    }
+
+    /// Sends `ei_connection.ping`, recording [`Event::Pong`] once the client answers it.
+    pub fn ping(&mut self) -> Result<(), Error> {
+        let ping = self.create("ei_pingpong");
+        let args = [Arg::Uint64(ping), Arg::Uint32(self.version("ei_pingpong"))];
+        self.send(self.connection, 3, "Ping", &args);
+        self.conn.flush_blocking()?;
+        Ok(())
+    }
+
+    /// Takes the events recorded so far.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Gives the client a device on `seat` with the interfaces in `capabilities`.
+    fn add_device(&mut self, seat: u64, capabilities: u64) {
+        let device = self.create("ei_device");
+        let args = [Arg::Uint64(device), Arg::Uint32(self.version("ei_device"))];
+        self.send(seat, 4, "Device", &args);
+        self.send(device, 1, "Name", &[Arg::String(Some("test device"))]);
+        self.send(device, 2, "DeviceType", &[Arg::Uint32(DEVICE_TYPE_VIRTUAL)]);
+        for region in self.regions.clone() {
+            let args = [
+                Arg::Uint32(region.x),
+                Arg::Uint32(region.y),
+                Arg::Uint32(region.width),
+                Arg::Uint32(region.height),
+                Arg::Float(region.scale),
+            ];
+            self.send(device, 4, "Region", &args);
+        }
+        let mut interfaces = Vec::new();
+        for (i, &interface) in CAPABILITIES.iter().enumerate() {
+            if capabilities & (1 << i) == 0 || !self.versions.contains_key(interface) {
+                continue;
+            }
+            let id = self.create(interface);
+            let args = [
+                Arg::Uint64(id),
+                Arg::String(Some(interface)),
+                Arg::Uint32(self.version(interface)),
+            ];
+            self.send(device, 5, "Interface", &args);
+            interfaces.push(interface);
+        }
+        self.send(device, 6, "Done", &[]);
+        let serial = self.next_serial();
+        self.send(device, 7, "Resumed", &[Arg::Uint32(serial)]);
+        self.events.push(Event::Bound { interfaces });
+    }
+
+    fn read_request(&mut self) -> Option<(u64, Result<Request, Error>)> {
+        let objects = &self.objects;
+        let (object, interface, request) = self.conn.read_message(|msg| {
+            let object = msg.object();
+            let interface = objects.get(&object).copied();
+            let request = match interface {
+                Some(interface) => decode(interface, msg),
+                None => Some(Request::UnknownObject),
+            };
+            Some((object, interface, request))
+        })?;
+        let request = request.ok_or(Error::Protocol("malformed request"));
+        if let (Some(interface), Ok(request)) = (interface, &request) {
+            self.conn.trace(false, interface, object, request);
+        }
+        Some((object, request))
+    }
+
+    fn send(&mut self, object: u64, opcode: u32, name: &str, args: &[Arg<'_>]) {
+        let interface = self.objects.get(&object).copied().unwrap_or("unknown");
+        self.conn
+            .trace(true, interface, object, &format_args!("{name}{args:?}"));
+        self.conn.write_message(object, opcode, args, []);
+    }
+
+    fn create(&mut self, interface: &'static str) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.objects.insert(id, interface);
+        id
+    }
+
+    fn next_serial(&mut self) -> u32 {
+        self.serial = self.serial.wrapping_add(1);
+        self.serial
+    }
+
+    fn version(&self, interface: &str) -> u32 {
+        self.versions.get(interface).copied().unwrap_or(1)
+    }
+}
+
+/// Decodes a request on an object of `interface`, returning `None` if it's malformed.
+fn decode(interface: &str, mut msg: Message<'_>) -> Option<Request> {
+    Some(match (interface, msg.opcode()) {
+        ("ei_handshake", 0) => Request::HandshakeVersion(msg.read_uint32()?),
+        ("ei_handshake", 1) => Request::Finish,
+        ("ei_handshake", 2) => Request::ContextType(msg.read_uint32()?),
+        ("ei_handshake", 3) => Request::Name(msg.read_string()??),
+        ("ei_handshake", 4) => Request::InterfaceVersion(msg.read_string()??, msg.read_uint32()?),
+        ("ei_connection", 0) => Request::Sync(msg.read_uint64()?),
+        ("ei_connection", 1) => Request::Disconnect,
+        ("ei_pingpong", 0) => Request::Pong,
+        ("ei_seat", 1) => Request::Bind(msg.read_uint64()?),
+        (_, 0) => Request::Release,
+        ("ei_device", 1) => {
+            msg.read_uint32()?;
+            Request::Event(Event::StartEmulating {
+                sequence: msg.read_uint32()?,
+            })
+        }
+        ("ei_device", 2) => Request::Event(Event::StopEmulating),
+        ("ei_device", 3) => {
+            msg.read_uint32()?;
+            Request::Event(Event::Frame {
+                timestamp: msg.read_uint64()?,
+            })
+        }
+        ("ei_pointer", 1) => Request::Event(Event::MotionRelative {
+            x: msg.read_float()?,
+            y: msg.read_float()?,
+        }),
+        ("ei_pointer_absolute", 1) => Request::Event(Event::MotionAbsolute {
+            x: msg.read_float()?,
+            y: msg.read_float()?,
+        }),
+        ("ei_scroll", 1) => Request::Event(Event::Scroll {
+            x: msg.read_float()?,
+            y: msg.read_float()?,
+        }),
+        ("ei_scroll", 2) => Request::Event(Event::ScrollDiscrete {
+            x: msg.read_int32()?,
+            y: msg.read_int32()?,
+        }),
+        ("ei_scroll", 3) => Request::Event(Event::ScrollStop {
+            x: msg.read_uint32()?,
+            y: msg.read_uint32()?,
+            is_cancel: msg.read_uint32()?,
+        }),
+        ("ei_button", 1) => Request::Event(Event::Button {
+            button: msg.read_uint32()?,
+            state: msg.read_uint32()?,
+        }),
+        ("ei_keyboard", 1) => Request::Event(Event::Key {
+            key: msg.read_uint32()?,
+            state: msg.read_uint32()?,
+        }),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handshake::CONTEXT_TYPE_SENDER, Device, Handshake};
+    use rustix::net::{socketpair, AddressFamily, SocketFlags, SocketType};
+
+    struct Conn(Connection);
+
+    impl AsMut<Connection> for Conn {
+        fn as_mut(&mut self) -> &mut Connection {
+            &mut self.0
+        }
+    }
+
+    /// Reads events until one `done` returns true for, returning the ones before it.
+    fn read_until(
+        conn: &mut Connection,
+        done: impl Fn(u64, u32) -> bool,
+    ) -> Vec<(u64, u32, Vec<u64>)> {
+        let mut events = Vec::new();
+        loop {
+            // Reads the first argument of each event, which is all the test needs.
+            while let Some((object, opcode, arg)) =
+                conn.read_message(|mut msg| Some((msg.object(), msg.opcode(), msg.read_uint64())))
+            {
+                if done(object, opcode) {
+                    return events;
+                }
+                events.push((object, opcode, arg.into_iter().collect()));
+            }
+            conn.read_blocking().unwrap();
+        }
+    }
+
+    #[test]
+    fn sender() {
+        let (client, server) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let server = std::thread::spawn(move || {
+            let mut server = Server::new(Connection::new(server)).region(Region {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 100,
+                scale: 1.0,
+            });
+            server.accept().unwrap();
+            assert_eq!(server.client_name(), Some("test"));
+            // Answered by the client while it reads the seat or the device.
+            server.ping().unwrap();
+            while server.dispatch().unwrap() {}
+            server.take_events()
+        });
+
+        let mut client = Conn(Connection::new(client));
+        let mut handshake = Handshake::new(CONTEXT_TYPE_SENDER, "test");
+        for &(interface, version) in INTERFACES {
+            handshake = handshake.interface(interface, version);
+        }
+        let connected = handshake.run(&mut client.0).unwrap();
+
+        // ei_seat.done, after the seat and its capabilities.
+        let seat_events = read_until(&mut client.0, |_, opcode| opcode == 3);
+        let (seat, capabilities) = (seat_events[0].2[0], &seat_events[2..]);
+        assert_eq!(seat_events[0].0, connected.connection);
+        assert_eq!(capabilities.len(), CAPABILITIES.len());
+        let absolute = capabilities[1].2[0];
+        let button = capabilities[3].2[0];
+        let bind = [Arg::Uint64(absolute | button)];
+        client.0.write_message(seat, 1, &bind, []);
+        client.0.flush_blocking().unwrap();
+
+        // ei_device.resumed, after the device and its interfaces.
+        let device_events = read_until(&mut client.0, |_, opcode| opcode == 7);
+        let device = device_events[0].2[0];
+        let interfaces: Vec<u64> = device_events
+            .iter()
+            .filter(|&&(object, opcode, _)| object == device && opcode == 5)
+            .map(|(_, _, args)| args[0])
+            .collect();
+        let [pointer_absolute, button] = interfaces[..] else {
+            panic!("expected two interfaces, got {interfaces:?}");
+        };
+
+        let mut device = Device::new(device);
+        let mut emulating = device.start_emulating(&mut client, 1);
+        let motion = [Arg::Float(10.0), Arg::Float(20.0)];
+        emulating
+            .conn()
+            .0
+            .write_message(pointer_absolute, 1, &motion, []);
+        emulating.frame();
+        let press = [Arg::Uint32(0x110), Arg::Uint32(1)];
+        emulating.conn().0.write_message(button, 1, &press, []);
+        drop(emulating);
+        client.0.flush_blocking().unwrap();
+        drop(client);
+
+        let mut events = server.join().unwrap();
+        let pongs = events.iter().filter(|&event| *event == Event::Pong).count();
+        assert_eq!(pongs, 1);
+        events.retain(|event| *event != Event::Pong);
+        for event in &mut events {
+            if let Event::Frame { timestamp } = event {
+                assert_ne!(*timestamp, 0);
+                *timestamp = 0;
+            }
+        }
+        assert_eq!(
+            events,
+            [
+                Event::Bound {
+                    interfaces: vec!["ei_pointer_absolute", "ei_button"],
+                },
+                Event::StartEmulating { sequence: 0 },
+                Event::MotionAbsolute { x: 10.0, y: 20.0 },
+                Event::Frame { timestamp: 0 },
+                Event::Button {
+                    button: 0x110,
+                    state: 1,
+                },
+                Event::Frame { timestamp: 0 },
+                Event::StopEmulating,
+            ],
+        );
+    }
+}
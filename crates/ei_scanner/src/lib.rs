@@ -308,15 +308,32 @@ impl<'a> GenContext<'a> {
         let global_enum_name = format_ident!("{kind}");
         let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
         let type_name = format_ident!("{}{kind}", interface.name.to_upper_camel_case());
-        let variants = messages
-            .iter()
-            .filter(|msg| {
-                self.context_type.is_none() || msg.context_type.is_none() || {
-                    msg.context_type
-                        .is_some_and(|it| it == self.context_type.unwrap())
-                }
-            })
+        let messages_in_context = messages.iter().filter(|msg| {
+            self.context_type.is_none() || msg.context_type.is_none() || {
+                msg.context_type
+                    .is_some_and(|it| it == self.context_type.unwrap())
+            }
+        });
+        let variants = messages_in_context
+            .clone()
             .map(|message| self.gen_message_enum_variant(interface, message));
+        let interface_field_name = format_ident!("{}", interface.name.to_snake_case());
+        let (object_arms, since_arms): (Vec<_>, Vec<_>) = messages_in_context
+            .map(|message| {
+                let variant_name = format_ident!("{}", message.name.to_upper_camel_case());
+                let since = message.since;
+                (
+                    quote!(#type_name::#variant_name { #interface_field_name, .. } => #interface_field_name.0,),
+                    quote!(#type_name::#variant_name { .. } => #since,),
+                )
+            })
+            .unzip();
+        // A reference to an empty enum isn't itself uninhabited, so it has to be dereferenced.
+        let scrutinee = if object_arms.is_empty() {
+            quote!(*self)
+        } else {
+            quote!(self)
+        };
         let type_needs_lifetime = message_type_needs_lifetime(messages, self.context_type);
         let generic = if type_needs_lifetime {
             quote!('a)
@@ -341,6 +358,20 @@ impl<'a> GenContext<'a> {
             }
             #reader
             #writer
+            impl #generics #type_name #generics {
+                /// The id of the object the message is for.
+                pub fn object(&self) -> u64 {
+                    match #scrutinee {
+                        #(#object_arms)*
+                    }
+                }
+                /// The interface version the message was added in.
+                pub fn since(&self) -> u32 {
+                    match #scrutinee {
+                        #(#since_arms)*
+                    }
+                }
+            }
             // TODO make this lifetime optional
             impl #global_generic From<#type_name #generics> for #global_enum_name #global_generic {
                 fn from(v: #type_name #generics) -> #global_enum_name #global_generic {
@@ -436,6 +467,14 @@ impl<'a> GenContext<'a> {
                 Interface::#interface_type_name => unreachable!("disabled"),
             }
         });
+        let accessor_variants = enabled_interfaces.clone().map(|interface| {
+            let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
+            quote!(#kind_ident::#interface_type_name(it))
+        });
+        let object_variants = accessor_variants
+            .clone()
+            .map(|variant| quote!(#variant => it.object(),));
+        let since_variants = accessor_variants.map(|variant| quote!(#variant => it.since(),));
         let write_variants = enabled_interfaces.map(|interface| {
             let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
             quote! {
@@ -464,6 +503,18 @@ impl<'a> GenContext<'a> {
                         #(#write_variants)*
                     }
                 }
+                /// The id of the object the message is for.
+                pub fn object(&self) -> u64 {
+                    match self {
+                        #(#object_variants)*
+                    }
+                }
+                /// The interface version the message was added in.
+                pub fn since(&self) -> u32 {
+                    match self {
+                        #(#since_variants)*
+                    }
+                }
             }
         };
         (tokens, any_variant_needs_lifetime)
@@ -2,12 +2,24 @@
 
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Directive {
     pub name: String,
     pub params: Vec<String>,
     pub children: Vec<Directive>,
     pub line: usize,
+    /// Location of the name.
+    pub span: Span,
+    /// Locations of the parameters, including any quotes.
+    pub param_spans: Vec<Span>,
+}
+
+/// The location of a word, with columns counted in bytes from the start of the line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug)]
@@ -68,8 +80,6 @@ impl<'a> Parser<'a> {
                     .find('\n')
                     .unwrap_or(self.text.len() - self.pos);
                 self.pos += len;
-                self.line += 1;
-                self.column = 0;
                 continue;
             }
             break;
@@ -77,6 +87,14 @@ impl<'a> Parser<'a> {
         self.skip_wsp();
     }
 
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            line: self.line,
+            start,
+            end: self.column,
+        }
+    }
+
     fn at(&self, expected: char) -> bool {
         self.text[self.pos..].starts_with(expected)
     }
@@ -109,6 +127,14 @@ fn parse_config(p: &mut Parser) -> Result<Vec<Directive>, Error> {
     let mut directives = Vec::new();
     p.skip_newline();
     while !p.at_end() {
+        if p.at('}') {
+            // A closing brace without a block to close.
+            return Err(Error {
+                expected: '\n',
+                line: p.line,
+                column: p.column,
+            });
+        }
         directives.push(parse_directive(p)?);
     }
     Ok(directives)
@@ -116,9 +142,11 @@ fn parse_config(p: &mut Parser) -> Result<Vec<Directive>, Error> {
 
 fn parse_directive(p: &mut Parser) -> Result<Directive, Error> {
     let line = p.line;
+    let start = p.column;
     let name = parse_word(p)?;
+    let span = p.span_from(start);
     p.skip_wsp();
-    let params = parse_directive_params(p)?;
+    let (params, param_spans) = parse_directive_params(p)?;
     p.skip_wsp();
     let directives = if p.at('{') {
         parse_block(p)?
@@ -131,16 +159,22 @@ fn parse_directive(p: &mut Parser) -> Result<Directive, Error> {
         params,
         children: directives,
         line,
+        span,
+        param_spans,
     })
 }
 
-fn parse_directive_params(p: &mut Parser) -> Result<Vec<String>, Error> {
+fn parse_directive_params(p: &mut Parser) -> Result<(Vec<String>, Vec<Span>), Error> {
     let mut params = Vec::new();
-    while !p.at('\n') && !p.at('{') && !p.at_end() {
+    let mut spans = Vec::new();
+    // Blocks can also be closed on the same line, as in `name { child }`.
+    while !p.at('\n') && !p.at('{') && !p.at('}') && !p.at_end() {
+        let start = p.column;
         params.push(parse_word(p)?);
+        spans.push(p.span_from(start));
         p.skip_wsp();
     }
-    Ok(params)
+    Ok((params, spans))
 }
 
 fn parse_block(p: &mut Parser) -> Result<Vec<Directive>, Error> {
@@ -165,21 +199,39 @@ fn parse_word(p: &mut Parser) -> Result<String, Error> {
 }
 
 fn parse_atom(p: &mut Parser<'_>) -> Result<String, Error> {
-    let word = parse_word_impl(p, true, |c| {
-        matches!(
-            c,
-            '\u{21}'
-            | '\u{23}'..='\u{26}'
-            | '\u{28}'..='\u{5B}'
-            | '\u{5D}'..='\u{7A}'
-            | '\u{7C}'
-            | '\u{7E}'
-            | '\u{80}'..='\u{10FFFF}',
-        )
-    })?;
+    let word = parse_word_impl(p, true, is_atom_char)?;
     Ok(word)
 }
 
+fn is_atom_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{21}'
+        | '\u{23}'..='\u{26}'
+        | '\u{28}'..='\u{5B}'
+        | '\u{5D}'..='\u{7A}'
+        | '\u{7C}'
+        | '\u{7E}'
+        | '\u{80}'..='\u{10FFFF}',
+    )
+}
+
+/// Quotes a word if it needs to be, so that parsing the result gives back the same word.
+pub fn quote(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(is_atom_char) {
+        return word.to_owned();
+    }
+    let mut quoted = String::from('"');
+    for c in word.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
 fn parse_dquote_word(p: &mut Parser<'_>) -> Result<String, Error> {
     p.expect('"')?;
     let word = parse_word_impl(p, true, |c| {
@@ -252,6 +304,15 @@ mod tests {
     use super::*;
     use expect_test::{expect, Expect};
 
+    #[test]
+    fn test_quote() {
+        for word in ["simple", "#ffffff", "two words", "", "a\"b\\c", "{}", "'"] {
+            let directives = parse(&format!("d {}", quote(word))).unwrap();
+            assert_eq!(directives[0].params, [word]);
+        }
+        assert_eq!(quote("simple"), "simple");
+    }
+
     #[test]
     fn test_parse() {
         fn check(s: &str, expected: Expect) {
@@ -278,12 +339,74 @@ mod tests {
                             params: [],
                             children: [],
                             line: 0,
+                            span: Span {
+                                line: 0,
+                                start: 0,
+                                end: 6,
+                            },
+                            param_spans: [],
+                        },
+                    ],
+                )
+            "#]],
+        );
+
+        check(
+            "one-line { child param }",
+            expect![[r#"
+                Ok(
+                    [
+                        Directive {
+                            name: "one-line",
+                            params: [],
+                            children: [
+                                Directive {
+                                    name: "child",
+                                    params: [
+                                        "param",
+                                    ],
+                                    children: [],
+                                    line: 0,
+                                    span: Span {
+                                        line: 0,
+                                        start: 11,
+                                        end: 16,
+                                    },
+                                    param_spans: [
+                                        Span {
+                                            line: 0,
+                                            start: 17,
+                                            end: 22,
+                                        },
+                                    ],
+                                },
+                            ],
+                            line: 0,
+                            span: Span {
+                                line: 0,
+                                start: 0,
+                                end: 8,
+                            },
+                            param_spans: [],
                         },
                     ],
                 )
             "#]],
         );
 
+        check(
+            "stray }",
+            expect![[r#"
+                Err(
+                    Error {
+                        expected: '\n',
+                        line: 0,
+                        column: 6,
+                    },
+                )
+            "#]],
+        );
+
         check(
             "unclosed {",
             expect![[r#"
@@ -310,7 +433,13 @@ mod tests {
                             name: "directive",
                             params: [],
                             children: [],
-                            line: 3,
+                            line: 2,
+                            span: Span {
+                                line: 2,
+                                start: 16,
+                                end: 25,
+                            },
+                            param_spans: [],
                         },
                     ],
                 )
@@ -330,6 +459,23 @@ mod tests {
                             ],
                             children: [],
                             line: 0,
+                            span: Span {
+                                line: 0,
+                                start: 0,
+                                end: 7,
+                            },
+                            param_spans: [
+                                Span {
+                                    line: 0,
+                                    start: 8,
+                                    end: 10,
+                                },
+                                Span {
+                                    line: 0,
+                                    start: 11,
+                                    end: 13,
+                                },
+                            ],
                         },
                     ],
                 )
@@ -374,6 +520,18 @@ mod tests {
                                             ],
                                             children: [],
                                             line: 2,
+                                            span: Span {
+                                                line: 2,
+                                                start: 20,
+                                                end: 29,
+                                            },
+                                            param_spans: [
+                                                Span {
+                                                    line: 2,
+                                                    start: 30,
+                                                    end: 37,
+                                                },
+                                            ],
                                         },
                                         Directive {
                                             name: "weight",
@@ -382,6 +540,18 @@ mod tests {
                                             ],
                                             children: [],
                                             line: 3,
+                                            span: Span {
+                                                line: 3,
+                                                start: 20,
+                                                end: 26,
+                                            },
+                                            param_spans: [
+                                                Span {
+                                                    line: 3,
+                                                    start: 27,
+                                                    end: 33,
+                                                },
+                                            ],
                                         },
                                         Directive {
                                             name: "lines-served",
@@ -391,9 +561,38 @@ mod tests {
                                             ],
                                             children: [],
                                             line: 5,
+                                            span: Span {
+                                                line: 5,
+                                                start: 20,
+                                                end: 32,
+                                            },
+                                            param_spans: [
+                                                Span {
+                                                    line: 5,
+                                                    start: 33,
+                                                    end: 42,
+                                                },
+                                                Span {
+                                                    line: 5,
+                                                    start: 43,
+                                                    end: 53,
+                                                },
+                                            ],
                                         },
                                     ],
                                     line: 1,
+                                    span: Span {
+                                        line: 1,
+                                        start: 16,
+                                        end: 21,
+                                    },
+                                    param_spans: [
+                                        Span {
+                                            line: 1,
+                                            start: 22,
+                                            end: 26,
+                                        },
+                                    ],
                                 },
                                 Directive {
                                     name: "model",
@@ -408,6 +607,18 @@ mod tests {
                                             ],
                                             children: [],
                                             line: 9,
+                                            span: Span {
+                                                line: 9,
+                                                start: 20,
+                                                end: 29,
+                                            },
+                                            param_spans: [
+                                                Span {
+                                                    line: 9,
+                                                    start: 30,
+                                                    end: 37,
+                                                },
+                                            ],
                                         },
                                         Directive {
                                             name: "weight",
@@ -416,6 +627,18 @@ mod tests {
                                             ],
                                             children: [],
                                             line: 10,
+                                            span: Span {
+                                                line: 10,
+                                                start: 20,
+                                                end: 26,
+                                            },
+                                            param_spans: [
+                                                Span {
+                                                    line: 10,
+                                                    start: 27,
+                                                    end: 31,
+                                                },
+                                            ],
                                         },
                                         Directive {
                                             name: "lines-served",
@@ -425,12 +648,53 @@ mod tests {
                                             ],
                                             children: [],
                                             line: 12,
+                                            span: Span {
+                                                line: 12,
+                                                start: 20,
+                                                end: 32,
+                                            },
+                                            param_spans: [
+                                                Span {
+                                                    line: 12,
+                                                    start: 33,
+                                                    end: 43,
+                                                },
+                                                Span {
+                                                    line: 12,
+                                                    start: 44,
+                                                    end: 53,
+                                                },
+                                            ],
                                         },
                                     ],
                                     line: 8,
+                                    span: Span {
+                                        line: 8,
+                                        start: 16,
+                                        end: 21,
+                                    },
+                                    param_spans: [
+                                        Span {
+                                            line: 8,
+                                            start: 22,
+                                            end: 26,
+                                        },
+                                    ],
                                 },
                             ],
                             line: 0,
+                            span: Span {
+                                line: 0,
+                                start: 0,
+                                end: 5,
+                            },
+                            param_spans: [
+                                Span {
+                                    line: 0,
+                                    start: 6,
+                                    end: 18,
+                                },
+                            ],
                         },
                     ],
                 )
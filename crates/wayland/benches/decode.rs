@@ -0,0 +1,166 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use wayland::{
+    rustix::io::Errno, Arg, ArgType, BufferLimits, Connection, Describe, Error, Fixed, Message,
+    MessageDesc,
+};
+
+/// Counts allocations, to check that decoding doesn't make any.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// How many messages are sent before they're all read back.
+const BATCH: usize = 256;
+
+static MOTION: MessageDesc = MessageDesc {
+    interface: "wl_pointer",
+    name: "motion",
+    args: &[ArgType::Uint, ArgType::Fixed, ArgType::Fixed],
+};
+
+static NAMES: MessageDesc = MessageDesc {
+    interface: "test",
+    name: "names",
+    args: &[ArgType::String, ArgType::Array],
+};
+
+struct Decoded(usize);
+
+impl Describe for Decoded {
+    fn describe(&self) -> &'static MessageDesc {
+        &MOTION
+    }
+}
+
+fn decode_motion(mut msg: Message<'_>) -> Result<Decoded, Error> {
+    let time = msg.read_uint()?;
+    let x = msg.read_fixed()?;
+    let y = msg.read_fixed()?;
+    Ok(Decoded(time as usize + x.0 as usize + y.0 as usize))
+}
+
+fn decode_names(mut msg: Message<'_>) -> Result<Decoded, Error> {
+    let name = msg.read_non_null_str()?;
+    let bytes = msg.read_bytes()?;
+    Ok(Decoded(name.len() + bytes.len()))
+}
+
+/// Sends `BATCH` messages written by `write` and reads them all into the server's buffer.
+fn send_batch(
+    client: &mut Connection,
+    server: &mut Connection,
+    write: &impl Fn(&mut Connection, usize),
+) {
+    for i in 0..BATCH {
+        write(client, i);
+    }
+    client.flush_blocking().unwrap();
+    loop {
+        match server.read_nonblocking() {
+            Ok(_) => {}
+            Err(Error::Io(Errno::WOULDBLOCK)) => break,
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+/// Measures decoding batches of messages, leaving out the time spent sending them.
+fn bench_decode<'a>(
+    client: &'a mut Connection,
+    server: &'a mut Connection,
+    write: impl Fn(&mut Connection, usize) + 'a,
+    decode: fn(Message<'_>) -> Result<Decoded, Error>,
+) -> impl FnMut(u64) -> Duration + 'a {
+    // The buffers grow to the size they need during the first few batches.
+    for _ in 0..4 {
+        send_batch(client, server, &write);
+        assert_eq!(decode_batch(server, decode), BATCH);
+    }
+    move |iters| {
+        let mut total = Duration::ZERO;
+        for _ in 0..iters {
+            send_batch(client, server, &write);
+            let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+            let start = Instant::now();
+            let decoded = decode_batch(server, decode);
+            total += start.elapsed();
+            assert_eq!(decoded, BATCH);
+            assert_eq!(
+                ALLOCATIONS.load(Ordering::Relaxed),
+                allocations,
+                "decoding allocated"
+            );
+        }
+        total
+    }
+}
+
+/// Decodes every message read so far, returning how many there were.
+fn decode_batch(
+    server: &mut Connection,
+    decode: fn(Message<'_>) -> Result<Decoded, Error>,
+) -> usize {
+    let mut decoded = 0;
+    while let Some(msg) = server.read_message(decode).unwrap() {
+        black_box(msg.0);
+        decoded += 1;
+    }
+    decoded
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(BATCH as u64));
+
+    let (mut client, mut server) = Connection::pair().unwrap();
+    let write_motion = |conn: &mut Connection, i: usize| {
+        let args = [
+            Arg::Uint(i as u32),
+            Arg::Fixed(Fixed::from(i as i32)),
+            Arg::Fixed(Fixed::from(0.5)),
+        ];
+        conn.write_message(3, 2, &MOTION, &args, []).unwrap();
+    };
+    let mut routine = bench_decode(&mut client, &mut server, write_motion, decode_motion);
+    group.bench_function("motion", |b| b.iter_custom(&mut routine));
+
+    // The buffer keeps its size between batches, and batches don't divide it, so messages keep
+    // wrapping around its end.
+    let (mut client, mut server) = Connection::pair().unwrap();
+    server.set_buffer_limits(BufferLimits {
+        shrink_when_idle: false,
+        ..BufferLimits::default()
+    });
+    let write_names = |conn: &mut Connection, i: usize| {
+        let name = &"xkb_v1 evdev+aliases(qwerty) pc+us+inet(evdev)"[..16 + i % 24];
+        let args = [Arg::String(Some(name)), Arg::Array(&[0; 36])];
+        conn.write_message(4, 0, &NAMES, &args, []).unwrap();
+    };
+    let mut routine = bench_decode(&mut client, &mut server, write_names, decode_names);
+    group.bench_function("strings", |b| b.iter_custom(&mut routine));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,75 @@
+use crate::{Connection, Describe, Error, Message};
+use async_io::Async;
+use rustix::{fd::OwnedFd, io::Errno};
+use std::{io, sync::Arc};
+
+/// A [`Connection`] driven by the async-io reactor instead of blocking reads and writes.
+#[derive(Debug)]
+pub struct AsyncConnection {
+    inner: Connection,
+    /// The connection's socket, registered with the reactor on its own so that nothing done
+    /// to the connection can close it while it's registered.
+    socket: Async<Arc<OwnedFd>>,
+}
+
+impl AsyncConnection {
+    pub fn new(conn: Connection) -> Result<AsyncConnection, Error> {
+        let socket = Async::new(conn.reader.socket.clone()).map_err(io_error)?;
+        Ok(AsyncConnection {
+            inner: conn,
+            socket,
+        })
+    }
+
+    pub fn get_ref(&self) -> &Connection {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut Connection {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> Connection {
+        self.inner
+    }
+
+    /// Decodes the next message, reading from the socket until one is complete, or returns
+    /// `None` once the compositor closes the connection.
+    pub async fn read_message<F, Msg>(&mut self, decoder: F) -> Result<Option<Msg>, Error>
+    where
+        for<'a> F: Fn(Message<'a>) -> Result<Msg, Error>,
+        Msg: Describe,
+    {
+        loop {
+            if let Some(msg) = self.get_mut().read_message(&decoder)? {
+                return Ok(Some(msg));
+            }
+            match self.get_mut().read_nonblocking() {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(Error::Io(Errno::WOULDBLOCK)) => {
+                    self.socket.readable().await.map_err(io_error)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes everything written to the connection so far to the socket.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        while self.inner.has_pending_writes() {
+            match self.get_mut().flush_nonblocking() {
+                Ok(_) => {}
+                Err(Error::Io(Errno::WOULDBLOCK)) => {
+                    self.socket.writable().await.map_err(io_error)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Io(Errno::from_io_error(&e).unwrap_or(Errno::IO))
+}
@@ -0,0 +1,132 @@
+use crate::{Connection, Describe, Error, Message};
+use calloop::{
+    generic::Generic, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+use rustix::{fd::OwnedFd, io::Errno};
+use std::{fmt, marker::PhantomData, sync::Arc};
+
+/// A [`Connection`] as a calloop event source, which decodes the messages it reads with a
+/// decoder and passes each to the callback along with the connection.
+///
+/// Everything written to the connection is flushed before the event loop sleeps. The source
+/// removes itself once the compositor closes the connection.
+pub struct ConnectionSource<F, Msg> {
+    conn: Connection,
+    /// The connection's socket, registered with the event loop on its own so the connection
+    /// can be passed to the callback mutably.
+    socket: Generic<Arc<OwnedFd>, Error>,
+    decoder: F,
+    _msg: PhantomData<fn() -> Msg>,
+}
+
+impl<F, Msg> fmt::Debug for ConnectionSource<F, Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionSource")
+            .field("conn", &self.conn)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, Msg> ConnectionSource<F, Msg>
+where
+    F: for<'a> FnMut(Message<'a>) -> Result<Msg, Error>,
+    Msg: Describe,
+{
+    pub fn new(conn: Connection, decoder: F) -> ConnectionSource<F, Msg> {
+        let socket =
+            Generic::new_with_error(conn.reader.socket.clone(), Interest::READ, Mode::Level);
+        ConnectionSource {
+            conn,
+            socket,
+            decoder,
+            _msg: PhantomData,
+        }
+    }
+
+    pub fn get_ref(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    pub fn into_inner(self) -> Connection {
+        self.conn
+    }
+}
+
+impl<F, Msg> EventSource for ConnectionSource<F, Msg>
+where
+    F: for<'a> FnMut(Message<'a>) -> Result<Msg, Error>,
+    Msg: Describe,
+{
+    type Event = Msg;
+    type Metadata = Connection;
+    type Ret = ();
+    type Error = Error;
+
+    const NEEDS_EXTRA_LIFECYCLE_EVENTS: bool = true;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> Result<PostAction, Error>
+    where
+        C: FnMut(Msg, &mut Connection),
+    {
+        let ConnectionSource {
+            conn,
+            socket,
+            decoder,
+            ..
+        } = self;
+        socket.process_events(readiness, token, |_, _| {
+            let open = match conn.read_nonblocking() {
+                Ok(open) => open,
+                Err(Error::Io(Errno::WOULDBLOCK)) => true,
+                Err(e) => return Err(e),
+            };
+            while let Some(msg) = conn.read_message(&mut *decoder)? {
+                callback(msg, conn);
+            }
+            conn.flush_blocking()?;
+            Ok(if open {
+                PostAction::Continue
+            } else {
+                PostAction::Remove
+            })
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.socket.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        self.socket.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.socket.unregister(poll)
+    }
+
+    fn before_sleep(&mut self) -> calloop::Result<Option<(Readiness, Token)>> {
+        // Other sources' callbacks can write messages too, which would otherwise wait until
+        // the compositor sends something.
+        self.conn
+            .flush_blocking()
+            .map_err(|e| calloop::Error::OtherError(e.into()))?;
+        Ok(None)
+    }
+}
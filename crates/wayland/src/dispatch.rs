@@ -0,0 +1,96 @@
+use crate::{Connection, Describe, Error, Message, MessageDesc};
+use std::{collections::HashMap, fmt};
+
+type Handler<S, E> = Box<dyn FnMut(&mut S, &mut Connection, E)>;
+
+/// Routes decoded events to handlers registered for the objects they're for.
+///
+/// Each handler gets the application's state and the connection, so it can send requests in
+/// response. Events for objects without a handler go to the fallback handler, if there is one.
+pub struct Dispatcher<S, E> {
+    handlers: HashMap<u32, Handler<S, E>>,
+    fallback: Option<Handler<S, E>>,
+}
+
+impl<S, E> Default for Dispatcher<S, E> {
+    fn default() -> Dispatcher<S, E> {
+        Dispatcher {
+            handlers: HashMap::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<S, E> fmt::Debug for Dispatcher<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("objects", &self.handlers.keys())
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl<S, E: fmt::Debug> Dispatcher<S, E> {
+    pub fn new() -> Dispatcher<S, E> {
+        Dispatcher::default()
+    }
+
+    /// Sends events for `object` to `handler`, replacing any handler it had.
+    pub fn register(
+        &mut self,
+        object: u32,
+        handler: impl FnMut(&mut S, &mut Connection, E) + 'static,
+    ) {
+        self.handlers.insert(object, Box::new(handler));
+    }
+
+    /// Stops sending events for `object` to its handler, like once it's destroyed.
+    pub fn unregister(&mut self, object: u32) {
+        self.handlers.remove(&object);
+    }
+
+    /// Sends events for objects without a handler to `handler`.
+    pub fn set_fallback(&mut self, handler: impl FnMut(&mut S, &mut Connection, E) + 'static) {
+        self.fallback = Some(Box::new(handler));
+    }
+
+    /// Sends an event for `object` to its handler.
+    pub fn dispatch(&mut self, state: &mut S, conn: &mut Connection, object: u32, event: E) {
+        match self.handlers.get_mut(&object).or(self.fallback.as_mut()) {
+            Some(handler) => handler(state, conn, event),
+            None => log::debug!("unhandled event {event:?} ignored"),
+        }
+    }
+
+    /// Decodes the messages read so far with `decoder` and dispatches them.
+    pub fn dispatch_pending<F>(
+        &mut self,
+        state: &mut S,
+        conn: &mut Connection,
+        decoder: F,
+    ) -> Result<(), Error>
+    where
+        for<'a> F: Fn(Message<'a>) -> Result<E, Error>,
+        E: Describe,
+    {
+        while let Some(Decoded { object, event }) = conn.read_message(|msg| {
+            let object = msg.object();
+            decoder(msg).map(|event| Decoded { object, event })
+        })? {
+            self.dispatch(state, conn, object, event);
+        }
+        Ok(())
+    }
+}
+
+/// An event along with the object it's for.
+struct Decoded<E> {
+    object: u32,
+    event: E,
+}
+
+impl<E: Describe> Describe for Decoded<E> {
+    fn describe(&self) -> &'static MessageDesc {
+        self.event.describe()
+    }
+}
@@ -1,57 +1,193 @@
 pub extern crate rustix;
 
+mod dispatch;
+mod object_map;
+mod poll;
+mod trace;
+
+pub use dispatch::Dispatcher;
+pub use object_map::ObjectMap;
+pub use poll::{PollToken, Poller, Ready};
+pub use trace::{ArgType, Describe, MessageDesc};
+
+use trace::TraceValue;
+
+#[cfg(feature = "async-io")]
+mod async_io;
+
+#[cfg(feature = "async-io")]
+pub use async_io::AsyncConnection;
+
+#[cfg(feature = "calloop")]
+mod calloop;
+
+#[cfg(feature = "calloop")]
+pub use calloop::ConnectionSource;
+
+#[cfg(feature = "stats")]
+mod stats;
+
+#[cfg(feature = "stats")]
+pub use stats::{ReadStats, WriteStats};
+
 use circbuf::CircBuf;
 use rustix::{
     cmsg_space,
     event::{PollFd, PollFlags},
-    fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
     io::{fcntl_getfd, fcntl_setfd, Errno, FdFlags},
     net::{
-        connect_unix, recvmsg, sendmsg, AddressFamily, RecvAncillaryBuffer, RecvAncillaryMessage,
-        RecvFlags, SendAncillaryBuffer, SendAncillaryMessage, SendFlags, SocketAddrUnix,
-        SocketType,
+        connect_unix, recvmsg, sendmsg, socketpair, AddressFamily, RecvAncillaryBuffer,
+        RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer, SendAncillaryMessage, SendFlags,
+        SocketAddrUnix, SocketFlags, SocketType,
     },
 };
 use std::{
+    borrow::Cow,
     collections::VecDeque,
-    fmt::Debug,
+    ffi::OsString,
+    fmt::{self, Debug, Display},
     io::{self, IoSlice, IoSliceMut, Read, Write},
+    ops,
     os::unix::prelude::OsStringExt,
+    path::Path,
+    string::FromUtf8Error,
+    sync::Arc,
 };
 
+/// An error reading, decoding or writing messages on a connection.
+#[derive(Debug)]
+pub enum Error {
+    /// A message had an opcode its object's interface doesn't have.
+    Protocol {
+        object: u32,
+        opcode: u16,
+    },
+    /// A message was for an object that doesn't exist.
+    UnknownObject {
+        object: u32,
+    },
+    /// A message's arguments didn't match its signature.
+    Decode {
+        object: u32,
+        opcode: u16,
+    },
+    /// A message's header had a size smaller than the header or that isn't a multiple of 4, so
+    /// there's no telling where the next message starts.
+    InvalidSize {
+        object: u32,
+        size: u16,
+    },
+    Io(Errno),
+    /// A string argument wasn't valid UTF-8.
+    Utf8(FromUtf8Error),
+    /// A message to be written didn't fit in the 64 KiB the wire format allows.
+    MessageTooLarge {
+        object: u32,
+        opcode: u16,
+    },
+    /// A buffer couldn't grow to fit a message, because it reached its maximum size or memory
+    /// ran out.
+    OutOfMemory,
+    /// The compositor closed the connection while messages were being sent to it.
+    ConnectionClosed,
+    /// More file descriptors arrived at once than [`BufferLimits::max_fds_per_read`] has room
+    /// for, and the rest were lost.
+    TooManyFds,
+    /// The compositor sent `wl_display.error`, after which it closes the connection. Generated
+    /// code puts the decoded error in here.
+    Fatal(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Protocol { object, opcode } => {
+                write!(f, "unknown opcode {opcode} for object {object}")
+            }
+            Error::UnknownObject { object } => write!(f, "message for unknown object {object}"),
+            Error::Decode { object, opcode } => {
+                write!(f, "malformed message {opcode} for object {object}")
+            }
+            Error::InvalidSize { object, size } => {
+                write!(f, "message for object {object} has invalid size {size}")
+            }
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::Utf8(e) => write!(f, "invalid string argument: {e}"),
+            Error::MessageTooLarge { object, opcode } => {
+                write!(f, "message {opcode} for object {object} is too large")
+            }
+            Error::OutOfMemory => write!(f, "no room in the buffer for the message"),
+            Error::ConnectionClosed => write!(f, "the connection was closed"),
+            Error::TooManyFds => write!(f, "too many file descriptors received at once"),
+            Error::Fatal(e) => write!(f, "fatal error from the compositor: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Utf8(e) => Some(e),
+            Error::Fatal(e) => Some(&**e),
+            Error::Protocol { .. }
+            | Error::UnknownObject { .. }
+            | Error::Decode { .. }
+            | Error::InvalidSize { .. }
+            | Error::MessageTooLarge { .. }
+            | Error::OutOfMemory
+            | Error::ConnectionClosed
+            | Error::TooManyFds => None,
+        }
+    }
+}
+
+impl From<Errno> for Error {
+    fn from(e: Errno) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Error {
+        Error::Utf8(e)
+    }
+}
+
+/// Connects to the socket of a Wayland compositor at `path`, regardless of the environment.
+pub fn connect_to_path(path: impl AsRef<Path>) -> Result<OwnedFd, Errno> {
+    let fd = rustix::net::socket(AddressFamily::UNIX, SocketType::STREAM, None)?;
+    let addr = SocketAddrUnix::new(path.as_ref())?;
+    connect_unix(&fd, &addr)?;
+    Ok(fd)
+}
+
 pub fn client_socket_from_env() -> Result<Option<OwnedFd>, Errno> {
     fn socket_fd_from_wayland_socket_env() -> Option<OwnedFd> {
         let socket = std::env::var_os("WAYLAND_SOCKET")?;
         std::env::remove_var("WAYLAND_SOCKET");
         let Some(socket) = socket.to_str() else {
-            eprintln!(
-                "warning: WAYLAND_SOCKET could not be parsed as a file descriptor so it was ignored"
-            );
+            log::warn!("WAYLAND_SOCKET could not be parsed as a file descriptor so it was ignored");
             return None;
         };
         let Ok(fd) = socket.parse::<RawFd>() else {
-            eprintln!(
-                "warning: WAYLAND_SOCKET could not be parsed as a file descriptor so it was ignored"
-            );
+            log::warn!("WAYLAND_SOCKET could not be parsed as a file descriptor so it was ignored");
             return None;
         };
         let fd = unsafe { OwnedFd::from_raw_fd(fd) };
         match fcntl_getfd(&fd) {
             Err(e) => {
-                eprintln!(
-                    "warning: fcntl(F_GETFD) on WAYLAND_SOCKET failed ({e}) so it was ignored"
-                );
+                log::warn!("fcntl(F_GETFD) on WAYLAND_SOCKET failed ({e}) so it was ignored");
                 return None;
             }
-            Ok(flags) => {
-                match fcntl_setfd(&fd, flags | FdFlags::CLOEXEC) {
-                    Err(e) => {
-                        eprintln!("warning: fcntl(F_SETFD) on WAYLAND_SOCKET failed ({e}) so it was ignored");
-                        return None;
-                    }
-                    Ok(()) => {}
+            Ok(flags) => match fcntl_setfd(&fd, flags | FdFlags::CLOEXEC) {
+                Err(e) => {
+                    log::warn!("fcntl(F_SETFD) on WAYLAND_SOCKET failed ({e}) so it was ignored");
+                    return None;
                 }
-            }
+                Ok(()) => {}
+            },
         };
         Some(fd)
     }
@@ -63,9 +199,7 @@ pub fn client_socket_from_env() -> Result<Option<OwnedFd>, Errno> {
             return Some(display);
         }
         let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") else {
-            eprintln!(
-                "warning: WAYLAND_DISPLAY was not an absolute path and XDG_RUNTIME_PATH is unset"
-            );
+            log::warn!("WAYLAND_DISPLAY was not an absolute path and XDG_RUNTIME_PATH is unset");
             return None;
         };
         let mut path = runtime_dir.into_vec();
@@ -74,28 +208,29 @@ pub fn client_socket_from_env() -> Result<Option<OwnedFd>, Errno> {
         Some(path)
     }
 
-    fn socket_fd_from_socket_path(path: Vec<u8>) -> Result<OwnedFd, Errno> {
-        let fd = rustix::net::socket(AddressFamily::UNIX, SocketType::STREAM, None)?;
-        let addr = SocketAddrUnix::new(path)?;
-        connect_unix(&fd, &addr)?;
-        Ok(fd)
-    }
-
     socket_fd_from_wayland_socket_env()
         .map(Ok)
-        .or_else(|| socket_path_from_wayland_display_env().map(socket_fd_from_socket_path))
+        .or_else(|| {
+            socket_path_from_wayland_display_env()
+                .map(|path| connect_to_path(OsString::from_vec(path)))
+        })
         .transpose()
 }
 
+/// `MSG_CTRUNC`, which rustix doesn't have a name for.
+const CONTROL_TRUNCATED: RecvFlags = RecvFlags::from_bits_retain(0x8);
+
 fn read_from_socket<'fds>(
     buf: &mut CircBuf,
     socket: BorrowedFd<'_>,
     fds: &mut impl Extend<OwnedFd>,
-) -> Result<bool, Errno> {
-    let mut cmsg_data = vec![0; cmsg_space!(ScmRights(32))];
-    let mut ctl = RecvAncillaryBuffer::new(&mut cmsg_data);
+    cmsg_data: &mut [u8],
+) -> Result<bool, Error> {
+    let mut ctl = RecvAncillaryBuffer::new(cmsg_data);
     let [first_half, second_half] = buf.get_avail();
-    let rustix::net::RecvMsgReturn { bytes: n, .. } = recvmsg(
+    let rustix::net::RecvMsgReturn {
+        bytes: n, flags, ..
+    } = recvmsg(
         &socket,
         &mut [IoSliceMut::new(first_half), IoSliceMut::new(second_half)],
         &mut ctl,
@@ -108,38 +243,196 @@ fn read_from_socket<'fds>(
         };
         fds.extend(fd_iter);
     }
+    // The bytes the lost file descriptors came with were still read, so there's no telling
+    // which messages are missing theirs.
+    if flags.contains(CONTROL_TRUNCATED) {
+        return Err(Error::TooManyFds);
+    }
     Ok(n > 0)
 }
 
+/// Returns a buffer with room for the control message that receives as many file descriptors as
+/// `limits` allow.
+fn cmsg_buffer(limits: &BufferLimits) -> Vec<u8> {
+    vec![0; cmsg_space!(ScmRights(limits.max_fds_per_read))]
+}
+
+/// Writes up to `len` bytes from `buf`, sending `fds` along with the first one.
 fn write_to_socket(
     buf: &mut CircBuf,
     socket: BorrowedFd<'_>,
     fds: &[BorrowedFd<'_>],
-) -> Result<bool, Errno> {
-    let mut cmsg_data = vec![0; cmsg_space!(ScmRights(fds.len()))];
+    len: usize,
+) -> Result<usize, Error> {
+    // Most messages have no file descriptors, and an empty buffer doesn't allocate.
+    let mut cmsg_data = match fds {
+        [] => Vec::new(),
+        _ => vec![0; cmsg_space!(ScmRights(fds.len()))],
+    };
     let mut ctl = SendAncillaryBuffer::new(&mut cmsg_data);
-    ctl.push(SendAncillaryMessage::ScmRights(fds));
+    if !fds.is_empty() {
+        ctl.push(SendAncillaryMessage::ScmRights(fds));
+    }
     let [first_half, second_half] = buf.get_bytes();
+    let first_half = &first_half[..first_half.len().min(len)];
+    let second_half = &second_half[..second_half.len().min(len - first_half.len())];
+    // Without `NOSIGNAL`, writing to a socket the compositor closed raises `SIGPIPE`, which
+    // kills the process.
     let n = sendmsg(
         &socket,
         &[IoSlice::new(first_half), IoSlice::new(second_half)],
         &mut ctl,
-        SendFlags::DONTWAIT,
-    )?;
+        SendFlags::DONTWAIT | SendFlags::NOSIGNAL,
+    )
+    .map_err(|e| match e {
+        Errno::PIPE => Error::ConnectionClosed,
+        e => Error::Io(e),
+    })?;
     buf.advance_read_raw(n);
-    Ok(n > 0)
+    Ok(n)
+}
+
+/// Limits on the memory a connection's buffers use.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferLimits {
+    /// The most bytes each buffer grows to, which is never less than the 64 KiB the largest
+    /// message needs. By default buffers grow as much as they need to.
+    pub max_size: usize,
+    /// Whether a buffer that grew goes back to its initial size once it's empty.
+    pub shrink_when_idle: bool,
+    /// How many file descriptors each read from the socket has room for at least. Reading more
+    /// than there's room for at once fails with [`Error::TooManyFds`].
+    pub max_fds_per_read: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> BufferLimits {
+        BufferLimits {
+            max_size: usize::MAX,
+            shrink_when_idle: true,
+            max_fds_per_read: 32,
+        }
+    }
+}
+
+/// When a [`WriteHalf`] flushes messages without being asked to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlushPolicy {
+    /// Once this many bytes are buffered, writing a message also writes as much as the socket
+    /// takes without blocking.
+    pub auto_flush_threshold: Option<usize>,
+    /// What happens when a message doesn't fit in the buffer at its largest size.
+    pub backpressure: Backpressure,
+}
+
+/// What writing a message does when the write buffer is full, which it only gets to be with a
+/// [`BufferLimits::max_size`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Fail with [`Error::OutOfMemory`].
+    #[default]
+    Fail,
+    /// Wait until the buffer has been flushed to the socket.
+    Block,
+}
+
+/// Grows `buf` to its next size, unless that would be more than `limits` allow.
+fn grow_buffer(buf: &mut CircBuf, limits: &BufferLimits) -> Result<(), Error> {
+    // A buffer holds one byte less than its size.
+    let size = (buf.cap() + 1) * 2;
+    if size > limits.max_size.max(1 << 16) {
+        return Err(Error::OutOfMemory);
+    }
+    buf.grow().map_err(|_| Error::OutOfMemory)
+}
+
+/// Frees the memory `buf` grew to if it's empty and `limits` allow it.
+fn shrink_buffer(buf: &mut CircBuf, limits: &BufferLimits) {
+    if limits.shrink_when_idle && buf.is_empty() && buf.cap() >= circbuf::DEFAULT_CAPACITY {
+        *buf = CircBuf::new();
+    }
+}
+
+/// Writes an argument in the wire format, where strings and arrays are prefixed with their length
+/// and padded to a multiple of 4 bytes.
+fn write_arg(out: &mut impl Write, arg: Arg<'_>) {
+    match arg {
+        Arg::Int(v) | Arg::Fixed(Fixed(v)) => out.write_all(&v.to_ne_bytes()).unwrap(),
+        Arg::Uint(v) => out.write_all(&v.to_ne_bytes()).unwrap(),
+        Arg::String(None) => out.write_all(&0u32.to_ne_bytes()).unwrap(),
+        Arg::String(Some(s)) => {
+            let s_len = u32::try_from(s.len() + 1).unwrap();
+            out.write_all(&s_len.to_ne_bytes()).unwrap();
+            out.write_all(s.as_bytes()).unwrap();
+            let padding_len = (s.len() + 4) / 4 * 4 - s.len();
+            let zeros = [0; 4];
+            out.write_all(&zeros[0..padding_len]).unwrap();
+        }
+        Arg::Array(s) => {
+            let s_len = u32::try_from(s.len()).unwrap();
+            out.write_all(&s_len.to_ne_bytes()).unwrap();
+            out.write_all(s).unwrap();
+            let padding_len = (s.len() + 3) / 4 * 4 - s.len();
+            let zeros = [0; 3];
+            out.write_all(&zeros[0..padding_len]).unwrap();
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Connection {
-    socket: OwnedFd,
-    read_buf: CircBuf,
-    write_buf: CircBuf,
-    read_fds: VecDeque<OwnedFd>,
-    write_fds: VecDeque<OwnedFd>,
+    reader: ReadHalf,
+    writer: WriteHalf,
+}
+
+/// The half of a [`Connection`] that reads messages, which can be used independently of the
+/// [`WriteHalf`] it was split from.
+#[derive(Debug)]
+pub struct ReadHalf {
+    socket: Arc<OwnedFd>,
+    buf: CircBuf,
+    fds: VecDeque<OwnedFd>,
+    /// The size of the last message read, which is still at the start of `buf`.
+    consumed: usize,
+    /// Where a message that wraps around the end of `buf` is copied to be decoded, so its
+    /// arguments can still be borrowed without allocating each time.
+    scratch: Vec<u8>,
+    /// Where file descriptors are received, sized for `buffer_limits.max_fds_per_read`.
+    cmsg: Vec<u8>,
+    buffer_limits: BufferLimits,
+    #[cfg(feature = "stats")]
+    stats: ReadStats,
+}
+
+/// The half of a [`Connection`] that writes messages, which can be used independently of the
+/// [`ReadHalf`] it was split from.
+#[derive(Debug)]
+pub struct WriteHalf {
+    socket: Arc<OwnedFd>,
+    buf: CircBuf,
+    /// File descriptors to send, with the offset of the first byte of their message.
+    fds: VecDeque<(u64, OwnedFd)>,
+    /// How many bytes have been sent, which is the offset of the first byte in `buf`.
+    sent: u64,
+    buffer_limits: BufferLimits,
+    flush_policy: FlushPolicy,
+    #[cfg(feature = "stats")]
+    stats: WriteStats,
 }
 
 impl AsFd for Connection {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.reader.as_fd()
+    }
+}
+
+impl AsFd for ReadHalf {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+impl AsFd for WriteHalf {
     fn as_fd(&self) -> BorrowedFd<'_> {
         self.socket.as_fd()
     }
@@ -147,35 +440,204 @@ impl AsFd for Connection {
 
 impl Connection {
     pub fn new(fd: OwnedFd) -> Connection {
+        let socket = Arc::new(fd);
         Connection {
-            socket: fd,
-            write_buf: CircBuf::new(),
-            read_buf: CircBuf::new(),
-            read_fds: VecDeque::new(),
-            write_fds: VecDeque::new(),
+            reader: ReadHalf {
+                socket: socket.clone(),
+                buf: CircBuf::new(),
+                fds: VecDeque::new(),
+                consumed: 0,
+                scratch: Vec::new(),
+                cmsg: cmsg_buffer(&BufferLimits::default()),
+                buffer_limits: BufferLimits::default(),
+                #[cfg(feature = "stats")]
+                stats: ReadStats::default(),
+            },
+            writer: WriteHalf {
+                socket,
+                buf: CircBuf::new(),
+                fds: VecDeque::new(),
+                sent: 0,
+                buffer_limits: BufferLimits::default(),
+                flush_policy: FlushPolicy::default(),
+                #[cfg(feature = "stats")]
+                stats: WriteStats::default(),
+            },
         }
     }
 
-    pub fn flush_nonblocking(&mut self) -> Result<bool, Errno> {
-        if self.write_buf.is_empty() {
-            return Ok(true);
+    /// Creates two connections to each other over a socket pair, so messages can be sent and
+    /// received without a compositor.
+    pub fn pair() -> Result<(Connection, Connection), Errno> {
+        let (a, b) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )?;
+        Ok((Connection::new(a), Connection::new(b)))
+    }
+
+    /// Splits the connection into halves that can be moved to different threads.
+    pub fn split(self) -> (ReadHalf, WriteHalf) {
+        (self.reader, self.writer)
+    }
+
+    /// Borrows the halves of the connection separately, so messages can be written while a
+    /// message read from it is still borrowed.
+    pub fn split_mut(&mut self) -> (&mut ReadHalf, &mut WriteHalf) {
+        (&mut self.reader, &mut self.writer)
+    }
+
+    /// Puts back together halves that [`split`](Connection::split) returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the halves weren't split from the same connection.
+    pub fn unsplit(reader: ReadHalf, writer: WriteHalf) -> Connection {
+        assert!(
+            Arc::ptr_eq(&reader.socket, &writer.socket),
+            "halves are from different connections"
+        );
+        Connection { reader, writer }
+    }
+
+    pub fn set_buffer_limits(&mut self, limits: BufferLimits) {
+        self.reader.set_buffer_limits(limits);
+        self.writer.set_buffer_limits(limits);
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.writer.set_flush_policy(policy);
+    }
+
+    pub fn flush_nonblocking(&mut self) -> Result<bool, Error> {
+        self.writer.flush_nonblocking()
+    }
+
+    pub fn flush_blocking(&mut self) -> Result<bool, Error> {
+        self.writer.flush_blocking()
+    }
+
+    pub fn read_blocking(&mut self) -> Result<bool, Error> {
+        self.reader.read_blocking()
+    }
+
+    pub fn read_nonblocking(&mut self) -> Result<bool, Error> {
+        self.reader.read_nonblocking()
+    }
+
+    pub fn write_message<'a>(
+        &mut self,
+        obj: u32,
+        op: u16,
+        desc: &MessageDesc,
+        args: &[Arg<'a>],
+        fds: impl IntoIterator<Item = OwnedFd>,
+    ) -> Result<(), Error> {
+        self.writer.write_message(obj, op, desc, args, fds)
+    }
+
+    /// Decodes the next message if it has been read completely.
+    ///
+    /// See [`ReadHalf::read_message`].
+    pub fn read_message<'c, F, Msg>(&'c mut self, decoder: F) -> Result<Option<Msg>, Error>
+    where
+        F: FnOnce(Message<'c>) -> Result<Msg, Error>,
+        Msg: Describe,
+    {
+        self.reader.read_message(decoder)
+    }
+
+    /// Whether there are messages written that haven't been flushed to the socket yet.
+    pub fn has_pending_writes(&self) -> bool {
+        self.writer.has_pending_writes()
+    }
+
+    /// How many bytes have been read from the socket that haven't been decoded yet.
+    pub fn read_buffered_len(&self) -> usize {
+        self.reader.buffered_len()
+    }
+
+    /// How many bytes have been written that haven't been flushed to the socket yet.
+    pub fn write_buffered_len(&self) -> usize {
+        self.writer.buffered_len()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn read_stats(&self) -> ReadStats {
+        self.reader.stats()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn write_stats(&self) -> WriteStats {
+        self.writer.stats()
+    }
+}
+
+impl WriteHalf {
+    pub fn set_buffer_limits(&mut self, limits: BufferLimits) {
+        self.buffer_limits = limits;
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Whether there are messages written that haven't been flushed to the socket yet.
+    pub fn has_pending_writes(&self) -> bool {
+        !self.buf.is_empty()
+    }
+
+    /// How many bytes have been written that haven't been flushed to the socket yet.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> WriteStats {
+        self.stats
+    }
+
+    pub fn flush_nonblocking(&mut self) -> Result<bool, Error> {
+        while !self.buf.is_empty() {
+            // File descriptors are received along with the first byte they're sent with, so each
+            // message that has some starts a new write, which ends before the next such message.
+            let count = self
+                .fds
+                .iter()
+                .take_while(|(offset, _)| *offset == self.sent)
+                .count();
+            let len = self.fds.get(count).map_or(self.buf.len(), |(offset, _)| {
+                usize::try_from(offset - self.sent).unwrap()
+            });
+            let fds = self
+                .fds
+                .range(..count)
+                .map(|(_, fd)| fd.as_fd())
+                .collect::<Vec<_>>();
+            let n = write_to_socket(&mut self.buf, self.socket.as_fd(), &fds, len)?;
+            // However little of the message was written, its file descriptors went with it.
+            self.fds.drain(..count);
+            self.sent += u64::try_from(n).unwrap();
+            #[cfg(feature = "stats")]
+            {
+                self.stats.bytes += u64::try_from(n).unwrap();
+                self.stats.fds += u64::try_from(count).unwrap();
+            }
+            if n == 0 {
+                return Ok(false);
+            }
         }
-        let fds = self
-            .write_fds
-            .make_contiguous()
-            .iter()
-            .map(|fd| fd.as_fd())
-            .collect::<Vec<_>>();
-        let r = write_to_socket(&mut self.write_buf, self.socket.as_fd(), &fds)?;
-        self.write_fds.clear();
-        Ok(r)
+        shrink_buffer(&mut self.buf, &self.buffer_limits);
+        Ok(true)
     }
 
-    pub fn flush_blocking(&mut self) -> Result<bool, Errno> {
+    pub fn flush_blocking(&mut self) -> Result<bool, Error> {
         loop {
             match self.flush_nonblocking() {
                 Ok(v) => break Ok(v),
-                Err(Errno::WOULDBLOCK) => {
+                Err(Error::Io(Errno::WOULDBLOCK)) => {
                     rustix::event::poll(
                         &mut [PollFd::from_borrowed_fd(
                             self.socket.as_fd(),
@@ -189,35 +651,14 @@ impl Connection {
         }
     }
 
-    pub fn read_blocking(&mut self) -> Result<bool, Errno> {
-        loop {
-            match self.read_nonblocking() {
-                Ok(v) => break Ok(v),
-                Err(Errno::WOULDBLOCK) => {
-                    rustix::event::poll(
-                        &mut [PollFd::from_borrowed_fd(
-                            self.socket.as_fd(),
-                            PollFlags::IN | PollFlags::HUP | PollFlags::ERR,
-                        )],
-                        -1,
-                    )?;
-                }
-                Err(e) => break Err(e),
-            }
-        }
-    }
-
-    pub fn read_nonblocking(&mut self) -> Result<bool, Errno> {
-        read_from_socket(&mut self.read_buf, self.socket.as_fd(), &mut self.read_fds)
-    }
-
     pub fn write_message<'a>(
         &mut self,
         obj: u32,
         op: u16,
+        desc: &MessageDesc,
         args: &[Arg<'a>],
         fds: impl IntoIterator<Item = OwnedFd>,
-    ) {
+    ) -> Result<(), Error> {
         let bytes_len = args
             .iter()
             .map(|it| match it {
@@ -227,75 +668,228 @@ impl Connection {
                 Arg::Array(s) => 4 + (s.len() + 3) / 4 * 4,
             })
             .sum::<usize>();
-        self.write_fds.extend(fds);
-        assert!(bytes_len < usize::from(u16::MAX - 8));
-        let size = u16::from(8 + bytes_len as u16);
-        while self.write_buf.avail() < size.into() {
-            self.write_buf.grow().unwrap();
-        }
-        self.write_buf.write_all(&obj.to_ne_bytes()).unwrap();
-        self.write_buf
+        if bytes_len >= usize::from(u16::MAX - 8) {
+            return Err(Error::MessageTooLarge {
+                object: obj,
+                opcode: op,
+            });
+        }
+        let size = 8 + bytes_len as u16;
+        while self.buf.avail() < size.into() {
+            match grow_buffer(&mut self.buf, &self.buffer_limits) {
+                Ok(()) => {}
+                Err(_) if self.flush_policy.backpressure == Backpressure::Block => {
+                    if !self.flush_blocking()? {
+                        return Err(Error::ConnectionClosed);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let fds = fds.into_iter().collect::<Vec<_>>();
+        if trace::enabled() {
+            let mut args = args.iter();
+            let mut fds = fds.iter();
+            let values = desc
+                .args
+                .iter()
+                .filter_map(|ty| match ty {
+                    ArgType::Fd => fds.next().map(|fd| TraceValue::Fd(fd.as_raw_fd())),
+                    _ => args.next().map(|&arg| match arg {
+                        Arg::Int(v) | Arg::Fixed(Fixed(v)) => TraceValue::Int(v),
+                        Arg::Uint(v) => TraceValue::Uint(v),
+                        Arg::String(s) => TraceValue::String(s.map(str::to_owned)),
+                        Arg::Array(s) => TraceValue::Array(s.len()),
+                    }),
+                })
+                .collect();
+            trace::trace(true, obj, desc, values);
+        }
+        let offset = self.sent + u64::try_from(self.buf.len()).unwrap();
+        self.fds.extend(fds.into_iter().map(|fd| (offset, fd)));
+        self.buf.write_all(&obj.to_ne_bytes()).unwrap();
+        self.buf
             .write_all(&((u32::from(size) << 16) | u32::from(op)).to_ne_bytes())
             .unwrap();
         for &arg in args {
-            match arg {
-                Arg::Int(v) | Arg::Fixed(Fixed(v)) => {
-                    self.write_buf.write_all(&v.to_ne_bytes()).unwrap()
-                }
-                Arg::Uint(v) => self.write_buf.write_all(&v.to_ne_bytes()).unwrap(),
-                Arg::String(None) => self.write_buf.write_all(&0u32.to_ne_bytes()).unwrap(),
-                Arg::String(Some(s)) => {
-                    let s_len = u32::try_from(s.len() + 1).unwrap();
-                    self.write_buf.write_all(&s_len.to_ne_bytes()).unwrap();
-                    self.write_buf.write_all(&s.as_bytes()).unwrap();
-                    let padding_len = (s.len() + 4) / 4 * 4 - s.len();
-                    let zeros = [0; 4];
-                    self.write_buf.write_all(&zeros[0..padding_len]).unwrap();
-                }
-                Arg::Array(s) => {
-                    let s_len = u32::try_from(s.len() + 1).unwrap();
-                    self.write_buf.write_all(&s_len.to_ne_bytes()).unwrap();
-                    self.write_buf.write_all(s).unwrap();
-                    let padding_len = (s.len() + 3) / 4 * 4 - s.len();
-                    let zeros = [0; 3];
-                    self.write_buf.write_all(&zeros[0..padding_len]).unwrap();
+            write_arg(&mut self.buf, arg);
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.messages += 1;
+            self.stats.max_buffered = self.stats.max_buffered.max(self.buf.len());
+        }
+        if self
+            .flush_policy
+            .auto_flush_threshold
+            .is_some_and(|threshold| self.buf.len() >= threshold)
+        {
+            match self.flush_nonblocking() {
+                Ok(_) | Err(Error::Io(Errno::WOULDBLOCK)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReadHalf {
+    pub fn set_buffer_limits(&mut self, limits: BufferLimits) {
+        if limits.max_fds_per_read != self.buffer_limits.max_fds_per_read {
+            self.cmsg = cmsg_buffer(&limits);
+        }
+        self.buffer_limits = limits;
+    }
+
+    /// How many bytes have been read from the socket that haven't been decoded yet.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len() - self.consumed
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> ReadStats {
+        self.stats
+    }
+
+    pub fn read_blocking(&mut self) -> Result<bool, Error> {
+        loop {
+            match self.read_nonblocking() {
+                Ok(v) => break Ok(v),
+                Err(Error::Io(Errno::WOULDBLOCK)) => {
+                    rustix::event::poll(
+                        &mut [PollFd::from_borrowed_fd(
+                            self.socket.as_fd(),
+                            PollFlags::IN | PollFlags::HUP | PollFlags::ERR,
+                        )],
+                        -1,
+                    )?;
                 }
+                Err(e) => break Err(e),
             }
         }
     }
 
-    pub fn read_message<F, Msg>(&mut self, decoder: F) -> Option<Msg>
+    pub fn read_nonblocking(&mut self) -> Result<bool, Error> {
+        self.consume_read();
+        // Shrinking here rather than as the last message is decoded keeps decoding free of
+        // allocations.
+        shrink_buffer(&mut self.buf, &self.buffer_limits);
+        // As long as complete messages are read before reading more, the buffer only fills up
+        // when a message doesn't fit in it.
+        if self.buf.avail() == 0 {
+            grow_buffer(&mut self.buf, &self.buffer_limits)?;
+        }
+        #[cfg(feature = "stats")]
+        let (len, fd_count) = (self.buf.len(), self.fds.len());
+        let open = read_from_socket(
+            &mut self.buf,
+            self.socket.as_fd(),
+            &mut self.fds,
+            &mut self.cmsg,
+        )?;
+        #[cfg(feature = "stats")]
+        {
+            self.stats.bytes += u64::try_from(self.buf.len() - len).unwrap();
+            self.stats.fds += u64::try_from(self.fds.len() - fd_count).unwrap();
+            self.stats.max_buffered = self.stats.max_buffered.max(self.buf.len());
+        }
+        Ok(open)
+    }
+
+    /// Decodes the next message if it has been read completely.
+    ///
+    /// The decoded message can borrow strings and arrays from the read buffer until this half
+    /// is used again. A message that fails to decode is still consumed, so
+    /// reading can continue after an error.
+    pub fn read_message<'c, F, Msg>(&'c mut self, decoder: F) -> Result<Option<Msg>, Error>
     where
-        for<'a> F: Fn(Message<'a>) -> Option<Msg>,
+        F: FnOnce(Message<'c>) -> Result<Msg, Error>,
+        Msg: Describe,
     {
-        if self.read_buf.len() < 2 {
-            return None;
+        self.consume_read();
+        if self.buf.len() < 8 {
+            return Ok(None);
         }
         let mut buf = [0u8; 8];
-        self.read_buf.reader_peek().read_exact(&mut buf).unwrap();
+        self.buf.reader_peek().read_exact(&mut buf).unwrap();
         let obj = u32::from_ne_bytes(buf[0..4].try_into().unwrap());
         let size_op = u32::from_ne_bytes(buf[4..8].try_into().unwrap());
         let size = (size_op >> 16) as u16;
         let op = size_op as u16;
-        if self.read_buf.len() < usize::try_from(size).unwrap() {
-            return None;
+        // The header is all that tells where the next message starts, so there's no recovering
+        // from a bad one.
+        if size < 8 || !size.is_multiple_of(4) {
+            #[cfg(feature = "stats")]
+            {
+                self.stats.decode_failures += 1;
+            }
+            return Err(Error::InvalidSize { object: obj, size });
+        }
+        if self.buf.len() < usize::try_from(size).unwrap() {
+            return Ok(None);
         }
-        let buf_bytes = self.read_buf.get_bytes_upto_size(size.into());
-        let mut data = SplitSlice(buf_bytes);
+        let trace_fds =
+            trace::enabled().then(|| self.fds.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>());
+        let ReadHalf {
+            buf,
+            fds,
+            consumed,
+            scratch,
+            #[cfg(feature = "stats")]
+            stats,
+            ..
+        } = self;
+        *consumed = usize::from(size);
+        let buf: &'c CircBuf = buf;
+        let mut data = match buf.get_bytes_upto_size(size.into()) {
+            [bytes, []] => SplitSlice([bytes, &[]]),
+            [first, second] => {
+                scratch.clear();
+                // Growing a bit more than needed means it rarely has to grow again.
+                scratch.reserve(usize::from(size).next_power_of_two().max(256));
+                scratch.extend_from_slice(first);
+                scratch.extend_from_slice(second);
+                SplitSlice([scratch, &[]])
+            }
+        };
         data.advance(8);
+        let trace_data = trace_fds.as_ref().map(|_| data.clone());
         let msg = decoder(Message {
             object: obj,
             opcode: op,
             data,
-            fds: &mut self.read_fds,
-        })
-        .expect("decoder failed!");
-        self.read_buf.advance_read_raw(usize::from(size));
-        Some(msg)
+            fds,
+        });
+        #[cfg(feature = "stats")]
+        match msg {
+            Ok(_) => stats.messages += 1,
+            Err(_) => stats.decode_failures += 1,
+        }
+        if let (Ok(msg), Some(data), Some(fds)) = (&msg, trace_data, trace_fds) {
+            let desc = msg.describe();
+            trace::trace(false, obj, desc, trace::read_values(desc, data, &fds));
+        }
+        msg.map(Some)
+    }
+
+    /// Drops the last message read from the buffer, which is only kept until now in case what
+    /// it decoded to borrows from it.
+    fn consume_read(&mut self) {
+        self.buf
+            .advance_read_raw(std::mem::take(&mut self.consumed));
+        // File descriptors arrive along with their message's bytes, so any left once every
+        // message has been read were for messages that failed to decode.
+        if self.buf.is_empty() && !self.fds.is_empty() {
+            log::warn!(
+                "closing {} file descriptors no message took",
+                self.fds.len()
+            );
+            self.fds.clear();
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct SplitSlice<'a>([&'a [u8]; 2]);
 
 impl SplitSlice<'_> {
@@ -312,6 +906,25 @@ impl SplitSlice<'_> {
     }
 }
 
+impl<'a> SplitSlice<'a> {
+    /// Takes the next `n` bytes, which are only copied if they're split across both halves.
+    fn take_bytes(&mut self, n: usize) -> Option<Cow<'a, [u8]>> {
+        if n > self.len() {
+            return None;
+        }
+        let [s0, s1] = self.0;
+        let bytes = if n <= s0.len() {
+            Cow::Borrowed(&s0[..n])
+        } else if s0.is_empty() {
+            Cow::Borrowed(&s1[..n])
+        } else {
+            Cow::Owned([s0, &s1[..n - s0.len()]].concat())
+        };
+        self.advance(n);
+        Some(bytes)
+    }
+}
+
 impl Read for SplitSlice<'_> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let n = buf.len().min(self.len());
@@ -337,42 +950,160 @@ pub struct Message<'a> {
 }
 
 impl<'a> Message<'a> {
-    pub fn read_int(&mut self) -> Option<i32> {
+    /// Creates a message for `object` from the bytes of its arguments, after the header, taking
+    /// file descriptors from the front of `fds`.
+    ///
+    /// [`Connection`] creates messages itself, so this is for decoding messages from elsewhere.
+    pub fn new(
+        object: u32,
+        opcode: u16,
+        data: &'a [u8],
+        fds: &'a mut VecDeque<OwnedFd>,
+    ) -> Message<'a> {
+        Message {
+            object,
+            opcode,
+            data: SplitSlice([data, &[]]),
+            fds,
+        }
+    }
+
+    pub fn read_int(&mut self) -> Result<i32, Error> {
         self.read_uint().map(|i| i as i32)
     }
 
-    pub fn read_uint(&mut self) -> Option<u32> {
+    pub fn read_uint(&mut self) -> Result<u32, Error> {
         let mut buf = [0u8; 4];
-        self.data.read_exact(&mut buf).ok()?;
-        Some(u32::from_ne_bytes(buf))
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_ne_bytes(buf))
     }
 
-    pub fn read_fixed(&mut self) -> Option<Fixed> {
+    pub fn read_fixed(&mut self) -> Result<Fixed, Error> {
         self.read_int().map(Fixed)
     }
 
-    pub fn read_string(&mut self) -> Option<Option<String>> {
-        let length = self.read_uint()?;
+    pub fn read_string(&mut self) -> Result<Option<String>, Error> {
+        let length = self.read_length()?;
         if length == 0 {
-            Some(None)
+            Ok(None)
         } else {
-            let mut buf = vec![0u8; usize::try_from((length + 3) / 4 * 4).unwrap()];
-            self.data.read_exact(&mut buf).ok()?;
-            buf.truncate(usize::try_from(length - 1).unwrap());
-            Some(Some(String::from_utf8(buf).unwrap()))
+            let mut buf = vec![0u8; length.div_ceil(4) * 4];
+            self.read_exact(&mut buf)?;
+            buf.truncate(length - 1);
+            Ok(Some(String::from_utf8(buf)?))
+        }
+    }
+
+    /// Reads a string argument, borrowing it from the connection's buffer unless it wraps
+    /// around the end of it.
+    pub fn read_str(&mut self) -> Result<Option<Cow<'a, str>>, Error> {
+        let Some(bytes) = self.read_str_bytes()? else {
+            return Ok(None);
+        };
+        let text = match bytes {
+            Cow::Borrowed(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) => Cow::Borrowed(text),
+                Err(_) => return Err(String::from_utf8(bytes.to_vec()).unwrap_err().into()),
+            },
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8(bytes)?),
+        };
+        Ok(Some(text))
+    }
+
+    /// Reads a string argument like [`Message::read_str`], but replaces anything that isn't
+    /// UTF-8 with U+FFFD instead of failing.
+    pub fn read_str_lossy(&mut self) -> Result<Option<Cow<'a, str>>, Error> {
+        Ok(self.read_str_bytes()?.map(|bytes| match bytes {
+            Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+            Cow::Owned(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => Cow::Owned(text),
+                Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            },
+        }))
+    }
+
+    /// Reads a string argument as the bytes before its terminating nul, without checking that
+    /// they're UTF-8.
+    pub fn read_str_bytes(&mut self) -> Result<Option<Cow<'a, [u8]>>, Error> {
+        let length = self.read_length()?;
+        if length == 0 {
+            return Ok(None);
         }
+        Ok(Some(match self.read_padded(length)? {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[..length - 1]),
+            Cow::Owned(mut bytes) => {
+                bytes.truncate(length - 1);
+                Cow::Owned(bytes)
+            }
+        }))
     }
 
-    pub fn read_array(&mut self) -> Option<Vec<u8>> {
-        let length = self.read_uint()?;
-        let mut buf = vec![0u8; usize::try_from(length / 4 * 4).unwrap()];
-        self.data.read_exact(&mut buf).ok()?;
-        buf.truncate(usize::try_from(length).unwrap());
-        Some(buf)
+    /// Reads a string argument that isn't allowed to be null, like [`Message::read_str`].
+    pub fn read_non_null_str(&mut self) -> Result<Cow<'a, str>, Error> {
+        self.read_str()?.ok_or_else(|| self.decode_error())
     }
 
-    pub fn read_fd(&mut self) -> Option<OwnedFd> {
-        self.fds.pop_back()
+    pub fn read_array(&mut self) -> Result<Vec<u8>, Error> {
+        let length = self.read_length()?;
+        let mut buf = vec![0u8; length.div_ceil(4) * 4];
+        self.read_exact(&mut buf)?;
+        buf.truncate(length);
+        Ok(buf)
+    }
+
+    /// Reads an array argument, borrowing it from the connection's buffer unless it wraps
+    /// around the end of it.
+    pub fn read_bytes(&mut self) -> Result<Cow<'a, [u8]>, Error> {
+        let length = self.read_length()?;
+        self.read_padded(length)
+    }
+
+    pub fn read_fd(&mut self) -> Result<OwnedFd, Error> {
+        self.fds.pop_front().ok_or_else(|| self.decode_error())
+    }
+
+    /// Returns the error for an opcode this message's object doesn't have.
+    pub fn protocol_error(&self) -> Error {
+        Error::Protocol {
+            object: self.object,
+            opcode: self.opcode,
+        }
+    }
+
+    fn decode_error(&self) -> Error {
+        Error::Decode {
+            object: self.object,
+            opcode: self.opcode,
+        }
+    }
+
+    /// Reads the length of a string or array, which has to fit in the rest of the message so
+    /// a bad one can't make it allocate much.
+    fn read_length(&mut self) -> Result<usize, Error> {
+        let length = usize::try_from(self.read_uint()?).unwrap();
+        if length > self.data.len() {
+            return Err(self.decode_error());
+        }
+        Ok(length)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.data.read_exact(buf).map_err(|_| self.decode_error())
+    }
+
+    /// Reads `length` bytes and the padding after them.
+    fn read_padded(&mut self, length: usize) -> Result<Cow<'a, [u8]>, Error> {
+        let bytes = self
+            .data
+            .take_bytes(length.div_ceil(4) * 4)
+            .ok_or_else(|| self.decode_error())?;
+        Ok(match bytes {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[..length]),
+            Cow::Owned(mut bytes) => {
+                bytes.truncate(length);
+                Cow::Owned(bytes)
+            }
+        })
     }
 
     pub fn object(&self) -> u32 {
@@ -382,6 +1113,159 @@ impl<'a> Message<'a> {
     pub fn opcode(&self) -> u16 {
         self.opcode
     }
+
+    /// The number of bytes of arguments not read yet.
+    pub fn remaining_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The number of file descriptors received that haven't been read yet, which can include
+    /// ones for messages after this one.
+    pub fn remaining_fds(&self) -> usize {
+        self.fds.len()
+    }
+
+    /// Returns the bytes of arguments not read yet, without reading them.
+    pub fn remaining_bytes(&self) -> Cow<'a, [u8]> {
+        let mut data = self.data.clone();
+        data.take_bytes(data.len()).unwrap()
+    }
+
+    /// Reads the next argument as a value of type `ty`.
+    pub fn read_arg(&mut self, ty: ArgType) -> Result<ArgValue<'a>, Error> {
+        Ok(match ty {
+            ArgType::Int => ArgValue::Int(self.read_int()?),
+            ArgType::Uint => ArgValue::Uint(self.read_uint()?),
+            ArgType::Fixed => ArgValue::Fixed(self.read_fixed()?),
+            ArgType::String => ArgValue::String(self.read_str()?),
+            ArgType::Object(_) => ArgValue::Object(self.read_uint()?),
+            ArgType::NewId(_) => ArgValue::NewId(self.read_uint()?),
+            ArgType::Array => ArgValue::Array(self.read_bytes()?),
+            ArgType::Fd => ArgValue::Fd(self.read_fd()?),
+        })
+    }
+
+    /// Reads the rest of the arguments, which have the types in `types`.
+    pub fn read_args(&mut self, types: &[ArgType]) -> Result<Vec<ArgValue<'a>>, Error> {
+        types.iter().map(|&ty| self.read_arg(ty)).collect()
+    }
+
+    /// Skips the next argument, which has type `ty`. A file descriptor is closed.
+    pub fn skip_arg(&mut self, ty: ArgType) -> Result<(), Error> {
+        match ty {
+            ArgType::Int
+            | ArgType::Uint
+            | ArgType::Fixed
+            | ArgType::Object(_)
+            | ArgType::NewId(_) => {
+                self.read_uint()?;
+            }
+            ArgType::String | ArgType::Array => {
+                let length = self.read_length()?;
+                self.read_padded(length)?;
+            }
+            ArgType::Fd => {
+                self.read_fd()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An argument read without generated code, by [`Message::read_arg`].
+#[derive(Debug)]
+pub enum ArgValue<'a> {
+    Int(i32),
+    Uint(u32),
+    Fixed(Fixed),
+    String(Option<Cow<'a, str>>),
+    Object(u32),
+    NewId(u32),
+    Array(Cow<'a, [u8]>),
+    Fd(OwnedFd),
+}
+
+/// Something messages are written to and read from, which generated code marshals messages to.
+///
+/// [`Connection`] is the one that talks to a compositor. Others, such as an in-memory transport
+/// in tests, can decode messages with [`Message::new`].
+pub trait Wire {
+    /// Encodes a message with arguments `args`, which should match `desc`, and the file
+    /// descriptors `fds`.
+    fn write_message(
+        &mut self,
+        obj: u32,
+        op: u16,
+        desc: &MessageDesc,
+        args: &[Arg<'_>],
+        fds: impl IntoIterator<Item = OwnedFd>,
+    ) -> Result<(), Error>;
+
+    /// Decodes the next message with `decoder` if there is one.
+    fn read_message<'c, F, Msg>(&'c mut self, decoder: F) -> Result<Option<Msg>, Error>
+    where
+        F: FnOnce(Message<'c>) -> Result<Msg, Error>,
+        Msg: Describe;
+}
+
+impl Wire for Connection {
+    fn write_message(
+        &mut self,
+        obj: u32,
+        op: u16,
+        desc: &MessageDesc,
+        args: &[Arg<'_>],
+        fds: impl IntoIterator<Item = OwnedFd>,
+    ) -> Result<(), Error> {
+        Connection::write_message(self, obj, op, desc, args, fds)
+    }
+
+    fn read_message<'c, F, Msg>(&'c mut self, decoder: F) -> Result<Option<Msg>, Error>
+    where
+        F: FnOnce(Message<'c>) -> Result<Msg, Error>,
+        Msg: Describe,
+    {
+        Connection::read_message(self, decoder)
+    }
+}
+
+/// A connection that knows enough of the core protocol to wait for the compositor to process
+/// everything sent so far.
+pub trait Roundtrip {
+    type Event;
+
+    fn connection(&mut self) -> &mut Connection;
+
+    /// Sends `wl_display.sync` with a new `wl_callback`, returning the callback's id.
+    fn send_sync(&mut self) -> Result<u32, Error>;
+
+    /// Decodes the next event if it has been read completely.
+    fn read_event(&mut self) -> Result<Option<Self::Event>, Error>;
+
+    /// Returns whether `event` is the `done` event of the `wl_callback` with id `callback`.
+    fn is_sync_done(&self, event: &Self::Event, callback: u32) -> bool;
+
+    /// Sends `wl_display.sync` and passes every event to `handler` until the compositor answers
+    /// it.
+    fn roundtrip(&mut self, mut handler: impl FnMut(&mut Self, Self::Event)) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        let callback = self.send_sync()?;
+        let mut done = false;
+        while !done {
+            self.connection().flush_blocking()?;
+            self.connection().read_blocking()?;
+            while let Some(event) = self.read_event()? {
+                if self.is_sync_done(&event, callback) {
+                    done = true;
+                } else {
+                    handler(self, event);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 pub trait Object<I>: Debug + Copy {
@@ -404,29 +1288,767 @@ pub enum Arg<'a> {
     String(Option<&'a str>),
 }
 
-#[derive(Clone, Copy)]
+/// A signed 24.8 fixed-point number, the wire format's `fixed` type.
+///
+/// Arithmetic and comparisons are done on the fixed-point value, so they're exact where
+/// converting to a float first might not be. Like conversions, arithmetic saturates at
+/// [`Fixed::MIN`] and [`Fixed::MAX`] instead of overflowing; only dividing by zero panics.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Fixed(pub i32);
 
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(256);
+    /// The smallest difference between two values.
+    pub const EPSILON: Fixed = Fixed(1);
+    pub const MIN: Fixed = Fixed(i32::MIN);
+    pub const MAX: Fixed = Fixed(i32::MAX);
+
+    /// Rounds towards negative infinity.
+    pub const fn floor(self) -> Fixed {
+        Fixed(self.0 & !0xff)
+    }
+
+    /// Rounds towards positive infinity.
+    pub const fn ceil(self) -> Fixed {
+        Fixed(self.0.saturating_add(0xff) & !0xff)
+    }
+
+    /// Rounds to the nearest integer, and halfway values towards positive infinity.
+    pub const fn round(self) -> Fixed {
+        Fixed(self.0.saturating_add(0x80) & !0xff)
+    }
+
+    /// Returns the integer part, rounding towards zero.
+    pub const fn trunc_to_i32(self) -> i32 {
+        self.0 / 256
+    }
+
+    pub const fn abs(self) -> Fixed {
+        Fixed(self.0.saturating_abs())
+    }
+}
+
 impl Debug for Fixed {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("Fixed").field(&f32::from(*self)).finish()
+        f.debug_tuple("Fixed").field(&f64::from(*self)).finish()
+    }
+}
+
+impl Display for Fixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&f64::from(*self), f)
     }
 }
 
 impl From<Fixed> for f32 {
     fn from(Fixed(v): Fixed) -> f32 {
-        v as f32 / 128.0
+        v as f32 / 256.0
+    }
+}
+
+impl From<Fixed> for f64 {
+    fn from(Fixed(v): Fixed) -> f64 {
+        f64::from(v) / 256.0
     }
 }
 
 impl From<f32> for Fixed {
     fn from(value: f32) -> Fixed {
-        Fixed((value * 128.0) as i32)
+        Fixed::from(f64::from(value))
+    }
+}
+
+impl From<f64> for Fixed {
+    /// Rounds to the nearest value, saturating at the bounds.
+    fn from(value: f64) -> Fixed {
+        Fixed((value * 256.0).round() as i32)
     }
 }
 
 impl From<i32> for Fixed {
+    /// Saturates at the bounds, since only integers up to 2^23 fit.
     fn from(value: i32) -> Fixed {
-        Fixed(value.checked_mul(128).unwrap())
+        Fixed(value.saturating_mul(256))
+    }
+}
+
+/// Converts a product or quotient back to 24.8, saturating at the bounds.
+fn saturate(value: i64) -> Fixed {
+    Fixed(value.clamp(i32::MIN.into(), i32::MAX.into()) as i32)
+}
+
+impl ops::Add for Fixed {
+    type Output = Fixed;
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl ops::Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl ops::Mul for Fixed {
+    type Output = Fixed;
+    /// Multiplies, rounding towards negative infinity.
+    fn mul(self, rhs: Fixed) -> Fixed {
+        let product = (i64::from(self.0) * i64::from(rhs.0)) >> 8;
+        saturate(product)
+    }
+}
+
+impl ops::Div for Fixed {
+    type Output = Fixed;
+    /// Divides, rounding towards zero.
+    fn div(self, rhs: Fixed) -> Fixed {
+        let quotient = (i64::from(self.0) << 8) / i64::from(rhs.0);
+        saturate(quotient)
+    }
+}
+
+impl ops::Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed(self.0.saturating_neg())
+    }
+}
+
+impl ops::AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Fixed) {
+        *self = *self + rhs;
+    }
+}
+
+impl ops::SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Fixed) {
+        *self = *self - rhs;
+    }
+}
+
+impl ops::MulAssign for Fixed {
+    fn mul_assign(&mut self, rhs: Fixed) {
+        *self = *self * rhs;
+    }
+}
+
+impl ops::DivAssign for Fixed {
+    fn div_assign(&mut self, rhs: Fixed) {
+        *self = *self / rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DESC: MessageDesc = MessageDesc {
+        interface: "test",
+        name: "message",
+        args: &[
+            ArgType::Int,
+            ArgType::Uint,
+            ArgType::Fixed,
+            ArgType::String,
+            ArgType::String,
+            ArgType::Array,
+            ArgType::Fd,
+        ],
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct TestMessage {
+        int: i32,
+        uint: u32,
+        fixed: i32,
+        string: Option<String>,
+        null_string: Option<String>,
+        array: Vec<u8>,
+    }
+
+    struct Received(TestMessage, Vec<OwnedFd>);
+
+    impl Describe for Received {
+        fn describe(&self) -> &'static MessageDesc {
+            &DESC
+        }
+    }
+
+    struct Dumped(String);
+
+    impl Describe for Dumped {
+        fn describe(&self) -> &'static MessageDesc {
+            &DESC
+        }
+    }
+
+    fn expected() -> TestMessage {
+        TestMessage {
+            int: -7,
+            uint: 42,
+            fixed: 640,
+            string: Some("hello".to_owned()),
+            null_string: None,
+            array: vec![1, 2, 3, 4, 5],
+        }
+    }
+
+    fn send(writer: &mut WriteHalf, fds: impl IntoIterator<Item = OwnedFd>) {
+        write(writer, fds);
+        assert!(writer.flush_blocking().unwrap());
+    }
+
+    fn write(writer: &mut WriteHalf, fds: impl IntoIterator<Item = OwnedFd>) {
+        writer
+            .write_message(
+                3,
+                1,
+                &DESC,
+                &[
+                    Arg::Int(-7),
+                    Arg::Uint(42),
+                    Arg::Fixed(Fixed(640)),
+                    Arg::String(Some("hello")),
+                    Arg::String(None),
+                    Arg::Array(&[1, 2, 3, 4, 5]),
+                ],
+                fds,
+            )
+            .unwrap();
+    }
+
+    /// Receives a message sent by `send`, which had `fd_count` file descriptors.
+    fn receive(reader: &mut ReadHalf, fd_count: usize) -> Received {
+        loop {
+            let received = reader
+                .read_message(|mut msg| {
+                    assert_eq!((msg.object(), msg.opcode()), (3, 1));
+                    let message = TestMessage {
+                        int: msg.read_int()?,
+                        uint: msg.read_uint()?,
+                        fixed: msg.read_fixed()?.0,
+                        string: msg.read_string()?,
+                        null_string: msg.read_string()?,
+                        array: msg.read_array()?,
+                    };
+                    let fds = (0..fd_count)
+                        .map(|_| msg.read_fd())
+                        .collect::<Result<_, _>>()?;
+                    Ok(Received(message, fds))
+                })
+                .unwrap();
+            if let Some(received) = received {
+                return received;
+            }
+            assert!(reader.read_blocking().unwrap());
+        }
+    }
+
+    #[test]
+    fn fixed_arithmetic() {
+        let a = Fixed::from(2.5);
+        let b = Fixed::from(-1.25);
+        assert_eq!(a.0, 640);
+        assert_eq!(f64::from(a + b), 1.25);
+        assert_eq!(f64::from(a - b), 3.75);
+        assert_eq!(f64::from(a * b), -3.125);
+        assert_eq!(f64::from(a / b), -2.0);
+        assert_eq!(f64::from(-a), -2.5);
+        assert!(b < a);
+        assert_eq!(Fixed::from(3) * Fixed::EPSILON, Fixed(3));
+        assert_eq!(f64::from(b.floor()), -2.0);
+        assert_eq!(f64::from(b.ceil()), -1.0);
+        assert_eq!(f64::from(b.round()), -1.0);
+        assert_eq!(f64::from(a.round()), 3.0);
+        assert_eq!(b.trunc_to_i32(), -1);
+
+        assert_eq!(Fixed::from(1 << 23), Fixed::MAX);
+        assert_eq!(Fixed::from(i32::MIN), Fixed::MIN);
+        assert_eq!(Fixed::from(1e9), Fixed::MAX);
+        assert_eq!(Fixed::MAX + Fixed::ONE, Fixed::MAX);
+        assert_eq!(Fixed::MIN - Fixed::ONE, Fixed::MIN);
+        assert_eq!(-Fixed::MIN, Fixed::MAX);
+        assert_eq!(Fixed::MIN.abs(), Fixed::MAX);
+        assert_eq!(Fixed::MAX * Fixed::from(2), Fixed::MAX);
+        assert_eq!(Fixed::MIN / Fixed::from(0.5), Fixed::MIN);
+    }
+
+    #[test]
+    fn read_args_without_generated_code() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        send(client.split_mut().1, []);
+        server.read_blocking().unwrap();
+        let Dumped(args) = server
+            .read_message(|mut msg| {
+                assert_eq!(msg.remaining_len(), 40);
+                msg.skip_arg(ArgType::Int)?;
+                msg.skip_arg(ArgType::Uint)?;
+                assert_eq!(msg.remaining_bytes().len(), 32);
+                let args = msg.read_args(&DESC.args[2..6])?;
+                assert_eq!(msg.remaining_len(), 0);
+                Ok(Dumped(format!("{args:?}")))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            args,
+            r#"[Fixed(Fixed(2.5)), String(Some("hello")), String(None), Array([1, 2, 3, 4, 5])]"#
+        );
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        send(client.split_mut().1, []);
+        let Received(message, fds) = receive(server.split_mut().0, 0);
+        assert_eq!(message, expected());
+        assert!(fds.is_empty());
+        assert!(!server.has_pending_writes());
+    }
+
+    #[test]
+    fn fd_round_trip() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        let (a, b) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        send(client.split_mut().1, [a]);
+        let Received(message, fds) = receive(server.split_mut().0, 1);
+        assert_eq!(message, expected());
+        let [a] = <[OwnedFd; 1]>::try_from(fds).unwrap();
+        rustix::io::write(&a, b"ping").unwrap();
+        let mut buf = [0; 4];
+        rustix::io::read(&b, &mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn fds_arrive_with_their_messages() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        let pairs = (0..2)
+            .map(|_| {
+                socketpair(
+                    AddressFamily::UNIX,
+                    SocketType::STREAM,
+                    SocketFlags::CLOEXEC,
+                    None,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let (mut sent, mut kept): (Vec<_>, Vec<_>) = pairs.into_iter().unzip();
+        let (_, writer) = client.split_mut();
+        write(writer, []);
+        write(writer, [sent.remove(0)]);
+        write(writer, [sent.remove(0)]);
+        assert!(writer.flush_blocking().unwrap());
+        let (reader, _) = server.split_mut();
+        assert!(receive(reader, 0).1.is_empty());
+        for (i, kept) in kept.iter_mut().enumerate() {
+            let Received(_, fds) = receive(reader, 1);
+            let [fd] = <[OwnedFd; 1]>::try_from(fds).unwrap();
+            rustix::io::write(&fd, &[i as u8]).unwrap();
+            let mut buf = [0];
+            rustix::io::read(&*kept, &mut buf).unwrap();
+            assert_eq!(buf, [i as u8]);
+        }
+    }
+
+    #[test]
+    fn non_utf8_strings() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        // A string is sent as an array with a nul at the end.
+        let string = Arg::Array(b"caf\xe9\0");
+        client
+            .write_message(3, 1, &DESC, &[string, string, string], [])
+            .unwrap();
+        client.flush_blocking().unwrap();
+        server.read_blocking().unwrap();
+        let Dumped(strings) = server
+            .read_message(|mut msg| {
+                assert!(matches!(msg.read_str(), Err(Error::Utf8(_))));
+                let lossy = msg.read_str_lossy()?.unwrap();
+                let bytes = msg.read_str_bytes()?.unwrap();
+                Ok(Dumped(format!("{lossy} {bytes:?}")))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(strings, "caf\u{fffd} [99, 97, 102, 233]");
+    }
+
+    #[test]
+    fn auto_flush() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        client.set_flush_policy(FlushPolicy {
+            auto_flush_threshold: Some(64),
+            ..FlushPolicy::default()
+        });
+        write(&mut client.writer, []);
+        let len = client.write_buffered_len();
+        assert!(len < 64);
+        write(&mut client.writer, []);
+        assert_eq!(client.write_buffered_len(), 0);
+        server.read_blocking().unwrap();
+        assert_eq!(server.read_buffered_len(), len * 2);
+        receive(&mut server.reader, 0);
+        assert_eq!(server.read_buffered_len(), len);
+    }
+
+    #[test]
+    fn backpressure() {
+        let (mut client, _server) = Connection::pair().unwrap();
+        let write_large = |client: &mut Connection| {
+            let args = [Arg::Array(&[0; 1024]); 6];
+            client.write_message(3, 1, &DESC, &args, [])
+        };
+        // Without a limit, the buffer grows as long as the messages keep coming.
+        for _ in 0..256 {
+            write_large(&mut client).unwrap();
+        }
+        assert!(client.write_buffered_len() > 1 << 20);
+
+        let (mut client, _server) = Connection::pair().unwrap();
+        client.set_buffer_limits(BufferLimits {
+            max_size: 0,
+            ..BufferLimits::default()
+        });
+        let err = loop {
+            if let Err(e) = write_large(&mut client) {
+                break e;
+            }
+        };
+        assert!(matches!(err, Error::OutOfMemory));
+        client.set_flush_policy(FlushPolicy {
+            backpressure: Backpressure::Block,
+            ..FlushPolicy::default()
+        });
+        // The full buffer is flushed to make room, and only the new message is left.
+        write_large(&mut client).unwrap();
+        assert_eq!(client.write_buffered_len(), 8 + 6 * (4 + 1024));
+    }
+
+    #[test]
+    fn ids_reused_after_delete_id() {
+        let mut objects = ObjectMap::new();
+        assert_eq!(objects.insert("wl_display", 0), 1);
+        assert_eq!(objects.insert("wl_callback", 1), 2);
+        assert_eq!(objects.insert("wl_callback", 2), 3);
+        // An object the client destroyed keeps its id until the compositor deletes it.
+        assert_eq!(objects.insert("wl_callback", 3), 4);
+        assert_eq!(objects.interface(2), Some("wl_callback"));
+        assert_eq!(objects.delete_id(2), Some(1));
+        assert_eq!(objects.get(2), None);
+        assert_eq!(objects.delete_id(2), None);
+        assert_eq!(objects.delete_id(0), None);
+        assert_eq!(objects.delete_id(0xff00_0000), None);
+        assert_eq!(objects.insert("wl_callback", 4), 2);
+        assert_eq!(objects.insert("wl_callback", 5), 5);
+    }
+
+    #[test]
+    fn write_after_close() {
+        let (mut client, server) = Connection::pair().unwrap();
+        drop(server);
+        write(&mut client.writer, []);
+        assert!(matches!(
+            client.flush_blocking(),
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        let (fd, _) = socketpair(
+            AddressFamily::UNIX,
+            SocketType::STREAM,
+            SocketFlags::CLOEXEC,
+            None,
+        )
+        .unwrap();
+        write(&mut client.writer, []);
+        let len = client.write_buffered_len();
+        send(&mut client.writer, [fd]);
+        receive(&mut server.reader, 0);
+        assert!(server
+            .read_message(|_| Err::<Dumped, _>(Error::Decode {
+                object: 3,
+                opcode: 1
+            }))
+            .is_err());
+        let len = u64::try_from(len).unwrap();
+        assert_eq!(
+            client.write_stats(),
+            WriteStats {
+                messages: 2,
+                bytes: len * 2,
+                fds: 1,
+                max_buffered: usize::try_from(len).unwrap() * 2,
+            }
+        );
+        assert_eq!(
+            server.read_stats(),
+            ReadStats {
+                messages: 1,
+                bytes: len * 2,
+                fds: 1,
+                max_buffered: usize::try_from(len).unwrap() * 2,
+                decode_failures: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn poll_connections() {
+        let (mut client, server) = Connection::pair().unwrap();
+        let (other_client, other_server) = Connection::pair().unwrap();
+        let mut poller = Poller::new();
+        let token = poller.add(&server);
+        let other_token = poller.add(&other_server);
+        let ready = poller.poll(Some(std::time::Instant::now())).unwrap();
+        assert!(!ready.is_readable(token) && !ready.is_readable(other_token));
+        send(&mut client.writer, []);
+        drop(other_client);
+        let mut poller = Poller::new();
+        let token = poller.add(&server);
+        let other_token = poller.add(&other_server);
+        let ready = poller.poll(None).unwrap();
+        assert!(ready.is_readable(token) && ready.is_readable(other_token));
+    }
+
+    #[test]
+    fn too_many_fds() {
+        let (mut client, mut server) = Connection::pair().unwrap();
+        server.set_buffer_limits(BufferLimits {
+            max_fds_per_read: 1,
+            ..BufferLimits::default()
+        });
+        // The control message is padded, so it has room for a few more than asked for.
+        let fds = (0..8).map(|_| {
+            let (fd, _) = socketpair(
+                AddressFamily::UNIX,
+                SocketType::STREAM,
+                SocketFlags::CLOEXEC,
+                None,
+            )
+            .unwrap();
+            fd
+        });
+        send(&mut client.writer, fds.collect::<Vec<_>>());
+        assert!(matches!(server.read_blocking(), Err(Error::TooManyFds)));
+    }
+
+    /// Keeps messages in memory instead of sending them.
+    #[derive(Default)]
+    struct MemoryWire {
+        messages: VecDeque<(u32, u16, Vec<u8>)>,
+        fds: VecDeque<OwnedFd>,
+        /// The arguments of the last message read, which it borrows from.
+        current: Vec<u8>,
+    }
+
+    impl Wire for MemoryWire {
+        fn write_message(
+            &mut self,
+            obj: u32,
+            op: u16,
+            _desc: &MessageDesc,
+            args: &[Arg<'_>],
+            fds: impl IntoIterator<Item = OwnedFd>,
+        ) -> Result<(), Error> {
+            let mut data = Vec::new();
+            for &arg in args {
+                write_arg(&mut data, arg);
+            }
+            self.messages.push_back((obj, op, data));
+            self.fds.extend(fds);
+            Ok(())
+        }
+
+        fn read_message<'c, F, Msg>(&'c mut self, decoder: F) -> Result<Option<Msg>, Error>
+        where
+            F: FnOnce(Message<'c>) -> Result<Msg, Error>,
+            Msg: Describe,
+        {
+            let Some((obj, op, data)) = self.messages.pop_front() else {
+                return Ok(None);
+            };
+            self.current = data;
+            decoder(Message::new(obj, op, &self.current, &mut self.fds)).map(Some)
+        }
+    }
+
+    /// Writes a message and decodes it again, through any wire.
+    fn exchange(wire: &mut impl Wire) -> String {
+        let args = [Arg::Uint(7), Arg::Fixed(Fixed::from(1.5))];
+        wire.write_message(3, 1, &DESC, &args, []).unwrap();
+        let Dumped(values) = wire
+            .read_message(|mut msg| {
+                assert_eq!((msg.object(), msg.opcode()), (3, 1));
+                Ok(Dumped(format!(
+                    "{} {}",
+                    msg.read_uint()?,
+                    msg.read_fixed()?
+                )))
+            })
+            .unwrap()
+            .unwrap();
+        values
+    }
+
+    #[test]
+    fn memory_wire() {
+        let mut wire = MemoryWire::default();
+        assert_eq!(exchange(&mut wire), "7 1.5");
+        assert!(wire
+            .read_message(|_| Ok(Dumped(String::new())))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn memory_wire_strings() {
+        static GLOBAL: MessageDesc = MessageDesc {
+            interface: "wl_registry",
+            name: "global",
+            args: &[ArgType::Uint, ArgType::String, ArgType::Uint],
+        };
+        let mut wire = MemoryWire::default();
+        let args = [Arg::Uint(5), Arg::String(Some("wl_seat")), Arg::Uint(7)];
+        wire.write_message(2, 0, &GLOBAL, &args, []).unwrap();
+        let args = [Arg::String(None), Arg::Array(&[1, 2, 3, 4, 5])];
+        wire.write_message(2, 1, &DESC, &args, []).unwrap();
+
+        let Dumped(global) = wire
+            .read_message(|mut msg| {
+                Ok(Dumped(format!(
+                    "{} {:?} {}",
+                    msg.read_uint()?,
+                    msg.read_str()?,
+                    msg.read_uint()?
+                )))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(global, "5 Some(\"wl_seat\") 7");
+        let Dumped(rest) = wire
+            .read_message(|mut msg| {
+                Ok(Dumped(format!(
+                    "{:?} {:?}",
+                    msg.read_str()?,
+                    msg.read_array()?
+                )))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(rest, "None [1, 2, 3, 4, 5]");
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for (size, body) in [(4, &[][..]), (10, &[0, 0][..]), (12, &[0xff; 4][..])] {
+            let (client, mut server) = Connection::pair().unwrap();
+            let mut bytes = 3u32.to_ne_bytes().to_vec();
+            bytes.extend_from_slice(&((size << 16) | 1u32).to_ne_bytes());
+            bytes.extend_from_slice(body);
+            rustix::io::write(&client, &bytes).unwrap();
+            server.read_nonblocking().unwrap();
+            let decoded = server.read_message(|mut msg| {
+                // The length of the string is far more than the message holds.
+                msg.read_str()?;
+                Ok(Dumped(String::new()))
+            });
+            match size {
+                12 => assert!(matches!(decoded, Err(Error::Decode { .. }))),
+                _ => assert!(matches!(decoded, Err(Error::InvalidSize { .. }))),
+            }
+        }
+    }
+
+    /// Formats every log message and throws it away, so tests go through tracing.
+    struct DiscardLogger;
+
+    impl log::Log for DiscardLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let _ = record.args().to_string();
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn malformed_messages() {
+        log::set_logger(&DiscardLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        assert!(trace::enabled());
+        // A fixed seed keeps any failure reproducible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..1000 {
+            let (client, mut server) = Connection::pair().unwrap();
+            let mut bytes = Vec::new();
+            for _ in 0..next() % 4 + 1 {
+                let words = next() % 8;
+                // Mostly sizes that match the arguments, so the arguments get decoded.
+                let size = match next() % 4 {
+                    0 => next() as u16,
+                    _ => 8 + words as u16 * 4,
+                };
+                bytes.extend_from_slice(&(next() as u32 % 8).to_ne_bytes());
+                bytes.extend_from_slice(
+                    &((u32::from(size) << 16) | (next() as u32 % 4)).to_ne_bytes(),
+                );
+                for _ in 0..words {
+                    // Small values are likely to be valid lengths.
+                    let word = match next() % 2 {
+                        0 => next() as u32 % 32,
+                        _ => next() as u32,
+                    };
+                    bytes.extend_from_slice(&word.to_ne_bytes());
+                }
+            }
+            rustix::io::write(&client, &bytes).unwrap();
+            server.read_nonblocking().unwrap();
+            loop {
+                let decoded = server.read_message(|mut msg| {
+                    msg.read_args(DESC.args)?;
+                    Ok(Dumped(String::new()))
+                });
+                match decoded {
+                    Ok(Some(_)) | Err(Error::Decode { .. } | Error::Utf8(_)) => {}
+                    Ok(None) | Err(Error::InvalidSize { .. }) => break,
+                    Err(e) => panic!("{e}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_halves_on_different_threads() {
+        let (client, mut server) = Connection::pair().unwrap();
+        let (mut reader, mut writer) = client.split();
+        let receiver = std::thread::spawn(move || receive(&mut reader, 0).0);
+        send(&mut writer, []);
+        let Received(message, _) = receive(server.split_mut().0, 0);
+        send(server.split_mut().1, []);
+        assert_eq!(message, expected());
+        assert_eq!(receiver.join().unwrap(), expected());
     }
 }
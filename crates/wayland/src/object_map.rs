@@ -0,0 +1,85 @@
+use crate::Object;
+
+#[derive(Debug)]
+struct Entry<I, T> {
+    interface: I,
+    data: T,
+}
+
+/// The objects a client created, with their interfaces and some data for each, by id.
+///
+/// An id is only reused once the compositor sends `wl_display.delete_id` for it, so events
+/// for an object the client already destroyed can still be decoded.
+#[derive(Debug)]
+pub struct ObjectMap<I, T> {
+    /// The object with id `n` is at index `n - 1`.
+    objects: Vec<Option<Entry<I, T>>>,
+    free: Vec<u32>,
+}
+
+impl<I, T> Default for ObjectMap<I, T> {
+    fn default() -> ObjectMap<I, T> {
+        ObjectMap {
+            objects: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<I: Copy, T> ObjectMap<I, T> {
+    pub fn new() -> ObjectMap<I, T> {
+        ObjectMap::default()
+    }
+
+    /// Allocates an id for a new object. The first one is 1, the id of `wl_display`.
+    pub fn insert(&mut self, interface: I, data: T) -> u32 {
+        let entry = Some(Entry { interface, data });
+        match self.free.pop() {
+            Some(id) => {
+                self.objects[usize::try_from(id).unwrap() - 1] = entry;
+                id
+            }
+            None => {
+                self.objects.push(entry);
+                u32::try_from(self.objects.len()).unwrap()
+            }
+        }
+    }
+
+    /// Allocates an id for a new object of type `O`, to pass to the request that creates it.
+    pub fn create<O: Object<I>>(&mut self, data: T) -> O {
+        O::new(self.insert(O::INTERFACE, data))
+    }
+
+    pub fn interface(&self, id: u32) -> Option<I> {
+        self.entry(id).map(|entry| entry.interface)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&T> {
+        self.entry(id).map(|entry| &entry.data)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut T> {
+        let entry = self.objects.get_mut(index(id)?)?.as_mut()?;
+        Some(&mut entry.data)
+    }
+
+    /// Handles `wl_display.delete_id`, freeing `id` for reuse and returning its object's data.
+    ///
+    /// Returns `None` for an id that isn't in use, so a repeated `delete_id` can't free an id
+    /// twice.
+    pub fn delete_id(&mut self, id: u32) -> Option<T> {
+        let entry = self.objects.get_mut(index(id)?)?.take()?;
+        self.free.push(id);
+        Some(entry.data)
+    }
+
+    fn entry(&self, id: u32) -> Option<&Entry<I, T>> {
+        self.objects.get(index(id)?)?.as_ref()
+    }
+}
+
+/// Returns the index of the object with `id`, or `None` for the null object.
+fn index(id: u32) -> Option<usize> {
+    usize::try_from(id.checked_sub(1)?).ok()
+}
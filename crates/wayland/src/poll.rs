@@ -0,0 +1,74 @@
+use rustix::{
+    event::{PollFd, PollFlags},
+    fd::AsFd,
+    io::Errno,
+};
+use std::time::Instant;
+
+/// Waits for any of several connections or other file descriptors to become readable, such as
+/// a Wayland connection and an EI connection in the same loop.
+#[derive(Debug, Default)]
+pub struct Poller<'a> {
+    fds: Vec<PollFd<'a>>,
+}
+
+/// Identifies a file descriptor added to a [`Poller`], to check whether it's ready once it has
+/// been polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollToken(usize);
+
+/// Which of a [`Poller`]'s file descriptors are ready.
+#[derive(Debug)]
+pub struct Ready {
+    revents: Vec<PollFlags>,
+}
+
+impl<'a> Poller<'a> {
+    pub fn new() -> Poller<'a> {
+        Poller::default()
+    }
+
+    /// Adds a file descriptor to wait for reading from.
+    pub fn add(&mut self, fd: &'a impl AsFd) -> PollToken {
+        self.fds.push(PollFd::new(fd, PollFlags::IN));
+        PollToken(self.fds.len() - 1)
+    }
+
+    /// Waits until one of the file descriptors is ready or `deadline` passes, or without a
+    /// deadline until one is ready.
+    ///
+    /// A signal interrupting the wait returns early with nothing ready, so timers can be
+    /// checked again.
+    pub fn poll(mut self, deadline: Option<Instant>) -> Result<Ready, Errno> {
+        let timeout = match deadline {
+            // Rounding up means a timer isn't polled for again with no time left until it's due.
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .try_into()
+                .unwrap_or(i32::MAX),
+            None => -1,
+        };
+        match rustix::event::poll(&mut self.fds, timeout) {
+            Ok(_) => {}
+            Err(Errno::INTR) => {
+                return Ok(Ready {
+                    revents: vec![PollFlags::empty(); self.fds.len()],
+                })
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(Ready {
+            revents: self.fds.iter().map(PollFd::revents).collect(),
+        })
+    }
+}
+
+impl Ready {
+    /// Whether the file descriptor can be read from without blocking, which includes reading
+    /// that the other end hung up or that there was an error.
+    pub fn is_readable(&self, token: PollToken) -> bool {
+        self.revents[token.0].intersects(PollFlags::IN | PollFlags::HUP | PollFlags::ERR)
+    }
+}
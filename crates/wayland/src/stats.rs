@@ -0,0 +1,25 @@
+/// Counts of what a [`ReadHalf`](crate::ReadHalf) has received since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadStats {
+    /// Messages decoded successfully.
+    pub messages: u64,
+    pub bytes: u64,
+    pub fds: u64,
+    /// The most bytes that were in the read buffer at once.
+    pub max_buffered: usize,
+    /// Messages with a bad header or that their decoder returned an error for.
+    pub decode_failures: u64,
+}
+
+/// Counts of what a [`WriteHalf`](crate::WriteHalf) has sent since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Messages written to the buffer, which may not all have been flushed yet.
+    pub messages: u64,
+    /// Bytes flushed to the socket.
+    pub bytes: u64,
+    /// File descriptors flushed to the socket.
+    pub fds: u64,
+    /// The most bytes that were in the write buffer at once.
+    pub max_buffered: usize,
+}
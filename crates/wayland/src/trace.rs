@@ -0,0 +1,145 @@
+use crate::SplitSlice;
+use std::{
+    fmt::Write as _,
+    io::Read,
+    os::fd::RawFd,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The names and argument types of a message, which tracing prints it with.
+#[derive(Debug)]
+pub struct MessageDesc {
+    pub interface: &'static str,
+    pub name: &'static str,
+    pub args: &'static [ArgType],
+}
+
+/// The type of a message argument, with the interface of object arguments if the protocol
+/// names one.
+#[derive(Debug, Clone, Copy)]
+pub enum ArgType {
+    Int,
+    Uint,
+    Fixed,
+    String,
+    Object(Option<&'static str>),
+    NewId(Option<&'static str>),
+    Array,
+    Fd,
+}
+
+/// Implemented by generated message types to say which message they are.
+pub trait Describe {
+    fn describe(&self) -> &'static MessageDesc;
+}
+
+/// An argument's value as tracing prints it.
+pub(crate) enum TraceValue {
+    Int(i32),
+    Uint(u32),
+    String(Option<String>),
+    /// The length of an array.
+    Array(usize),
+    Fd(RawFd),
+}
+
+/// Whether messages are traced, which is when the `wayland::wire` target is logged at the trace
+/// level.
+pub(crate) fn enabled() -> bool {
+    log::log_enabled!(target: "wayland::wire", log::Level::Trace)
+}
+
+/// Logs a message the way libwayland prints it when `WAYLAND_DEBUG` is set.
+pub(crate) fn trace(sent: bool, object: u32, desc: &MessageDesc, values: Vec<TraceValue>) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    let mut line = format!(
+        "[{:7}.{:03}] {}{}@{object}.{}(",
+        (time / 1000) as u32,
+        time % 1000,
+        if sent { " -> " } else { "" },
+        desc.interface,
+        desc.name,
+    );
+    for (i, (ty, value)) in desc.args.iter().zip(values).enumerate() {
+        if i > 0 {
+            line.push_str(", ");
+        }
+        let _ = match (ty, value) {
+            (ArgType::Fixed, TraceValue::Int(v)) => write!(line, "{:.6}", f64::from(v) / 256.0),
+            (_, TraceValue::Int(v)) => write!(line, "{v}"),
+            (ArgType::Object(_) | ArgType::NewId(_), TraceValue::Uint(0)) => write!(line, "nil"),
+            (ArgType::Object(interface), TraceValue::Uint(id)) => {
+                write!(line, "{}@{id}", interface.unwrap_or("[unknown]"))
+            }
+            (ArgType::NewId(interface), TraceValue::Uint(id)) => {
+                write!(line, "new id {}@{id}", interface.unwrap_or("[unknown]"))
+            }
+            (_, TraceValue::Uint(v)) => write!(line, "{v}"),
+            (_, TraceValue::String(Some(s))) => write!(line, "\"{s}\""),
+            (_, TraceValue::String(None)) => write!(line, "nil"),
+            (_, TraceValue::Array(len)) => write!(line, "array[{len}]"),
+            (_, TraceValue::Fd(fd)) => write!(line, "fd {fd}"),
+        };
+    }
+    line.push(')');
+    log::trace!(target: "wayland::wire", "{line}");
+}
+
+/// Reads the values of a received message's arguments, stopping early if it's malformed.
+pub(crate) fn read_values(
+    desc: &MessageDesc,
+    mut data: SplitSlice<'_>,
+    fds: &[RawFd],
+) -> Vec<TraceValue> {
+    let mut fds = fds.iter();
+    let mut values = Vec::new();
+    for ty in desc.args {
+        let value = match ty {
+            ArgType::Int | ArgType::Fixed => {
+                read_uint(&mut data).map(|v| TraceValue::Int(v as i32))
+            }
+            ArgType::Uint | ArgType::Object(_) | ArgType::NewId(_) => {
+                read_uint(&mut data).map(TraceValue::Uint)
+            }
+            ArgType::String => read_bytes(&mut data).map(|bytes| {
+                TraceValue::String(bytes.map(|bytes| {
+                    let text = bytes.strip_suffix(&[0]).unwrap_or(&bytes);
+                    String::from_utf8_lossy(text).into_owned()
+                }))
+            }),
+            ArgType::Array => {
+                read_bytes(&mut data).map(|bytes| TraceValue::Array(bytes.map_or(0, |it| it.len())))
+            }
+            ArgType::Fd => fds.next().map(|&fd| TraceValue::Fd(fd)),
+        };
+        let Some(value) = value else {
+            break;
+        };
+        values.push(value);
+    }
+    values
+}
+
+fn read_uint(data: &mut SplitSlice<'_>) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    data.read_exact(&mut buf).ok()?;
+    Some(u32::from_ne_bytes(buf))
+}
+
+/// Reads a length-prefixed, padded argument, or `None` inside if its length is 0.
+fn read_bytes(data: &mut SplitSlice<'_>) -> Option<Option<Vec<u8>>> {
+    let length = usize::try_from(read_uint(data)?).ok()?;
+    if length == 0 {
+        return Some(None);
+    }
+    if length > data.len() {
+        return None;
+    }
+    let mut buf = vec![0u8; length.div_ceil(4) * 4];
+    data.read_exact(&mut buf).ok()?;
+    buf.truncate(length);
+    Some(Some(buf))
+}
@@ -47,12 +47,14 @@ impl Config {
             })
             .collect::<Vec<_>>();
 
-        let interfaces = protocols
+        let mut interfaces: BTreeMap<String, Interface> = protocols
             .into_iter()
             .flat_map(|protocol| protocol.interfaces)
             .map(|interface| (interface.name.clone(), interface))
             .collect();
 
+        remove_unknown_interface_messages(&mut interfaces);
+
         let dependency_graph = make_dependency_graph(&interfaces);
 
         let global_allowlist = interfaces
@@ -174,6 +176,23 @@ fn preprocess_interfaces(
         .collect()
 }
 
+/// Removes messages referring to interfaces from protocols that weren't loaded, like
+/// `wp_cursor_shape_manager_v1.get_tablet_tool_v2`. The remaining messages keep their opcodes.
+fn remove_unknown_interface_messages(interfaces: &mut BTreeMap<String, Interface>) {
+    let names = interfaces.keys().cloned().collect::<HashSet<String>>();
+    let is_known = |message: &Message| {
+        message.args.iter().all(|arg| {
+            arg.interface
+                .as_ref()
+                .is_none_or(|interface| names.contains(interface))
+        })
+    };
+    for interface in interfaces.values_mut() {
+        interface.requests.retain(is_known);
+        interface.events.retain(is_known);
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum DependencyKind {
     AnyVersion,
@@ -267,12 +286,14 @@ impl<'a> GenContext<'a> {
             self.gen_global_message_enum(|interface| &interface.requests, MessageKind::Request);
         let event_enum =
             self.gen_global_message_enum(|interface| &interface.events, MessageKind::Event);
+        let protocol_error = self.gen_protocol_error();
         quote! {
             extern crate wayland;
-            use wayland::{Arg, Connection, Message, Fixed, Object};
+            use wayland::{Arg, Message, Fixed, Object, Wire};
             #interface_enum
             #request_enum
             #event_enum
+            #protocol_error
             #(#interfaces)*
         }
     }
@@ -350,6 +371,8 @@ impl<'a> GenContext<'a> {
         let generics = quote!(<#generic>);
         let reader = self.gen_message_unmarshaler(interface, messages, kind);
         let writer = self.gen_message_marshaler(interface, messages, kind);
+        let describer = self.gen_message_describer(interface, messages, kind);
+        let into_owned = self.gen_message_into_owned(interface, messages, kind);
         quote! {
             #[derive(Debug)]
             pub enum #type_name #generics {
@@ -357,6 +380,8 @@ impl<'a> GenContext<'a> {
             }
             #reader
             #writer
+            #describer
+            #into_owned
             // TODO make this lifetime optional
             impl<'a> From<#type_name #generics> for #global_enum_name<'a> {
                 fn from(v: #type_name #generics) -> #global_enum_name<'a> {
@@ -449,35 +474,71 @@ impl<'a> GenContext<'a> {
                 Interface::#interface_type_name => unreachable!("disabled"),
             }
         });
-        let write_variants = enabled_interfaces.map(|interface| {
+        let write_variants = enabled_interfaces.clone().map(|interface| {
             let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
             quote! {
                 #kind_ident::#interface_type_name(it) => it.marshal(conn),
             }
         });
-        let generics = if any_variant_needs_lifetime {
-            quote!(<'a>)
+        let describe_variants = enabled_interfaces.clone().map(|interface| {
+            let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
+            quote! {
+                #kind_ident::#interface_type_name(it) => it.describe(),
+            }
+        });
+        let into_owned_variants = enabled_interfaces.map(|interface| {
+            let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
+            if message_type_needs_lifetime(selector(interface)) {
+                quote! {
+                    #kind_ident::#interface_type_name(it) => #kind_ident::#interface_type_name(it.into_owned()),
+                }
+            } else {
+                quote! {
+                    #kind_ident::#interface_type_name(it) => #kind_ident::#interface_type_name(it),
+                }
+            }
+        });
+        let (generics, message_lifetime, into_owned) = if any_variant_needs_lifetime {
+            let into_owned = quote! {
+                impl<'a> #type_name<'a> {
+                    /// Copies any strings and arrays borrowed from the connection's buffer.
+                    pub fn into_owned(self) -> #type_name<'static> {
+                        match self {
+                            #(#into_owned_variants)*
+                        }
+                    }
+                }
+            };
+            (quote!(<'a>), quote!('a), into_owned)
         } else {
-            quote!()
+            (quote!(), quote!('_), quote!())
         };
         quote! {
+            #into_owned
             #[derive(Debug)]
             pub enum #type_name #generics {
                 #(#variants)*
             }
             impl #generics #type_name #generics {
-                pub fn unmarshal(interface: Interface, mut msg: Message<'_>) -> Option<#type_name #generics> {
-                    Some(match interface {
+                pub fn unmarshal(interface: Interface, mut msg: Message<#message_lifetime>) -> Result<#type_name #generics, wayland::Error> {
+                    Ok(match interface {
                         #(#read_variants)*
                         #(#read_disabled_variants)*
                     })
                 }
-                pub fn marshal(self, conn: &mut Connection) {
+                pub fn marshal(self, conn: &mut impl Wire) -> Result<(), wayland::Error> {
                     match self {
                         #(#write_variants)*
                     }
                 }
             }
+            impl #generics wayland::Describe for #type_name #generics {
+                fn describe(&self) -> &'static wayland::MessageDesc {
+                    match self {
+                        #(#describe_variants)*
+                    }
+                }
+            }
         }
     }
 
@@ -500,6 +561,7 @@ impl<'a> GenContext<'a> {
     }
 
     fn gen_interface_enum(&self, interface: &Interface, enm: &Enum) -> TokenStream {
+        let error_enum = is_error_enum(enm).then(|| self.gen_error_enum(interface, enm));
         let entries = enm.entries.iter().map(|entry| {
             let const_name = format_ident!(
                 "{}_{}_{}",
@@ -512,7 +574,165 @@ impl<'a> GenContext<'a> {
                 pub const #const_name: u32 = #value;
             }
         });
-        quote!(#(#entries)*)
+        quote! {
+            #(#entries)*
+            #error_enum
+        }
+    }
+
+    /// Generates a Rust enum for the codes `wl_display.error` can carry for an interface.
+    fn gen_error_enum(&self, interface: &Interface, enm: &Enum) -> TokenStream {
+        let type_name = format_ident!("{}Error", interface.name.to_upper_camel_case());
+        let variants = enm
+            .entries
+            .iter()
+            .map(|entry| format_ident!("{}", entry.name.to_upper_camel_case()));
+        let from_code_arms = enm
+            .entries
+            .iter()
+            .zip(variants.clone())
+            .map(|(entry, variant)| {
+                let value = entry.value;
+                quote! {
+                    #value => Some(#type_name::#variant),
+                }
+            });
+        let code_arms = enm
+            .entries
+            .iter()
+            .zip(variants.clone())
+            .map(|(entry, variant)| {
+                let value = entry.value;
+                quote! {
+                    #type_name::#variant => #value,
+                }
+            });
+        let name_arms = enm
+            .entries
+            .iter()
+            .zip(variants.clone())
+            .map(|(entry, variant)| {
+                let name = &entry.name;
+                quote! {
+                    #type_name::#variant => #name,
+                }
+            });
+        quote! {
+            // The variants are named by the protocol.
+            #[allow(clippy::enum_variant_names)]
+            #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+            pub enum #type_name {
+                #(#variants,)*
+            }
+
+            impl #type_name {
+                pub const fn from_code(code: u32) -> Option<#type_name> {
+                    match code {
+                        #(#from_code_arms)*
+                        _ => None,
+                    }
+                }
+                pub const fn code(self) -> u32 {
+                    match self {
+                        #(#code_arms)*
+                    }
+                }
+                pub const fn name(self) -> &'static str {
+                    match self {
+                        #(#name_arms)*
+                    }
+                }
+            }
+        }
+    }
+
+    /// Generates the types `wl_display.error` is decoded into, which say what the code means
+    /// for the interface of the object the error is about.
+    fn gen_protocol_error(&self) -> TokenStream {
+        let interfaces = self
+            .interfaces
+            .values()
+            .filter(|interface| interface.version != 0 && interface.enums.iter().any(is_error_enum))
+            .collect::<Vec<_>>();
+        let variants = interfaces
+            .iter()
+            .map(|interface| format_ident!("{}", interface.name.to_upper_camel_case()))
+            .collect::<Vec<_>>();
+        let error_types = interfaces
+            .iter()
+            .map(|interface| format_ident!("{}Error", interface.name.to_upper_camel_case()))
+            .collect::<Vec<_>>();
+        quote! {
+            /// The meaning of a `wl_display.error` code, as defined by the interface of the
+            /// object the error is about.
+            #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+            pub enum ErrorCode {
+                #(#variants(#error_types),)*
+                /// A code the interface doesn't define.
+                Unknown(Interface, u32),
+            }
+
+            impl ErrorCode {
+                pub fn new(interface: Interface, code: u32) -> ErrorCode {
+                    match interface {
+                        #(Interface::#variants => #error_types::from_code(code).map(ErrorCode::#variants),)*
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                    .unwrap_or(ErrorCode::Unknown(interface, code))
+                }
+            }
+
+            impl std::fmt::Display for ErrorCode {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        #(ErrorCode::#variants(e) => f.write_str(e.name()),)*
+                        ErrorCode::Unknown(_, code) => write!(f, "error {code}"),
+                    }
+                }
+            }
+
+            /// A fatal error the compositor sent with `wl_display.error`.
+            #[derive(Debug, Clone)]
+            pub struct ProtocolError {
+                pub object: u32,
+                pub interface: Interface,
+                pub code: ErrorCode,
+                pub message: String,
+            }
+
+            impl ProtocolError {
+                pub fn new(object: u32, interface: Interface, code: u32, message: String) -> ProtocolError {
+                    ProtocolError {
+                        object,
+                        interface,
+                        code: ErrorCode::new(interface, code),
+                        message,
+                    }
+                }
+            }
+
+            impl std::fmt::Display for ProtocolError {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(
+                        f,
+                        "{}@{}: {}: {}",
+                        self.interface.name(),
+                        self.object,
+                        self.code,
+                        self.message,
+                    )
+                }
+            }
+
+            impl std::error::Error for ProtocolError {}
+
+            impl From<ProtocolError> for wayland::Error {
+                fn from(e: ProtocolError) -> wayland::Error {
+                    wayland::Error::Fatal(Box::new(e))
+                }
+            }
+        }
     }
 
     fn gen_message_unmarshaler(
@@ -528,15 +748,70 @@ impl<'a> GenContext<'a> {
         } else {
             quote!()
         };
-        let variants = messages.iter().enumerate().map(|(i, message)| {
-            self.gen_message_reader_variant(u16::try_from(i).unwrap(), interface, message, kind)
+        let message_lifetime = if needs_lifetime {
+            quote!('a)
+        } else {
+            quote!('_)
+        };
+        let variants = messages.iter().map(|message| {
+            self.gen_message_reader_variant(message.opcode, interface, message, kind)
         });
         quote! {
             impl #generics #type_name #generics {
-                pub fn unmarshal(mut msg: Message<'_>) -> Option<#type_name #generics> {
+                pub fn unmarshal(mut msg: Message<#message_lifetime>) -> Result<#type_name #generics, wayland::Error> {
                     match msg.opcode() {
                         #(#variants)*
-                        _ => None
+                        _ => Err(msg.protocol_error())
+                    }
+                }
+            }
+        }
+    }
+
+    fn gen_message_into_owned(
+        &self,
+        interface: &Interface,
+        messages: &[Message],
+        kind: MessageKind,
+    ) -> TokenStream {
+        if !message_type_needs_lifetime(messages) {
+            return quote!();
+        }
+        let type_name = format_ident!("{}{kind}", interface.name.to_upper_camel_case());
+        let interface_field_name = format_ident!("{}", interface.name.to_snake_case());
+        let variants = messages.iter().map(|message| {
+            let variant_name = format_ident!("{}", message.name.to_upper_camel_case());
+            let field_names = message
+                .args
+                .iter()
+                .map(|arg| format_ident!("{}", arg.name.to_snake_case()))
+                .collect::<Vec<_>>();
+            let field_values =
+                message
+                    .args
+                    .iter()
+                    .zip(&field_names)
+                    .map(|(arg, name)| match arg.kind {
+                        ArgKind::String if arg.allow_null => quote! {
+                            #name: #name.map(|it| std::borrow::Cow::Owned(it.into_owned())),
+                        },
+                        ArgKind::String | ArgKind::Array => quote! {
+                            #name: std::borrow::Cow::Owned(#name.into_owned()),
+                        },
+                        _ => quote!(#name,),
+                    });
+            quote! {
+                #type_name::#variant_name { #interface_field_name, #(#field_names),* } => {
+                    #type_name::#variant_name { #interface_field_name, #(#field_values)* }
+                }
+            }
+        });
+        quote! {
+            impl<'a> #type_name<'a> {
+                /// Copies any strings and arrays borrowed from the connection's buffer.
+                pub fn into_owned(self) -> #type_name<'static> {
+                    match self {
+                        #(#variants)*
                     }
                 }
             }
@@ -556,12 +831,12 @@ impl<'a> GenContext<'a> {
         } else {
             quote!()
         };
-        let variants = messages.iter().enumerate().map(|(i, message)| {
-            self.gen_message_marshaler_variant(u16::try_from(i).unwrap(), interface, message, kind)
+        let variants = messages.iter().map(|message| {
+            self.gen_message_marshaler_variant(message.opcode, interface, message, kind)
         });
         quote! {
             impl #generics #type_name #generics {
-                pub fn marshal(self, conn: &mut Connection) {
+                pub fn marshal(self, conn: &mut impl Wire) -> Result<(), wayland::Error> {
                     match self {
                         #(#variants)*
                     }
@@ -570,6 +845,64 @@ impl<'a> GenContext<'a> {
         }
     }
 
+    fn gen_message_describer(
+        &self,
+        interface: &Interface,
+        messages: &[Message],
+        kind: MessageKind,
+    ) -> TokenStream {
+        let type_name = format_ident!("{}{kind}", interface.name.to_upper_camel_case());
+        let generics = if message_type_needs_lifetime(messages) {
+            quote!(<'a>)
+        } else {
+            quote!()
+        };
+        let variants = messages.iter().map(|message| {
+            let variant_name = format_ident!("{}", message.name.to_upper_camel_case());
+            let desc = self.gen_message_desc(interface, message);
+            quote! {
+                #type_name::#variant_name { .. } => #desc,
+            }
+        });
+        quote! {
+            impl #generics wayland::Describe for #type_name #generics {
+                fn describe(&self) -> &'static wayland::MessageDesc {
+                    match *self {
+                        #(#variants)*
+                    }
+                }
+            }
+        }
+    }
+
+    fn gen_message_desc(&self, interface: &Interface, message: &Message) -> TokenStream {
+        let interface_name = &interface.name;
+        let message_name = &message.name;
+        let arg_types = message.args.iter().map(|arg| {
+            let arg_interface = match &arg.interface {
+                Some(interface) => quote!(Some(#interface)),
+                None => quote!(None),
+            };
+            match arg.kind {
+                ArgKind::NewId => quote!(wayland::ArgType::NewId(#arg_interface)),
+                ArgKind::Int => quote!(wayland::ArgType::Int),
+                ArgKind::Uint => quote!(wayland::ArgType::Uint),
+                ArgKind::Fixed => quote!(wayland::ArgType::Fixed),
+                ArgKind::String => quote!(wayland::ArgType::String),
+                ArgKind::Object => quote!(wayland::ArgType::Object(#arg_interface)),
+                ArgKind::Array => quote!(wayland::ArgType::Array),
+                ArgKind::Fd => quote!(wayland::ArgType::Fd),
+            }
+        });
+        quote! {
+            &wayland::MessageDesc {
+                interface: #interface_name,
+                name: #message_name,
+                args: &[#(#arg_types),*],
+            }
+        }
+    }
+
     fn gen_message_reader_variant(
         &self,
         i: u16,
@@ -585,11 +918,21 @@ impl<'a> GenContext<'a> {
             .args
             .iter()
             .map(|arg| self.gen_message_reader_variant_arg(arg));
+        // File descriptors are taken first, so they're closed rather than left for the next
+        // message if the rest of the message fails to decode.
+        let fds = message
+            .args
+            .iter()
+            .filter(|arg| arg.kind == ArgKind::Fd)
+            .map(|arg| format_ident!("{}", arg.name.to_snake_case()));
         quote! {
-            #i => Some(#enum_type_name::#variant_name {
-                #interface_field_name: #interface_type_name(msg.object()),
-                #(#fields)*
-            }),
+            #i => {
+                #(let #fds = msg.read_fd()?;)*
+                Ok(#enum_type_name::#variant_name {
+                    #interface_field_name: #interface_type_name(msg.object()),
+                    #(#fields)*
+                })
+            }
         }
     }
 
@@ -604,6 +947,7 @@ impl<'a> GenContext<'a> {
         let interface_type_name = format_ident!("{}", interface.name.to_upper_camel_case());
         let type_name = format_ident!("{}{kind}", interface.name.to_upper_camel_case());
         let variant_name = format_ident!("{}", message.name.to_upper_camel_case());
+        let desc = self.gen_message_desc(interface, message);
         let arg_field_names = iter::once(format_ident!("{}", interface_field_name)).chain(
             message
                 .args
@@ -652,7 +996,7 @@ impl<'a> GenContext<'a> {
             .map(|(i, _arg)| format_ident!("arg{i}"));
         quote! {
             #type_name::#variant_name { #(#arg_field_names: #arg_bindings),* } => {
-                conn.write_message(object, #i, &[#(#arg_values),*], [#(#fd_values),*])
+                conn.write_message(object, #i, #desc, &[#(#arg_values),*], [#(#fd_values),*])
             },
         }
     }
@@ -669,20 +1013,12 @@ impl<'a> GenContext<'a> {
             ArgKind::Int => quote!(msg.read_int()?),
             ArgKind::Uint => quote!(msg.read_uint()?),
             ArgKind::Fixed => quote!(msg.read_fixed()?),
-            ArgKind::String if arg.allow_null => {
-                quote!(msg
-                    .read_string()
-                    .map(|opt| opt.map(std::borrow::Cow::Owned))?)
-            }
-            ArgKind::String => {
-                quote!(msg
-                    .read_string()
-                    .map(|opt| opt.unwrap())
-                    .map(std::borrow::Cow::Owned)?)
-            }
+            ArgKind::String if arg.allow_null => quote!(msg.read_str()?),
+            ArgKind::String => quote!(msg.read_non_null_str()?),
             ArgKind::Object => quote!(msg.read_uint()?),
-            ArgKind::Array => quote!(msg.read_array().map(std::borrow::Cow::Owned)?),
-            ArgKind::Fd => quote!(msg.read_fd()?),
+            ArgKind::Array => quote!(msg.read_bytes()?),
+            // Bound before the rest of the message is read.
+            ArgKind::Fd => return quote!(#field_name,),
         };
         quote! {
             #field_name: #field_value,
@@ -736,6 +1072,11 @@ impl<'a> GenContext<'a> {
     }
 }
 
+/// Whether `enm` lists the codes `wl_display.error` can carry for its interface.
+fn is_error_enum(enm: &Enum) -> bool {
+    enm.name == "error" && !enm.bitfield
+}
+
 fn message_type_needs_lifetime(messages: &[Message]) -> bool {
     messages.iter().any(|message| {
         message
@@ -39,6 +39,7 @@ pub struct Interface {
 #[derive(Clone, Default, Debug, Eq, PartialEq)]
 pub struct Message {
     pub name: String,
+    pub opcode: u16,
     pub destructor: bool,
     pub since: u32,
     pub description: Option<Description>,
@@ -190,8 +191,16 @@ impl<'a> ParseContext<'a> {
                     self.attrs = Some(attrs);
                     match &*name {
                         "description" => interface.description = self.description()?.into(),
-                        "request" => interface.requests.push(self.message()?),
-                        "event" => interface.events.push(self.message()?),
+                        "request" => {
+                            let mut message = self.message()?;
+                            message.opcode = u16::try_from(interface.requests.len()).ok()?;
+                            interface.requests.push(message);
+                        }
+                        "event" => {
+                            let mut message = self.message()?;
+                            message.opcode = u16::try_from(interface.events.len()).ok()?;
+                            interface.events.push(message);
+                        }
                         "enum" => interface.enums.push(self.enumeration()?),
                         _ => return None,
                     }
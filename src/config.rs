@@ -1,10 +1,19 @@
 use crate::{
+    region::Region,
     wl_gen::{WL_POINTER_AXIS_HORIZONTAL_SCROLL, WL_POINTER_AXIS_VERTICAL_SCROLL},
     ModIndices,
 };
 use anyhow::{bail, ensure, Context, Result};
 use bitflags::bitflags;
-use std::{cmp::Ordering, collections::HashMap, path::PathBuf};
+use scfg::Span;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use tiny_skia::Color;
 use xkbcommon::xkb;
 
 #[derive(Clone, Copy, Debug)]
@@ -31,7 +40,13 @@ pub(crate) enum Cmd {
     Release(Button),
     Cut(Direction),
     Move(Direction),
+    /// Scrolls along an axis; the amount is multiplied by the configured scroll speed.
     Scroll(u32, f64),
+    Mode(ModeId),
+    /// Selects a grid cell by its zero-based index in row-major order.
+    Cell(u32),
+    /// Selects a grid cell by typing its hint label.
+    Hint,
 }
 
 bitflags! {
@@ -48,11 +63,1260 @@ bitflags! {
     }
 }
 
+pub(crate) type ModeId = usize;
+
+/// The mode containing top-level bindings, active on startup.
+pub(crate) const DEFAULT_MODE: ModeId = 0;
+
 pub(crate) struct Config {
+    modes: Vec<Mode>,
+    pub(crate) appearance: Appearance,
+    pub(crate) scroll_speed: ScrollSpeed,
+    pub(crate) initial_region: InitialRegion,
+    pub(crate) grid: Grid,
+    pub(crate) hints: Hints,
+    pub(crate) warp: Warp,
+    /// The font labels are drawn with, as a path or a fontconfig pattern.
+    pub(crate) font: String,
+    /// Whether click, press and release commands use the right button for left and vice versa.
+    pub(crate) swap_buttons: bool,
+    pub(crate) backend: Backend,
+    /// The most verbose messages that are logged.
+    pub(crate) log_level: log::LevelFilter,
+    /// Number of previous regions kept for undo, or zero to keep all of them.
+    pub(crate) history_limit: u32,
+    /// Milliseconds to wait for the next key of a sequence, or zero to wait indefinitely.
+    pub(crate) sequence_timeout: u32,
+    /// Milliseconds within which the keys of a chord have to be pressed.
+    pub(crate) chord_timeout: u32,
+    /// Key repeat rate in repeats per second, overriding the compositor's.
+    pub(crate) repeat_rate: Option<u32>,
+    /// Key repeat delay in milliseconds, overriding the compositor's.
+    pub(crate) repeat_delay: Option<u32>,
+    /// How far the region moves, as a length or a percentage of its size.
+    pub(crate) move_distance: Length,
+    /// Whether duplicate bindings are errors rather than warnings.
+    strict: bool,
+    /// The profile whose blocks are applied, as selected on the command line.
+    profile: Option<String>,
+    /// Whether a block for the selected profile has been seen.
+    profile_found: bool,
+    pub(crate) warnings: Vec<String>,
+    /// Locations of bindings defined by the config layer currently being applied.
+    layer_bindings: HashMap<(ModeId, Trigger), String>,
+    /// Bindings of the built-in default config that no later layer has replaced or unbound,
+    /// which `no-default-bindings` removes.
+    default_bindings: HashSet<(ModeId, Trigger)>,
+    /// Locations of all bindings, for errors about the grid size found once the config is
+    /// loaded.
+    binding_locations: HashMap<(ModeId, Trigger), String>,
+    /// Commands that user-defined command names stand for.
+    aliases: HashMap<String, Vec<Cmd>>,
+    /// Appearance settings from `output` blocks, by output name, applied over `appearance`.
+    output_appearances: Vec<(String, Vec<scfg::Directive>)>,
+    /// Appearances of the outputs named in `output` blocks, worked out once the config is
+    /// loaded.
+    resolved_output_appearances: HashMap<String, Appearance>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Mode {
+    pub(crate) name: String,
     bindings: HashMap<(Mods, xkb::Keysym), Vec<Cmd>>,
+    /// Bindings for buttons of the physical pointer, by button code.
+    button_bindings: HashMap<u32, Vec<Cmd>>,
+    /// Bindings for sequences of two or more keys.
+    sequences: HashMap<Vec<(Mods, xkb::Keysym)>, Vec<Cmd>>,
+    /// Bindings for two keys pressed together, with the keysyms in ascending order.
+    chords: HashMap<(Mods, xkb::Keysym, xkb::Keysym), Vec<Cmd>>,
+    defined: bool,
+    /// Line of the first command switching to this mode.
+    referenced_on: Option<usize>,
+}
+
+impl Mode {
+    /// Returns every binding of the mode along with what triggers it.
+    fn triggers(&self) -> impl Iterator<Item = (Trigger, &Vec<Cmd>)> {
+        let keys = self
+            .bindings
+            .iter()
+            .map(|(&(mods, keysym), cmds)| (Trigger::Key(mods, keysym), cmds));
+        let buttons = self
+            .button_bindings
+            .iter()
+            .map(|(&button, cmds)| (Trigger::Button(button), cmds));
+        let sequences = self
+            .sequences
+            .iter()
+            .map(|(keys, cmds)| (Trigger::Sequence(keys.clone()), cmds));
+        let chords = self
+            .chords
+            .iter()
+            .map(|(&(mods, first, second), cmds)| (Trigger::Chord(mods, first, second), cmds));
+        keys.chain(buttons).chain(sequences).chain(chords)
+    }
+}
+
+/// The number of cells the region is divided into, used by cuts and the cell command.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Grid {
+    pub(crate) cols: u32,
+    pub(crate) rows: u32,
+}
+
+impl Default for Grid {
+    fn default() -> Grid {
+        Grid { cols: 2, rows: 2 }
+    }
+}
+
+/// When the pointer is moved to the center of the region.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Warp {
+    /// Whenever the region changes.
+    #[default]
+    Always,
+    /// Only before clicking or scrolling.
+    Click,
+    /// Never; clicks and scrolls only print the position instead of being sent.
+    Never,
+}
+
+/// How pointer input is emulated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Backend {
+    /// The virtual pointer protocol if the compositor supports it, otherwise libei.
+    #[default]
+    Auto,
+    VirtualPointer,
+    Libei,
+}
+
+impl Backend {
+    pub(crate) const NAMES: &'static [&'static str] = &["auto", "virtual-pointer", "libei"];
+
+    pub(crate) fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "auto" => Some(Backend::Auto),
+            "virtual-pointer" => Some(Backend::VirtualPointer),
+            "libei" => Some(Backend::Libei),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Auto => "auto",
+            Backend::VirtualPointer => "virtual-pointer",
+            Backend::Libei => "libei",
+        }
+    }
+}
+
+/// How region transitions speed up and slow down.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Easing {
+    Linear,
+    EaseIn,
+    #[default]
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the fraction of the animation's duration that has passed to the fraction of the
+    /// distance covered.
+    pub(crate) fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t.powi(3),
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4.0 * t.powi(3),
+            Easing::EaseInOut => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+        }
+    }
+}
+
+/// How the overlay is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Renderer {
+    /// Into shared memory buffers on the CPU.
+    #[default]
+    Shm,
+    /// As rectangles made of single-pixel buffers scaled by the compositor, where possible.
+    SinglePixel,
+}
+
+/// Where the guide lines inside the region are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Guides {
+    /// Between the cells of the grid.
+    #[default]
+    Grid,
+    /// At the thirds of the region, whatever the grid is.
+    Thirds,
+}
+
+impl Guides {
+    /// Returns the grid whose cell boundaries the guide lines are drawn on.
+    pub(crate) fn grid(self, grid: Grid) -> Grid {
+        match self {
+            Guides::Grid => grid,
+            Guides::Thirds => Grid { cols: 3, rows: 3 },
+        }
+    }
+}
+
+/// What the pointer looks like over the overlay, when pointer buttons are bound.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Cursor {
+    /// Whatever the compositor shows by default.
+    Default,
+    /// A crosshair, if the compositor supports cursor-shape-v1.
+    #[default]
+    Crosshair,
+    Hidden,
+}
+
+/// The characters and length of the labels typed to select grid cells.
+#[derive(Clone, Debug)]
+pub(crate) struct Hints {
+    pub(crate) chars: Vec<char>,
+    pub(crate) length: u32,
+}
+
+impl Default for Hints {
+    fn default() -> Hints {
+        Hints {
+            chars: "asdfghjkl".chars().collect(),
+            length: 1,
+        }
+    }
+}
+
+impl Hints {
+    /// Returns the index of the cell with the given label.
+    pub(crate) fn index_of(&self, label: &str) -> Option<u32> {
+        let base = self.chars.len() as u32;
+        label.chars().try_fold(0u32, |index, c| {
+            let digit = self.chars.iter().position(|&it| it == c)? as u32;
+            index.checked_mul(base)?.checked_add(digit)
+        })
+    }
+
+    /// Returns the label of the cell at `index`, or `None` if there aren't enough labels.
+    pub(crate) fn label(&self, index: u32) -> Option<String> {
+        if u64::from(index) >= self.capacity() {
+            return None;
+        }
+        let base = self.chars.len() as u32;
+        let mut label = vec![self.chars[0]; self.length as usize];
+        let mut rest = index;
+        for c in label.iter_mut().rev() {
+            *c = self.chars[(rest % base) as usize];
+            rest /= base;
+        }
+        Some(label.into_iter().collect())
+    }
+
+    /// Returns the number of distinct labels.
+    fn capacity(&self) -> u64 {
+        (self.chars.len() as u64).saturating_pow(self.length)
+    }
+}
+
+/// The region selected when waypoint starts.
+#[derive(Clone, Debug, Default)]
+pub(crate) enum InitialRegion {
+    /// The bounding box of all outputs.
+    #[default]
+    Desktop,
+    /// The output with the given name.
+    Output(String),
+    /// An explicit region in global logical coordinates.
+    Region(Region),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ScrollSpeed {
+    pub(crate) vertical: f64,
+    pub(crate) horizontal: f64,
+    /// Whether scroll commands go the other way on each axis, for natural scrolling.
+    pub(crate) invert_vertical: bool,
+    pub(crate) invert_horizontal: bool,
+}
+
+impl ScrollSpeed {
+    pub(crate) fn for_axis(self, axis: u32) -> f64 {
+        let (speed, invert) = match axis {
+            WL_POINTER_AXIS_HORIZONTAL_SCROLL => (self.horizontal, self.invert_horizontal),
+            _ => (self.vertical, self.invert_vertical),
+        };
+        if invert {
+            -speed
+        } else {
+            speed
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Appearance {
+    pub(crate) border_color: Color,
+    pub(crate) border_thickness: f32,
+    /// Length of the dashes and gaps of the border in pixels, or 0 for a solid border.
+    pub(crate) border_dash: f32,
+    /// Color drawn in the gaps between dashes.
+    pub(crate) border_dash_color: Color,
+    /// How far in pixels a glow behind the border reaches past it, or 0 for none.
+    pub(crate) glow: f32,
+    /// Color of the glow closest to the border, fading out further away.
+    pub(crate) glow_color: Color,
+    pub(crate) cross_color: Color,
+    pub(crate) cross_thickness: f32,
+    pub(crate) guides: Guides,
+    /// Diameter in pixels of the dot marking the point clicked, or 0 to not draw it.
+    pub(crate) center_dot: f32,
+    pub(crate) center_dot_color: Color,
+    /// Color of the ring around the dot, which keeps it visible on any background.
+    pub(crate) center_ring_color: Color,
+    /// Fill color for the area outside the selected region.
+    pub(crate) dim_color: Color,
+    /// Fill color for the inside of the selected region.
+    pub(crate) fill_color: Color,
+    /// Border color while a sequence of keys is being typed.
+    pub(crate) sequence_color: Color,
+    /// Whether the region moves smoothly to its new position rather than jumping there.
+    pub(crate) animate: bool,
+    /// Milliseconds a region transition takes.
+    pub(crate) animation_duration: u32,
+    pub(crate) animation_easing: Easing,
+    /// Milliseconds the overlay takes to fade out after a click, or 0 to hide it at once.
+    pub(crate) fade_out: u32,
+    /// Height of label text in pixels.
+    pub(crate) font_size: f32,
+    pub(crate) label_color: Color,
+    /// Fill color for the box behind each label.
+    pub(crate) label_background: Color,
+    /// Whether each grid cell shows its hint label even when no hint is being typed.
+    pub(crate) cell_labels: bool,
+    pub(crate) renderer: Renderer,
+    pub(crate) cursor: Cursor,
+    /// How many times the magnifier enlarges the screen around the region's center, or 0 to
+    /// not show it.
+    pub(crate) magnifier_zoom: u32,
+    /// Width and height of the magnifier in pixels.
+    pub(crate) magnifier_size: f32,
+    /// Whether the mode and pending input are shown in a corner of the output.
+    pub(crate) hud: bool,
+    /// Whether lines are drawn over a dark outline, so they stand out on any background.
+    pub(crate) outline: bool,
+    pub(crate) anti_alias: bool,
+    /// Whether line widths are rounded to whole pixels and lines are placed where they're sharp.
+    pub(crate) pixel_snap: bool,
+}
+
+/// The type of a setting's value.
+#[derive(Clone, Copy)]
+enum SettingType {
+    /// A color as #rrggbb or #rrggbbaa.
+    Color,
+    /// A finite number that isn't negative.
+    Number,
+    /// A whole number within an inclusive range.
+    Integer(u32, u32),
+    /// Two whole numbers within an inclusive range, given as two parameters.
+    IntegerPair(u32, u32),
+    /// A number of milliseconds within an inclusive range, as `250ms`, `2s` or just `250`.
+    Duration(u32, u32),
+    /// A length as `3px` or just `3`, or also as `40%` if percentages are allowed.
+    Length { percent: bool },
+    /// `true` or `false`.
+    Bool,
+    /// One of a fixed set of words.
+    Keyword(&'static [&'static str]),
+    /// Any word.
+    Text,
+    /// At least two different characters that aren't spaces, as a word.
+    UniqueChars,
+}
+
+#[derive(Clone)]
+enum SettingValue<'a> {
+    Color(Color),
+    Number(f64),
+    Integer(u32),
+    IntegerPair(u32, u32),
+    Duration(u32),
+    Length(Length),
+    Bool(bool),
+    Keyword(&'a str),
+    Text(&'a str),
+    Chars(Vec<char>),
+}
+
+/// A distance in pixels, or relative to the size of the region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Length {
+    Pixels(f64),
+    Percent(f64),
+}
+
+impl Length {
+    /// Returns the length in pixels for a region `size` pixels long.
+    pub(crate) fn resolve(self, size: i32) -> i32 {
+        match self {
+            Length::Pixels(pixels) => pixels.round() as i32,
+            Length::Percent(percent) => (f64::from(size) * percent / 100.0).round() as i32,
+        }
+    }
+}
+
+/// Splits a value like `250ms` into its number and unit, which may be empty.
+fn split_unit(s: &str) -> Option<(f64, &str)> {
+    let end = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(end);
+    let number = number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())?;
+    Some((number, unit))
+}
+
+impl SettingType {
+    /// Returns the number of parameters the setting takes.
+    fn arity(self) -> usize {
+        match self {
+            SettingType::IntegerPair(..) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Parses the parameters of a directive, of which there are as many as `arity` says.
+    fn parse_params<'a>(self, params: &[&'a str]) -> Option<SettingValue<'a>> {
+        match (self, params) {
+            (SettingType::IntegerPair(min, max), &[first, second]) => {
+                let parse = |s: &str| s.parse::<u32>().ok().filter(|n| (min..=max).contains(n));
+                Some(SettingValue::IntegerPair(parse(first)?, parse(second)?))
+            }
+            (_, &[param]) => self.parse(param),
+            _ => None,
+        }
+    }
+
+    /// Parses a value, with the parameters of settings taking more than one separated by a space.
+    fn parse(self, s: &str) -> Option<SettingValue<'_>> {
+        match self {
+            SettingType::Color => parse_color(s).map(SettingValue::Color),
+            SettingType::Number => s
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .map(SettingValue::Number),
+            SettingType::Integer(min, max) => s
+                .parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .map(SettingValue::Integer),
+            SettingType::IntegerPair(..) => {
+                let (first, second) = s.split_once(' ')?;
+                self.parse_params(&[first, second])
+            }
+            SettingType::Duration(min, max) => {
+                let (number, unit) = split_unit(s)?;
+                let millis = match unit {
+                    "" | "ms" => number,
+                    "s" => number * 1000.0,
+                    _ => return None,
+                };
+                (millis.fract() == 0.0 && (f64::from(min)..=f64::from(max)).contains(&millis))
+                    .then_some(SettingValue::Duration(millis as u32))
+            }
+            SettingType::Length { percent } => match split_unit(s)? {
+                (number, "" | "px") => Some(SettingValue::Length(Length::Pixels(number))),
+                (number, "%") if percent => Some(SettingValue::Length(Length::Percent(number))),
+                _ => None,
+            },
+            SettingType::Bool => match s {
+                "true" => Some(SettingValue::Bool(true)),
+                "false" => Some(SettingValue::Bool(false)),
+                _ => None,
+            },
+            SettingType::Keyword(words) => words.contains(&s).then_some(SettingValue::Keyword(s)),
+            SettingType::Text => Some(SettingValue::Text(s)),
+            SettingType::UniqueChars => {
+                let chars = s.chars().collect::<Vec<_>>();
+                let valid = chars.len() >= 2
+                    && chars.iter().enumerate().all(|(i, c)| {
+                        !chars[..i].contains(c) && !c.is_whitespace() && !c.is_control()
+                    });
+                valid.then_some(SettingValue::Chars(chars))
+            }
+        }
+    }
+
+    /// Describes the expected values, for error messages.
+    fn describe(self) -> String {
+        match self {
+            SettingType::Color => "a color as #rrggbb or #rrggbbaa".to_owned(),
+            SettingType::Number => "a number that isn't negative".to_owned(),
+            SettingType::Integer(min, u32::MAX) => format!("a whole number of at least {min}"),
+            SettingType::Integer(min, max) => format!("a whole number from {min} to {max}"),
+            SettingType::IntegerPair(min, max) => format!("two whole numbers from {min} to {max}"),
+            SettingType::Duration(min, u32::MAX) => {
+                format!("a duration like 250ms or 2s of at least {min}ms")
+            }
+            SettingType::Duration(min, max) => {
+                format!("a duration like 250ms or 2s from {min}ms to {max}ms")
+            }
+            SettingType::Length { percent: false } => "a length like 3px".to_owned(),
+            SettingType::Length { percent: true } => "a length like 3px or 40%".to_owned(),
+            SettingType::Bool => "true or false".to_owned(),
+            SettingType::Keyword(words) => format!("one of {}", words.join(", ")),
+            SettingType::Text => "a word".to_owned(),
+            SettingType::UniqueChars => {
+                "at least two different characters that aren't spaces".to_owned()
+            }
+        }
+    }
+}
+
+impl fmt::Display for SettingValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SettingValue::Color(color) => {
+                let color = color.to_color_u8();
+                write!(
+                    f,
+                    "#{:02x}{:02x}{:02x}{:02x}",
+                    color.red(),
+                    color.green(),
+                    color.blue(),
+                    color.alpha(),
+                )
+            }
+            SettingValue::Number(number) => write!(f, "{number}"),
+            SettingValue::Integer(integer) => write!(f, "{integer}"),
+            SettingValue::IntegerPair(first, second) => write!(f, "{first} {second}"),
+            SettingValue::Duration(millis) => write!(f, "{millis}ms"),
+            SettingValue::Length(Length::Pixels(pixels)) => write!(f, "{pixels}px"),
+            SettingValue::Length(Length::Percent(percent)) => write!(f, "{percent}%"),
+            SettingValue::Bool(value) => write!(f, "{value}"),
+            SettingValue::Keyword(keyword) => f.write_str(keyword),
+            SettingValue::Text(text) => f.write_str(&scfg::quote(text)),
+            SettingValue::Chars(ref chars) => f.write_str(&scfg::quote(&String::from_iter(chars))),
+        }
+    }
+}
+
+impl SettingValue<'_> {
+    fn color(self) -> Color {
+        match self {
+            SettingValue::Color(color) => color,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number(self) -> f64 {
+        match self {
+            SettingValue::Number(number) => number,
+            _ => unreachable!(),
+        }
+    }
+
+    fn integer(self) -> u32 {
+        match self {
+            SettingValue::Integer(integer) => integer,
+            _ => unreachable!(),
+        }
+    }
+
+    fn integer_pair(self) -> (u32, u32) {
+        match self {
+            SettingValue::IntegerPair(first, second) => (first, second),
+            _ => unreachable!(),
+        }
+    }
+
+    fn duration(self) -> u32 {
+        match self {
+            SettingValue::Duration(millis) => millis,
+            _ => unreachable!(),
+        }
+    }
+
+    fn length(self) -> Length {
+        match self {
+            SettingValue::Length(length) => length,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns a length that can't be a percentage in pixels.
+    fn pixels(self) -> f64 {
+        match self.length() {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(_) => unreachable!(),
+        }
+    }
+
+    fn boolean(self) -> bool {
+        match self {
+            SettingValue::Bool(value) => value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn keyword(&self) -> &str {
+        match self {
+            SettingValue::Keyword(keyword) => keyword,
+            _ => unreachable!(),
+        }
+    }
+
+    fn text(&self) -> &str {
+        match self {
+            SettingValue::Text(text) => text,
+            _ => unreachable!(),
+        }
+    }
+
+    fn chars(self) -> Vec<char> {
+        match self {
+            SettingValue::Chars(chars) => chars,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A single-valued option, settable in a `settings` block.
+struct Setting {
+    name: &'static str,
+    ty: SettingType,
+    /// The value used when the setting isn't set, if any.
+    default: Option<&'static str>,
+    /// The block, besides `settings`, the setting can also be set in.
+    section: Option<&'static str>,
+    set: fn(&mut Config, SettingValue),
+    /// Reads the setting back, or `None` if it's unset or only sets other settings.
+    get: fn(&Config) -> Option<SettingValue<'_>>,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        name: "border-color",
+        ty: SettingType::Color,
+        default: Some("#ffffff"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.border_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.border_color)),
+    },
+    Setting {
+        name: "border-thickness",
+        ty: SettingType::Length { percent: false },
+        default: Some("1px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.border_thickness = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.border_thickness.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "border-dash",
+        ty: SettingType::Length { percent: false },
+        default: Some("0px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.border_dash = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.border_dash.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "border-dash-color",
+        ty: SettingType::Color,
+        default: Some("#00000000"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.border_dash_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.border_dash_color)),
+    },
+    Setting {
+        name: "glow",
+        ty: SettingType::Length { percent: false },
+        default: Some("0px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.glow = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.glow.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "glow-color",
+        ty: SettingType::Color,
+        default: Some("#ffffff80"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.glow_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.glow_color)),
+    },
+    Setting {
+        name: "cross-color",
+        ty: SettingType::Color,
+        default: Some("#ffffff40"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.cross_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.cross_color)),
+    },
+    Setting {
+        name: "cross-thickness",
+        ty: SettingType::Length { percent: false },
+        default: Some("2px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.cross_thickness = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.cross_thickness.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "guides",
+        ty: SettingType::Keyword(&["grid", "thirds"]),
+        default: Some("grid"),
+        section: Some("appearance"),
+        set: |config, value| {
+            config.appearance.guides = match value.keyword() {
+                "thirds" => Guides::Thirds,
+                _ => Guides::Grid,
+            }
+        },
+        get: |config| {
+            Some(SettingValue::Keyword(match config.appearance.guides {
+                Guides::Grid => "grid",
+                Guides::Thirds => "thirds",
+            }))
+        },
+    },
+    Setting {
+        name: "anti-alias",
+        ty: SettingType::Bool,
+        default: Some("true"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.anti_alias = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.appearance.anti_alias)),
+    },
+    Setting {
+        name: "pixel-snap",
+        ty: SettingType::Bool,
+        default: Some("true"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.pixel_snap = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.appearance.pixel_snap)),
+    },
+    Setting {
+        name: "outline",
+        ty: SettingType::Bool,
+        default: Some("false"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.outline = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.appearance.outline)),
+    },
+    Setting {
+        name: "center-dot",
+        ty: SettingType::Length { percent: false },
+        default: Some("0px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.center_dot = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.center_dot.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "center-dot-color",
+        ty: SettingType::Color,
+        default: Some("#ffffff"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.center_dot_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.center_dot_color)),
+    },
+    Setting {
+        name: "center-ring-color",
+        ty: SettingType::Color,
+        default: Some("#000000"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.center_ring_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.center_ring_color)),
+    },
+    Setting {
+        name: "theme",
+        ty: SettingType::Keyword(&["dark", "light", "high-contrast", "colorblind-safe"]),
+        default: None,
+        section: Some("appearance"),
+        set: |config, value| config.apply_theme(value.keyword()),
+        // Only the settings it changed are kept.
+        get: |_| None,
+    },
+    Setting {
+        name: "dim-color",
+        ty: SettingType::Color,
+        default: Some("#00000060"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.dim_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.dim_color)),
+    },
+    Setting {
+        name: "fill-color",
+        ty: SettingType::Color,
+        default: Some("#00000000"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.fill_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.fill_color)),
+    },
+    Setting {
+        name: "sequence-color",
+        ty: SettingType::Color,
+        default: Some("#ffcc00"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.sequence_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.sequence_color)),
+    },
+    Setting {
+        name: "animation",
+        ty: SettingType::Bool,
+        default: Some("false"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.animate = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.appearance.animate)),
+    },
+    Setting {
+        name: "animation-duration",
+        ty: SettingType::Duration(1, 10000),
+        default: Some("150ms"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.animation_duration = value.duration(),
+        get: |config| Some(SettingValue::Duration(config.appearance.animation_duration)),
+    },
+    Setting {
+        name: "animation-easing",
+        ty: SettingType::Keyword(&["linear", "ease-in", "ease-out", "ease-in-out"]),
+        default: Some("ease-out"),
+        section: Some("appearance"),
+        set: |config, value| {
+            config.appearance.animation_easing = match value.keyword() {
+                "linear" => Easing::Linear,
+                "ease-in" => Easing::EaseIn,
+                "ease-in-out" => Easing::EaseInOut,
+                _ => Easing::EaseOut,
+            }
+        },
+        get: |config| {
+            Some(SettingValue::Keyword(
+                match config.appearance.animation_easing {
+                    Easing::Linear => "linear",
+                    Easing::EaseIn => "ease-in",
+                    Easing::EaseOut => "ease-out",
+                    Easing::EaseInOut => "ease-in-out",
+                },
+            ))
+        },
+    },
+    Setting {
+        name: "fade-out",
+        ty: SettingType::Duration(0, 10000),
+        default: Some("0ms"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.fade_out = value.duration(),
+        get: |config| Some(SettingValue::Duration(config.appearance.fade_out)),
+    },
+    Setting {
+        name: "font",
+        ty: SettingType::Text,
+        default: Some("monospace"),
+        section: Some("appearance"),
+        set: |config, value| config.font = value.text().to_owned(),
+        get: |config| Some(SettingValue::Text(&config.font)),
+    },
+    Setting {
+        name: "font-size",
+        ty: SettingType::Length { percent: false },
+        default: Some("16px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.font_size = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.font_size.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "label-color",
+        ty: SettingType::Color,
+        default: Some("#ffffff"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.label_color = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.label_color)),
+    },
+    Setting {
+        name: "label-background",
+        ty: SettingType::Color,
+        default: Some("#000000c0"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.label_background = value.color(),
+        get: |config| Some(SettingValue::Color(config.appearance.label_background)),
+    },
+    Setting {
+        name: "cell-labels",
+        ty: SettingType::Bool,
+        default: Some("false"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.cell_labels = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.appearance.cell_labels)),
+    },
+    Setting {
+        name: "renderer",
+        ty: SettingType::Keyword(&["shm", "single-pixel"]),
+        default: Some("shm"),
+        section: Some("appearance"),
+        set: |config, value| {
+            config.appearance.renderer = match value.keyword() {
+                "single-pixel" => Renderer::SinglePixel,
+                _ => Renderer::Shm,
+            }
+        },
+        get: |config| {
+            Some(SettingValue::Keyword(match config.appearance.renderer {
+                Renderer::Shm => "shm",
+                Renderer::SinglePixel => "single-pixel",
+            }))
+        },
+    },
+    Setting {
+        name: "cursor",
+        ty: SettingType::Keyword(&["default", "crosshair", "hidden"]),
+        default: Some("crosshair"),
+        section: Some("appearance"),
+        set: |config, value| {
+            config.appearance.cursor = match value.keyword() {
+                "default" => Cursor::Default,
+                "hidden" => Cursor::Hidden,
+                _ => Cursor::Crosshair,
+            }
+        },
+        get: |config| {
+            Some(SettingValue::Keyword(match config.appearance.cursor {
+                Cursor::Default => "default",
+                Cursor::Crosshair => "crosshair",
+                Cursor::Hidden => "hidden",
+            }))
+        },
+    },
+    Setting {
+        name: "magnifier-zoom",
+        ty: SettingType::Integer(0, 32),
+        default: Some("0"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.magnifier_zoom = value.integer(),
+        get: |config| Some(SettingValue::Integer(config.appearance.magnifier_zoom)),
+    },
+    Setting {
+        name: "magnifier-size",
+        ty: SettingType::Length { percent: false },
+        default: Some("160px"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.magnifier_size = value.pixels() as f32,
+        get: |config| {
+            Some(SettingValue::Length(Length::Pixels(
+                config.appearance.magnifier_size.into(),
+            )))
+        },
+    },
+    Setting {
+        name: "hud",
+        ty: SettingType::Bool,
+        default: Some("false"),
+        section: Some("appearance"),
+        set: |config, value| config.appearance.hud = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.appearance.hud)),
+    },
+    Setting {
+        name: "sequence-timeout",
+        ty: SettingType::Duration(0, u32::MAX),
+        default: Some("1000ms"),
+        section: None,
+        set: |config, value| config.sequence_timeout = value.duration(),
+        get: |config| Some(SettingValue::Duration(config.sequence_timeout)),
+    },
+    Setting {
+        name: "chord-timeout",
+        ty: SettingType::Duration(1, u32::MAX),
+        default: Some("50ms"),
+        section: None,
+        set: |config, value| config.chord_timeout = value.duration(),
+        get: |config| Some(SettingValue::Duration(config.chord_timeout)),
+    },
+    Setting {
+        name: "scroll-speed",
+        ty: SettingType::Number,
+        default: None,
+        section: None,
+        set: |config, value| {
+            let speed = value.number();
+            config.scroll_speed.vertical = speed;
+            config.scroll_speed.horizontal = speed;
+        },
+        get: |_| None,
+    },
+    Setting {
+        name: "vertical-scroll-speed",
+        ty: SettingType::Number,
+        default: Some("10"),
+        section: None,
+        set: |config, value| config.scroll_speed.vertical = value.number(),
+        get: |config| Some(SettingValue::Number(config.scroll_speed.vertical)),
+    },
+    Setting {
+        name: "horizontal-scroll-speed",
+        ty: SettingType::Number,
+        default: Some("10"),
+        section: None,
+        set: |config, value| config.scroll_speed.horizontal = value.number(),
+        get: |config| Some(SettingValue::Number(config.scroll_speed.horizontal)),
+    },
+    Setting {
+        name: "invert-scroll",
+        ty: SettingType::Keyword(&["none", "vertical", "horizontal", "both"]),
+        default: Some("none"),
+        section: None,
+        set: |config, value| {
+            let axes = value.keyword();
+            config.scroll_speed.invert_vertical = matches!(axes, "vertical" | "both");
+            config.scroll_speed.invert_horizontal = matches!(axes, "horizontal" | "both");
+        },
+        get: |config| {
+            let speed = config.scroll_speed;
+            Some(SettingValue::Keyword(
+                match (speed.invert_vertical, speed.invert_horizontal) {
+                    (false, false) => "none",
+                    (true, false) => "vertical",
+                    (false, true) => "horizontal",
+                    (true, true) => "both",
+                },
+            ))
+        },
+    },
+    Setting {
+        name: "swap-buttons",
+        ty: SettingType::Bool,
+        default: Some("false"),
+        section: None,
+        set: |config, value| config.swap_buttons = value.boolean(),
+        get: |config| Some(SettingValue::Bool(config.swap_buttons)),
+    },
+    Setting {
+        name: "backend",
+        ty: SettingType::Keyword(Backend::NAMES),
+        default: Some("auto"),
+        section: None,
+        set: |config, value| config.backend = Backend::from_name(value.keyword()).unwrap(),
+        get: |config| Some(SettingValue::Keyword(config.backend.name())),
+    },
+    Setting {
+        name: "history-limit",
+        ty: SettingType::Integer(0, u32::MAX),
+        default: Some("100"),
+        section: None,
+        set: |config, value| config.history_limit = value.integer(),
+        get: |config| Some(SettingValue::Integer(config.history_limit)),
+    },
+    Setting {
+        name: "log-level",
+        ty: SettingType::Keyword(&["error", "warn", "info", "debug", "trace"]),
+        default: Some("warn"),
+        section: None,
+        set: |config, value| config.log_level = value.keyword().parse().unwrap(),
+        get: |config| {
+            Some(SettingValue::Keyword(match config.log_level {
+                log::LevelFilter::Off | log::LevelFilter::Error => "error",
+                log::LevelFilter::Warn => "warn",
+                log::LevelFilter::Info => "info",
+                log::LevelFilter::Debug => "debug",
+                log::LevelFilter::Trace => "trace",
+            }))
+        },
+    },
+    Setting {
+        name: "grid",
+        ty: SettingType::IntegerPair(1, 64),
+        default: Some("2 2"),
+        section: None,
+        set: |config, value| {
+            let (cols, rows) = value.integer_pair();
+            config.grid = Grid { cols, rows };
+        },
+        get: |config| {
+            Some(SettingValue::IntegerPair(
+                config.grid.cols,
+                config.grid.rows,
+            ))
+        },
+    },
+    Setting {
+        name: "hint-chars",
+        ty: SettingType::UniqueChars,
+        default: Some("asdfghjkl"),
+        section: None,
+        set: |config, value| config.hints.chars = value.chars(),
+        get: |config| Some(SettingValue::Chars(config.hints.chars.clone())),
+    },
+    Setting {
+        name: "hint-length",
+        ty: SettingType::Integer(1, 8),
+        default: Some("1"),
+        section: None,
+        set: |config, value| config.hints.length = value.integer(),
+        get: |config| Some(SettingValue::Integer(config.hints.length)),
+    },
+    Setting {
+        name: "repeat-rate",
+        ty: SettingType::Integer(0, 1000),
+        default: None,
+        section: None,
+        set: |config, value| config.repeat_rate = Some(value.integer()),
+        get: |config| config.repeat_rate.map(SettingValue::Integer),
+    },
+    Setting {
+        name: "repeat-delay",
+        ty: SettingType::Duration(0, u32::MAX),
+        default: None,
+        section: None,
+        set: |config, value| config.repeat_delay = Some(value.duration()),
+        get: |config| config.repeat_delay.map(SettingValue::Duration),
+    },
+    Setting {
+        name: "move-distance",
+        ty: SettingType::Length { percent: true },
+        default: Some("100%"),
+        section: None,
+        set: |config, value| config.move_distance = value.length(),
+        get: |config| Some(SettingValue::Length(config.move_distance)),
+    },
+    Setting {
+        name: "warp",
+        ty: SettingType::Keyword(&["always", "click", "never"]),
+        default: Some("always"),
+        section: None,
+        set: |config, value| {
+            config.warp = match value.keyword() {
+                "click" => Warp::Click,
+                "never" => Warp::Never,
+                _ => Warp::Always,
+            }
+        },
+        get: |config| {
+            Some(SettingValue::Keyword(match config.warp {
+                Warp::Always => "always",
+                Warp::Click => "click",
+                Warp::Never => "never",
+            }))
+        },
+    },
+];
+
+/// Named sets of appearance settings that the `theme` setting applies.
+const THEMES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "dark",
+        &[
+            ("border-color", "#ffffff"),
+            ("cross-color", "#ffffff40"),
+            ("dim-color", "#00000060"),
+            ("sequence-color", "#ffcc00"),
+            ("label-color", "#ffffff"),
+            ("label-background", "#000000c0"),
+            ("outline", "false"),
+        ],
+    ),
+    (
+        "light",
+        &[
+            ("border-color", "#000000"),
+            ("cross-color", "#00000040"),
+            ("dim-color", "#ffffff60"),
+            ("sequence-color", "#c04000"),
+            ("label-color", "#000000"),
+            ("label-background", "#ffffffc0"),
+            ("outline", "false"),
+        ],
+    ),
+    (
+        "high-contrast",
+        &[
+            ("border-color", "#ffff00"),
+            ("border-thickness", "3px"),
+            ("cross-color", "#ffff00c0"),
+            ("cross-thickness", "2px"),
+            ("dim-color", "#000000a0"),
+            ("sequence-color", "#00ffff"),
+            ("label-color", "#000000"),
+            ("label-background", "#ffff00"),
+            ("outline", "true"),
+        ],
+    ),
+    (
+        // From the Okabe-Ito palette, which stays distinguishable with common color vision
+        // deficiencies.
+        "colorblind-safe",
+        &[
+            ("border-color", "#e69f00"),
+            ("cross-color", "#f0e44260"),
+            ("dim-color", "#00000060"),
+            ("sequence-color", "#56b4e9"),
+            ("label-color", "#000000"),
+            ("label-background", "#e69f00d0"),
+            ("outline", "false"),
+        ],
+    ),
+];
+
+/// What a binding is triggered by.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Trigger {
+    Key(Mods, xkb::Keysym),
+    /// A pointer button, by code.
+    Button(u32),
+    /// Two or more keys pressed one after another.
+    Sequence(Vec<(Mods, xkb::Keysym)>),
+    /// Two keys pressed together, with the keysyms in ascending order.
+    Chord(Mods, xkb::Keysym, xkb::Keysym),
 }
 
 impl Button {
+    /// Parses the name of a pointer button in a binding, like `mouse-left`.
+    fn from_binding_name(s: &str) -> Option<Button> {
+        match s {
+            "mouse-left" => Some(Button::Left),
+            "mouse-right" => Some(Button::Right),
+            "mouse-middle" => Some(Button::Middle),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::Middle => "middle",
+        }
+    }
+
     pub(crate) fn code(self) -> u32 {
         const BTN_LEFT: u32 = 0x110;
         const BTN_RIGHT: u32 = 0x111;
@@ -88,10 +1352,11 @@ impl Cmd {
             "move-down" => Some(Cmd::Move(Direction::Down)),
             "move-left" => Some(Cmd::Move(Direction::Left)),
             "move-right" => Some(Cmd::Move(Direction::Right)),
-            "scroll-up" => Some(Cmd::Scroll(WL_POINTER_AXIS_VERTICAL_SCROLL, -10.0)),
-            "scroll-down" => Some(Cmd::Scroll(WL_POINTER_AXIS_VERTICAL_SCROLL, 10.0)),
-            "scroll-left" => Some(Cmd::Scroll(WL_POINTER_AXIS_HORIZONTAL_SCROLL, -10.0)),
-            "scroll-right" => Some(Cmd::Scroll(WL_POINTER_AXIS_HORIZONTAL_SCROLL, 10.0)),
+            "scroll-up" => Some(Cmd::Scroll(WL_POINTER_AXIS_VERTICAL_SCROLL, -1.0)),
+            "scroll-down" => Some(Cmd::Scroll(WL_POINTER_AXIS_VERTICAL_SCROLL, 1.0)),
+            "scroll-left" => Some(Cmd::Scroll(WL_POINTER_AXIS_HORIZONTAL_SCROLL, -1.0)),
+            "scroll-right" => Some(Cmd::Scroll(WL_POINTER_AXIS_HORIZONTAL_SCROLL, 1.0)),
+            "hint" => Some(Cmd::Hint),
             _ => None,
         }
     }
@@ -128,179 +1393,1608 @@ impl Mods {
     }
 }
 
-impl Config {
-    pub(crate) fn load() -> Result<Config> {
-        let text = std::env::var_os("XDG_CONFIG_HOME")
-            .map(PathBuf::from)
-            .or_else(|| {
-                let home = PathBuf::from(std::env::var_os("HOME")?);
-                Some(home.join(".config"))
-            })
-            .map(|path| path.join("waypoint/config"))
-            .map(std::fs::read_to_string)
-            .and_then(Result::ok)
-            .unwrap_or_else(|| include_str!("../default_config").to_owned());
-        Config::parse(&text)
-    }
-
-    fn parse(s: &str) -> Result<Config> {
-        let directives = scfg::parse(s).context("invalid config")?;
-        let mut bindings = HashMap::new();
-        for directive in &directives {
-            match directive.name.as_str() {
-                "bindings" => {
-                    ensure!(
-                        directive.params.is_empty(),
-                        "invalid config: line {}: too many parameters to directive 'bindings'",
-                        directive.line,
-                    );
-
-                    for binding in &directive.children {
-                        let cmd_names: Vec<String> = if binding.params.is_empty() {
-                            let mut cmd_names = Vec::new();
-                            for binding_cmd in &binding.children {
-                                ensure!(
-                                    binding_cmd.params.is_empty(),
-                                    "invalid config: line {}: binding with command should not have extra parameters",
-                                    binding_cmd.line,
-                                );
-
-                                cmd_names.push(binding_cmd.name.clone());
-                            }
-                            cmd_names
-                        } else {
-                            ensure!(
-                                binding.children.is_empty(),
-                                "invalid config: line {}: binding with command should not have block",
-                                binding.line,
-                            );
-
-                            ensure!(
-                                binding.params.len() == 1,
-                                "invalid config: line {}: binding with command should have exactly one parameter",
-                                binding.line,
-                            );
-
-                            binding.params.clone()
-                        };
-
-                        let keys = &binding.name;
-                        let mut cmds = Vec::new();
+/// An error at a location in a config file.
+#[derive(Debug)]
+pub(crate) struct ConfigError {
+    span: Span,
+    message: String,
+    /// The offending line with the location underlined, once the config text is known.
+    snippet: Option<String>,
+}
 
-                        for cmd_name in cmd_names {
-                            let Some(cmd) = Cmd::from_kebab_case(&cmd_name) else {
-                                bail!(
-                                    "invalid config: line {}: invalid command {:?}",
-                                    binding.line,
-                                    cmd_name,
-                                );
-                            };
-                            cmds.push(cmd);
-                        }
+impl ConfigError {
+    fn at(span: &Span, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            span: span.clone(),
+            message: message.into(),
+            snippet: None,
+        }
+    }
+}
 
-                        let mut modifiers = Mods::empty();
-                        let mut keysym = None;
-
-                        for element in keys.split('+') {
-                            match Mods::one_from_str(element) {
-                                Some(modifier) => {
-                                    let old_modifiers = modifiers;
-                                    modifiers |= modifier;
-                                    ensure!(
-                                        old_modifiers != modifiers,
-                                        "invalid config: line {}: duplicate modifier {:?}",
-                                        binding.line,
-                                        element,
-                                    );
-                                }
-                                None => {
-                                    let parsed_keysym = xkb::keysym_from_name(
-                                        element,
-                                        xkb::KEYSYM_CASE_INSENSITIVE,
-                                    );
-                                    ensure!(
-                                        parsed_keysym != xkb::KEY_NoSymbol,
-                                        "invalid config: line {}: invalid key {:?}",
-                                        binding.line,
-                                        element,
-                                    );
-                                    ensure!(
-                                        keysym.is_none(),
-                                        "invalid config: line {}: too many keys",
-                                        binding.line,
-                                    );
-                                    keysym = Some(parsed_keysym);
-                                }
-                            }
-                        }
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid config: line {}: {}",
+            self.span.line + 1,
+            self.message,
+        )?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n{snippet}")?;
+        }
+        Ok(())
+    }
+}
 
-                        let keysym = keysym
-                            .context(format!("invalid config: line {}: no key", binding.line))?;
+impl std::error::Error for ConfigError {}
 
-                        bindings.insert((modifiers, keysym), cmds);
-                    }
-                }
-                _ => {
-                    bail!(
-                        "invalid config: line {}, invalid directive {:?}",
-                        directive.line,
-                        directive.name,
-                    );
+impl Config {
+    /// Loads the default config with the config files at `paths` on top of it in order, applying
+    /// the blocks of `profile` if one is given. Files that don't exist are skipped, and the
+    /// default config is too if any of the files has `use-defaults false`.
+    pub(crate) fn load(profile: Option<String>, paths: &[PathBuf]) -> Result<Config> {
+        let mut texts = Vec::new();
+        for path in paths {
+            match std::fs::read_to_string(path) {
+                Ok(text) => texts.push((path, text)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("failed to read config {}", path.display()));
                 }
             }
         }
-        Ok(Config { bindings })
+        let use_defaults = !texts
+            .iter()
+            .any(|(_, text)| disables_defaults(text, profile.as_deref()));
+        let mut config = if use_defaults {
+            Config::defaults(profile)?
+        } else {
+            Config {
+                profile,
+                ..Config::empty()
+            }
+        };
+        for (path, text) in &texts {
+            config
+                .apply_layer(text, Some(path))
+                .with_context(|| format!("failed to load config {}", path.display()))?;
+        }
+        if let (Some(profile), false) = (&config.profile, config.profile_found) {
+            bail!("profile {:?} is not defined in the config", profile);
+        }
+        config.resolve_output_appearances();
+        config.validate()?;
+        Ok(config)
     }
-}
 
-pub(crate) fn specialize_bindings(
-    keymap: &xkb::Keymap,
-    config: &Config,
-) -> (ModIndices, HashMap<(xkb::ModMask, xkb::Keycode), Vec<Cmd>>) {
-    let state = xkb::State::new(keymap);
-    let mod_indices = ModIndices {
-        shift: keymap.mod_get_index(xkb::MOD_NAME_SHIFT),
-        caps: keymap.mod_get_index(xkb::MOD_NAME_CAPS),
-        ctrl: keymap.mod_get_index(xkb::MOD_NAME_CTRL),
-        alt: keymap.mod_get_index(xkb::MOD_NAME_ALT),
-        num: keymap.mod_get_index(xkb::MOD_NAME_NUM),
-        mod3: keymap.mod_get_index("Mod3"),
-        logo: keymap.mod_get_index(xkb::MOD_NAME_LOGO),
-        mod5: keymap.mod_get_index("Mod5"),
-    };
+    /// Returns the built-in default config, with the blocks of `profile` applied if one is
+    /// given.
+    fn defaults(profile: Option<String>) -> Result<Config> {
+        let mut config = Config::empty();
+        config.profile = profile;
+        config
+            .apply(include_str!("../default_config"))
+            .context("failed to parse default config")?;
+        config.default_bindings = config.layer_bindings.keys().cloned().collect();
+        Ok(config)
+    }
 
-    let specialized = config
-        .bindings
-        .iter()
-        .flat_map(|(&(modifiers, keysym), cmds)| {
-            let mut keycodes = Vec::new();
+    #[cfg(test)]
+    fn parse(s: &str) -> Result<Config> {
+        let mut config = Config::empty();
+        config.apply(s)?;
+        config.resolve_output_appearances();
+        config.validate()?;
+        Ok(config)
+    }
 
-            keymap.key_for_each(|_, keycode| {
-                let got_keysym = state.key_get_one_sym(keycode);
-                if got_keysym != xkb::KEY_NoSymbol && got_keysym == keysym {
-                    keycodes.push(keycode);
-                }
-            });
+    /// Returns a config without bindings, with settings at their defaults.
+    fn empty() -> Config {
+        let mut config = Config {
+            modes: vec![Mode {
+                name: "default".to_owned(),
+                defined: true,
+                ..Mode::default()
+            }],
+            appearance: Appearance {
+                border_color: Color::TRANSPARENT,
+                border_thickness: 0.0,
+                border_dash: 0.0,
+                border_dash_color: Color::TRANSPARENT,
+                glow: 0.0,
+                glow_color: Color::TRANSPARENT,
+                cross_color: Color::TRANSPARENT,
+                cross_thickness: 0.0,
+                guides: Guides::default(),
+                center_dot: 0.0,
+                center_dot_color: Color::TRANSPARENT,
+                center_ring_color: Color::TRANSPARENT,
+                dim_color: Color::TRANSPARENT,
+                fill_color: Color::TRANSPARENT,
+                sequence_color: Color::TRANSPARENT,
+                animate: false,
+                animation_duration: 0,
+                animation_easing: Easing::default(),
+                fade_out: 0,
+                font_size: 0.0,
+                label_color: Color::TRANSPARENT,
+                label_background: Color::TRANSPARENT,
+                cell_labels: false,
+                renderer: Renderer::default(),
+                cursor: Cursor::default(),
+                magnifier_zoom: 0,
+                magnifier_size: 0.0,
+                hud: false,
+                outline: false,
+                anti_alias: false,
+                pixel_snap: false,
+            },
+            scroll_speed: ScrollSpeed::default(),
+            initial_region: InitialRegion::default(),
+            grid: Grid::default(),
+            hints: Hints::default(),
+            warp: Warp::default(),
+            font: String::new(),
+            swap_buttons: false,
+            backend: Backend::default(),
+            log_level: log::LevelFilter::Off,
+            history_limit: 0,
+            sequence_timeout: 0,
+            chord_timeout: 0,
+            repeat_rate: None,
+            repeat_delay: None,
+            move_distance: Length::Pixels(0.0),
+            strict: false,
+            profile: None,
+            profile_found: false,
+            warnings: Vec::new(),
+            layer_bindings: HashMap::new(),
+            default_bindings: HashSet::new(),
+            binding_locations: HashMap::new(),
+            aliases: HashMap::new(),
+            output_appearances: Vec::new(),
+            resolved_output_appearances: HashMap::new(),
+        };
+        for setting in SETTINGS {
+            if let Some(default) = setting.default {
+                let value = setting.ty.parse(default).expect("invalid setting default");
+                (setting.set)(&mut config, value);
+            }
+        }
+        config
+    }
 
-            let mod_index_array: &[xkb::ModMask; 8] = bytemuck::cast_ref(&mod_indices);
+    /// Applies a config file on top of the current configuration.
+    fn apply(&mut self, s: &str) -> Result<()> {
+        self.apply_layer(s, None)
+    }
 
-            let mod_mask: xkb::ModMask = modifiers
-                .into_iter()
-                .map(|modifier| 1 << mod_index_array[modifier.bits().trailing_zeros() as usize])
-                .fold(0, |acc, it| acc | it);
+    /// Applies a config file and everything it includes as one layer, in which bindings may
+    /// override those of earlier layers but not each other.
+    fn apply_layer(&mut self, s: &str, path: Option<&Path>) -> Result<()> {
+        self.layer_bindings.clear();
+        let mut includes = path
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.to_owned()))
+            .into_iter()
+            .collect();
+        self.apply_in(s, path, &mut includes)
+    }
 
-            keycodes
-                .into_iter()
-                .map(move |keycode| ((mod_mask, keycode), cmds.clone()))
+    /// Like `apply`, with `path` being the file `s` was read from, used to resolve relative
+    /// includes, and `includes` being the chain of files currently being applied.
+    fn apply_in(
+        &mut self,
+        s: &str,
+        path: Option<&Path>,
+        includes: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        self.apply_directives(s, path, includes).map_err(|mut err| {
+            if let Some(err) = err.downcast_mut::<ConfigError>() {
+                if err.snippet.is_none() {
+                    err.snippet = snippet(s, &err.span);
+                }
+            }
+            err
         })
-        .collect();
+    }
 
-    (mod_indices, specialized)
-}
+    fn apply_directives(
+        &mut self,
+        s: &str,
+        path: Option<&Path>,
+        includes: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let parsed = scfg::parse(s).map_err(|err| {
+            let span = Span {
+                line: err.line,
+                start: err.column,
+                end: err.column,
+            };
+            ConfigError::at(&span, format!("expected {:?}", err.expected))
+        })?;
+        let mut directives = Vec::new();
+        for directive in &parsed {
+            if directive.name != "profile" {
+                directives.push(directive);
+                continue;
+            }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            ensure!(
+                directive.params.len() == 1,
+                ConfigError::at(
+                    &directive.span,
+                    "directive 'profile' should have exactly one parameter"
+                )
+            );
+            if let Some(child) = directive
+                .children
+                .iter()
+                .find(|child| child.name == "profile")
+            {
+                bail!(ConfigError::at(&child.span, "profiles can't be nested"));
+            }
+
+            if self.profile.as_ref() == Some(&directive.params[0]) {
+                self.profile_found = true;
+                directives.extend(&directive.children);
+            }
+        }
+        if directives
+            .iter()
+            .any(|directive| directive.name == "strict")
+        {
+            self.strict = true;
+        }
+        for &directive in &directives {
+            let renamed_directive;
+            let directive =
+                match find_renamed(RENAMED_DIRECTIVES, &directive.name, &directive.params) {
+                    Some(renamed) => {
+                        self.warn_renamed(renamed, path, directive.line);
+                        let params = renamed.old.len() - 1;
+                        renamed_directive = scfg::Directive {
+                            name: renamed.new.to_owned(),
+                            params: directive.params[params..].to_vec(),
+                            param_spans: directive.param_spans[params..].to_vec(),
+                            ..directive.clone()
+                        };
+                        &renamed_directive
+                    }
+                    None => directive,
+                };
+            match directive.name.as_str() {
+                "bindings" => self.parse_bindings(directive, DEFAULT_MODE, path)?,
+                "unbind" => self.parse_unbind(directive, DEFAULT_MODE)?,
+                "mode" => {
+                    ensure!(
+                        directive.params.len() == 1,
+                        ConfigError::at(
+                            &directive.span,
+                            "directive 'mode' should have exactly one parameter"
+                        )
+                    );
+
+                    let mode = self.mode_id(&directive.params[0]);
+                    ensure!(
+                        mode != DEFAULT_MODE,
+                        ConfigError::at(
+                            &directive.param_spans[0],
+                            format!(
+                                "top-level bindings already belong to mode {:?}",
+                                directive.params[0]
+                            )
+                        )
+                    );
+                    self.modes[mode].defined = true;
+
+                    for child in &directive.children {
+                        match child.name.as_str() {
+                            "bindings" => self.parse_bindings(child, mode, path)?,
+                            "unbind" => self.parse_unbind(child, mode)?,
+                            _ => bail!(ConfigError::at(
+                                &child.span,
+                                format!("invalid directive {:?} in mode block", child.name)
+                            )),
+                        }
+                    }
+                }
+                "include" => {
+                    ensure!(
+                        directive.children.is_empty(),
+                        ConfigError::at(
+                            &directive.span,
+                            "directive 'include' should not have block"
+                        )
+                    );
+
+                    ensure!(
+                        directive.params.len() == 1,
+                        ConfigError::at(
+                            &directive.span,
+                            "directive 'include' should have exactly one parameter"
+                        )
+                    );
+
+                    let include_path =
+                        expand_path(&directive.params[0], |name| std::env::var_os(name))
+                            .with_context(|| {
+                                ConfigError::at(
+                                    &directive.param_spans[0],
+                                    format!("invalid path {:?}", directive.params[0]),
+                                )
+                            })?;
+                    let include_path = match path.and_then(Path::parent) {
+                        Some(dir) => dir.join(include_path),
+                        None => include_path,
+                    };
+                    self.include(&include_path, includes).with_context(|| {
+                        ConfigError::at(
+                            &directive.span,
+                            format!("failed to include {}", include_path.display()),
+                        )
+                    })?;
+                }
+                "no-default-bindings" | "strict" => {
+                    ensure!(
+                        directive.params.is_empty() && directive.children.is_empty(),
+                        ConfigError::at(
+                            &directive.span,
+                            format!("directive {:?} takes no parameters", directive.name)
+                        )
+                    );
+                    if directive.name == "no-default-bindings" {
+                        self.clear_default_bindings();
+                    }
+                }
+                // Whether it's false is checked before the default config is loaded.
+                "use-defaults" => {
+                    let valid = matches!(
+                        directive.params.as_slice(),
+                        [value] if value == "true" || value == "false"
+                    );
+                    ensure!(
+                        directive.children.is_empty() && valid,
+                        ConfigError::at(
+                            &directive.span,
+                            "directive 'use-defaults' should have 'true' or 'false' as its parameter"
+                        )
+                    );
+                }
+                "initial-region" => {
+                    ensure!(
+                        directive.children.is_empty(),
+                        ConfigError::at(
+                            &directive.span,
+                            "directive 'initial-region' should not have block"
+                        )
+                    );
+
+                    self.initial_region = match directive.params.as_slice() {
+                        [kind] if kind == "desktop" => InitialRegion::Desktop,
+                        [kind, name] if kind == "output" => InitialRegion::Output(name.clone()),
+                        [_, _, _, _] => {
+                            let mut coords = [0; 4];
+                            for ((coord, param), span) in coords
+                                .iter_mut()
+                                .zip(&directive.params)
+                                .zip(&directive.param_spans)
+                            {
+                                *coord = param.parse().ok().with_context(|| {
+                                    ConfigError::at(span, format!("invalid coordinate {:?}", param))
+                                })?;
+                            }
+                            let [x, y, width, height] = coords;
+                            ensure!(
+                                width > 0 && height > 0,
+                                ConfigError::at(&directive.span, "initial region must not be empty")
+                            );
+                            InitialRegion::Region(Region {
+                                x,
+                                y,
+                                width,
+                                height,
+                            })
+                        }
+                        _ => bail!(ConfigError::at(
+                            &directive.span,
+                            "directive 'initial-region' should be 'desktop', 'output <name>' or '<x> <y> <width> <height>'",
+                        )),
+                    };
+                }
+                name if SETTINGS
+                    .iter()
+                    .any(|setting| setting.name == name && setting.section.is_none()) =>
+                {
+                    self.apply_setting(directive, None)?;
+                }
+                "settings" => {
+                    ensure!(
+                        directive.params.is_empty(),
+                        ConfigError::at(
+                            &directive.span,
+                            "too many parameters to directive 'settings'"
+                        )
+                    );
+
+                    for setting in &directive.children {
+                        self.apply_setting(setting, None)?;
+                    }
+                }
+                "alias" => {
+                    let Some((name, params)) = directive.params.split_first() else {
+                        bail!(ConfigError::at(
+                            &directive.span,
+                            "directive 'alias' should have a name"
+                        ));
+                    };
+                    ensure!(
+                        !is_cmd_name(name),
+                        ConfigError::at(
+                            &directive.param_spans[0],
+                            format!("alias {:?} would replace a built-in command", name)
+                        )
+                    );
+
+                    let cmds = if params.is_empty() {
+                        let mut cmds = Vec::new();
+                        for child in &directive.children {
+                            cmds.extend(self.parse_child_cmds(child, path)?);
+                        }
+                        cmds
+                    } else {
+                        ensure!(
+                            directive.children.is_empty(),
+                            ConfigError::at(
+                                &directive.span,
+                                "alias with commands should not have block"
+                            )
+                        );
+                        self.parse_cmds(params, &directive.param_spans[1..], path)?
+                    };
+                    ensure!(
+                        !cmds.is_empty(),
+                        ConfigError::at(
+                            &directive.param_spans[0],
+                            format!("alias {:?} should have at least one command", name)
+                        )
+                    );
+                    self.aliases.insert(name.clone(), cmds);
+                }
+                "appearance" => {
+                    ensure!(
+                        directive.params.is_empty(),
+                        ConfigError::at(
+                            &directive.span,
+                            "too many parameters to directive 'appearance'"
+                        )
+                    );
+
+                    for option in &directive.children {
+                        self.apply_setting(option, Some("appearance"))?;
+                    }
+                }
+                "output" => {
+                    ensure!(
+                        directive.params.len() == 1,
+                        ConfigError::at(
+                            &directive.span,
+                            "directive 'output' should have exactly one parameter"
+                        )
+                    );
+
+                    // Check the settings now, so mistakes are reported with the rest.
+                    for option in &directive.children {
+                        ensure!(
+                            option.name != "font",
+                            ConfigError::at(&option.span, "the font can't differ between outputs")
+                        );
+                        Config::parse_setting(option, Some("appearance"))?;
+                    }
+                    self.output_appearances
+                        .push((directive.params[0].clone(), directive.children.clone()));
+                }
+                _ => {
+                    bail!(ConfigError::at(
+                        &directive.span,
+                        format!("invalid directive {:?}", directive.name)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_bindings(
+        &mut self,
+        directive: &scfg::Directive,
+        mode: ModeId,
+        path: Option<&Path>,
+    ) -> Result<()> {
+        ensure!(
+            directive.params.is_empty(),
+            ConfigError::at(
+                &directive.span,
+                "too many parameters to directive 'bindings'"
+            )
+        );
+
+        for binding in &directive.children {
+            // Parameters before the first command are further keys of a sequence.
+            let sequence_len = binding
+                .params
+                .iter()
+                .take_while(|param| !is_cmd_name(param) && !self.aliases.contains_key(*param))
+                .count();
+            let (keys, cmd_params) = binding.params.split_at(sequence_len);
+            let (key_spans, cmd_spans) = binding.param_spans.split_at(sequence_len);
+
+            let cmds = if cmd_params.is_empty() {
+                if let (Some(last), Some(span), true) =
+                    (keys.last(), key_spans.last(), binding.children.is_empty())
+                {
+                    bail!(ConfigError::at(span, format!("invalid command {:?}", last)));
+                }
+
+                let mut cmds = Vec::new();
+                for binding_cmd in &binding.children {
+                    cmds.extend(self.parse_child_cmds(binding_cmd, path)?);
+                }
+                cmds
+            } else {
+                ensure!(
+                    binding.children.is_empty(),
+                    ConfigError::at(&binding.span, "binding with command should not have block")
+                );
+
+                self.parse_cmds(cmd_params, cmd_spans, path)?
+            };
+
+            let trigger = if keys.is_empty() {
+                parse_trigger(&binding.name)
+                    .map_err(|err| ConfigError::at(&binding.span, err.to_string()))?
+            } else {
+                let mut sequence = Vec::new();
+                for (key, span) in std::iter::once((&binding.name, &binding.span))
+                    .chain(keys.iter().zip(key_spans))
+                {
+                    sequence.push(
+                        parse_key(key).map_err(|err| ConfigError::at(span, err.to_string()))?,
+                    );
+                }
+                Trigger::Sequence(sequence)
+            };
+            let name = std::iter::once(&binding.name)
+                .chain(keys)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let location = location(path, binding.line);
+            if let Some(previous) = self
+                .layer_bindings
+                .insert((mode, trigger.clone()), location.clone())
+            {
+                let message = format!(
+                    "binding for {:?} in mode {:?} overrides binding on {previous}",
+                    name, self.modes[mode].name,
+                );
+                ensure!(!self.strict, ConfigError::at(&binding.span, message));
+                self.warnings.push(format!("{location}: {message}"));
+            }
+            self.default_bindings.remove(&(mode, trigger.clone()));
+            self.binding_locations
+                .insert((mode, trigger.clone()), location);
+            match trigger {
+                Trigger::Key(modifiers, keysym) => {
+                    self.modes[mode].bindings.insert((modifiers, keysym), cmds);
+                }
+                Trigger::Button(button) => {
+                    self.modes[mode].button_bindings.insert(button, cmds);
+                }
+                Trigger::Sequence(keys) => {
+                    self.modes[mode].sequences.insert(keys, cmds);
+                }
+                Trigger::Chord(modifiers, first, second) => {
+                    self.modes[mode]
+                        .chords
+                        .insert((modifiers, first, second), cmds);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_unbind(&mut self, directive: &scfg::Directive, mode: ModeId) -> Result<()> {
+        ensure!(
+            directive.children.is_empty(),
+            ConfigError::at(&directive.span, "directive 'unbind' should not have block")
+        );
+
+        ensure!(
+            !directive.params.is_empty(),
+            ConfigError::at(
+                &directive.span,
+                "directive 'unbind' should have at least one parameter"
+            )
+        );
+
+        for (keys, span) in directive.params.iter().zip(&directive.param_spans) {
+            let trigger =
+                parse_trigger(keys).map_err(|err| ConfigError::at(span, err.to_string()))?;
+            self.remove_binding(mode, &trigger);
+            self.layer_bindings.remove(&(mode, trigger.clone()));
+            self.default_bindings.remove(&(mode, trigger));
+        }
+
+        Ok(())
+    }
+
+    fn remove_binding(&mut self, mode: ModeId, trigger: &Trigger) {
+        match trigger {
+            Trigger::Key(modifiers, keysym) => {
+                self.modes[mode].bindings.remove(&(*modifiers, *keysym));
+            }
+            Trigger::Button(button) => {
+                self.modes[mode].button_bindings.remove(button);
+            }
+            Trigger::Sequence(keys) => {
+                self.modes[mode].sequences.remove(keys);
+            }
+            Trigger::Chord(modifiers, first, second) => {
+                self.modes[mode]
+                    .chords
+                    .remove(&(*modifiers, *first, *second));
+            }
+        }
+    }
+
+    /// Removes the bindings of the built-in default config, leaving those of other layers.
+    fn clear_default_bindings(&mut self) {
+        for (mode, trigger) in std::mem::take(&mut self.default_bindings) {
+            self.remove_binding(mode, &trigger);
+        }
+    }
+
+    /// Parses commands written one after another, like `left-click quit`, expanding aliases.
+    fn parse_cmds(
+        &mut self,
+        params: &[String],
+        spans: &[Span],
+        path: Option<&Path>,
+    ) -> Result<Vec<Cmd>> {
+        let mut cmds = Vec::new();
+        let mut rest_params = params;
+        while let [name, rest @ ..] = rest_params {
+            let span = &spans[params.len() - rest_params.len()];
+            let (name, rest) = match find_renamed(RENAMED_COMMANDS, name, rest) {
+                Some(renamed) => {
+                    self.warn_renamed(renamed, path, span.line);
+                    (renamed.new, &rest_params[renamed.old.len()..])
+                }
+                None => (name.as_str(), rest),
+            };
+            if let Some(alias) = self.aliases.get(name) {
+                cmds.extend_from_slice(alias);
+                rest_params = rest;
+                continue;
+            }
+            let arity = cmd_arity(name).min(rest.len());
+            cmds.push(self.parse_cmd(name, &rest[..arity], span)?);
+            rest_params = &rest[arity..];
+        }
+        Ok(cmds)
+    }
+
+    /// Parses the commands on a line of a block, like the block of a binding.
+    fn parse_child_cmds(
+        &mut self,
+        child: &scfg::Directive,
+        path: Option<&Path>,
+    ) -> Result<Vec<Cmd>> {
+        ensure!(
+            child.children.is_empty(),
+            ConfigError::at(&child.span, "command should not have block")
+        );
+        let params = std::iter::once(&child.name)
+            .chain(&child.params)
+            .cloned()
+            .collect::<Vec<_>>();
+        let spans = std::iter::once(&child.span)
+            .chain(&child.param_spans)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.parse_cmds(&params, &spans, path)
+    }
+
+    /// Warns that a config uses the old name of something that was renamed.
+    fn warn_renamed(&mut self, renamed: &Renamed, path: Option<&Path>, line: usize) {
+        self.warnings.push(format!(
+            "{}: {:?} is deprecated and will be removed, use {:?} instead",
+            location(path, line),
+            renamed.old.join(" "),
+            renamed.new,
+        ));
+    }
+
+    fn parse_cmd(&mut self, name: &str, args: &[String], span: &Span) -> Result<Cmd> {
+        if name == "mode" {
+            ensure!(
+                args.len() == 1,
+                ConfigError::at(span, "command 'mode' should have exactly one parameter")
+            );
+            let mode = self.mode_id(&args[0]);
+            self.modes[mode].referenced_on.get_or_insert(span.line);
+            return Ok(Cmd::Mode(mode));
+        }
+
+        if name == "cell" {
+            let index = match args {
+                [index] => index.parse::<u32>().ok().filter(|&index| index >= 1),
+                _ => None,
+            };
+            let index = index.with_context(|| {
+                ConfigError::at(
+                    span,
+                    "command 'cell' should have a cell number starting at 1 as its parameter",
+                )
+            })?;
+            return Ok(Cmd::Cell(index - 1));
+        }
+
+        ensure!(
+            args.is_empty(),
+            ConfigError::at(
+                span,
+                format!("command {:?} should not have parameters", name)
+            )
+        );
+
+        Cmd::from_kebab_case(name)
+            .with_context(|| ConfigError::at(span, format!("invalid command {:?}", name)))
+    }
+
+    /// Applies a directive naming a setting from `SETTINGS`. If `section` is given, only settings
+    /// belonging to that block are accepted.
+    fn apply_setting(&mut self, directive: &scfg::Directive, section: Option<&str>) -> Result<()> {
+        let (setting, value) = Config::parse_setting(directive, section)?;
+        (setting.set)(self, value);
+        Ok(())
+    }
+
+    /// Finds the setting a directive names and parses its value, like `apply_setting`.
+    fn parse_setting<'a>(
+        directive: &'a scfg::Directive,
+        section: Option<&str>,
+    ) -> Result<(&'static Setting, SettingValue<'a>)> {
+        let setting = SETTINGS
+            .iter()
+            .find(|setting| {
+                setting.name == directive.name && (section.is_none() || setting.section == section)
+            })
+            .with_context(|| {
+                ConfigError::at(
+                    &directive.span,
+                    format!(
+                        "invalid {} option {:?}",
+                        section.unwrap_or("settings"),
+                        directive.name
+                    ),
+                )
+            })?;
+
+        let arity = setting.ty.arity();
+        ensure!(
+            directive.children.is_empty() && directive.params.len() == arity,
+            ConfigError::at(
+                &directive.span,
+                format!(
+                    "setting {:?} should have exactly {} parameter{}",
+                    setting.name,
+                    ["one", "two"][arity - 1],
+                    if arity == 1 { "" } else { "s" },
+                )
+            )
+        );
+
+        let params = directive
+            .params
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let value = setting.ty.parse_params(&params).with_context(|| {
+            let (first, last) = (&directive.param_spans[0], &directive.param_spans[arity - 1]);
+            ConfigError::at(
+                &Span {
+                    end: last.end,
+                    ..first.clone()
+                },
+                format!(
+                    "invalid value {:?} for setting {:?}, should be {}",
+                    params.join(" "),
+                    setting.name,
+                    setting.ty.describe()
+                ),
+            )
+        })?;
+        Ok((setting, value))
+    }
+
+    /// Applies the settings of a theme from `THEMES`.
+    fn apply_theme(&mut self, name: &str) {
+        let (_, settings) = THEMES
+            .iter()
+            .find(|&&(theme, _)| theme == name)
+            .expect("unknown theme");
+        for &(name, value) in *settings {
+            let setting = SETTINGS
+                .iter()
+                .find(|setting| setting.name == name)
+                .expect("unknown theme setting");
+            let value = setting.ty.parse(value).expect("invalid theme setting");
+            (setting.set)(self, value);
+        }
+    }
+
+    /// Returns the appearance of an output, with the settings of `output` blocks naming it
+    /// applied.
+    pub(crate) fn output_appearance(&self, name: &str) -> Appearance {
+        self.resolved_output_appearances
+            .get(name)
+            .copied()
+            .unwrap_or(self.appearance)
+    }
+
+    /// Works out the appearance of each output named in `output` blocks, applying their settings
+    /// over the final `appearance`.
+    fn resolve_output_appearances(&mut self) {
+        let appearance = self.appearance;
+        let output_appearances = std::mem::take(&mut self.output_appearances);
+        for (name, options) in &output_appearances {
+            self.appearance = self.output_appearance(name);
+            for option in options {
+                self.apply_setting(option, Some("appearance"))
+                    .expect("output settings were checked when parsed");
+            }
+            self.resolved_output_appearances
+                .insert(name.clone(), self.appearance);
+            self.appearance = appearance;
+        }
+        self.output_appearances = output_appearances;
+    }
+
+    /// Returns the name of a mode.
+    pub(crate) fn mode_name(&self, mode: ModeId) -> &str {
+        &self.modes[mode].name
+    }
+
+    /// Returns the commands bound to a pointer button in a mode.
+    pub(crate) fn button_binding(&self, mode: ModeId, button: u32) -> Option<&[Cmd]> {
+        self.modes
+            .get(mode)?
+            .button_bindings
+            .get(&button)
+            .map(Vec::as_slice)
+    }
+
+    /// Returns the code of the button a command acts on, with `swap-buttons` applied.
+    pub(crate) fn button_code(&self, button: Button) -> u32 {
+        let button = match (self.swap_buttons, button) {
+            (true, Button::Left) => Button::Right,
+            (true, Button::Right) => Button::Left,
+            (_, button) => button,
+        };
+        button.code()
+    }
+
+    /// Returns whether any pointer buttons are bound, in which case pointer input is taken.
+    pub(crate) fn has_button_bindings(&self) -> bool {
+        self.modes
+            .iter()
+            .any(|mode| !mode.button_bindings.is_empty())
+    }
+
+    /// Writes the config as it ends up after applying every file, in the config format, with
+    /// comments on how many keys each mode binds once resolved against `keymap`.
+    pub(crate) fn dump(&self, out: &mut impl fmt::Write, keymap: &xkb::Keymap) -> fmt::Result {
+        if let Some(profile) = &self.profile {
+            writeln!(out, "# profile {}", scfg::quote(profile))?;
+        }
+        if self.strict {
+            writeln!(out, "strict")?;
+        }
+        for setting in SETTINGS.iter().filter(|setting| setting.section.is_none()) {
+            if let Some(value) = (setting.get)(self) {
+                writeln!(out, "{} {value}", setting.name)?;
+            }
+        }
+        match &self.initial_region {
+            InitialRegion::Desktop => writeln!(out, "initial-region desktop")?,
+            InitialRegion::Output(name) => {
+                writeln!(out, "initial-region output {}", scfg::quote(name))?
+            }
+            InitialRegion::Region(region) => writeln!(
+                out,
+                "initial-region {} {} {} {}",
+                region.x, region.y, region.width, region.height,
+            )?,
+        }
+        let mut aliases = self.aliases.iter().collect::<Vec<_>>();
+        aliases.sort_by_key(|&(name, _)| name);
+        for (name, cmds) in aliases {
+            write!(out, "alias {}", scfg::quote(name))?;
+            for &cmd in cmds {
+                write!(out, " {}", self.cmd_name(cmd))?;
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(out, "appearance {{")?;
+        for setting in SETTINGS.iter().filter(|setting| setting.section.is_some()) {
+            if let Some(value) = (setting.get)(self) {
+                writeln!(out, "    {} {value}", setting.name)?;
+            }
+        }
+        writeln!(out, "}}")?;
+        for (name, options) in &self.output_appearances {
+            writeln!(out, "output {} {{", scfg::quote(name))?;
+            for option in options {
+                write!(out, "    {}", option.name)?;
+                for param in &option.params {
+                    write!(out, " {}", scfg::quote(param))?;
+                }
+                writeln!(out)?;
+            }
+            writeln!(out, "}}")?;
+        }
+
+        let (_, specialized) = specialize_bindings(keymap, self);
+        for (mode_id, mode) in self.modes.iter().enumerate() {
+            let indent = if mode_id == DEFAULT_MODE { "" } else { "    " };
+            writeln!(out)?;
+            writeln!(
+                out,
+                "# {} keys bound in mode {:?} with the default keymap",
+                specialized[mode_id].len(),
+                mode.name,
+            )?;
+            if mode_id != DEFAULT_MODE {
+                writeln!(out, "mode {} {{", scfg::quote(&mode.name))?;
+            }
+            writeln!(out, "{indent}bindings {{")?;
+
+            let key_name = |&(modifiers, keysym): &(Mods, xkb::Keysym)| {
+                let mut name = mods_prefix(modifiers);
+                name.push_str(&xkb::keysym_get_name(keysym));
+                name
+            };
+            let buttons = [Button::Left, Button::Right, Button::Middle];
+            let mut lines = Vec::new();
+            for (key, cmds) in &mode.bindings {
+                lines.push((key_name(key), cmds));
+            }
+            for (&code, cmds) in &mode.button_bindings {
+                let Some(button) = buttons.into_iter().find(|button| button.code() == code) else {
+                    continue;
+                };
+                let name = match button {
+                    Button::Left => "mouse-left",
+                    Button::Right => "mouse-right",
+                    Button::Middle => "mouse-middle",
+                };
+                lines.push((name.to_owned(), cmds));
+            }
+            for (keys, cmds) in &mode.sequences {
+                let name = keys.iter().map(key_name).collect::<Vec<_>>().join(" ");
+                lines.push((name, cmds));
+            }
+            for (&(modifiers, first, second), cmds) in &mode.chords {
+                let name = format!(
+                    "{}{}+{}",
+                    mods_prefix(modifiers),
+                    xkb::keysym_get_name(first),
+                    xkb::keysym_get_name(second),
+                );
+                lines.push((name, cmds));
+            }
+            lines.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+            for (name, cmds) in lines {
+                write!(out, "{indent}    {}", scfg::quote(&name))?;
+                for &cmd in cmds {
+                    write!(out, " {}", self.cmd_name(cmd))?;
+                }
+                writeln!(out)?;
+            }
+            writeln!(out, "{indent}}}")?;
+            if mode_id != DEFAULT_MODE {
+                writeln!(out, "}}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns how a command is written in the config, with its parameters.
+    fn cmd_name(&self, cmd: Cmd) -> String {
+        let button = |button| match button {
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::Middle => "middle",
+        };
+        let direction = |direction| match direction {
+            Direction::Up => "up",
+            Direction::Down => "down",
+            Direction::Left => "left",
+            Direction::Right => "right",
+        };
+        match cmd {
+            Cmd::Quit => "quit".to_owned(),
+            Cmd::Undo => "undo".to_owned(),
+            Cmd::Click(b) => format!("{}-click", button(b)),
+            Cmd::Press(b) => format!("{}-press", button(b)),
+            Cmd::Release(b) => format!("{}-release", button(b)),
+            Cmd::Cut(dir) => format!("cut-{}", direction(dir)),
+            Cmd::Move(dir) => format!("move-{}", direction(dir)),
+            Cmd::Scroll(axis, amount) => {
+                let dir = match (axis == WL_POINTER_AXIS_HORIZONTAL_SCROLL, amount < 0.0) {
+                    (false, true) => Direction::Up,
+                    (false, false) => Direction::Down,
+                    (true, true) => Direction::Left,
+                    (true, false) => Direction::Right,
+                };
+                format!("scroll-{}", direction(dir))
+            }
+            Cmd::Mode(mode) => format!("mode {}", scfg::quote(&self.modes[mode].name)),
+            Cmd::Cell(index) => format!("cell {}", index + 1),
+            Cmd::Hint => "hint".to_owned(),
+        }
+    }
+
+    /// Looks up a mode by name, creating it if it doesn't exist yet.
+    fn mode_id(&mut self, name: &str) -> ModeId {
+        match self.modes.iter().position(|mode| mode.name == name) {
+            Some(mode) => mode,
+            None => {
+                self.modes.push(Mode {
+                    name: name.to_owned(),
+                    ..Mode::default()
+                });
+                self.modes.len() - 1
+            }
+        }
+    }
+
+    /// Checks that every mode referenced by a command has been defined, that every cell
+    /// selected by a binding is in the grid, and that there are enough hint labels for the
+    /// grid if they're used.
+    fn validate(&self) -> Result<()> {
+        let cells = self.grid.cols * self.grid.rows;
+        let mut hints_used = self.appearance.cell_labels
+            || self
+                .resolved_output_appearances
+                .values()
+                .any(|appearance| appearance.cell_labels);
+        for (id, mode) in self.modes.iter().enumerate() {
+            if let (false, Some(line)) = (mode.defined, mode.referenced_on) {
+                bail!(
+                    "invalid config: line {}: mode {:?} is not defined",
+                    line + 1,
+                    mode.name,
+                );
+            }
+            for (trigger, cmds) in mode.triggers() {
+                for cmd in cmds {
+                    match *cmd {
+                        Cmd::Cell(index) if index >= cells => {
+                            let location = match self.binding_locations.get(&(id, trigger)) {
+                                Some(location) => format!("{location}: "),
+                                None => String::new(),
+                            };
+                            bail!(
+                                "invalid config: {location}cell {} in mode {:?} is outside of the {}x{} grid",
+                                index + 1,
+                                mode.name,
+                                self.grid.cols,
+                                self.grid.rows,
+                            );
+                        }
+                        Cmd::Hint => hints_used = true,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        ensure!(
+            !hints_used || self.hints.capacity() >= u64::from(cells),
+            "invalid config: hint labels of length {} can't label all {} cells of the grid",
+            self.hints.length,
+            cells,
+        );
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, includes: &mut Vec<PathBuf>) -> Result<()> {
+        let path = path.canonicalize()?;
+        if includes.contains(&path) {
+            let chain = includes
+                .iter()
+                .chain([&path])
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            bail!("include cycle: {}", chain.join(" -> "));
+        }
+        let text = std::fs::read_to_string(&path)?;
+        includes.push(path.clone());
+        let result = self.apply_in(&text, Some(&path), includes);
+        includes.pop();
+        result
+    }
+}
+
+/// A name that was replaced, which is still accepted with a warning for a release so configs
+/// written for older versions keep working.
+struct Renamed {
+    /// The old name, followed by any parameters that became part of the new name.
+    old: &'static [&'static str],
+    new: &'static str,
+}
+
+const RENAMED_DIRECTIVES: &[Renamed] = &[];
+
+const RENAMED_COMMANDS: &[Renamed] = &[];
+
+/// Returns the entry of `table` for a directive or command, given its name and parameters.
+fn find_renamed(
+    table: &'static [Renamed],
+    name: &str,
+    params: &[String],
+) -> Option<&'static Renamed> {
+    table.iter().find(|renamed| {
+        renamed.old.len() <= params.len() + 1
+            && std::iter::once(name)
+                .chain(params.iter().map(String::as_str))
+                .zip(renamed.old)
+                .all(|(word, old)| word == *old)
+    })
+}
+
+/// Returns whether a config file has `use-defaults false`, at the top level or in the blocks of
+/// `profile`. Mistakes are left for when the file is applied.
+fn disables_defaults(text: &str, profile: Option<&str>) -> bool {
+    let Ok(directives) = scfg::parse(text) else {
+        return false;
+    };
+    let profile_directives = directives
+        .iter()
+        .filter(|directive| {
+            directive.name == "profile"
+                && profile.is_some_and(|profile| directive.params == [profile])
+        })
+        .flat_map(|directive| &directive.children);
+    directives
+        .iter()
+        .chain(profile_directives)
+        .any(|directive| directive.name == "use-defaults" && directive.params == ["false"])
+}
+
+/// Describes a line of a config file for warnings.
+fn location(path: Option<&Path>, line: usize) -> String {
+    match path {
+        Some(path) => format!("{}: line {}", path.display(), line + 1),
+        None => format!("line {}", line + 1),
+    }
+}
+
+/// Returns whether a word in an inline binding is the name of a command, rather than a key.
+fn is_cmd_name(name: &str) -> bool {
+    cmd_arity(name) > 0
+        || Cmd::from_kebab_case(name).is_some()
+        || RENAMED_COMMANDS
+            .iter()
+            .any(|renamed| renamed.old[0] == name)
+}
+
+/// Returns the number of parameters a command takes, for splitting up inline bindings.
+fn cmd_arity(name: &str) -> usize {
+    match name {
+        "mode" | "cell" => 1,
+        _ => 0,
+    }
+}
+
+/// Returns the paths config files are read from, in the order they're applied: system-wide
+/// configs from `/etc/waypoint` and `$XDG_CONFIG_DIRS`, then the user's config. Environment
+/// variables are looked up with `var`.
+pub(crate) fn config_paths(var: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    let var = |name| var(name).filter(|value| !value.is_empty());
+
+    let mut paths = vec![PathBuf::from("/etc/waypoint/config")];
+    let config_dirs = var("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".into());
+    // Earlier directories are more important, so they're applied later.
+    let mut config_dirs = std::env::split_paths(&config_dirs)
+        .filter(|dir| dir.is_absolute())
+        .collect::<Vec<_>>();
+    config_dirs.reverse();
+    paths.extend(
+        config_dirs
+            .into_iter()
+            .map(|dir| dir.join("waypoint/config")),
+    );
+
+    let config_home = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(var("HOME")?).join(".config")));
+    paths.extend(config_home.map(|dir| dir.join("waypoint/config")));
+    paths
+}
+
+/// Expands a leading `~` and `$VAR` or `${VAR}` environment variable references in a path,
+/// looking variables up with `var`.
+fn expand_path(s: &str, var: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+    let mut expanded = OsString::new();
+    let mut rest = s;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push(var("HOME").context("HOME is not set")?);
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.find('$') {
+        expanded.push(&rest[..i]);
+        rest = &rest[i + 1..];
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .context("unterminated variable reference")?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        ensure!(!name.is_empty(), "empty variable name");
+        expanded.push(var(name).with_context(|| format!("variable {name:?} is not set"))?);
+        rest = after;
+    }
+    expanded.push(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+/// Parses the name of a binding, which is a pointer button, a key specification, or several
+/// key specifications separated by spaces.
+fn parse_trigger(name: &str) -> Result<Trigger> {
+    if name.split_whitespace().nth(1).is_some() {
+        let keys = name
+            .split_whitespace()
+            .map(parse_key)
+            .collect::<Result<_>>()?;
+        return Ok(Trigger::Sequence(keys));
+    }
+    let name = name.trim();
+    match Button::from_binding_name(name) {
+        Some(button) => Ok(Trigger::Button(button.code())),
+        None => match parse_keys(name)? {
+            (modifiers, keysym, None) => Ok(Trigger::Key(modifiers, keysym)),
+            (modifiers, first, Some(second)) => Ok(Trigger::Chord(
+                modifiers,
+                first.min(second),
+                first.max(second),
+            )),
+        },
+    }
+}
+
+/// Returns the modifiers as written before a key, like `ctrl+shift+`.
+fn mods_prefix(modifiers: Mods) -> String {
+    modifiers
+        .iter_names()
+        .map(|(name, _)| format!("{}+", name.to_ascii_lowercase()))
+        .collect()
+}
+
+/// Parses a key specification like `shift+h` into its modifiers and keysym.
+fn parse_key(keys: &str) -> Result<(Mods, xkb::Keysym)> {
+    match parse_keys(keys)? {
+        (modifiers, keysym, None) => Ok((modifiers, keysym)),
+        (_, _, Some(_)) => bail!("chords can't be part of a sequence"),
+    }
+}
+
+/// Parses a key specification with one key, or the two keys of a chord like `j+k`.
+fn parse_keys(keys: &str) -> Result<(Mods, xkb::Keysym, Option<xkb::Keysym>)> {
+    let mut modifiers = Mods::empty();
+    let mut keysym = None;
+    let mut chord_keysym = None;
+
+    let mut elements = keys.split('+').peekable();
+    while let Some(element) = elements.next() {
+        // `U+00E9` names a key by the character it types.
+        let codepoint = match (element, elements.peek()) {
+            ("U" | "u", Some(hex))
+                if (4..=6).contains(&hex.len()) && hex.bytes().all(|b| b.is_ascii_hexdigit()) =>
+            {
+                let codepoint = u32::from_str_radix(hex, 16).unwrap();
+                elements.next();
+                Some(codepoint)
+            }
+            _ => None,
+        };
+
+        match Mods::one_from_str(element).filter(|_| codepoint.is_none()) {
+            Some(modifier) => {
+                let old_modifiers = modifiers;
+                modifiers |= modifier;
+                ensure!(
+                    old_modifiers != modifiers,
+                    "duplicate modifier {:?}",
+                    element
+                );
+            }
+            None => {
+                let parsed_keysym = match codepoint {
+                    Some(codepoint) => codepoint_keysym(codepoint)
+                        .with_context(|| format!("invalid character U+{codepoint:04X}"))?,
+                    None => xkb::keysym_from_name(element, xkb::KEYSYM_CASE_INSENSITIVE),
+                };
+                ensure!(
+                    parsed_keysym != xkb::KEY_NoSymbol,
+                    "invalid key {:?}",
+                    element
+                );
+                match keysym {
+                    None => keysym = Some(parsed_keysym),
+                    Some(first) => {
+                        ensure!(chord_keysym.is_none(), "too many keys");
+                        ensure!(first != parsed_keysym, "duplicate key {:?}", element);
+                        chord_keysym = Some(parsed_keysym);
+                    }
+                }
+            }
+        }
+    }
+
+    let keysym = keysym.context("no key")?;
+
+    Ok((modifiers, keysym, chord_keysym))
+}
+
+/// Returns the keysym for typing a character, like libxkbcommon's `xkb_utf32_to_keysym`: the
+/// legacy keysym if there is one, since that's what keymaps use, or else the Unicode keysym.
+fn codepoint_keysym(codepoint: u32) -> Option<xkb::Keysym> {
+    if char::from_u32(codepoint).is_none_or(char::is_control) {
+        return None;
+    }
+    match codepoint {
+        0x20..=0x7e | 0xa0..=0xff => Some(codepoint),
+        _ => Some(
+            (0x100..0x2100)
+                .find(|&keysym| xkb::keysym_to_utf32(keysym) == codepoint)
+                .unwrap_or(0x0100_0000 | codepoint),
+        ),
+    }
+}
+
+/// Renders the line of `text` containing `span`, with the span underlined.
+fn snippet(text: &str, span: &Span) -> Option<String> {
+    let line = text.lines().nth(span.line)?;
+    let indent = line
+        .get(..span.start)?
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let width = line.get(span.start..span.end)?.chars().count().max(1);
+    let number = (span.line + 1).to_string();
+    let gutter = " ".repeat(number.len());
+    Some(format!(
+        "{gutter} |\n{number} | {line}\n{gutter} | {indent}{}",
+        "^".repeat(width),
+    ))
+}
+
+/// Parses a color in `#rrggbb` or `#rrggbbaa` notation.
+fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+    if !matches!(hex.len(), 6 | 8) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    let alpha = if hex.len() == 8 { channel(6)? } else { 0xff };
+    Some(Color::from_rgba8(
+        channel(0)?,
+        channel(2)?,
+        channel(4)?,
+        alpha,
+    ))
+}
+
+/// Returns the hint characters that can't be typed with the keymap.
+pub(crate) fn untypeable_hint_chars(keymap: &xkb::Keymap, config: &Config) -> Vec<char> {
+    let mut typeable = Vec::new();
+    keymap.key_for_each(|keymap, keycode| {
+        for layout in 0..keymap.num_layouts_for_key(keycode) {
+            for level in 0..keymap.num_levels_for_key(keycode, layout) {
+                for &keysym in keymap.key_get_syms_by_level(keycode, layout, level) {
+                    typeable.extend(char::from_u32(xkb::keysym_to_utf32(keysym)));
+                }
+            }
+        }
+    });
+    config
+        .hints
+        .chars
+        .iter()
+        .copied()
+        .filter(|c| !typeable.contains(c))
+        .collect()
+}
+
+/// Bindings resolved against a keymap, keyed on modifier mask and keycode.
+pub(crate) type SpecializedBindings = HashMap<(xkb::ModMask, xkb::Keycode), BindingNode>;
+
+/// A bound key, which runs commands, starts or continues a sequence of keys, or both.
+#[derive(Debug, Default)]
+pub(crate) struct BindingNode {
+    pub(crate) cmds: Option<Vec<Cmd>>,
+    /// Keys that can follow this one in a sequence.
+    pub(crate) next: SpecializedBindings,
+    /// Commands run when this key is pressed together with another one, by keycode.
+    pub(crate) chords: HashMap<xkb::Keycode, Vec<Cmd>>,
+}
+
+/// Returns the binding reached by pressing `keys` one after another.
+pub(crate) fn lookup_sequence<'a>(
+    bindings: &'a SpecializedBindings,
+    keys: &[(xkb::ModMask, xkb::Keycode)],
+) -> Option<&'a BindingNode> {
+    let (first, rest) = keys.split_first()?;
+    rest.iter()
+        .try_fold(bindings.get(first)?, |node, key| node.next.get(key))
+}
+
+/// Adds a sequence to `bindings`, with each step listing the keys that can be pressed for it.
+fn insert_sequence(
+    bindings: &mut SpecializedBindings,
+    steps: &[Vec<(xkb::ModMask, xkb::Keycode)>],
+    cmds: &[Cmd],
+) {
+    let Some((step, rest)) = steps.split_first() else {
+        return;
+    };
+    for &key in step {
+        let node = bindings.entry(key).or_default();
+        if rest.is_empty() {
+            node.cmds = Some(cmds.to_vec());
+        } else {
+            insert_sequence(&mut node.next, rest, cmds);
+        }
+    }
+}
+
+pub(crate) fn specialize_bindings(
+    keymap: &xkb::Keymap,
+    config: &Config,
+) -> (ModIndices, Vec<SpecializedBindings>) {
+    let state = xkb::State::new(keymap);
+    let mod_indices = ModIndices {
+        shift: keymap.mod_get_index(xkb::MOD_NAME_SHIFT),
+        caps: keymap.mod_get_index(xkb::MOD_NAME_CAPS),
+        ctrl: keymap.mod_get_index(xkb::MOD_NAME_CTRL),
+        alt: keymap.mod_get_index(xkb::MOD_NAME_ALT),
+        num: keymap.mod_get_index(xkb::MOD_NAME_NUM),
+        mod3: keymap.mod_get_index("Mod3"),
+        logo: keymap.mod_get_index(xkb::MOD_NAME_LOGO),
+        mod5: keymap.mod_get_index("Mod5"),
+    };
+
+    let mod_index_array: &[xkb::ModMask; 8] = bytemuck::cast_ref(&mod_indices);
+
+    // Zero if the keymap has no shift modifier.
+    let shift_mask = 1u32.checked_shl(mod_indices.shift).unwrap_or(0);
+    let mut shift_state = xkb::State::new(keymap);
+    shift_state.update_mask(shift_mask, 0, 0, 0, 0, 0);
+
+    // Finds the keys producing a keysym, and whether they need shift to do so.
+    let resolve = |(modifiers, keysym): (Mods, xkb::Keysym)| {
+        let mod_mask: xkb::ModMask = modifiers
+            .into_iter()
+            .map(|modifier| 1 << mod_index_array[modifier.bits().trailing_zeros() as usize])
+            .fold(0, |acc, it| acc | it);
+
+        let mut keys = Vec::new();
+        keymap.key_for_each(|_, keycode| {
+            let got_keysym = state.key_get_one_sym(keycode);
+            if got_keysym != xkb::KEY_NoSymbol && got_keysym == keysym {
+                keys.push(((mod_mask, keycode), false));
+            } else if shift_mask != 0 && shift_state.key_get_one_sym(keycode) == keysym {
+                keys.push(((mod_mask | shift_mask, keycode), true));
+            }
+        });
+        keys
+    };
+
+    let specialized = config
+        .modes
+        .iter()
+        .map(|mode| {
+            let mut bindings = SpecializedBindings::new();
+            // Bindings reached through shift are added last so that explicit `shift+` bindings
+            // on the same key take precedence.
+            let mut shifted = Vec::new();
+
+            for (&key, cmds) in &mode.bindings {
+                for (key, is_shifted) in resolve(key) {
+                    if is_shifted {
+                        shifted.push((key, cmds));
+                    } else {
+                        bindings.entry(key).or_default().cmds = Some(cmds.clone());
+                    }
+                }
+            }
+
+            for (key, cmds) in shifted {
+                let node = bindings.entry(key).or_default();
+                node.cmds.get_or_insert_with(|| cmds.clone());
+            }
+
+            for (sequence, cmds) in &mode.sequences {
+                let steps = sequence
+                    .iter()
+                    .map(|&key| resolve(key).into_iter().map(|(key, _)| key).collect())
+                    .collect::<Vec<_>>();
+                insert_sequence(&mut bindings, &steps, cmds);
+            }
+
+            for (&(modifiers, first, second), cmds) in &mode.chords {
+                for (first, _) in resolve((modifiers, first)) {
+                    for (second, _) in resolve((modifiers, second)) {
+                        // Either key can be pressed first.
+                        let node = bindings.entry(first).or_default();
+                        node.chords.insert(second.1, cmds.clone());
+                        let node = bindings.entry(second).or_default();
+                        node.chords.insert(first.1, cmds.clone());
+                    }
+                }
+            }
+
+            bindings
+        })
+        .collect();
+
+    (mod_indices, specialized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for a test's files, removed once the test is done with it, even
+    /// if it fails.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(test: &str) -> TestDir {
+            let dir = std::env::temp_dir().join(format!("waypoint-{test}-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl std::ops::Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
 
     #[test]
     fn test_one_modifier_from_str() {
@@ -330,4 +3024,638 @@ mod tests {
             check(modifier_name, &modifier_name.to_uppercase());
         }
     }
+
+    #[test]
+    fn test_parse_color() {
+        #[track_caller]
+        fn check(s: &str, expected: Option<[u8; 4]>) {
+            let color = parse_color(s).map(|color| {
+                let color = color.to_color_u8();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            });
+            assert_eq!(color, expected);
+        }
+
+        check("#000000", Some([0x00, 0x00, 0x00, 0xff]));
+        check("#ff8000", Some([0xff, 0x80, 0x00, 0xff]));
+        check("#FF800040", Some([0xff, 0x80, 0x00, 0x40]));
+        check("ff8000", None);
+        check("#ff80", None);
+        check("#gg8000", None);
+    }
+
+    #[test]
+    fn test_default_config() {
+        Config::parse(include_str!("../default_config")).unwrap();
+    }
+
+    #[test]
+    fn test_unbind() {
+        let mut config = Config::parse(include_str!("../default_config")).unwrap();
+        let h = parse_key("h").unwrap();
+        let shift_h = parse_key("shift+h").unwrap();
+        assert!(config.modes[DEFAULT_MODE].bindings.contains_key(&h));
+        config.apply("unbind h").unwrap();
+        assert!(!config.modes[DEFAULT_MODE].bindings.contains_key(&h));
+        assert!(config.modes[DEFAULT_MODE].bindings.contains_key(&shift_h));
+
+        let mut config = Config::defaults(None).unwrap();
+        config
+            .apply("bindings {\n    x left-click\n    h quit\n}\nno-default-bindings\nbindings {\n    y quit\n}")
+            .unwrap();
+        let bindings = &config.modes[DEFAULT_MODE].bindings;
+        assert_eq!(bindings.len(), 3);
+        for key in ["x", "h", "y"] {
+            assert!(bindings.contains_key(&parse_key(key).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_no_default_bindings_in_include() {
+        let dir = TestDir::new("test_no_default_bindings_in_include");
+        std::fs::write(
+            dir.join("config"),
+            "bindings {\n    x quit\n}\ninclude extra",
+        )
+        .unwrap();
+        std::fs::write(dir.join("extra"), "no-default-bindings").unwrap();
+
+        let mut config = Config::defaults(None).unwrap();
+        let text = std::fs::read_to_string(dir.join("config")).unwrap();
+        config
+            .apply_layer(&text, Some(&dir.join("config")))
+            .unwrap();
+        let bindings = &config.modes[DEFAULT_MODE].bindings;
+        assert_eq!(bindings.len(), 1);
+        assert!(bindings.contains_key(&parse_key("x").unwrap()));
+    }
+
+    #[test]
+    fn test_expand_path() {
+        #[track_caller]
+        fn check(s: &str, expected: Option<&str>) {
+            let var = |name: &str| match name {
+                "HOME" => Some("/home/user".into()),
+                "WAYPOINT_TEST_DIR" => Some("/tmp/waypoint".into()),
+                _ => None,
+            };
+            assert_eq!(expand_path(s, var).ok(), expected.map(PathBuf::from));
+        }
+
+        check("a/b", Some("a/b"));
+        check("~/config", Some("/home/user/config"));
+        check("$WAYPOINT_TEST_DIR/config", Some("/tmp/waypoint/config"));
+        check("${WAYPOINT_TEST_DIR}x", Some("/tmp/waypointx"));
+        check("$WAYPOINT_TEST_UNSET/config", None);
+        check("${WAYPOINT_TEST_DIR", None);
+        check("$/config", None);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = TestDir::new("test_include");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("config"), "include sub/bindings").unwrap();
+        std::fs::write(dir.join("sub/bindings"), "bindings {\n    x quit\n}").unwrap();
+        std::fs::write(dir.join("sub/cycle"), "include ../config\ninclude cycle").unwrap();
+
+        let mut config = Config::parse("").unwrap();
+        config
+            .include(&dir.join("config"), &mut Vec::new())
+            .unwrap();
+        assert!(config.modes[DEFAULT_MODE]
+            .bindings
+            .contains_key(&parse_key("x").unwrap()));
+
+        let mut config = Config::parse("").unwrap();
+        let err = config
+            .include(&dir.join("sub/cycle"), &mut Vec::new())
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("include cycle"));
+    }
+
+    #[test]
+    fn test_modes() {
+        let config = Config::parse(
+            "bindings {\n    m mode resize\n}\nmode resize {\n    bindings {\n        escape {\n            mode default\n        }\n    }\n}",
+        )
+        .unwrap();
+        assert_eq!(config.modes.len(), 2);
+        let m = parse_key("m").unwrap();
+        let escape = parse_key("escape").unwrap();
+        assert!(matches!(
+            config.modes[DEFAULT_MODE].bindings[&m][..],
+            [Cmd::Mode(1)]
+        ));
+        assert!(matches!(
+            config.modes[1].bindings[&escape][..],
+            [Cmd::Mode(DEFAULT_MODE)]
+        ));
+
+        let config = Config::parse(
+            "bindings {\n    m mode resize left-click\n}\nmode resize {\n    bindings {\n        q cell 1 quit\n    }\n}",
+        )
+        .unwrap();
+        let q = parse_key("q").unwrap();
+        assert!(matches!(
+            config.modes[DEFAULT_MODE].bindings[&m][..],
+            [Cmd::Mode(1), Cmd::Click(Button::Left)]
+        ));
+        assert!(matches!(
+            config.modes[1].bindings[&q][..],
+            [Cmd::Cell(0), Cmd::Quit]
+        ));
+
+        let err = Config::parse("bindings {\n    m mode missing\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 2: mode \"missing\" is not defined",
+        );
+    }
+
+    #[test]
+    fn test_hint_labels() {
+        let hints = Hints {
+            chars: vec!['a', 's', 'd'],
+            length: 2,
+        };
+        assert_eq!(hints.capacity(), 9);
+        for (index, label) in ["aa", "as", "ad", "sa", "ss", "sd", "da"]
+            .iter()
+            .enumerate()
+        {
+            assert_eq!(hints.index_of(label), Some(index as u32));
+            assert_eq!(hints.label(index as u32).as_deref(), Some(*label));
+        }
+        assert_eq!(hints.index_of("ax"), None);
+        assert_eq!(hints.label(9), None);
+
+        // The labels only have to cover the grid when they're used.
+        Config::parse("grid 4 4\nhint-chars abc\nhint-length 1").unwrap();
+        let mut config = Config::defaults(None).unwrap();
+        config.apply("grid 4 4").unwrap();
+        config.validate().unwrap();
+        let err =
+            Config::parse("grid 4 4\nhint-chars abc\nhint-length 2\nbindings {\n    f hint\n}")
+                .err()
+                .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: hint labels of length 2 can't label all 16 cells of the grid",
+        );
+        let err = Config::parse("grid 4 4\nappearance {\n    cell-labels true\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: hint labels of length 1 can't label all 16 cells of the grid",
+        );
+
+        let err = Config::parse("grid 2 2\nbindings {\n    a cell 1\n    b cell 5\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 4: cell 5 in mode \"default\" is outside of the 2x2 grid",
+        );
+    }
+
+    #[test]
+    fn test_duplicate_bindings() {
+        let mut config = Config::parse(include_str!("../default_config")).unwrap();
+        assert!(config.warnings.is_empty());
+        config.apply("bindings {\n    h quit\n}").unwrap();
+        assert!(config.warnings.is_empty());
+        config
+            .apply("bindings {\n    h quit\n    H undo\n}")
+            .unwrap();
+        assert_eq!(
+            config.warnings,
+            ["line 3: binding for \"H\" in mode \"default\" overrides binding on line 2"],
+        );
+
+        let err = Config::parse("strict\nbindings {\n    h quit\n    h undo\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 4: binding for \"h\" in mode \"default\" overrides binding on line 3\n  |\n4 |     h undo\n  |     ^",
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let text = "grid 2 2\nprofile work {\n    grid 3 3\n    bindings {\n        w quit\n    }\n}\nprofile home {\n    grid 4 4\n}";
+        let w = parse_key("w").unwrap();
+
+        let config = Config::parse(text).unwrap();
+        assert_eq!((config.grid.cols, config.grid.rows), (2, 2));
+        assert!(!config.modes[DEFAULT_MODE].bindings.contains_key(&w));
+
+        let mut config = Config::empty();
+        config.profile = Some("work".to_owned());
+        config.apply(text).unwrap();
+        assert!(config.profile_found);
+        assert_eq!((config.grid.cols, config.grid.rows), (3, 3));
+        assert!(config.modes[DEFAULT_MODE].bindings.contains_key(&w));
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut config = Config::parse("").unwrap();
+        assert_eq!(config.scroll_speed.vertical, 10.0);
+        assert_eq!(config.appearance.border_thickness, 1.0);
+        assert_eq!(config.warp, Warp::Always);
+        config
+            .apply("settings {\n    warp click\n    repeat-rate 0\n}")
+            .unwrap();
+        assert_eq!(config.warp, Warp::Click);
+        assert_eq!(config.repeat_rate, Some(0));
+        assert!(config.apply("repeat-rate 1001").is_err());
+        config
+            .apply("appearance {\n    animation true\n    animation-easing ease-in-out\n}")
+            .unwrap();
+        assert!(config.appearance.animate);
+        let easing = config.appearance.animation_easing;
+        assert_eq!(easing, Easing::EaseInOut);
+        assert_eq!(
+            (easing.apply(0.0), easing.apply(0.5), easing.apply(1.0)),
+            (0.0, 0.5, 1.0)
+        );
+        config.apply("backend libei").unwrap();
+        assert_eq!(config.backend, Backend::Libei);
+        config.apply("swap-buttons true").unwrap();
+        let err = config.apply("swap-buttons yes").err().unwrap();
+        assert!(err.to_string().contains("should be true or false"));
+        assert_eq!(config.button_code(Button::Left), Button::Right.code());
+        assert_eq!(config.button_code(Button::Middle), Button::Middle.code());
+        config
+            .apply("scroll-speed 4\nhorizontal-scroll-speed 6")
+            .unwrap();
+        assert_eq!(config.scroll_speed.vertical, 4.0);
+        assert_eq!(config.scroll_speed.horizontal, 6.0);
+        config.apply("scroll-speed 10").unwrap();
+        assert_eq!(config.scroll_speed.horizontal, 10.0);
+        config.apply("invert-scroll vertical").unwrap();
+        let speed = config.scroll_speed;
+        assert_eq!(speed.for_axis(WL_POINTER_AXIS_VERTICAL_SCROLL), -10.0);
+        assert_eq!(speed.for_axis(WL_POINTER_AXIS_HORIZONTAL_SCROLL), 10.0);
+        config
+            .apply("sequence-timeout 2s\nchord-timeout 80\nmove-distance 40%")
+            .unwrap();
+        assert_eq!((config.sequence_timeout, config.chord_timeout), (2000, 80));
+        assert_eq!(config.move_distance.resolve(200), 80);
+        config.apply("move-distance 25px").unwrap();
+        assert_eq!(config.move_distance, Length::Pixels(25.0));
+
+        let err = Config::parse("settings {\n    hint-length 9\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 2: invalid value \"9\" for setting \"hint-length\", should be a whole number from 1 to 8\n  |\n2 |     hint-length 9\n  |                 ^",
+        );
+        let err = Config::parse("appearance {\n    border-thickness 10%\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 2: invalid value \"10%\" for setting \"border-thickness\", should be a length like 3px\n  |\n2 |     border-thickness 10%\n  |                      ^^^",
+        );
+        let err = Config::parse("chord-timeout 1.5ms").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 1: invalid value \"1.5ms\" for setting \"chord-timeout\", should be a duration like 250ms or 2s of at least 1ms\n  |\n1 | chord-timeout 1.5ms\n  |               ^^^^^",
+        );
+        config
+            .apply("settings {\n    grid 3 4\n    hint-chars qwer\n}")
+            .unwrap();
+        assert_eq!((config.grid.cols, config.grid.rows), (3, 4));
+        assert_eq!(config.hints.chars, ['q', 'w', 'e', 'r']);
+        let err = Config::parse("grid 3 65").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 1: invalid value \"3 65\" for setting \"grid\", should be two whole numbers from 1 to 64\n  |\n1 | grid 3 65\n  |      ^^^^",
+        );
+        let err = Config::parse("grid 3").err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("setting \"grid\" should have exactly two parameters"));
+        let err = Config::parse("hint-chars abca").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 1: invalid value \"abca\" for setting \"hint-chars\", should be at least two different characters that aren't spaces\n  |\n1 | hint-chars abca\n  |            ^^^^",
+        );
+        let err = Config::parse("appearance {\n    warp never\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 2: invalid appearance option \"warp\"\n  |\n2 |     warp never\n  |     ^^^^",
+        );
+    }
+
+    #[test]
+    fn test_shifted_bindings() {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap =
+            xkb::Keymap::new_from_names(&context, "", "", "us", "", None, xkb::COMPILE_NO_FLAGS)
+                .unwrap();
+        let mut config = Config::parse("bindings {\n    colon quit\n    shift+h undo\n}").unwrap();
+        config.apply("bindings {\n    H quit\n}").unwrap();
+        let (mod_indices, specialized) = specialize_bindings(&keymap, &config);
+        let shift = 1 << mod_indices.shift;
+        let semicolon = keymap.key_by_name("AC10").unwrap();
+        let h = keymap.key_by_name("AC06").unwrap();
+        let bindings = &specialized[DEFAULT_MODE];
+        assert!(matches!(
+            bindings[&(shift, semicolon)].cmds.as_deref(),
+            Some([Cmd::Quit])
+        ));
+        assert!(matches!(
+            bindings[&(shift, h)].cmds.as_deref(),
+            Some([Cmd::Undo])
+        ));
+        assert!(!bindings.contains_key(&(0, semicolon)));
+    }
+
+    #[test]
+    fn test_config_paths() {
+        let paths = config_paths(|name| match name {
+            "XDG_CONFIG_DIRS" => Some("/etc/first:relative:/etc/second".into()),
+            "XDG_CONFIG_HOME" => Some("/home/user/.config".into()),
+            _ => None,
+        });
+        assert_eq!(
+            paths,
+            [
+                "/etc/waypoint/config",
+                "/etc/second/waypoint/config",
+                "/etc/first/waypoint/config",
+                "/home/user/.config/waypoint/config",
+            ]
+            .map(PathBuf::from),
+        );
+
+        let paths = config_paths(|name| match name {
+            "XDG_CONFIG_DIRS" => Some("".into()),
+            "HOME" => Some("/home/user".into()),
+            _ => None,
+        });
+        assert_eq!(
+            paths,
+            [
+                "/etc/waypoint/config",
+                "/etc/xdg/waypoint/config",
+                "/home/user/.config/waypoint/config",
+            ]
+            .map(PathBuf::from),
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = TestDir::new("test_load");
+        std::fs::write(dir.join("system"), "grid 2 2\nbindings {\n    x quit\n}").unwrap();
+        std::fs::write(dir.join("user"), "grid 3 3").unwrap();
+
+        let paths = ["system", "missing", "user"].map(|name| dir.join(name));
+        let config = Config::load(None, &paths).unwrap();
+        assert_eq!((config.grid.cols, config.grid.rows), (3, 3));
+        assert!(config.modes[DEFAULT_MODE]
+            .bindings
+            .contains_key(&parse_key("x").unwrap()));
+
+        std::fs::write(dir.join("user"), "grid 0 0").unwrap();
+        let err = Config::load(None, &paths).err().unwrap();
+        assert!(err.to_string().starts_with("failed to load config"));
+    }
+
+    #[test]
+    fn test_use_defaults() {
+        let dir = TestDir::new("test_use_defaults");
+        std::fs::write(
+            dir.join("system"),
+            "alias done quit\nbindings {\n    x done\n}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("user"),
+            "use-defaults true\nbindings {\n    y quit\n}",
+        )
+        .unwrap();
+        let paths = ["system", "user"].map(|name| dir.join(name));
+        let h = parse_key("h").unwrap();
+
+        let config = Config::load(None, &paths).unwrap();
+        assert!(config.modes[DEFAULT_MODE].bindings.contains_key(&h));
+
+        std::fs::write(
+            dir.join("user"),
+            "bindings {\n    y quit\n}\nprofile bare {\n    use-defaults false\n}",
+        )
+        .unwrap();
+        let config = Config::load(None, &paths).unwrap();
+        assert!(config.modes[DEFAULT_MODE].bindings.contains_key(&h));
+
+        // Nothing of the default config is applied, but every file given is.
+        let config = Config::load(Some("bare".to_owned()), &paths).unwrap();
+        let bindings = &config.modes[DEFAULT_MODE].bindings;
+        assert_eq!(bindings.len(), 2);
+        assert!(bindings.contains_key(&parse_key("x").unwrap()));
+        assert!(bindings.contains_key(&parse_key("y").unwrap()));
+        assert!(config.aliases.contains_key("done"));
+        assert_eq!(config.appearance.border_thickness, 1.0);
+
+        assert!(Config::parse("use-defaults maybe").is_err());
+    }
+
+    #[test]
+    fn test_button_bindings() {
+        let mut config =
+            Config::parse("bindings {\n    mouse-right undo\n    mouse-middle quit\n}").unwrap();
+        assert!(config.has_button_bindings());
+        assert!(matches!(
+            config.button_binding(DEFAULT_MODE, Button::Right.code()),
+            Some([Cmd::Undo])
+        ));
+        config.apply("unbind mouse-right mouse-middle").unwrap();
+        assert!(!config.has_button_bindings());
+    }
+
+    #[test]
+    fn test_sequences() {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap =
+            xkb::Keymap::new_from_names(&context, "", "", "us", "", None, xkb::COMPILE_NO_FLAGS)
+                .unwrap();
+        let mut config = Config::parse("bindings {\n    g g quit\n    g undo\n}").unwrap();
+        assert!(Config::parse("bindings {\n    g g\n}").is_err());
+        let (_, specialized) = specialize_bindings(&keymap, &config);
+        let g = keymap.key_by_name("AC05").unwrap();
+        let bindings = &specialized[DEFAULT_MODE];
+        let node = lookup_sequence(bindings, &[(0, g)]).unwrap();
+        assert!(matches!(node.cmds.as_deref(), Some([Cmd::Undo])));
+        let node = lookup_sequence(bindings, &[(0, g), (0, g)]).unwrap();
+        assert!(matches!(node.cmds.as_deref(), Some([Cmd::Quit])));
+        assert!(node.next.is_empty());
+
+        config.apply("unbind \"g g\"").unwrap();
+        let (_, specialized) = specialize_bindings(&keymap, &config);
+        assert!(lookup_sequence(&specialized[DEFAULT_MODE], &[(0, g), (0, g)]).is_none());
+    }
+
+    #[test]
+    fn test_chords() {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap =
+            xkb::Keymap::new_from_names(&context, "", "", "us", "", None, xkb::COMPILE_NO_FLAGS)
+                .unwrap();
+        let mut config = Config::parse("bindings {\n    k+j quit\n}").unwrap();
+        assert!(Config::parse("bindings {\n    j+j quit\n}").is_err());
+        assert!(Config::parse("bindings {\n    j+k+l quit\n}").is_err());
+        assert!(Config::parse("bindings {\n    g j+k quit\n}").is_err());
+        let (_, specialized) = specialize_bindings(&keymap, &config);
+        let j = keymap.key_by_name("AC07").unwrap();
+        let k = keymap.key_by_name("AC08").unwrap();
+        let bindings = &specialized[DEFAULT_MODE];
+        assert!(bindings[&(0, j)].cmds.is_none());
+        assert!(matches!(bindings[&(0, j)].chords[&k][..], [Cmd::Quit]));
+        assert!(matches!(bindings[&(0, k)].chords[&j][..], [Cmd::Quit]));
+
+        config.apply("unbind j+k").unwrap();
+        let (_, specialized) = specialize_bindings(&keymap, &config);
+        assert!(specialized[DEFAULT_MODE].is_empty());
+    }
+
+    #[test]
+    fn test_codepoint_keys() {
+        assert_eq!(parse_key("U+00E9").unwrap(), parse_key("eacute").unwrap());
+        assert_eq!(
+            parse_key("u+0430").unwrap(),
+            parse_key("Cyrillic_a").unwrap()
+        );
+        assert_eq!(
+            parse_key("ctrl+U+1F600").unwrap(),
+            (Mods::CTRL, 0x0101_f600)
+        );
+        assert!(parse_key("U+0007").is_err());
+        assert_eq!(
+            parse_trigger("U+k").unwrap(),
+            Trigger::Chord(Mods::empty(), xkb::KEY_k, xkb::KEY_u)
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let config = Config::parse(
+            "alias center { move-up cut-down }\nalias center-click center left-click\nbindings {\n    c center-click quit\n    g g center\n    x {\n        center\n        undo\n    }\n}",
+        )
+        .unwrap();
+        let mode = &config.modes[DEFAULT_MODE];
+        assert!(matches!(
+            mode.bindings[&parse_key("c").unwrap()][..],
+            [
+                Cmd::Move(Direction::Up),
+                Cmd::Cut(Direction::Down),
+                Cmd::Click(Button::Left),
+                Cmd::Quit,
+            ]
+        ));
+        assert_eq!(mode.sequences.len(), 1);
+        assert_eq!(mode.bindings[&parse_key("x").unwrap()].len(), 3);
+
+        let err = Config::parse("alias quit undo").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: line 1: alias \"quit\" would replace a built-in command\n  |\n1 | alias quit undo\n  |       ^^^^",
+        );
+    }
+
+    #[test]
+    fn test_renamed() {
+        static RENAMED: &[Renamed] = &[
+            Renamed {
+                old: &["old-name"],
+                new: "new-name",
+            },
+            Renamed {
+                old: &["split", "vertical"],
+                new: "split-vertical",
+            },
+        ];
+        let params = |params: &[&str]| params.iter().map(|&p| p.to_owned()).collect::<Vec<_>>();
+        let find = |name, p: &[&str]| find_renamed(RENAMED, name, &params(p)).map(|r| r.new);
+        assert_eq!(find("old-name", &[]), Some("new-name"));
+        assert_eq!(find("old-name", &["1"]), Some("new-name"));
+        assert_eq!(find("split", &["vertical", "2"]), Some("split-vertical"));
+        assert_eq!(find("split", &["horizontal"]), None);
+        assert_eq!(find("split", &[]), None);
+        assert_eq!(find("new-name", &[]), None);
+
+        let mut config = Config::empty();
+        config.warn_renamed(&RENAMED[1], None, 2);
+        assert_eq!(
+            config.warnings,
+            ["line 3: \"split vertical\" is deprecated and will be removed, use \"split-vertical\" instead"],
+        );
+    }
+
+    #[test]
+    fn test_themes() {
+        let config =
+            Config::parse("appearance {\n    theme high-contrast\n    label-color #ff0000\n}")
+                .unwrap();
+        assert_eq!(config.appearance.border_thickness, 3.0);
+        assert!(config.appearance.outline);
+        assert_eq!(
+            config.appearance.label_color,
+            parse_color("#ff0000").unwrap()
+        );
+
+        let SettingType::Keyword(names) = SETTINGS.iter().find(|s| s.name == "theme").unwrap().ty
+        else {
+            unreachable!();
+        };
+        for name in names {
+            Config::empty().apply_theme(name);
+        }
+    }
+
+    #[test]
+    fn test_output_appearance() {
+        let config = Config::parse(
+            "appearance {\n    border-color #ff0000\n}\noutput HDMI-A-1 {\n    border-thickness 4px\n}",
+        )
+        .unwrap();
+        let appearance = config.output_appearance("HDMI-A-1");
+        assert_eq!(appearance.border_thickness, 4.0);
+        assert_eq!(appearance.border_color, parse_color("#ff0000").unwrap());
+        assert_eq!(config.output_appearance("DP-1").border_thickness, 1.0);
+
+        assert!(Config::parse("output DP-1 {\n    font sans\n}").is_err());
+        assert!(Config::parse("output DP-1 {\n    grid 2 2\n}").is_err());
+    }
+
+    #[test]
+    fn test_dump() {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap =
+            xkb::Keymap::new_from_names(&context, "", "", "us", "", None, xkb::COMPILE_NO_FLAGS)
+                .unwrap();
+        let mut config = Config::parse(include_str!("../default_config")).unwrap();
+        config
+            .apply("mode \"two words\" {\n    bindings {\n        g g cell 2 quit\n        ctrl+j+k mode default\n        mouse-left scroll-up\n    }\n}\nbindings {\n    x mode \"two words\"\n}")
+            .unwrap();
+        let mut dumped = String::new();
+        config.dump(&mut dumped, &keymap).unwrap();
+        assert!(dumped.contains("    ctrl+j+k mode default\n"));
+
+        let mut dumped_again = String::new();
+        let config = Config::parse(&dumped).unwrap();
+        config.dump(&mut dumped_again, &keymap).unwrap();
+        assert_eq!(dumped, dumped_again);
+    }
 }
@@ -0,0 +1,32 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Prints log messages to standard error, prefixed with their level.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        eprintln!("{level}: {}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, showing warnings and errors until the config sets a level.
+pub(crate) fn init() {
+    log::set_logger(&Logger).expect("logger should only be installed once");
+    log::set_max_level(LevelFilter::Warn);
+}
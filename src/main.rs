@@ -13,51 +13,74 @@ mod ei_gen {
 extern crate waypoint_scfg as scfg;
 
 mod config;
+mod logger;
 mod region;
+mod text;
 
 use crate::{
-    config::{specialize_bindings, Cmd, Config, Direction},
-    region::Region,
+    config::{
+        lookup_sequence, specialize_bindings, untypeable_hint_chars, Appearance, Backend, Button,
+        Cmd, Config, Cursor, Direction, Grid, InitialRegion, ModeId, Renderer, SpecializedBindings,
+        Warp, DEFAULT_MODE,
+    },
+    region::{Point, Region},
+    text::Font,
 };
-use anyhow::{Context as _, Result};
+use anyhow::{bail, ensure, Context as _, Result};
 use bytemuck::{Pod, Zeroable};
 use ei::Object as _;
 use ei_gen::{
-    EiButton, EiButtonEvent, EiButtonRequest, EiCallbackEvent, EiConnectionEvent, EiDevice,
-    EiDeviceEvent, EiDeviceRequest, EiHandshake, EiHandshakeEvent, EiHandshakeRequest,
-    EiPingpongRequest, EiPointerAbsolute, EiPointerAbsoluteEvent, EiPointerAbsoluteRequest,
-    EiScroll, EiScrollEvent, EiScrollRequest, EiSeatEvent, EiSeatRequest,
-    EI_BUTTON_BUTTON_STATE_PRESS, EI_BUTTON_BUTTON_STATE_RELEASED,
-    EI_HANDSHAKE_CONTEXT_TYPE_SENDER,
+    EiButton, EiButtonEvent, EiButtonRequest, EiCallback, EiCallbackEvent, EiConnection,
+    EiConnectionEvent, EiConnectionRequest, EiDevice, EiDeviceEvent, EiHandshakeEvent, EiKeyboard,
+    EiKeyboardEvent, EiPointer, EiPointerAbsolute, EiPointerAbsoluteEvent,
+    EiPointerAbsoluteRequest, EiPointerEvent, EiPointerRequest, EiScroll, EiScrollEvent,
+    EiScrollRequest, EiSeatEvent, EiSeatRequest, EI_BUTTON_BUTTON_STATE_PRESS,
+    EI_BUTTON_BUTTON_STATE_RELEASED, EI_HANDSHAKE_CONTEXT_TYPE_SENDER,
 };
 use handy::typed::{TypedHandle, TypedHandleMap};
 use memmap2::{MmapMut, MmapOptions};
-use rustix::event::{PollFd, PollFlags};
+use rustix::io::Errno;
 use std::{
-    collections::{HashMap, HashSet},
-    io::Write,
+    borrow::Cow,
+    cell::OnceCell,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
     ops::RangeInclusive,
-    os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd},
     time::{Duration, Instant},
 };
-use tiny_skia::{Color, Paint, PathBuilder, Shader, Stroke, Transform};
-use wayland::Object as _;
+use tiny_skia::{
+    Color, FillRule, FilterQuality, Paint, PathBuilder, PixmapPaint, PremultipliedColorU8, Shader,
+    Stroke, StrokeDash, Transform,
+};
+use wayland::{Object as _, Roundtrip as _};
 use wl_gen::{
-    Event, Request, WlBuffer, WlBufferEvent, WlBufferRequest, WlCallback, WlCallbackEvent,
-    WlCompositor, WlCompositorRequest, WlDisplay, WlDisplayEvent, WlDisplayRequest, WlKeyboard,
-    WlKeyboardEvent, WlOutput, WlOutputEvent, WlPointerEvent, WlRegistry, WlRegistryEvent,
-    WlRegistryRequest, WlSeat, WlSeatEvent, WlSeatRequest, WlShm, WlShmEvent, WlShmPool,
-    WlShmPoolRequest, WlShmRequest, WlSurface, WlSurfaceEvent, WlSurfaceRequest, WlTouchEvent,
-    ZwlrLayerShellV1, ZwlrLayerShellV1Request, ZwlrLayerSurfaceV1, ZwlrLayerSurfaceV1Event,
-    ZwlrLayerSurfaceV1Request, ZwlrVirtualPointerManagerV1, ZwlrVirtualPointerManagerV1Request,
-    ZwlrVirtualPointerV1, ZwlrVirtualPointerV1Request, ZxdgOutputManagerV1,
-    ZxdgOutputManagerV1Request, ZxdgOutputV1, ZxdgOutputV1Event, WL_KEYBOARD_KEYMAP_FORMAT_XKB_V1,
-    WL_KEYBOARD_KEY_STATE_PRESSED, WL_KEYBOARD_KEY_STATE_RELEASED,
-    WL_POINTER_AXIS_HORIZONTAL_SCROLL, WL_POINTER_AXIS_VERTICAL_SCROLL,
-    WL_POINTER_BUTTON_STATE_PRESSED, WL_POINTER_BUTTON_STATE_RELEASED, WL_SEAT_CAPABILITY_KEYBOARD,
-    WL_SHM_FORMAT_ABGR8888, ZWLR_LAYER_SHELL_V1_LAYER_OVERLAY, ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM,
-    ZWLR_LAYER_SURFACE_V1_ANCHOR_LEFT, ZWLR_LAYER_SURFACE_V1_ANCHOR_RIGHT,
-    ZWLR_LAYER_SURFACE_V1_ANCHOR_TOP, ZWLR_LAYER_SURFACE_V1_KEYBOARD_INTERACTIVITY_EXCLUSIVE,
+    Event, ProtocolError, Request, WlBuffer, WlBufferEvent, WlBufferRequest, WlCallback,
+    WlCallbackEvent, WlCompositor, WlCompositorRequest, WlDisplay, WlDisplayEvent,
+    WlDisplayRequest, WlKeyboard, WlKeyboardEvent, WlOutput, WlOutputEvent, WlPointer,
+    WlPointerEvent, WlPointerRequest, WlRegistry, WlRegistryEvent, WlRegistryRequest, WlSeat,
+    WlSeatEvent, WlSeatRequest, WlShm, WlShmEvent, WlShmPool, WlShmPoolRequest, WlShmRequest,
+    WlSubcompositor, WlSubcompositorRequest, WlSubsurface, WlSubsurfaceRequest, WlSurface,
+    WlSurfaceEvent, WlSurfaceRequest, WlTouchEvent, WpCursorShapeDeviceV1,
+    WpCursorShapeDeviceV1Request, WpCursorShapeManagerV1, WpCursorShapeManagerV1Request,
+    WpFractionalScaleManagerV1, WpFractionalScaleManagerV1Request, WpFractionalScaleV1,
+    WpFractionalScaleV1Event, WpSinglePixelBufferManagerV1, WpSinglePixelBufferManagerV1Request,
+    WpViewport, WpViewportRequest, WpViewporter, WpViewporterRequest, ZwlrLayerShellV1,
+    ZwlrLayerShellV1Request, ZwlrLayerSurfaceV1, ZwlrLayerSurfaceV1Event,
+    ZwlrLayerSurfaceV1Request, ZwlrScreencopyFrameV1, ZwlrScreencopyFrameV1Event,
+    ZwlrScreencopyFrameV1Request, ZwlrScreencopyManagerV1, ZwlrScreencopyManagerV1Request,
+    ZwlrVirtualPointerManagerV1, ZwlrVirtualPointerManagerV1Request, ZwlrVirtualPointerV1,
+    ZwlrVirtualPointerV1Request, ZxdgOutputManagerV1, ZxdgOutputManagerV1Request, ZxdgOutputV1,
+    ZxdgOutputV1Event, WL_KEYBOARD_KEYMAP_FORMAT_XKB_V1, WL_KEYBOARD_KEY_STATE_PRESSED,
+    WL_KEYBOARD_KEY_STATE_RELEASED, WL_POINTER_AXIS_HORIZONTAL_SCROLL,
+    WL_POINTER_AXIS_VERTICAL_SCROLL, WL_POINTER_BUTTON_STATE_PRESSED,
+    WL_POINTER_BUTTON_STATE_RELEASED, WL_SEAT_CAPABILITY_KEYBOARD, WL_SEAT_CAPABILITY_POINTER,
+    WL_SHM_FORMAT_ABGR8888, WL_SHM_FORMAT_ARGB8888, WL_SHM_FORMAT_XBGR8888, WL_SHM_FORMAT_XRGB8888,
+    WP_CURSOR_SHAPE_DEVICE_V1_SHAPE_CROSSHAIR, ZWLR_LAYER_SHELL_V1_LAYER_OVERLAY,
+    ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM, ZWLR_LAYER_SURFACE_V1_ANCHOR_LEFT,
+    ZWLR_LAYER_SURFACE_V1_ANCHOR_RIGHT, ZWLR_LAYER_SURFACE_V1_ANCHOR_TOP,
+    ZWLR_LAYER_SURFACE_V1_KEYBOARD_INTERACTIVITY_EXCLUSIVE,
+    ZWLR_SCREENCOPY_FRAME_V1_FLAGS_Y_INVERT,
 };
 use xkbcommon::xkb;
 
@@ -72,26 +95,217 @@ struct App {
     outputs: TypedHandleMap<Output>,
     buffers: TypedHandleMap<Buffer>,
     config: Config,
+    mode: ModeId,
+    /// The hint label typed so far, if a hint is being typed.
+    hint_input: Option<String>,
     region: Region,
-    region_history: Vec<Region>,
+    /// Previous regions for undo, oldest first.
+    region_history: VecDeque<Region>,
+    /// The region shown when the current transition started, and when that was.
+    animation: Option<(Region, Instant)>,
+    /// When the overlay started fading out after a click, if it has.
+    fading_out: Option<Instant>,
     global_bounds: Region,
     ei_state: EiState,
+    /// The font for labels, loaded when first needed, or `None` if it couldn't be loaded.
+    font: OnceCell<Option<Font>>,
+    /// Single-pixel buffers by premultiplied color, shared by all rectangles of that color.
+    single_pixel_buffers: HashMap<[u8; 4], WlBuffer>,
+    magnifier: Magnifier,
+    /// The pixel formats the compositor supports for shm buffers.
+    shm_formats: HashSet<u32>,
+}
+
+/// Copies of the screen around the region's center, shown enlarged next to it.
+#[derive(Default)]
+struct Magnifier {
+    /// The copy in progress, or null if there's none.
+    frame: ZwlrScreencopyFrameV1,
+    /// The output and output-local area of the copy in progress.
+    pending: Option<(OutputId, Region)>,
+    /// Whether the copy in progress is upside down.
+    y_invert: bool,
+    /// The buffer the screen is copied into, kept busy so no surface draws into it.
+    buffer: Option<BufferId>,
+    format: u32,
+    stride: i32,
+    /// The output and output-local area of the last copy, whether or not it succeeded.
+    copied: Option<(OutputId, Region)>,
+    /// The last successful copy.
+    image: Option<tiny_skia::Pixmap>,
 }
 
 #[derive(Default)]
 struct EiState {
-    sequence: u32,
+    /// What the EIS implementation sent in `ei_connection.disconnected`, if it did.
+    disconnected: Option<ei::Disconnected>,
     last_serial: u32,
     seat_capabilities: HashMap<u64, u64>,
     devices: HashMap<u64, EiDeviceInterfaces>,
 }
 
+impl EiState {
+    /// Chooses the device to emulate pointer motion, buttons and scrolling at `point` with,
+    /// returning it along with the point in the EIS implementation's coordinates.
+    ///
+    /// That's the device with a region containing the point if there is one, then one that
+    /// didn't announce any regions, then any that can move the pointer absolutely, and only
+    /// then one that can move it relatively, which is less precise.
+    fn pointer_device_at(
+        &self,
+        outputs: &[Region],
+        point: Point,
+    ) -> Option<(EiDevice, (f32, f32))> {
+        let (x, y) = self.position(outputs, point);
+        let contains = |region: &Region| {
+            let (left, top) = (region.x as f32, region.y as f32);
+            x >= left
+                && x < left + region.width as f32
+                && y >= top
+                && y < top + region.height as f32
+        };
+        let devices = || {
+            self.devices
+                .values()
+                .filter(|data| data.pointer_absolute.id() != 0)
+        };
+        let device = devices()
+            .find(|data| data.regions.iter().any(contains))
+            .or_else(|| devices().find(|data| data.regions.is_empty()))
+            .or_else(|| devices().next())
+            .or_else(|| self.devices.values().find(|data| data.pointer.id() != 0))?;
+        Some((device.device, (x, y)))
+    }
+
+    fn start_emulating<'a>(
+        &mut self,
+        ei_conn: &'a mut LibeiConnection,
+        device: EiDevice,
+    ) -> ei::Emulating<'a, LibeiConnection> {
+        let data = self.devices.get_mut(&device.id()).unwrap();
+        data.emulation.start_emulating(ei_conn, self.last_serial)
+    }
+
+    /// Moves the pointer to `(x, y)`, in the EIS implementation's coordinates, with `device`.
+    ///
+    /// Where the pointer is isn't known, so a device that can only move it relatively first
+    /// moves it past the top-left corner of the desktop, where it stops, then from there.
+    fn move_pointer(
+        &self,
+        emulating: &mut ei::Emulating<LibeiConnection>,
+        device: EiDevice,
+        (x, y): (f32, f32),
+    ) {
+        let data = &self.devices[&device.id()];
+        if data.pointer_absolute.id() != 0 {
+            emulating
+                .conn()
+                .send(EiPointerAbsoluteRequest::MotionAbsolute {
+                    ei_pointer_absolute: data.pointer_absolute,
+                    x,
+                    y,
+                });
+            emulating.frame();
+        } else if data.pointer.id() != 0 {
+            let bounds = self
+                .devices
+                .values()
+                .flat_map(|data| &data.regions)
+                .copied()
+                .reduce(|a, b| a.union(&b));
+            // Without regions, the desktop is assumed to start at the origin and be smaller
+            // than this.
+            let bounds = bounds.unwrap_or(Region {
+                x: 0,
+                y: 0,
+                width: 1 << 16,
+                height: 1 << 16,
+            });
+            emulating.conn().send(EiPointerRequest::MotionRelative {
+                ei_pointer: data.pointer,
+                x: -(bounds.right() as f32),
+                y: -(bounds.bottom() as f32),
+            });
+            emulating.frame();
+            emulating.conn().send(EiPointerRequest::MotionRelative {
+                ei_pointer: data.pointer,
+                x: x - bounds.x as f32,
+                y: y - bounds.y as f32,
+            });
+            emulating.frame();
+        }
+    }
+
+    /// Converts a point in the compositor's logical coordinates into the EIS implementation's,
+    /// whose regions can have a different origin and size than the outputs they're for.
+    ///
+    /// A region is matched to the output containing the point by its area, then its position,
+    /// then by order if there are as many regions as outputs. Points that can't be matched are
+    /// passed through unchanged.
+    fn position(&self, outputs: &[Region], point: Point) -> (f32, f32) {
+        let unchanged = (point.x as f32, point.y as f32);
+        let Some(output) = outputs
+            .iter()
+            .find(|output| output.contains(point.x, point.y))
+        else {
+            return unchanged;
+        };
+        let by_position = |a: &&Region, b: &&Region| (a.y, a.x).cmp(&(b.y, b.x));
+        let mut regions: Vec<&Region> = self
+            .devices
+            .values()
+            .flat_map(|data| &data.regions)
+            .collect();
+        regions.sort_by(by_position);
+        regions.dedup();
+        let region = regions
+            .iter()
+            .find(|region| **region == output)
+            .or_else(|| {
+                regions
+                    .iter()
+                    .find(|region| (region.x, region.y) == (output.x, output.y))
+            })
+            .or_else(|| {
+                let mut outputs: Vec<&Region> = outputs.iter().collect();
+                outputs.sort_by(by_position);
+                let index = outputs.iter().position(|other| *other == output)?;
+                (regions.len() == outputs.len()).then(|| &regions[index])
+            });
+        let Some(region) = region else {
+            return unchanged;
+        };
+        let map = |point: i32, from: i32, from_len: i32, to: i32, to_len: i32| {
+            let offset = f64::from(point - from) * f64::from(to_len) / f64::from(from_len);
+            (f64::from(to) + offset) as f32
+        };
+        (
+            map(point.x, output.x, output.width, region.x, region.width),
+            map(point.y, output.y, output.height, region.y, region.height),
+        )
+    }
+
+    fn keyboard_device(&mut self, keyboard: EiKeyboard) -> Option<&mut EiDeviceInterfaces> {
+        self.devices
+            .values_mut()
+            .find(|data| data.keyboard.id() == keyboard.id())
+    }
+}
+
 #[derive(Default)]
 struct EiDeviceInterfaces {
     device: EiDevice,
+    /// Numbers the device's emulation sequences.
+    emulation: ei::Device,
     pointer_absolute: EiPointerAbsolute,
+    pointer: EiPointer,
     button: EiButton,
     scroll: EiScroll,
+    keyboard: EiKeyboard,
+    /// The areas of the EIS implementation's desktop the device can send events for.
+    regions: Vec<Region>,
+    /// The keyboard's keymap, once the EIS implementation sends one.
+    keymap: Option<ei::keyboard::Keyboard>,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -113,6 +327,22 @@ struct Globals {
     xdg_output: ZxdgOutputManagerV1,
     layer_shell: ZwlrLayerShellV1,
     virtual_pointer_manager: ZwlrVirtualPointerManagerV1,
+    /// Null if the compositor doesn't support fractional scaling, as are the globals below.
+    fractional_scale_manager: WpFractionalScaleManagerV1,
+    viewporter: WpViewporter,
+    subcompositor: WlSubcompositor,
+    single_pixel_buffer_manager: WpSinglePixelBufferManagerV1,
+    cursor_shape_manager: WpCursorShapeManagerV1,
+    screencopy_manager: ZwlrScreencopyManagerV1,
+}
+
+impl Globals {
+    /// Returns whether the overlay can be built from single-pixel buffers.
+    fn supports_single_pixel(&self) -> bool {
+        !self.viewporter.is_null()
+            && !self.subcompositor.is_null()
+            && !self.single_pixel_buffer_manager.is_null()
+    }
 }
 
 struct Seat {
@@ -121,16 +351,30 @@ struct Seat {
     xkb: xkb::Context,
     xkb_state: Option<xkb::State>,
     keyboard: WlKeyboard,
+    /// Only requested when pointer buttons are bound.
+    pointer: WlPointer,
+    /// Null unless the compositor supports cursor-shape-v1 and the crosshair cursor is used.
+    cursor_shape_device: WpCursorShapeDeviceV1,
     buttons_down: HashSet<u32>,
     mod_indices: ModIndices,
-    specialized_bindings: HashMap<(xkb::ModMask, xkb::Keycode), Vec<Cmd>>,
+    /// Bindings for each mode, indexed by mode id.
+    specialized_bindings: Vec<SpecializedBindings>,
     repeat_period: Duration,
     repeat_delay: Duration,
     key_repeat: Option<(Instant, xkb::Keycode)>,
+    /// Keys pressed so far of a sequence binding.
+    sequence: Vec<(xkb::ModMask, xkb::Keycode)>,
+    /// When the pending sequence ends and what its keys so far are bound to runs.
+    sequence_timeout: Option<Instant>,
+    /// A key that can start a chord, held back until the chord window ends, with its press time.
+    pending_chord: Option<(Instant, u32, u32)>,
 }
 
 #[derive(Default)]
 struct Output {
+    name: Option<String>,
+    /// The appearance with the overrides for the output, once its name is known.
+    appearance: Option<Appearance>,
     surface: Option<Surface>,
     wl_output: WlOutput,
     xdg_output: ZxdgOutputV1,
@@ -151,15 +395,58 @@ struct Surface {
     output: OutputId,
     wl_surface: WlSurface,
     layer_surface: ZwlrLayerSurfaceV1,
+    fractional_scale: WpFractionalScaleV1,
+    viewport: WpViewport,
+    /// The scale the compositor prefers in 120ths, used instead of the output's integer scale.
+    preferred_scale: Option<u32>,
+    /// The integer scale the compositor prefers for the surface, which is more reliable than the
+    /// output's when both are known.
+    preferred_buffer_scale: Option<u32>,
+    /// Whether a frame callback is pending, when changes made until then are drawn.
+    frame_pending: bool,
+    /// What the surface was last drawn with.
+    drawn: Option<DrawnState>,
+    /// Subsurfaces showing the rectangles of the overlay with the single-pixel renderer, bottom
+    /// to top.
+    rects: Vec<RectSurface>,
     width: u32,
     height: u32,
 }
 
+/// A subsurface showing a single-pixel buffer scaled to a rectangle.
+struct RectSurface {
+    wl_surface: WlSurface,
+    subsurface: WlSubsurface,
+    viewport: WpViewport,
+    /// Whether a buffer is attached.
+    visible: bool,
+}
+
+/// Everything a surface's contents depend on that changes while waypoint runs.
+#[derive(Clone, PartialEq)]
+struct DrawnState {
+    /// The region, or `None` if it's nowhere near the output.
+    region: Option<Region>,
+    sequence_pending: bool,
+    hint_input: Option<String>,
+    /// The area the magnifier shows, if it's shown on the output.
+    magnified: Option<Region>,
+    /// The heads-up display text, if it's shown on the output.
+    hud: Option<String>,
+    opacity: f32,
+}
+
 #[derive(Default)]
 struct Buffer {
     pool: WlShmPool,
     wl_buffer: WlBuffer,
     mmap: Option<MmapMut>,
+    /// The output whose surface the buffer is drawn for.
+    output: OutputId,
+    width: i32,
+    height: i32,
+    /// Whether the buffer is attached and not yet released by the compositor.
+    busy: bool,
 }
 
 #[derive(Default)]
@@ -188,16 +475,29 @@ impl Default for Seat {
             virtual_pointer: Default::default(),
             xkb_state: Default::default(),
             keyboard: Default::default(),
+            pointer: Default::default(),
+            cursor_shape_device: Default::default(),
             buttons_down: Default::default(),
             mod_indices: Default::default(),
             specialized_bindings: Default::default(),
             key_repeat: Default::default(),
+            sequence: Default::default(),
+            sequence_timeout: Default::default(),
+            pending_chord: Default::default(),
             repeat_period: Default::default(),
             repeat_delay: Default::default(),
         }
     }
 }
 
+/// The logical areas of the outputs whose geometry is known.
+fn output_regions(outputs: &TypedHandleMap<Output>) -> Vec<Region> {
+    let outputs = outputs
+        .iter()
+        .filter(|output| output.state.current.is_some());
+    outputs.map(Output::region).collect()
+}
+
 impl Output {
     fn region(&self) -> Region {
         let current = self.state.current.as_ref().unwrap();
@@ -217,360 +517,901 @@ fn handle_key_pressed(
     seat_id: SeatId,
     conn: &mut WaylandConnection,
     ei_conn: Option<&mut LibeiConnection>,
-) {
+) -> Result<()> {
+    let seat = &mut state.seats[seat_id];
+
+    let keycode = key + 8;
+    let Some(mod_mask) = mod_mask(seat, key) else {
+        return Ok(());
+    };
+
+    let cmds = match state.hint_input.as_mut() {
+        Some(input) => {
+            let text = seat.xkb_state.as_ref().unwrap().key_get_utf8(keycode);
+            let hints = &state.config.hints;
+            match text.chars().next() {
+                // Keys that don't produce text, like modifiers, don't affect the hint.
+                None => Vec::new(),
+                Some(c) if hints.chars.contains(&c) => {
+                    input.push(c);
+                    if input.chars().count() < hints.length as usize {
+                        Vec::new()
+                    } else {
+                        let cell = hints.index_of(input);
+                        state.hint_input = None;
+                        cell.map(Cmd::Cell).into_iter().collect()
+                    }
+                }
+                Some(_) => {
+                    state.hint_input = None;
+                    Vec::new()
+                }
+            }
+        }
+        None => {
+            let keysym = seat.xkb_state.as_ref().unwrap().key_get_one_sym(keycode);
+            let Some(bindings) = seat.specialized_bindings.get(state.mode) else {
+                return Ok(());
+            };
+            // Modifiers may be needed for later keys, so they don't break sequences.
+            if !seat.sequence.is_empty() && is_modifier_keysym(keysym) {
+                return Ok(());
+            }
+
+            let mut cmds = Vec::new();
+            let mut keys = std::mem::take(&mut seat.sequence);
+            keys.push((mod_mask, keycode));
+            let mut node = lookup_sequence(bindings, &keys);
+            if node.is_none() && keys.len() > 1 {
+                // The sequence was abandoned, so run what its keys so far are bound to.
+                let pending = lookup_sequence(bindings, &keys[..keys.len() - 1]);
+                cmds.extend(
+                    pending
+                        .and_then(|node| node.cmds.clone())
+                        .unwrap_or_default(),
+                );
+                keys.drain(..keys.len() - 1);
+                node = lookup_sequence(bindings, &keys);
+            }
+
+            match node {
+                Some(node) if !node.next.is_empty() => {
+                    seat.sequence = keys;
+                    seat.sequence_timeout = match state.config.sequence_timeout {
+                        0 => None,
+                        timeout => Some(Instant::now() + Duration::from_millis(timeout.into())),
+                    };
+                    if cmds.is_empty() {
+                        state.draw_all(conn)?;
+                        return Ok(());
+                    }
+                }
+                node => {
+                    seat.sequence_timeout = None;
+                    cmds.extend(node.and_then(|node| node.cmds.clone()).unwrap_or_default());
+                }
+            }
+            cmds
+        }
+    };
+
+    run_cmds(state, time, &cmds, seat_id, conn, ei_conn)
+}
+
+/// Handles a key press, holding back keys that can start a chord until the chord window ends.
+/// Returns whether the key completed a chord.
+fn handle_key_down(
+    state: &mut App,
+    time: u32,
+    key: u32,
+    seat_id: SeatId,
+    conn: &mut WaylandConnection,
+    mut ei_conn: Option<&mut LibeiConnection>,
+) -> Result<bool> {
+    let seat = &mut state.seats[seat_id];
+    let pending = seat.pending_chord.take();
+    // Chords only start bindings, and aren't matched while typing a hint.
+    let node = match mod_mask(seat, key) {
+        Some(mod_mask) if state.hint_input.is_none() && seat.sequence.is_empty() => seat
+            .specialized_bindings
+            .get(state.mode)
+            .and_then(|bindings| bindings.get(&(mod_mask, key + 8))),
+        _ => None,
+    };
+    let starts_chord = node.is_some_and(|node| !node.chords.is_empty());
+    let chord_cmds = pending
+        .and_then(|(_, pending_key, _)| node?.chords.get(&(pending_key + 8)))
+        .cloned();
+
+    match pending {
+        Some(_) if chord_cmds.is_some() => {
+            run_cmds(state, time, &chord_cmds.unwrap(), seat_id, conn, ei_conn)?;
+            return Ok(true);
+        }
+        // Not a chord, so both keys act on their own.
+        Some((_, pending_key, pending_time)) => handle_key_pressed(
+            state,
+            pending_time,
+            pending_key,
+            seat_id,
+            conn,
+            ei_conn.as_deref_mut(),
+        )?,
+        None if starts_chord => {
+            let window = Duration::from_millis(state.config.chord_timeout.into());
+            seat.pending_chord = Some((Instant::now() + window, key, time));
+            return Ok(false);
+        }
+        None => {}
+    }
+
+    handle_key_pressed(state, time, key, seat_id, conn, ei_conn)?;
+    Ok(false)
+}
+
+/// Handles the held back first key of a possible chord on its own.
+fn flush_pending_chord(
+    state: &mut App,
+    seat_id: SeatId,
+    conn: &mut WaylandConnection,
+    ei_conn: Option<&mut LibeiConnection>,
+) -> Result<()> {
+    if let Some((_, key, time)) = state.seats[seat_id].pending_chord.take() {
+        handle_key_pressed(state, time, key, seat_id, conn, ei_conn)?;
+    }
+    Ok(())
+}
+
+/// Returns the relevant modifiers that are active, or `None` before the keymap is known.
+fn mod_mask(seat: &Seat, key: u32) -> Option<xkb::ModMask> {
+    let xkb_state = seat.xkb_state.as_ref()?;
+    let keymap = xkb_state.get_keymap();
+    let mut mod_mask: xkb::ModMask = 0;
+    for i in 0..keymap.num_mods() {
+        let is_active = xkb_state.mod_index_is_active(i, xkb::STATE_MODS_EFFECTIVE);
+        let _is_consumed = xkb_state.mod_index_is_consumed(key, i);
+        let is_relevant = i != seat.mod_indices.caps && i != seat.mod_indices.num;
+        if is_active && is_relevant {
+            mod_mask |= 1 << i;
+        }
+    }
+    Some(mod_mask)
+}
+
+/// Ends a pending sequence once it times out, running what its keys so far are bound to.
+fn handle_sequence_timeout(
+    state: &mut App,
+    seat_id: SeatId,
+    conn: &mut WaylandConnection,
+    ei_conn: Option<&mut LibeiConnection>,
+) -> Result<()> {
+    let seat = &mut state.seats[seat_id];
+    seat.sequence_timeout = None;
+    let keys = std::mem::take(&mut seat.sequence);
+    let cmds = seat
+        .specialized_bindings
+        .get(state.mode)
+        .and_then(|bindings| lookup_sequence(bindings, &keys))
+        .and_then(|node| node.cmds.clone());
+    match cmds {
+        Some(cmds) => run_cmds(state, 0, &cmds, seat_id, conn, ei_conn),
+        None => state.draw_all(conn),
+    }
+}
+
+fn is_modifier_keysym(keysym: xkb::Keysym) -> bool {
+    (xkb::KEY_Shift_L..=xkb::KEY_Hyper_R).contains(&keysym)
+        || (xkb::KEY_ISO_Lock..=xkb::KEY_ISO_Last_Group_Lock).contains(&keysym)
+        || keysym == xkb::KEY_Mode_switch
+}
+
+fn handle_button_pressed(
+    state: &mut App,
+    time: u32,
+    button: u32,
+    seat_id: SeatId,
+    conn: &mut WaylandConnection,
+    ei_conn: Option<&mut LibeiConnection>,
+) -> Result<()> {
+    if state.hint_input.is_some() {
+        return Ok(());
+    }
+    let Some(cmds) = state.config.button_binding(state.mode, button) else {
+        return Ok(());
+    };
+    let cmds = cmds.to_vec();
+    run_cmds(state, time, &cmds, seat_id, conn, ei_conn)
+}
+
+/// Runs the commands of a binding, then redraws and emits the resulting pointer input.
+fn run_cmds(
+    state: &mut App,
+    time: u32,
+    cmds: &[Cmd],
+    seat_id: SeatId,
+    conn: &mut WaylandConnection,
+    ei_conn: Option<&mut LibeiConnection>,
+) -> Result<()> {
     fn update(
         region: &mut Region,
-        region_history: &mut Vec<Region>,
+        region_history: &mut VecDeque<Region>,
+        history_limit: u32,
         global_bounds: Region,
-        cut: fn(Region) -> Region,
+        cut: impl FnOnce(Region) -> Region,
     ) {
-        region_history.push(*region);
+        if history_limit != 0 && region_history.len() >= history_limit as usize {
+            region_history.pop_front();
+        }
+        region_history.push_back(*region);
         let new_region = cut(*region);
         if global_bounds.contains_region(&new_region) {
             *region = new_region;
         }
     }
 
-    let seat = &mut state.seats[seat_id];
-
-    let keycode = key + 8;
-    let mod_mask = {
-        let Some(xkb_state) = seat.xkb_state.as_mut() else {
-            return;
-        };
-        let keymap = xkb_state.get_keymap();
-        let mut mod_mask: xkb::ModMask = 0;
-        for i in 0..keymap.num_mods() {
-            let is_active = xkb_state.mod_index_is_active(i, xkb::STATE_MODS_EFFECTIVE);
-            let _is_consumed = xkb_state.mod_index_is_consumed(key, i);
-            let is_relevant = i != seat.mod_indices.caps && i != seat.mod_indices.num;
-            if is_active && is_relevant {
-                mod_mask |= 1 << i;
-            }
-        }
-        mod_mask
-    };
+    // Nothing more happens once the overlay is fading out.
+    if state.fading_out.is_some() {
+        return Ok(());
+    }
 
     let mut should_press = None;
     let mut should_release = None;
     let mut should_scroll = Vec::new();
+    let now = Instant::now();
+    let shown_region = state.shown_region(now);
+    let old_region = state.region;
 
-    for cmd in seat
-        .specialized_bindings
-        .get(&(mod_mask, keycode))
-        .map(Vec::as_slice)
-        .unwrap_or_default()
-    {
-        match *cmd {
+    for &cmd in cmds {
+        match cmd {
             Cmd::Quit => {
                 state.quit = true;
             }
             Cmd::Undo => {
-                if let Some(region) = state.region_history.pop() {
+                if let Some(region) = state.region_history.pop_back() {
                     state.region = region;
                 }
             }
-            Cmd::Cut(dir) => update(
-                &mut state.region,
-                &mut state.region_history,
-                state.global_bounds,
-                match dir {
-                    Direction::Up => Region::cut_up,
-                    Direction::Down => Region::cut_down,
-                    Direction::Left => Region::cut_left,
-                    Direction::Right => Region::cut_right,
-                },
-            ),
+            Cmd::Cut(dir) => {
+                let Grid { cols, rows } = state.config.grid;
+                update(
+                    &mut state.region,
+                    &mut state.region_history,
+                    state.config.history_limit,
+                    state.global_bounds,
+                    |region| match dir {
+                        Direction::Up => region.cell(1, rows, 0, 0),
+                        Direction::Down => region.cell(1, rows, 0, rows - 1),
+                        Direction::Left => region.cell(cols, 1, 0, 0),
+                        Direction::Right => region.cell(cols, 1, cols - 1, 0),
+                    },
+                )
+            }
+            Cmd::Cell(index) => {
+                let Grid { cols, rows } = state.config.grid;
+                if index < cols * rows {
+                    update(
+                        &mut state.region,
+                        &mut state.region_history,
+                        state.config.history_limit,
+                        state.global_bounds,
+                        |region| region.cell(cols, rows, index % cols, index / cols),
+                    )
+                } else {
+                    log::debug!("no cell {} in the {cols}x{rows} grid", index + 1);
+                }
+            }
             Cmd::Move(dir) => update(
                 &mut state.region,
                 &mut state.region_history,
+                state.config.history_limit,
                 state.global_bounds,
-                match dir {
-                    Direction::Up => Region::move_up,
-                    Direction::Down => Region::move_down,
-                    Direction::Left => Region::move_left,
-                    Direction::Right => Region::move_right,
+                |region| {
+                    let distance = state.config.move_distance;
+                    match dir {
+                        Direction::Up => region.move_up(distance.resolve(region.height)),
+                        Direction::Down => region.move_down(distance.resolve(region.height)),
+                        Direction::Left => region.move_left(distance.resolve(region.width)),
+                        Direction::Right => region.move_right(distance.resolve(region.width)),
+                    }
                 },
             ),
             Cmd::Click(btn) => {
-                should_press = Some(btn.code());
-                should_release = Some(btn.code());
-                state.quit = true;
+                should_press = Some(state.config.button_code(btn));
+                should_release = Some(state.config.button_code(btn));
+                if state.config.appearance.fade_out == 0 {
+                    state.quit = true;
+                } else {
+                    state.fading_out = Some(now);
+                }
             }
             Cmd::Press(btn) => {
-                should_press = Some(btn.code());
+                should_press = Some(state.config.button_code(btn));
             }
             Cmd::Release(btn) => {
-                should_release = Some(btn.code());
+                should_release = Some(state.config.button_code(btn));
             }
             Cmd::Scroll(axis, amount) => {
-                should_scroll.push((axis, amount));
+                should_scroll.push((axis, amount * state.config.scroll_speed.for_axis(axis)));
+            }
+            Cmd::Mode(mode) => {
+                state.mode = mode;
+            }
+            Cmd::Hint => {
+                state.hint_input = Some(String::new());
             }
         }
     }
 
-    for output in state.outputs.iter() {
-        let surface = output.surface.as_ref().unwrap();
-        draw(
-            &state.globals,
-            &mut state.buffers,
-            conn,
-            output.state.current.as_ref().unwrap().integer_scale,
-            surface,
-            Region {
-                x: state.region.x - output.state.current.unwrap().logical_x,
-                y: state.region.y - output.state.current.unwrap().logical_y,
-                ..state.region
-            },
-        )
-        .unwrap();
+    if state.config.appearance.animate && state.region != old_region {
+        state.animation = Some((shown_region, now));
+    }
+    // A fade-out is drawn from the main loop once the click is sent, so it doesn't delay it.
+    if state.fading_out.is_none() {
+        state.draw_all(conn)?;
     }
 
-    if !seat.virtual_pointer.is_null() {
-        conn.send(ZwlrVirtualPointerV1Request::MotionAbsolute {
-            zwlr_virtual_pointer_v1: seat.virtual_pointer,
-            time,
-            x: state.region.center().x as u32,
-            y: state.region.center().y as u32,
-            x_extent: state.global_bounds.width as u32,
-            y_extent: state.global_bounds.height as u32,
-        });
-        conn.send(ZwlrVirtualPointerV1Request::Frame {
-            zwlr_virtual_pointer_v1: seat.virtual_pointer,
-        });
+    let acts = should_press.is_some() || should_release.is_some() || !should_scroll.is_empty();
+    if state.config.warp == Warp::Never && acts {
+        let Point { x, y } = state.region.center();
+        println!("{x} {y}");
+    }
 
-        for (axis, amount) in should_scroll {
-            conn.send(ZwlrVirtualPointerV1Request::Axis {
-                zwlr_virtual_pointer_v1: seat.virtual_pointer,
-                time,
-                axis,
-                value: wayland::Fixed::from(amount as f32),
-            });
+    let seat = &mut state.seats[seat_id];
+    let events = pointer_events(
+        state.config.warp,
+        &mut seat.buttons_down,
+        should_press,
+        should_release,
+        should_scroll,
+    );
+
+    if !seat.virtual_pointer.is_null() {
+        for event in events {
+            match event {
+                PointerEvent::Motion => {
+                    conn.send(ZwlrVirtualPointerV1Request::MotionAbsolute {
+                        zwlr_virtual_pointer_v1: seat.virtual_pointer,
+                        time,
+                        x: state.region.center().x as u32,
+                        y: state.region.center().y as u32,
+                        x_extent: state.global_bounds.width as u32,
+                        y_extent: state.global_bounds.height as u32,
+                    })?;
+                }
+                PointerEvent::Axis(axis, amount) => {
+                    conn.send(ZwlrVirtualPointerV1Request::Axis {
+                        zwlr_virtual_pointer_v1: seat.virtual_pointer,
+                        time,
+                        axis,
+                        value: wayland::Fixed::from(amount as f32),
+                    })?;
+                }
+                PointerEvent::Press(button) | PointerEvent::Release(button) => {
+                    conn.send(ZwlrVirtualPointerV1Request::Button {
+                        zwlr_virtual_pointer_v1: seat.virtual_pointer,
+                        time,
+                        button,
+                        state: if matches!(event, PointerEvent::Press(_)) {
+                            WL_POINTER_BUTTON_STATE_PRESSED
+                        } else {
+                            WL_POINTER_BUTTON_STATE_RELEASED
+                        },
+                    })?;
+                }
+            }
             conn.send(ZwlrVirtualPointerV1Request::Frame {
                 zwlr_virtual_pointer_v1: seat.virtual_pointer,
-            });
+            })?;
         }
+    } else if let (Some(ei_conn), Some((device, (x, y)))) = (
+        ei_conn,
+        state
+            .ei_state
+            .pointer_device_at(&output_regions(&state.outputs), state.region.center()),
+    ) {
+        let data = &state.ei_state.devices[&device.id()];
+        let (button, scroll) = (data.button, data.scroll);
+        let mut emulating = state.ei_state.start_emulating(ei_conn, device);
 
-        if let Some(button) = should_press {
-            if seat.buttons_down.insert(button) {
-                conn.send(ZwlrVirtualPointerV1Request::Button {
-                    zwlr_virtual_pointer_v1: seat.virtual_pointer,
-                    time,
-                    button,
-                    state: WL_POINTER_BUTTON_STATE_PRESSED,
-                });
-                conn.send(ZwlrVirtualPointerV1Request::Frame {
-                    zwlr_virtual_pointer_v1: seat.virtual_pointer,
-                });
+        for event in events {
+            match event {
+                PointerEvent::Motion => {
+                    state.ei_state.move_pointer(&mut emulating, device, (x, y));
+                    continue;
+                }
+                PointerEvent::Axis(axis, amount) => {
+                    emulating.conn().send(EiScrollRequest::Scroll {
+                        ei_scroll: scroll,
+                        x: if axis == WL_POINTER_AXIS_HORIZONTAL_SCROLL {
+                            amount as f32
+                        } else {
+                            0.0
+                        },
+                        y: if axis == WL_POINTER_AXIS_VERTICAL_SCROLL {
+                            amount as f32
+                        } else {
+                            0.0
+                        },
+                    });
+                }
+                PointerEvent::Press(button_index) => {
+                    emulating.conn().send(EiButtonRequest::Button {
+                        ei_button: button,
+                        button: button_index,
+                        state: EI_BUTTON_BUTTON_STATE_PRESS,
+                    });
+                }
+                PointerEvent::Release(button_index) => {
+                    emulating.conn().send(EiButtonRequest::Button {
+                        ei_button: button,
+                        button: button_index,
+                        state: EI_BUTTON_BUTTON_STATE_RELEASED,
+                    });
+                }
             }
+            emulating.frame();
         }
+    }
+    Ok(())
+}
 
-        if let Some(button) = should_release {
-            if seat.buttons_down.remove(&button) {
-                conn.send(ZwlrVirtualPointerV1Request::Button {
-                    zwlr_virtual_pointer_v1: seat.virtual_pointer,
-                    time,
-                    button,
-                    state: WL_POINTER_BUTTON_STATE_RELEASED,
-                });
-                conn.send(ZwlrVirtualPointerV1Request::Frame {
-                    zwlr_virtual_pointer_v1: seat.virtual_pointer,
-                });
-            }
-        }
-    } else if let (
-        Some(ei_conn),
-        Some(&EiDeviceInterfaces {
-            device,
-            pointer_absolute,
-            button,
-            scroll,
-        }),
-    ) = (ei_conn, state.ei_state.devices.values().next())
-    {
-        ei_conn.send(EiDeviceRequest::StartEmulating {
-            ei_device: device,
-            last_serial: state.ei_state.last_serial,
-            sequence: state.ei_state.sequence,
-        });
-        state.ei_state.sequence += 1;
+/// Pointer input emulated at the end of a batch of commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PointerEvent {
+    /// Moves the pointer to the center of the region.
+    Motion,
+    Axis(u32, f64),
+    Press(u32),
+    Release(u32),
+}
 
-        ei_conn.send(EiPointerAbsoluteRequest::MotionAbsolute {
-            ei_pointer_absolute: pointer_absolute,
-            x: state.region.center().x as f32,
-            y: state.region.center().y as f32,
-        });
-        ei_conn.send(EiDeviceRequest::Frame {
-            ei_device: device,
-            last_serial: state.ei_state.last_serial,
-            timestamp: time.into(),
-        });
+/// Returns the pointer input to emulate for a batch of commands, in the order it's sent. Presses
+/// of buttons that are already down and releases of ones that aren't are left out, and nothing is
+/// sent if the pointer is never warped, since it isn't where the region is.
+fn pointer_events(
+    warp: Warp,
+    buttons_down: &mut HashSet<u32>,
+    press: Option<u32>,
+    release: Option<u32>,
+    scroll: Vec<(u32, f64)>,
+) -> Vec<PointerEvent> {
+    let acts = press.is_some() || release.is_some() || !scroll.is_empty();
+    let warp = match warp {
+        Warp::Always => true,
+        Warp::Click => acts,
+        Warp::Never => return Vec::new(),
+    };
+    let mut events = Vec::new();
+    if warp {
+        events.push(PointerEvent::Motion);
+    }
+    let scroll = scroll.into_iter();
+    events.extend(scroll.map(|(axis, amount)| PointerEvent::Axis(axis, amount)));
+    let press = press.filter(|&button| buttons_down.insert(button));
+    events.extend(press.map(PointerEvent::Press));
+    let release = release.filter(|button| buttons_down.remove(button));
+    events.extend(release.map(PointerEvent::Release));
+    events
+}
 
-        for (axis, amount) in should_scroll {
-            ei_conn.send(EiScrollRequest::Scroll {
-                ei_scroll: scroll,
-                x: if axis == WL_POINTER_AXIS_HORIZONTAL_SCROLL {
-                    amount as f32
-                } else {
-                    0.0
-                },
-                y: if axis == WL_POINTER_AXIS_VERTICAL_SCROLL {
-                    amount as f32
-                } else {
-                    0.0
-                },
-            });
-            ei_conn.send(EiDeviceRequest::Frame {
-                ei_device: device,
-                last_serial: state.ei_state.last_serial,
-                timestamp: time.into(),
-            });
+/// Returns the rectangles making up the overlay of a surface `width` by `height` pixels, bottom to
+/// top, for the single-pixel renderer. Lines are centered on the edges like the strokes of
+/// `draw_inner`, and at least a pixel wide.
+fn overlay_rects(
+    region: Region,
+    width: i32,
+    height: i32,
+    appearance: &Appearance,
+    grid: Grid,
+) -> Vec<(Region, Color)> {
+    let mut rects = Vec::new();
+    let mut push = |x: i32, y: i32, w: i32, h: i32, color: Color| {
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = (x + w).min(width);
+        let bottom = (y + h).min(height);
+        if left < right && top < bottom && color.alpha() > 0.0 {
+            let rect = Region {
+                x: left,
+                y: top,
+                width: right - left,
+                height: bottom - top,
+            };
+            rects.push((rect, color));
         }
+    };
+    let Region { x, y, .. } = region;
+    let (right, bottom) = (region.right(), region.bottom());
+
+    let dim = appearance.dim_color;
+    push(0, 0, width, y, dim);
+    push(0, bottom, width, height - bottom, dim);
+    push(0, y, x, region.height, dim);
+    push(right, y, width - right, region.height, dim);
+    push(x, y, region.width, region.height, appearance.fill_color);
+
+    let line = |thickness: f32| (thickness.round() as i32).max(1);
+    let border = line(appearance.border_thickness);
+    let half = border / 2;
+    let color = appearance.border_color;
+    push(x - half, y - half, region.width + border, border, color);
+    push(
+        x - half,
+        bottom - half,
+        region.width + border,
+        border,
+        color,
+    );
+    push(
+        x - half,
+        y + border - half,
+        border,
+        region.height - border,
+        color,
+    );
+    push(
+        right - half,
+        y + border - half,
+        border,
+        region.height - border,
+        color,
+    );
 
-        if let Some(button_index) = should_press {
-            if seat.buttons_down.insert(button_index) {
-                ei_conn.send(EiButtonRequest::Button {
-                    ei_button: button,
-                    button: button_index,
-                    state: EI_BUTTON_BUTTON_STATE_PRESS,
-                });
-                ei_conn.send(EiDeviceRequest::Frame {
-                    ei_device: device,
-                    last_serial: state.ei_state.last_serial,
-                    timestamp: time.into(),
-                });
-            }
+    let cross = line(appearance.cross_thickness);
+    if appearance.cross_thickness > 0.0 {
+        for row in 1..grid.rows {
+            let line_y = region.cell(1, grid.rows, 0, row).y;
+            push(
+                x,
+                line_y - cross / 2,
+                region.width,
+                cross,
+                appearance.cross_color,
+            );
         }
+        for col in 1..grid.cols {
+            let line_x = region.cell(grid.cols, 1, col, 0).x;
+            push(
+                line_x - cross / 2,
+                y,
+                cross,
+                region.height,
+                appearance.cross_color,
+            );
+        }
+    }
+    rects
+}
 
-        if let Some(button_index) = should_release {
-            if seat.buttons_down.remove(&button_index) {
-                ei_conn.send(EiButtonRequest::Button {
-                    ei_button: button,
-                    button: button_index,
-                    state: EI_BUTTON_BUTTON_STATE_RELEASED,
-                });
-                ei_conn.send(EiDeviceRequest::Frame {
-                    ei_device: device,
-                    last_serial: state.ei_state.last_serial,
-                    timestamp: time.into(),
-                });
-            }
+/// Draws `image`, a copy of `area` of the screen, enlarged next to the center of the region.
+fn draw_magnifier(
+    region: Region,
+    scale: f64,
+    pixmap: &mut tiny_skia::PixmapMut<'_>,
+    image: &tiny_skia::Pixmap,
+    area: Region,
+    appearance: &Appearance,
+) {
+    let size = f64::from(appearance.magnifier_size) * scale;
+    let center = region.scale(scale).center();
+    // Keeping clear of the copied area keeps the magnifier out of the next copy.
+    let offset = f64::from(area.width.max(area.height)) * scale;
+    let place = |center: i32, limit: u32| {
+        let after = f64::from(center) + offset;
+        if after + size <= f64::from(limit) {
+            after
+        } else {
+            f64::from(center) - offset - size
         }
+    };
+    let x = place(center.x, pixmap.width()) as f32;
+    let y = place(center.y, pixmap.height()) as f32;
+    let size = size as f32;
+
+    let transform = Transform::from_row(
+        size / image.width() as f32,
+        0.0,
+        0.0,
+        size / image.height() as f32,
+        x,
+        y,
+    );
+    pixmap.draw_pixmap(
+        0,
+        0,
+        image.as_ref(),
+        &PixmapPaint {
+            quality: FilterQuality::Nearest,
+            ..Default::default()
+        },
+        transform,
+        None,
+    );
 
-        ei_conn.send(EiDeviceRequest::StopEmulating {
-            ei_device: device,
-            last_serial: state.ei_state.last_serial,
-        });
+    let Some(rect) = tiny_skia::Rect::from_xywh(x, y, size, size) else {
+        return;
+    };
+    let paint = Paint {
+        shader: Shader::SolidColor(appearance.border_color),
+        ..Default::default()
+    };
+    let stroke = Stroke {
+        width: appearance.border_thickness * scale as f32,
+        ..Default::default()
+    };
+    let path = PathBuilder::from_rect(rect);
+    if appearance.outline {
+        stroke_outline(pixmap, &path, &stroke, scale, appearance.anti_alias);
     }
+    _ = pixmap.stroke_path(&path, &paint, &stroke, Transform::default(), None);
 }
 
-fn draw(
-    globals: &Globals,
-    buffers: &mut TypedHandleMap<Buffer>,
-    conn: &mut WaylandConnection,
-    scale: u32,
-    surface: &Surface,
+/// Strokes `path` in black a pixel wider than `stroke` on each side, to draw the line with
+/// `stroke` over.
+fn stroke_outline(
+    pixmap: &mut tiny_skia::PixmapMut<'_>,
+    path: &tiny_skia::Path,
+    stroke: &Stroke,
+    scale: f64,
+    anti_alias: bool,
+) {
+    let paint = Paint {
+        shader: Shader::SolidColor(Color::BLACK),
+        anti_alias,
+        ..Default::default()
+    };
+    let stroke = Stroke {
+        width: stroke.width + 2.0 * scale as f32,
+        dash: None,
+        ..stroke.clone()
+    };
+    _ = pixmap.stroke_path(path, &paint, &stroke, Transform::default(), None);
+}
+
+/// Draws the label of each grid cell whose label starts with the hint typed so far.
+fn draw_hint_labels(
     region: Region,
-) -> Result<()> {
-    let buffer_data = make_buffer(
-        globals,
-        buffers,
-        conn,
-        i32::try_from(surface.width * scale).unwrap(),
-        i32::try_from(surface.height * scale).unwrap(),
-        i32::try_from(surface.width * scale * 4).unwrap(),
-        WL_SHM_FORMAT_ABGR8888,
-    )?;
-    let buffer = &mut buffers[buffer_data];
-    let mut pixmap = tiny_skia::PixmapMut::from_bytes(
-        buffer.mmap.as_deref_mut().unwrap(),
-        surface.width * scale,
-        surface.height * scale,
-    )
-    .expect("PixmapMut creation failed");
-    let border_color = Color::WHITE;
-    let cross_color = {
-        let mut color = Color::WHITE;
-        color.apply_opacity(0.25);
-        color
+    scale: f64,
+    pixmap: &mut tiny_skia::PixmapMut<'_>,
+    font: &Font,
+    input: &str,
+    config: &Config,
+    appearance: &Appearance,
+) {
+    let Grid { cols, rows } = config.grid;
+    let size = appearance.font_size * scale as f32;
+    let padding = size / 4.0;
+    let background_paint = Paint {
+        shader: Shader::SolidColor(appearance.label_background),
+        ..Default::default()
     };
-    let border_thickness = 1.0;
-    let cross_thickness = 2.0;
-    draw_inner(
-        region,
-        scale,
-        &mut pixmap,
-        border_color,
-        border_thickness,
-        cross_color,
-        cross_thickness,
-    );
-    conn.send(WlSurfaceRequest::SetBufferScale {
-        wl_surface: surface.wl_surface,
-        scale: i32::try_from(scale).unwrap(),
-    });
-    conn.send(WlSurfaceRequest::Attach {
-        wl_surface: surface.wl_surface,
-        buffer: buffer.wl_buffer,
-        x: 0,
-        y: 0,
-    });
-    conn.send(WlSurfaceRequest::DamageBuffer {
-        wl_surface: surface.wl_surface,
-        x: 0,
-        y: 0,
-        width: i32::MAX,
-        height: i32::MAX,
-    });
-    conn.send(WlSurfaceRequest::Commit {
-        wl_surface: surface.wl_surface,
-    });
-    Ok(())
+    let region = region.scale(scale);
+    for index in 0..cols * rows {
+        let Some(label) = config.hints.label(index) else {
+            break;
+        };
+        if !label.starts_with(input) {
+            continue;
+        }
+        let center = region.cell(cols, rows, index % cols, index / cols).center();
+        let width = font.measure(&label, size);
+        let x = center.x as f32 - width / 2.0;
+        let y = center.y as f32 - size / 2.0;
+        if let Some(rect) = tiny_skia::Rect::from_xywh(
+            x - padding,
+            y - padding,
+            width + padding * 2.0,
+            size + padding * 2.0,
+        ) {
+            pixmap.fill_rect(rect, &background_paint, Transform::default(), None);
+        }
+        font.draw(pixmap, &label, size, x, y, appearance.label_color);
+    }
+}
+
+/// Draws the heads-up display text in the bottom left corner of the output.
+fn draw_hud(
+    scale: f64,
+    pixmap: &mut tiny_skia::PixmapMut<'_>,
+    font: &Font,
+    text: &str,
+    appearance: &Appearance,
+) {
+    let size = appearance.font_size * scale as f32;
+    let padding = size / 4.0;
+    let width = font.measure(text, size);
+    let x = padding * 2.0;
+    let y = pixmap.height() as f32 - padding * 2.0 - size;
+    if let Some(rect) = tiny_skia::Rect::from_xywh(
+        x - padding,
+        y - padding,
+        width + padding * 2.0,
+        size + padding * 2.0,
+    ) {
+        let background_paint = Paint {
+            shader: Shader::SolidColor(appearance.label_background),
+            ..Default::default()
+        };
+        pixmap.fill_rect(rect, &background_paint, Transform::default(), None);
+    }
+    font.draw(pixmap, text, size, x, y, appearance.label_color);
 }
 
 fn draw_inner(
     region: Region,
-    scale: u32,
+    scale: f64,
     pixmap: &mut tiny_skia::PixmapMut<'_>,
-    border_color: Color,
-    border_thickness: f32,
-    cross_color: Color,
-    cross_thickness: f32,
+    appearance: &Appearance,
+    grid: Grid,
 ) {
+    let Appearance {
+        border_color,
+        border_thickness,
+        border_dash,
+        border_dash_color,
+        glow,
+        glow_color,
+        cross_color,
+        cross_thickness,
+        guides: _,
+        center_dot,
+        center_dot_color,
+        center_ring_color,
+        dim_color,
+        fill_color,
+        sequence_color: _,
+        animate: _,
+        animation_duration: _,
+        animation_easing: _,
+        fade_out: _,
+        font_size: _,
+        label_color: _,
+        label_background: _,
+        cell_labels: _,
+        renderer: _,
+        cursor: _,
+        magnifier_zoom: _,
+        magnifier_size: _,
+        hud: _,
+        outline,
+        anti_alias,
+        pixel_snap,
+    } = *appearance;
+
     let region = region.scale(scale);
     let region_x = region.x as f32;
     let region_y = region.y as f32;
     let region_width = region.width as f32;
     let region_height = region.height as f32;
 
+    if dim_color.alpha() > 0.0 {
+        let dim_paint = Paint {
+            shader: Shader::SolidColor(dim_color),
+            ..Default::default()
+        };
+
+        let mut path = PathBuilder::new();
+        path.push_rect(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32);
+        path.push_rect(region_x, region_y, region_width, region_height);
+        if let Some(path) = path.finish() {
+            pixmap.fill_path(
+                &path,
+                &dim_paint,
+                FillRule::EvenOdd,
+                Transform::default(),
+                None,
+            );
+        }
+    }
+
+    if fill_color.alpha() > 0.0 {
+        let fill_paint = Paint {
+            shader: Shader::SolidColor(fill_color),
+            ..Default::default()
+        };
+        if let Some(rect) =
+            tiny_skia::Rect::from_xywh(region_x, region_y, region_width, region_height)
+        {
+            pixmap.fill_rect(rect, &fill_paint, Transform::default(), None);
+        }
+    }
+
     let border_paint = Paint {
         shader: Shader::SolidColor(border_color),
+        anti_alias,
         ..Default::default()
     };
 
-    let border_stroke = Stroke {
-        width: border_thickness,
+    // Thicknesses are in logical pixels, so the overlay looks the same on outputs of any scale.
+    let stroke_width = |thickness: f32| {
+        let width = thickness * scale as f32;
+        if pixel_snap {
+            width.round().max(1.0)
+        } else {
+            width
+        }
+    };
+    // Lines of an odd width are only sharp centered on a pixel, and of an even width between two.
+    let snap = |coordinate: f32, width: f32| {
+        if !pixel_snap {
+            coordinate
+        } else if width as i32 % 2 == 1 {
+            coordinate.floor() + 0.5
+        } else {
+            coordinate.round()
+        }
+    };
+
+    let mut border_stroke = Stroke {
+        width: stroke_width(border_thickness),
         ..Default::default()
     };
 
     let cross_paint = Paint {
         shader: Shader::SolidColor(cross_color),
+        anti_alias,
         ..Default::default()
     };
 
     let cross_stroke = Stroke {
-        width: cross_thickness,
+        width: stroke_width(cross_thickness),
         ..Default::default()
     };
 
+    let left = snap(region_x, border_stroke.width);
+    let top = snap(region_y, border_stroke.width);
+    let right = snap(region_x + region_width, border_stroke.width);
+    let bottom = snap(region_y + region_height, border_stroke.width);
     let mut path = PathBuilder::new();
-    path.move_to(region_x, region_y);
-    path.line_to(region_x + region_width, region_y);
-    path.line_to(region_x + region_width, region_y + region_height);
-    path.line_to(region_x, region_y + region_height);
+    path.move_to(left, top);
+    path.line_to(right, top);
+    path.line_to(right, bottom);
+    path.line_to(left, bottom);
     path.close();
     let path = path.finish().expect("invalid path created");
 
+    if glow > 0.0 {
+        // Each pass is wider than the next and they overlap, so the glow gets more opaque
+        // towards the border.
+        const GLOW_PASSES: u32 = 8;
+        let mut glow_color = glow_color;
+        glow_color.set_alpha(glow_color.alpha() / GLOW_PASSES as f32);
+        let glow_paint = Paint {
+            shader: Shader::SolidColor(glow_color),
+            anti_alias,
+            ..Default::default()
+        };
+        for pass in (1..=GLOW_PASSES).rev() {
+            let reach = glow * scale as f32 * pass as f32 / GLOW_PASSES as f32;
+            let glow_stroke = Stroke {
+                width: border_stroke.width + reach * 2.0,
+                ..Default::default()
+            };
+            _ = pixmap.stroke_path(&path, &glow_paint, &glow_stroke, Transform::default(), None);
+        }
+    }
+    if outline {
+        stroke_outline(pixmap, &path, &border_stroke, scale, anti_alias);
+    }
+    if border_dash > 0.0 {
+        if border_dash_color.alpha() > 0.0 {
+            let gap_paint = Paint {
+                shader: Shader::SolidColor(border_dash_color),
+                anti_alias,
+                ..Default::default()
+            };
+            _ = pixmap.stroke_path(
+                &path,
+                &gap_paint,
+                &border_stroke,
+                Transform::default(),
+                None,
+            );
+        }
+        let dash = border_dash * scale as f32;
+        border_stroke.dash = StrokeDash::new(vec![dash, dash], 0.0);
+    }
+
     _ = pixmap.stroke_path(
         &path,
         &border_paint,
@@ -579,21 +1420,102 @@ fn draw_inner(
         None,
     );
 
-    let mut path = path.clear();
-    path.move_to(region_x, region_y + region_height / 2.0);
-    path.line_to(region_x + region_width, region_y + region_height / 2.0);
-    path.close();
-    path.move_to(region_x + region_width / 2.0, region_y);
-    path.line_to(region_x + region_width / 2.0, region_y + region_height);
-    let path = path.finish().expect("invalid path created");
+    // A zero-width stroke would still be drawn as a hairline.
+    if cross_thickness > 0.0 {
+        let mut path = path.clear();
+        for row in 1..grid.rows {
+            let y = snap(
+                region_y + region_height * row as f32 / grid.rows as f32,
+                cross_stroke.width,
+            );
+            path.move_to(region_x, y);
+            path.line_to(region_x + region_width, y);
+            path.close();
+        }
+        for col in 1..grid.cols {
+            let x = snap(
+                region_x + region_width * col as f32 / grid.cols as f32,
+                cross_stroke.width,
+            );
+            path.move_to(x, region_y);
+            path.line_to(x, region_y + region_height);
+            path.close();
+        }
+        if let Some(path) = path.finish() {
+            if outline {
+                stroke_outline(pixmap, &path, &cross_stroke, scale, anti_alias);
+            }
+            _ = pixmap.stroke_path(
+                &path,
+                &cross_paint,
+                &cross_stroke,
+                Transform::default(),
+                None,
+            );
+        }
+    }
 
-    _ = pixmap.stroke_path(
-        &path,
-        &cross_paint,
-        &cross_stroke,
-        Transform::default(),
-        None,
-    );
+    if center_dot > 0.0 {
+        let center = region.center();
+        let radius = center_dot * scale as f32 / 2.0;
+        let ring = (radius / 2.0).max(scale as f32);
+        for (radius, color) in [
+            (radius + ring, center_ring_color),
+            (radius, center_dot_color),
+        ] {
+            let paint = Paint {
+                shader: Shader::SolidColor(color),
+                anti_alias,
+                ..Default::default()
+            };
+            if let Some(path) = PathBuilder::from_circle(center.x as f32, center.y as f32, radius) {
+                pixmap.fill_path(&path, &paint, FillRule::Winding, Transform::default(), None);
+            }
+        }
+    }
+}
+
+/// Returns a released buffer of the given size for an output's surface, creating one if there
+/// isn't any. Released buffers of other sizes, left over from before a resize, are destroyed.
+fn surface_buffer(
+    globals: &Globals,
+    buffers: &mut TypedHandleMap<Buffer>,
+    conn: &mut WaylandConnection,
+    output: OutputId,
+    width: i32,
+    height: i32,
+    format: u32,
+) -> Result<BufferId> {
+    let mut found = None;
+    let mut stale = Vec::new();
+    for (buffer_id, buffer) in buffers.iter_with_handles() {
+        if buffer.output != output || buffer.busy {
+            continue;
+        }
+        if (buffer.width, buffer.height) != (width, height) {
+            stale.push(buffer_id);
+        } else if found.is_none() {
+            found = Some(buffer_id);
+        }
+    }
+    for buffer_id in stale {
+        let buffer = buffers.remove(buffer_id).unwrap();
+        conn.send(WlShmPoolRequest::Destroy {
+            wl_shm_pool: buffer.pool,
+        })?;
+        conn.send(WlBufferRequest::Destroy {
+            wl_buffer: buffer.wl_buffer,
+        })?;
+    }
+    if let Some(buffer_id) = found {
+        return Ok(buffer_id);
+    }
+    let buffer_id = make_buffer(globals, buffers, conn, width, height, width * 4, format)?;
+    let buffer = &mut buffers[buffer_id];
+    buffer.output = output;
+    buffer.width = width;
+    buffer.height = height;
+    Ok(buffer_id)
 }
 
 fn make_buffer(
@@ -610,14 +1532,14 @@ fn make_buffer(
     let memfd = memfd::MemfdOptions::new().create("waypoint-buffer")?;
     let len_i32 = stride.checked_mul(height).expect("buffer too big");
     let len_usize = usize::try_from(len_i32).expect("buffer too big");
-    memfd.as_file().write_all(&vec![0u8; len_i32 as usize])?;
+    memfd.as_file().set_len(len_usize as u64)?;
     let borrowed_memfd = unsafe { BorrowedFd::borrow_raw(memfd.as_raw_fd()) };
     let wl_shm_pool = conn.send_constructor(0, |id| WlShmRequest::CreatePool {
         wl_shm: globals.wl_shm,
         id,
         fd: borrowed_memfd.as_fd().try_clone_to_owned().unwrap(),
         size: len_i32,
-    });
+    })?;
     let wl_buffer =
         conn.send_constructor(buffer_id.into_raw(), |id| WlShmPoolRequest::CreateBuffer {
             wl_shm_pool,
@@ -627,7 +1549,7 @@ fn make_buffer(
             height,
             stride,
             format,
-        });
+        })?;
     let mmap = unsafe { MmapOptions::new().len(len_usize).map_mut(memfd.as_file())? };
     this.pool = wl_shm_pool;
     this.wl_buffer = wl_buffer;
@@ -636,81 +1558,117 @@ fn make_buffer(
 }
 
 #[derive(Debug)]
-struct IdAllocator<I: std::fmt::Debug> {
-    next: u32,
-    free: Vec<u32>,
-    data: Vec<I>,
+struct LibeiConnection {
+    wire: ei::Connection,
+    connection: EiConnection,
+    next_id: u64,
+    /// The interface and version of each object, by id.
+    objects: HashMap<u64, (ei_gen::Interface, u32)>,
+    /// The versions of each interface agreed on during the handshake, by name.
+    interface_versions: HashMap<String, u32>,
 }
 
-impl<I: std::fmt::Debug> IdAllocator<I> {
-    fn new() -> IdAllocator<I> {
-        Self {
-            next: 1,
-            free: Vec::new(),
-            data: Vec::new(),
-        }
+impl AsMut<ei::Connection> for LibeiConnection {
+    fn as_mut(&mut self) -> &mut ei::Connection {
+        &mut self.wire
     }
+}
 
-    pub fn allocate(&mut self, data: I) -> u32 {
-        match self.free.pop() {
-            Some(id) => {
-                self.data[usize::try_from(id).unwrap() - 1] = data;
-                id
-            }
-            None => {
-                let id = self.next;
-                self.next += 1;
-                self.data.push(data);
-                id
-            }
-        }
+impl LibeiConnection {
+    fn send<'a>(&mut self, request: impl Into<ei_gen::Request<'a>>) {
+        let request = request.into();
+        self.trace(true, request.object(), &request);
+        debug_assert!(
+            self.version_of(request.object())
+                .is_some_and(|version| version >= request.since()),
+            "{request:?} is newer than the version of its object",
+        );
+        request.marshal(&mut self.wire);
     }
 
-    pub fn release(&mut self, id: u32) {
-        self.free.push(id);
+    fn trace(&self, sent: bool, object: u64, message: &dyn fmt::Debug) {
+        let interface = self
+            .objects
+            .get(&object)
+            .map(|(interface, _)| interface.name());
+        let interface = interface.unwrap_or("[unknown]");
+        self.wire.trace(sent, interface, object, message);
     }
 
-    pub fn data_for(&self, id: u32) -> &I {
-        &self.data[usize::try_from(id).unwrap() - 1]
+    /// Records an object the EIS implementation created.
+    fn insert(&mut self, id: u64, interface: ei_gen::Interface, version: u32) {
+        self.objects.insert(id, (interface, version));
     }
-}
 
-#[derive(Debug)]
-struct LibeiConnection {
-    wire: ei::Connection,
-    next_id: u64,
-    interfaces: HashMap<u64, ei_gen::Interface>,
-}
+    /// Returns the version of the object with `id`, or `None` if there's no such object.
+    fn version_of(&self, id: u64) -> Option<u32> {
+        self.objects.get(&id).map(|&(_, version)| version)
+    }
 
-impl LibeiConnection {
-    fn send<'a>(&mut self, request: impl Into<ei_gen::Request<'a>>) {
-        let request = request.into();
-        #[cfg(debug_assertions)]
-        {
-            if std::env::var("LIBEI_DEBUG").is_ok_and(|v| v != "0") {
-                eprintln!("-> {request:?}");
-            }
-        }
-        request.marshal(&mut self.wire);
+    /// Returns the version of `interface` agreed on during the handshake, which new objects of
+    /// the interface the client creates have.
+    fn negotiated_version(&self, interface: ei_gen::Interface) -> u32 {
+        let version = self.interface_versions.get(interface.name()).copied();
+        version.unwrap_or(1).min(interface.version())
     }
 
     fn create<O: ei::Object<ei_gen::Interface>>(&mut self) -> O {
         let id = self.next_id;
         self.next_id += 1;
-        assert!(self.interfaces.insert(id, O::INTERFACE).is_none());
+        let version = self.negotiated_version(O::INTERFACE);
+        assert!(self.objects.insert(id, (O::INTERFACE, version)).is_none());
         O::new(id)
     }
 
+    /// Sends `ei_connection.sync` and reads until the EIS implementation has processed every
+    /// request sent before it, passing the events that arrive meanwhile to `handler`.
+    fn sync(&mut self, mut handler: impl FnMut(&mut LibeiConnection, ei_gen::Event)) -> Result<()> {
+        let callback = self.create::<EiCallback>();
+        self.send(EiConnectionRequest::Sync {
+            ei_connection: self.connection,
+            callback,
+            version: self.version_of(callback.id()).unwrap(),
+        });
+        self.wire.flush_blocking()?;
+        let mut done = false;
+        loop {
+            self.handle_events(|conn, event| match event {
+                ei_gen::Event::EiCallback(EiCallbackEvent::Done { ei_callback, .. })
+                    if ei_callback.id() == callback.id() =>
+                {
+                    done = true;
+                }
+                event => handler(conn, event),
+            });
+            if done {
+                self.objects.remove(&callback.id());
+                return Ok(());
+            }
+            ensure!(
+                self.wire.read_blocking()?,
+                "the EIS implementation closed the connection"
+            );
+        }
+    }
+
     fn handle_events(&mut self, mut handler: impl FnMut(&mut LibeiConnection, ei_gen::Event)) {
+        // Events for objects waypoint doesn't know, like ones it already destroyed, decode to
+        // `None` and are skipped.
         while let Some(event) = self.wire.read_message(|msg| {
-            ei_gen::Event::unmarshal(self.interfaces.get(&msg.object()).copied().unwrap(), msg)
+            let Some(&(interface, _)) = self.objects.get(&msg.object()) else {
+                log::warn!(
+                    "ignoring event {} for unknown ei object {}",
+                    msg.opcode(),
+                    msg.object(),
+                );
+                return Some(None);
+            };
+            ei_gen::Event::unmarshal(interface, msg).map(Some)
         }) {
-            #[cfg(debug_assertions)]
-            {
-                if std::env::var("LIBEI_DEBUG").is_ok_and(|v| v != "0") {
-                    eprintln!("<- {event:?}");
-                }
-            }
+            let Some(event) = event else {
+                continue;
+            };
+            self.trace(false, event.object(), &event);
             match event {
                 _ => handler(self, event),
             }
@@ -721,30 +1679,15 @@ impl LibeiConnection {
 #[derive(Debug)]
 struct WaylandConnection {
     wire: wayland::Connection,
-    ids: IdAllocator<ObjectData>,
-    sync_callback: WlCallback,
-    sync_done: bool,
-}
-
-#[derive(Debug)]
-struct ObjectData {
-    interface: wl_gen::Interface,
-    data: u64,
+    ids: wayland::ObjectMap<wl_gen::Interface, u64>,
 }
 
 impl WaylandConnection {
-    fn send<'a>(&mut self, request: impl Into<Request<'a>>) {
-        let request = request.into();
-        #[cfg(debug_assertions)]
-        {
-            if std::env::var("WAYLAND_DEBUG").is_ok_and(|v| v != "0") {
-                eprintln!("-> {request:?}");
-            }
-        }
-        request.marshal(&mut self.wire);
+    fn send<'a>(&mut self, request: impl Into<Request<'a>>) -> Result<(), wayland::Error> {
+        request.into().marshal(&mut self.wire)
     }
 
-    fn send_constructor<'a, O, F, IR>(&mut self, data: u64, f: F) -> O
+    fn send_constructor<'a, O, F, IR>(&mut self, data: u64, f: F) -> Result<O, wayland::Error>
     where
         O: wayland::Object<wl_gen::Interface>,
         F: Fn(O) -> IR,
@@ -752,61 +1695,114 @@ impl WaylandConnection {
     {
         let obj = self.create(data);
         let request = f(obj).into();
-        #[cfg(debug_assertions)]
-        {
-            if std::env::var("WAYLAND_DEBUG").is_ok_and(|v| v != "0") {
-                eprintln!("-> {request:?}");
-            }
-        }
-        request.marshal(&mut self.wire);
-        obj
+        request.marshal(&mut self.wire)?;
+        Ok(obj)
     }
 
     fn create<O: wayland::Object<wl_gen::Interface>>(&mut self, data: u64) -> O {
-        let id = self.ids.allocate(ObjectData {
-            interface: O::INTERFACE,
-            data,
-        });
-        O::new(id)
+        self.ids.create(data)
     }
 
-    fn handle_events(&mut self, mut handler: impl FnMut(&mut WaylandConnection, Event)) {
-        while let Some(event) = self
-            .wire
-            .read_message(|msg| Event::unmarshal(self.ids.data_for(msg.object()).interface, msg))
-        {
-            #[cfg(debug_assertions)]
-            {
-                if std::env::var("WAYLAND_DEBUG").is_ok_and(|v| v != "0") {
-                    eprintln!("<- {event:?}");
-                }
+    /// Returns the data an object was created with.
+    fn data_for(&self, id: u32) -> u64 {
+        *self
+            .ids
+            .get(id)
+            .expect("events are only decoded for known objects")
+    }
+
+    fn handle_events(
+        &mut self,
+        mut handler: impl FnMut(&mut WaylandConnection, Event) -> Result<()>,
+    ) -> Result<()> {
+        while let Some(event) = self.read_event()? {
+            self.dispatch(event, &mut handler)?;
+        }
+        Ok(())
+    }
+
+    fn roundtrip(
+        &mut self,
+        mut handler: impl FnMut(&mut WaylandConnection, Event) -> Result<()>,
+    ) -> Result<()> {
+        // The first error from `handler` ends handling events, and is returned once the
+        // roundtrip is done.
+        let mut result = Ok(());
+        wayland::Roundtrip::roundtrip(self, |conn, event| {
+            if result.is_ok() {
+                result = conn.dispatch(event, &mut handler);
             }
-            match event {
-                Event::WlDisplay(WlDisplayEvent::DeleteId { wl_display: _, id }) => {
-                    self.ids.release(id);
-                }
-                Event::WlCallback(WlCallbackEvent::Done {
-                    wl_callback,
-                    callback_data: _,
-                }) if wl_callback == self.sync_callback => {
-                    self.sync_done = true;
-                }
-                _ => handler(self, event),
+        })?;
+        result
+    }
+
+    /// Passes an event to `handler`, unless it's one the connection handles itself.
+    fn dispatch(
+        &mut self,
+        event: Event<'static>,
+        handler: &mut impl FnMut(&mut WaylandConnection, Event) -> Result<()>,
+    ) -> Result<()> {
+        match event {
+            Event::WlDisplay(WlDisplayEvent::DeleteId { wl_display: _, id }) => {
+                self.ids.delete_id(id);
+                Ok(())
             }
+            _ => handler(self, event),
         }
     }
+}
+
+impl wayland::Roundtrip for WaylandConnection {
+    type Event = Event<'static>;
+
+    fn connection(&mut self) -> &mut wayland::Connection {
+        &mut self.wire
+    }
 
-    fn roundtrip(&mut self, mut handler: impl FnMut(&mut WaylandConnection, Event)) {
-        self.sync_done = false;
-        self.sync_callback = self.send_constructor(0, |callback| WlDisplayRequest::Sync {
+    fn send_sync(&mut self) -> Result<u32, wayland::Error> {
+        let callback: WlCallback = self.send_constructor(0, |callback| WlDisplayRequest::Sync {
             wl_display: WlDisplay(1),
             callback,
-        });
-        while !self.sync_done {
-            self.wire.flush_blocking().unwrap();
-            self.wire.read_blocking().unwrap();
-            self.handle_events(&mut handler);
+        })?;
+        Ok(callback.id())
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event<'static>>, wayland::Error> {
+        // Events are handled with the connection at hand to send requests, so they can't borrow
+        // from it.
+        let event = self.wire.read_message(|msg| {
+            let interface =
+                self.ids
+                    .interface(msg.object())
+                    .ok_or(wayland::Error::UnknownObject {
+                        object: msg.object(),
+                    })?;
+            Event::unmarshal(interface, msg).map(Event::into_owned)
+        })?;
+        if let Some(Event::WlDisplay(WlDisplayEvent::Error {
+            wl_display: _,
+            object_id,
+            code,
+            message,
+        })) = event
+        {
+            let interface = self
+                .ids
+                .interface(object_id)
+                .ok_or(wayland::Error::UnknownObject { object: object_id })?;
+            return Err(
+                ProtocolError::new(object_id, interface, code, message.into_owned()).into(),
+            );
         }
+        Ok(event)
+    }
+
+    fn is_sync_done(&self, event: &Event<'static>, callback: u32) -> bool {
+        matches!(
+            event,
+            Event::WlCallback(WlCallbackEvent::Done { wl_callback, .. })
+                if wl_callback.id() == callback
+        )
     }
 }
 
@@ -815,131 +1811,283 @@ fn bind_global<O: wayland::Object<wl_gen::Interface>>(
     registry: WlRegistry,
     globals: &HashMap<String, Vec<(u32, u32)>>,
     version: RangeInclusive<u32>,
-) -> Option<O> {
-    for &(name, sversion) in globals.get(O::INTERFACE.name())? {
+) -> Result<Option<O>, wayland::Error> {
+    let Some(globals) = globals.get(O::INTERFACE.name()) else {
+        return Ok(None);
+    };
+    for &(name, sversion) in globals {
         if &sversion >= version.start() {
-            return Some(conn.send_constructor(0, |new_id: O| {
-                Request::WlRegistry(WlRegistryRequest::Bind {
-                    wl_registry: registry,
-                    name,
-                    interface: O::INTERFACE.name().into(),
-                    version: sversion.min(*version.end()),
-                    id: new_id.id(),
+            return conn
+                .send_constructor(0, |new_id: O| {
+                    Request::WlRegistry(WlRegistryRequest::Bind {
+                        wl_registry: registry,
+                        name,
+                        interface: O::INTERFACE.name().into(),
+                        version: sversion.min(*version.end()),
+                        id: new_id.id(),
+                    })
                 })
-            }));
+                .map(Some);
         }
     }
-    None
+    Ok(None)
 }
 
-fn main() -> Result<()> {
-    let ei_fd = ei::client_socket_from_env()?;
-    let ei_wire_conn = ei_fd.map(ei::Connection::new);
-    let mut ei_conn = ei_wire_conn.map(|wire| LibeiConnection {
+#[cfg(feature = "portal")]
+type PortalSession = ei::portal::Session;
+#[cfg(not(feature = "portal"))]
+type PortalSession = std::convert::Infallible;
+
+/// Asks the remote desktop portal for a libei socket, along with the session it's for, which
+/// has to be kept for as long as the socket is used.
+#[cfg(feature = "portal")]
+fn ei_socket_from_portal() -> Result<(OwnedFd, Option<PortalSession>)> {
+    log::info!("asking the remote desktop portal for a libei socket");
+    let (fd, session) = ei::portal::connect()?;
+    Ok((fd, Some(session)))
+}
+
+#[cfg(not(feature = "portal"))]
+fn ei_socket_from_portal() -> Result<(OwnedFd, Option<PortalSession>)> {
+    bail!("waypoint was built without the portal feature")
+}
+
+/// Does the ei handshake on a new socket, returning the connection and the serial of the
+/// handshake's last event.
+fn connect_ei(fd: OwnedFd) -> Result<(LibeiConnection, u32)> {
+    let mut wire = ei::Connection::new(fd);
+    let handshake = [
+        ei_gen::Interface::EiCallback,
+        ei_gen::Interface::EiConnection,
+        ei_gen::Interface::EiSeat,
+        ei_gen::Interface::EiDevice,
+        ei_gen::Interface::EiPingpong,
+        ei_gen::Interface::EiPointerAbsolute,
+        ei_gen::Interface::EiPointer,
+        ei_gen::Interface::EiButton,
+        ei_gen::Interface::EiScroll,
+        ei_gen::Interface::EiKeyboard,
+    ]
+    .into_iter()
+    .fold(
+        ei::Handshake::new(EI_HANDSHAKE_CONTEXT_TYPE_SENDER, "waypoint"),
+        |handshake, interface| handshake.interface(interface.name(), interface.version()),
+    );
+    let connected = handshake
+        .run(&mut wire)
+        .context("the ei handshake failed")?;
+    log::debug!("ei connection established: {connected:?}");
+    let conn = LibeiConnection {
         wire,
-        next_id: 0,
-        interfaces: HashMap::new(),
-    });
+        connection: EiConnection(connected.connection),
+        // Id 0 is the handshake's.
+        next_id: 1,
+        objects: HashMap::from([(
+            connected.connection,
+            (ei_gen::Interface::EiConnection, connected.version),
+        )]),
+        interface_versions: connected.interfaces,
+    };
+    Ok((conn, connected.serial))
+}
 
-    if let Some(ei_conn) = ei_conn.as_mut() {
-        ei_conn.create::<EiHandshake>();
-        ei_conn.wire.read_blocking()?;
-        ei_conn.handle_events(|ei_conn, event| match event {
-            ei_gen::Event::EiHandshake(EiHandshakeEvent::HandshakeVersion {
-                ei_handshake,
-                version,
-            }) => {
-                ei_conn.send(EiHandshakeRequest::HandshakeVersion {
-                    ei_handshake,
-                    version,
-                });
-                ei_conn.send(EiHandshakeRequest::ContextType {
-                    ei_handshake,
-                    context_type: EI_HANDSHAKE_CONTEXT_TYPE_SENDER,
-                });
-                ei_conn.send(EiHandshakeRequest::Name {
-                    ei_handshake,
-                    name: "waypoint".into(),
-                });
-                for interface in [
-                    ei_gen::Interface::EiCallback,
-                    ei_gen::Interface::EiConnection,
-                    ei_gen::Interface::EiSeat,
-                    ei_gen::Interface::EiDevice,
-                    ei_gen::Interface::EiPingpong,
-                    ei_gen::Interface::EiPointerAbsolute,
-                    ei_gen::Interface::EiButton,
-                    ei_gen::Interface::EiScroll,
-                ] {
-                    ei_conn.send(EiHandshakeRequest::InterfaceVersion {
-                        ei_handshake,
-                        name: interface.name().into(),
-                        version: interface.version(),
-                    });
-                }
-                ei_conn.send(EiHandshakeRequest::Finish { ei_handshake });
+/// How long to wait between attempts to reconnect to the EIS implementation.
+const EI_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Gets a new socket from the environment and does the handshake on it, after the EIS
+/// implementation went away.
+fn reconnect_ei() -> Result<(LibeiConnection, u32)> {
+    let fd = ei::client_socket_from_env()?.context("LIBEI_SOCKET is no longer set")?;
+    connect_ei(fd)
+}
+
+fn main() -> Result<()> {
+    let mut profile = None;
+    let mut backend = None;
+    let mut dump_config = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dump-config" => dump_config = true,
+            "--profile" => {
+                profile = Some(args.next().context("option '--profile' requires a name")?);
+            }
+            "--backend" => {
+                backend = Some(args.next().context("option '--backend' requires a name")?);
             }
             _ => {
-                eprintln!("unexpected event {event:?} ignored");
+                if let Some(name) = arg.strip_prefix("--profile=") {
+                    profile = Some(name.to_owned());
+                } else if let Some(name) = arg.strip_prefix("--backend=") {
+                    backend = Some(name.to_owned());
+                } else {
+                    bail!("unexpected argument {arg:?}");
+                }
             }
-        });
+        }
+    }
+    let backend = match backend {
+        Some(name) => Some(Backend::from_name(&name).with_context(|| {
+            format!(
+                "invalid backend {name:?}, should be one of {}",
+                Backend::NAMES.join(", ")
+            )
+        })?),
+        None => None,
+    };
+
+    logger::init();
+    let paths = config::config_paths(|name| std::env::var_os(name));
+    let mut config = Config::load(profile, &paths)?;
+    if let Some(backend) = backend {
+        config.backend = backend;
+    }
+    log::set_max_level(config.log_level);
+    for warning in &config.warnings {
+        log::warn!("{warning}");
+    }
+    if dump_config {
+        // The compositor's keymap isn't known without connecting, so use the system default.
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap =
+            xkb::Keymap::new_from_names(&context, "", "", "", "", None, xkb::COMPILE_NO_FLAGS)
+                .context("failed to compile the default keymap")?;
+        let mut out = String::new();
+        config.dump(&mut out, &keymap)?;
+        print!("{out}");
+        return Ok(());
     }
 
     let wayland_fd = wayland::client_socket_from_env()?.context("no wayland display available")?;
-    let wl_wire_conn = wayland::Connection::new(wayland_fd);
     let mut wl_conn = WaylandConnection {
-        wire: wl_wire_conn,
-        ids: IdAllocator::new(),
-        sync_callback: Default::default(),
-        sync_done: false,
+        wire: wayland::Connection::new(wayland_fd),
+        ids: wayland::ObjectMap::new(),
     };
 
     let wl_display: WlDisplay = wl_conn.create(0);
     let wl_registry = wl_conn.send_constructor(0, |registry| WlDisplayRequest::GetRegistry {
         wl_display,
         registry,
-    });
+    })?;
     let mut global_list: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
-    wl_conn.roundtrip(|_conn, event| match event {
-        Event::WlRegistry(WlRegistryEvent::Global {
-            wl_registry: r,
-            name,
-            interface,
-            version,
-        }) if r == wl_registry => {
-            global_list
-                .entry(interface.into_owned())
-                .or_default()
-                .push((name, version));
-        }
-        _ => {
-            eprintln!("warning: unexpected event: {event:?}")
-        }
-    });
+    wl_conn.roundtrip(|_conn, event| {
+        match event {
+            Event::WlRegistry(WlRegistryEvent::Global {
+                wl_registry: r,
+                name,
+                interface,
+                version,
+            }) if r == wl_registry => {
+                global_list
+                    .entry(interface.into_owned())
+                    .or_default()
+                    .push((name, version));
+            }
+            _ => {
+                log::debug!("unexpected event {event:?} ignored")
+            }
+        }
+        Ok(())
+    })?;
+
+    let ei_socket = match config.backend {
+        Backend::VirtualPointer => None,
+        Backend::Libei => match ei::client_socket_from_env()? {
+            Some(fd) => Some((fd, None)),
+            None => Some(ei_socket_from_portal().context(
+                "the libei backend was selected but LIBEI_SOCKET is unset and the remote desktop portal failed",
+            )?),
+        },
+        Backend::Auto => match ei::client_socket_from_env()? {
+            Some(fd) => Some((fd, None)),
+            // The portal can ask the user for permission, so it's only used when there's no
+            // other way to emulate input.
+            None if !global_list
+                .contains_key(wl_gen::Interface::ZwlrVirtualPointerManagerV1.name()) =>
+            {
+                ei_socket_from_portal()
+                    .map_err(|e| log::warn!("the remote desktop portal failed: {e:#}"))
+                    .ok()
+            }
+            None => None,
+        },
+    };
+    // A socket from the portal can't be replaced without asking the user again, so only one
+    // from the environment is reconnected when the EIS implementation goes away.
+    let can_reconnect_ei = matches!(ei_socket, Some((_, None)));
+    // The portal's session ends when it's dropped, so it's kept until waypoint exits.
+    let (ei_fd, _portal_session) = ei_socket.unzip();
+    let (mut ei_conn, ei_serial) = match ei_fd {
+        Some(fd) => {
+            let (conn, serial) = connect_ei(fd)?;
+            (Some(conn), serial)
+        }
+        None => (None, 0),
+    };
+    let mut ei_reconnect_at = None;
 
     let mut app = App {
         quit: false,
         globals: Globals {
-            wl_shm: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)
+            wl_shm: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
                 .context("compositor doesn't support wl_shm")?,
-            wl_compositor: bind_global(&mut wl_conn, wl_registry, &global_list, 4..=4)
+            wl_compositor: bind_global(&mut wl_conn, wl_registry, &global_list, 4..=6)?
                 .context("compositor doesn't support wl_compositor")?,
-            xdg_output: bind_global(&mut wl_conn, wl_registry, &global_list, 3..=3)
+            xdg_output: bind_global(&mut wl_conn, wl_registry, &global_list, 3..=3)?
                 .context("compositor doesn't support xdg_output_manager_v1")?,
-            layer_shell: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)
+            layer_shell: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
                 .context("compositor doesn't support zwlr_layer_shell_v1")?,
-            virtual_pointer_manager: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)
+            virtual_pointer_manager: match config.backend {
+                Backend::Libei => Default::default(),
+                Backend::VirtualPointer => {
+                    bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?.context(
+                        "the virtual-pointer backend was selected but the compositor doesn't support zwlr_virtual_pointer_manager_v1",
+                    )?
+                }
+                Backend::Auto => {
+                    bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?.unwrap_or_default()
+                }
+            },
+            fractional_scale_manager: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
+                .unwrap_or_default(),
+            viewporter: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
+                .unwrap_or_default(),
+            subcompositor: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
+                .unwrap_or_default(),
+            single_pixel_buffer_manager: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
+                .unwrap_or_default(),
+            cursor_shape_manager: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
+                .unwrap_or_default(),
+            screencopy_manager: bind_global(&mut wl_conn, wl_registry, &global_list, 1..=1)?
                 .unwrap_or_default(),
         },
         seats: TypedHandleMap::new(),
         outputs: TypedHandleMap::new(),
         buffers: TypedHandleMap::new(),
-        config: Config::load()?,
+        config,
+        mode: DEFAULT_MODE,
+        hint_input: None,
         region: Region::default(),
-        region_history: Vec::new(),
+        region_history: VecDeque::new(),
+        animation: None,
+        fading_out: None,
         global_bounds: Region::default(),
-        ei_state: EiState::default(),
+        ei_state: EiState {
+            last_serial: ei_serial,
+            ..EiState::default()
+        },
+        font: OnceCell::new(),
+        single_pixel_buffers: HashMap::new(),
+        magnifier: Magnifier::default(),
+        shm_formats: HashSet::new(),
     };
+    if app.config.appearance.renderer == Renderer::SinglePixel
+        && !app.globals.supports_single_pixel()
+    {
+        log::warn!(
+            "the single-pixel renderer needs wl_subcompositor, wp_viewporter and wp_single_pixel_buffer_manager_v1, falling back to shm"
+        );
+    }
 
     if let Some(seat_list) = global_list.get(wl_gen::Interface::WlSeat.name()) {
         for &(name, sversion) in seat_list {
@@ -952,7 +2100,7 @@ fn main() -> Result<()> {
                     version: sversion.min(4),
                     id,
                 })
-            });
+            })?;
             let seat = &mut app.seats[seat_id];
             if !app.globals.virtual_pointer_manager.is_null() {
                 let virtual_pointer = wl_conn.send_constructor(0, |id| {
@@ -963,7 +2111,7 @@ fn main() -> Result<()> {
                             id,
                         },
                     )
-                });
+                })?;
                 seat.virtual_pointer = virtual_pointer;
             }
             seat.wl_seat = wl_seat;
@@ -983,28 +2131,49 @@ fn main() -> Result<()> {
                     version: sversion.min(2),
                     id,
                 })
-            });
+            })?;
             let xdg_output = wl_conn.send_constructor(output_id.into_raw(), |id| {
                 Request::ZxdgOutputManagerV1(ZxdgOutputManagerV1Request::GetXdgOutput {
                     zxdg_output_manager_v1: app.globals.xdg_output,
                     id,
                     output: wl_output,
                 })
-            });
+            })?;
             output.wl_output = wl_output;
             output.xdg_output = xdg_output;
         }
     }
 
-    wl_conn.roundtrip(|conn, event| {
-        app.handle_event(conn, ei_conn.as_mut(), event);
-    });
+    wl_conn.roundtrip(|conn, event| app.handle_event(conn, ei_conn.as_mut(), event))?;
 
     for output in app.outputs.iter() {
         app.global_bounds = app.global_bounds.union(&output.region());
     }
 
-    app.region = app.global_bounds;
+    app.region = match &app.config.initial_region {
+        InitialRegion::Desktop => app.global_bounds,
+        InitialRegion::Output(name) => {
+            match app
+                .outputs
+                .iter()
+                .find(|output| output.name.as_ref() == Some(name))
+            {
+                Some(output) => output.region(),
+                None => {
+                    log::warn!("initial region output {name:?} not found");
+                    app.global_bounds
+                }
+            }
+        }
+        &InitialRegion::Region(region) => {
+            if app.global_bounds.contains_region(&region) {
+                region
+            } else {
+                log::warn!("initial region {region:?} is outside of the desktop");
+                app.global_bounds
+            }
+        }
+    };
 
     for (output_id, output) in app.outputs.iter_mut_with_handles() {
         output.surface = Some(Surface::default());
@@ -1013,7 +2182,7 @@ fn main() -> Result<()> {
         let wl_surface = wl_conn.send_constructor(0, |id| WlCompositorRequest::CreateSurface {
             wl_compositor: app.globals.wl_compositor,
             id,
-        });
+        })?;
         let layer_surface = wl_conn.send_constructor(output_id.into_raw(), |id| {
             ZwlrLayerShellV1Request::GetLayerSurface {
                 zwlr_layer_shell_v1: app.globals.layer_shell,
@@ -1023,33 +2192,54 @@ fn main() -> Result<()> {
                 layer: ZWLR_LAYER_SHELL_V1_LAYER_OVERLAY,
                 namespace: "waypoint".into(),
             }
-        });
+        })?;
         wl_conn.send(ZwlrLayerSurfaceV1Request::SetSize {
             zwlr_layer_surface_v1: layer_surface,
             width: 0,
             height: 0,
-        });
+        })?;
         wl_conn.send(ZwlrLayerSurfaceV1Request::SetAnchor {
             zwlr_layer_surface_v1: layer_surface,
             anchor: ZWLR_LAYER_SURFACE_V1_ANCHOR_TOP
                 | ZWLR_LAYER_SURFACE_V1_ANCHOR_BOTTOM
                 | ZWLR_LAYER_SURFACE_V1_ANCHOR_LEFT
                 | ZWLR_LAYER_SURFACE_V1_ANCHOR_RIGHT,
-        });
+        })?;
         wl_conn.send(ZwlrLayerSurfaceV1Request::SetExclusiveZone {
             zwlr_layer_surface_v1: layer_surface,
             zone: -1,
-        });
+        })?;
         wl_conn.send(ZwlrLayerSurfaceV1Request::SetKeyboardInteractivity {
             zwlr_layer_surface_v1: layer_surface,
             keyboard_interactivity: ZWLR_LAYER_SURFACE_V1_KEYBOARD_INTERACTIVITY_EXCLUSIVE,
-        });
-        let region = wl_conn.send_constructor(0, |id| WlCompositorRequest::CreateRegion {
-            wl_compositor: app.globals.wl_compositor,
-            id,
-        });
-        wl_conn.send(WlSurfaceRequest::SetInputRegion { wl_surface, region });
-        wl_conn.send(WlSurfaceRequest::Commit { wl_surface });
+        })?;
+        // Let pointer input through to the windows below, unless buttons are bound.
+        if !app.config.has_button_bindings() {
+            let region = wl_conn.send_constructor(0, |id| WlCompositorRequest::CreateRegion {
+                wl_compositor: app.globals.wl_compositor,
+                id,
+            })?;
+            wl_conn.send(WlSurfaceRequest::SetInputRegion { wl_surface, region })?;
+        }
+        if !app.globals.viewporter.is_null() {
+            surface.viewport =
+                wl_conn.send_constructor(0, |id| WpViewporterRequest::GetViewport {
+                    wp_viewporter: app.globals.viewporter,
+                    id,
+                    surface: wl_surface,
+                })?;
+            if !app.globals.fractional_scale_manager.is_null() {
+                surface.fractional_scale =
+                    wl_conn.send_constructor(output_id.into_raw(), |id| {
+                        WpFractionalScaleManagerV1Request::GetFractionalScale {
+                            wp_fractional_scale_manager_v1: app.globals.fractional_scale_manager,
+                            id,
+                            surface: wl_surface,
+                        }
+                    })?;
+            }
+        }
+        wl_conn.send(WlSurfaceRequest::Commit { wl_surface })?;
 
         surface.output = output_id;
         surface.wl_surface = wl_surface;
@@ -1057,12 +2247,13 @@ fn main() -> Result<()> {
     }
 
     if let Some(ei_conn) = ei_conn.as_mut() {
-        ei_conn.wire.flush_blocking()?;
-        ei_conn.wire.read_blocking()?;
-        ei_conn.handle_events(|ei_conn, event| app.handle_ei_event(ei_conn, event));
+        // Seats are announced along with the connection and bound once they're done, then
+        // their devices are added, so it takes two syncs to have devices to warp with.
+        ei_conn.sync(|ei_conn, event| app.handle_ei_event(ei_conn, event))?;
+        ei_conn.sync(|ei_conn, event| app.handle_ei_event(ei_conn, event))?;
     }
 
-    for seat in app.seats.iter() {
+    for seat in app.seats.iter().filter(|_| app.config.warp == Warp::Always) {
         if !seat.virtual_pointer.is_null() {
             wl_conn.send(ZwlrVirtualPointerV1Request::MotionAbsolute {
                 zwlr_virtual_pointer_v1: seat.virtual_pointer,
@@ -1071,41 +2262,17 @@ fn main() -> Result<()> {
                 y: app.region.center().y as u32,
                 x_extent: app.global_bounds.width as u32,
                 y_extent: app.global_bounds.height as u32,
-            });
+            })?;
             wl_conn.send(ZwlrVirtualPointerV1Request::Frame {
                 zwlr_virtual_pointer_v1: seat.virtual_pointer,
-            });
-        } else if let (
-            Some(ei_conn),
-            Some(&EiDeviceInterfaces {
-                device,
-                pointer_absolute,
-                ..
-            }),
-        ) = (ei_conn.as_mut(), app.ei_state.devices.values().next())
-        {
-            ei_conn.send(EiDeviceRequest::StartEmulating {
-                ei_device: device,
-                last_serial: app.ei_state.last_serial,
-                sequence: app.ei_state.sequence,
-            });
-            app.ei_state.sequence += 1;
-
-            ei_conn.send(EiPointerAbsoluteRequest::MotionAbsolute {
-                ei_pointer_absolute: pointer_absolute,
-                x: app.region.center().x as f32,
-                y: app.region.center().y as f32,
-            });
-            ei_conn.send(EiDeviceRequest::Frame {
-                ei_device: device,
-                last_serial: app.ei_state.last_serial,
-                timestamp: 0,
-            });
-
-            ei_conn.send(EiDeviceRequest::StopEmulating {
-                ei_device: device,
-                last_serial: app.ei_state.last_serial,
-            });
+            })?;
+        } else if let (Some(ei_conn), Some((device, (x, y)))) = (
+            ei_conn.as_mut(),
+            app.ei_state
+                .pointer_device_at(&output_regions(&app.outputs), app.region.center()),
+        ) {
+            let mut emulating = app.ei_state.start_emulating(ei_conn, device);
+            app.ei_state.move_pointer(&mut emulating, device, (x, y));
         }
     }
 
@@ -1113,44 +2280,107 @@ fn main() -> Result<()> {
 
     while !app.quit {
         let now = Instant::now();
-        let next_timer = app
-            .seats
-            .iter()
-            .filter_map(|seat| seat.key_repeat)
-            .map(|(instant, _)| instant)
-            .min();
-        let timeout = match next_timer {
-            Some(instant) => instant.duration_since(now).as_millis() as i32,
-            None => -1,
-        };
-        let (wl_revents, ei_revents) = if let Some(ei_conn) = ei_conn.as_ref() {
-            let mut pollfds = [
-                PollFd::new(&wl_conn.wire, PollFlags::IN),
-                PollFd::new(&ei_conn.wire, PollFlags::IN),
-            ];
-            rustix::event::poll(&mut pollfds, timeout)?;
-            let wl_revents = pollfds[0].revents();
-            let ei_revents = pollfds[1].revents();
-            (wl_revents, ei_revents)
-        } else {
-            let mut pollfds = [PollFd::new(&wl_conn.wire, PollFlags::IN)];
-            rustix::event::poll(&mut pollfds, timeout)?;
-            let wl_revents = pollfds[0].revents();
-            (wl_revents, PollFlags::empty())
-        };
-        if wl_revents.contains(PollFlags::IN) {
+        let next_timer =
+            app.seats
+                .iter()
+                .flat_map(|seat| {
+                    let repeat = seat.key_repeat.map(|(instant, _)| instant);
+                    let chord = seat.pending_chord.map(|(instant, _, _)| instant);
+                    repeat.into_iter().chain(seat.sequence_timeout).chain(chord)
+                })
+                .chain(app.fading_out.map(|start| {
+                    start + Duration::from_millis(app.config.appearance.fade_out.into())
+                }))
+                .chain(ei_reconnect_at)
+                .min();
+        let mut poller = wayland::Poller::new();
+        let wl_token = poller.add(&wl_conn.wire);
+        let ei_token = ei_conn.as_ref().map(|ei_conn| poller.add(&ei_conn.wire));
+        let ready = poller.poll(next_timer)?;
+        if ready.is_readable(wl_token) {
             wl_conn.wire.read_nonblocking()?;
-            wl_conn.handle_events(|conn, event| app.handle_event(conn, ei_conn.as_mut(), event));
+            wl_conn.handle_events(|conn, event| app.handle_event(conn, ei_conn.as_mut(), event))?;
         }
-        if ei_revents.contains(PollFlags::IN) {
+        let mut ei_lost = false;
+        if ei_token.is_some_and(|token| ready.is_readable(token)) {
             let ei_conn = ei_conn.as_mut().unwrap();
-            ei_conn.wire.read_nonblocking()?;
+            match ei_conn.wire.read_nonblocking() {
+                Ok(true) | Err(Errno::WOULDBLOCK) => {}
+                Ok(false) => ei_lost = true,
+                Err(e) => {
+                    log::warn!("failed to read from the EIS implementation: {e}");
+                    ei_lost = true;
+                }
+            }
             ei_conn.handle_events(|ei_conn, event| app.handle_ei_event(ei_conn, event));
         }
         if let Some(ei_conn) = ei_conn.as_mut() {
-            ei_conn.wire.flush_blocking()?;
+            if let Err(e) = ei_conn.wire.flush_blocking() {
+                log::warn!("failed to write to the EIS implementation: {e}");
+                ei_lost = true;
+            }
+        }
+        if ei_lost || app.ei_state.disconnected.is_some() {
+            // Connecting again would only repeat an error waypoint made, and a purposeful
+            // disconnection shouldn't be undone.
+            let reconnect = match app.ei_state.disconnected.take() {
+                Some(e) => {
+                    log::warn!("{e}");
+                    !e.reason.is_client_error() && e.reason != ei::DisconnectReason::Disconnected
+                }
+                None => {
+                    log::warn!("lost the connection to the EIS implementation");
+                    true
+                }
+            };
+            ei_conn = None;
+            app.ei_state = EiState::default();
+            if can_reconnect_ei && reconnect {
+                ei_reconnect_at = Some(now);
+            }
+        }
+        if ei_reconnect_at.is_some_and(|at| at <= Instant::now()) {
+            match reconnect_ei() {
+                Ok((conn, serial)) => {
+                    log::info!("reconnected to the EIS implementation");
+                    ei_conn = Some(conn);
+                    app.ei_state.last_serial = serial;
+                    ei_reconnect_at = None;
+                }
+                Err(e) => {
+                    log::debug!("failed to reconnect to the EIS implementation: {e:#}");
+                    ei_reconnect_at = Some(Instant::now() + EI_RECONNECT_INTERVAL);
+                }
+            }
         }
         wl_conn.wire.flush_blocking()?;
+        if app.fading_out.is_some() {
+            if app.opacity(Instant::now()) == 0.0 {
+                app.quit = true;
+            } else {
+                app.draw_all(&mut wl_conn)?;
+                wl_conn.wire.flush_blocking()?;
+            }
+        }
+        let mut chord_seats = Vec::new();
+        let mut sequence_seats = Vec::new();
+        for (seat_id, seat) in app.seats.iter_with_handles() {
+            if seat
+                .pending_chord
+                .is_some_and(|(instant, _, _)| instant <= now)
+            {
+                chord_seats.push(seat_id);
+            }
+            if seat.sequence_timeout.is_some_and(|instant| instant <= now) {
+                sequence_seats.push(seat_id);
+            }
+        }
+        for seat_id in chord_seats {
+            flush_pending_chord(&mut app, seat_id, &mut wl_conn, ei_conn.as_mut())?;
+        }
+        for seat_id in sequence_seats {
+            handle_sequence_timeout(&mut app, seat_id, &mut wl_conn, ei_conn.as_mut())?;
+        }
         let mut seats = Vec::new();
         for (seat_id, seat) in app.seats.iter_mut_with_handles() {
             if let Some((instant, _)) = seat.key_repeat {
@@ -1161,7 +2391,9 @@ fn main() -> Result<()> {
         }
         for seat_id in seats {
             let seat = &mut app.seats[seat_id];
-            let (instant, keycode) = seat.key_repeat.unwrap();
+            let Some((instant, keycode)) = seat.key_repeat else {
+                continue;
+            };
             handle_key_pressed(
                 &mut app,
                 0,
@@ -1169,7 +2401,7 @@ fn main() -> Result<()> {
                 seat_id,
                 &mut wl_conn,
                 ei_conn.as_mut(),
-            );
+            )?;
             let seat = &mut app.seats[seat_id];
             seat.key_repeat = Some((instant + seat.repeat_period, keycode))
         }
@@ -1182,10 +2414,10 @@ fn main() -> Result<()> {
                 time: 0,
                 button,
                 state: WL_POINTER_BUTTON_STATE_RELEASED,
-            });
+            })?;
             wl_conn.send(ZwlrVirtualPointerV1Request::Frame {
                 zwlr_virtual_pointer_v1: seat.virtual_pointer,
-            });
+            })?;
         }
     }
     wl_conn.wire.flush_blocking()?;
@@ -1194,6 +2426,656 @@ fn main() -> Result<()> {
 }
 
 impl App {
+    fn draw(&mut self, conn: &mut WaylandConnection, output_id: OutputId) -> Result<()> {
+        let output = &self.outputs[output_id];
+        let current = output.state.current.as_ref().unwrap();
+        let shown_region = self.shown_region(Instant::now());
+        let drawn = self.drawn_state(output_id, shown_region);
+        let region = Region {
+            x: shown_region.x - current.logical_x,
+            y: shown_region.y - current.logical_y,
+            ..shown_region
+        };
+        let mut appearance = output.appearance.unwrap_or(self.config.appearance);
+        if drawn.sequence_pending {
+            appearance.border_color = appearance.sequence_color;
+        }
+
+        // Text, dashes, glows, outlines, the center dot, the magnifier and fading are only drawn
+        // in software.
+        if appearance.renderer == Renderer::SinglePixel
+            && self.globals.supports_single_pixel()
+            && drawn.hint_input.is_none()
+            && !appearance.cell_labels
+            && drawn.hud.is_none()
+            && appearance.border_dash == 0.0
+            && appearance.glow == 0.0
+            && appearance.center_dot == 0.0
+            && !appearance.outline
+            && drawn.magnified.is_none()
+            && drawn.opacity == 1.0
+        {
+            self.draw_single_pixel(conn, output_id, region, &appearance)?;
+        } else {
+            self.draw_shm(conn, output_id, region, &appearance, &drawn)?;
+            self.place_rects(conn, output_id, &[])?;
+        }
+
+        let surface = self.outputs[output_id].surface.as_mut().unwrap();
+        let wl_surface = surface.wl_surface;
+        surface.drawn = Some(drawn);
+        // Until the compositor shows this frame, changes are only drawn once it's done.
+        if !surface.frame_pending {
+            surface.frame_pending = true;
+            conn.send_constructor(output_id.into_raw(), |callback: WlCallback| {
+                WlSurfaceRequest::Frame {
+                    wl_surface,
+                    callback,
+                }
+            })?;
+        }
+        conn.send(WlSurfaceRequest::Commit { wl_surface })?;
+        Ok(())
+    }
+
+    /// Draws an output's overlay into a shared memory buffer and attaches it.
+    fn draw_shm(
+        &mut self,
+        conn: &mut WaylandConnection,
+        output_id: OutputId,
+        region: Region,
+        appearance: &Appearance,
+        drawn: &DrawnState,
+    ) -> Result<()> {
+        let output = &self.outputs[output_id];
+        let surface = output.surface.as_ref().unwrap();
+        let current = output.state.current.as_ref().unwrap();
+        let integer_scale = surface
+            .preferred_buffer_scale
+            .unwrap_or(current.integer_scale);
+        let scale = match surface.preferred_scale {
+            Some(scale) => f64::from(scale) / 120.0,
+            None => f64::from(integer_scale),
+        };
+        let width = (f64::from(surface.width) * scale).round() as u32;
+        let height = (f64::from(surface.height) * scale).round() as u32;
+
+        // tiny-skia's RGBA bytes are ABGR8888 in the little-endian terms of wl_shm, but only
+        // ARGB8888 is supported everywhere, which needs red and blue swapped.
+        let format = if self.shm_formats.contains(&WL_SHM_FORMAT_ABGR8888) {
+            WL_SHM_FORMAT_ABGR8888
+        } else {
+            WL_SHM_FORMAT_ARGB8888
+        };
+        let buffer_data = surface_buffer(
+            &self.globals,
+            &mut self.buffers,
+            conn,
+            output_id,
+            i32::try_from(width).unwrap(),
+            i32::try_from(height).unwrap(),
+            format,
+        )?;
+        let buffer = &mut self.buffers[buffer_data];
+        buffer.busy = true;
+        let mut pixmap =
+            tiny_skia::PixmapMut::from_bytes(buffer.mmap.as_deref_mut().unwrap(), width, height)
+                .expect("PixmapMut creation failed");
+        pixmap.fill(Color::TRANSPARENT);
+        draw_inner(
+            region,
+            scale,
+            &mut pixmap,
+            appearance,
+            appearance.guides.grid(self.config.grid),
+        );
+        if let (Some(area), Some(image)) = (drawn.magnified, &self.magnifier.image) {
+            draw_magnifier(region, scale, &mut pixmap, image, area, appearance);
+        }
+        // With cell labels on, every label is shown until a hint is being typed.
+        let label_input = drawn
+            .hint_input
+            .as_deref()
+            .or(appearance.cell_labels.then_some(""));
+        if label_input.is_some() || drawn.hud.is_some() {
+            let font = self.font.get_or_init(|| {
+                Font::load(&self.config.font)
+                    .map_err(|err| log::warn!("can't draw text: {err:#}"))
+                    .ok()
+            });
+            if let Some(font) = font {
+                if let Some(input) = label_input {
+                    draw_hint_labels(
+                        region,
+                        scale,
+                        &mut pixmap,
+                        font,
+                        input,
+                        &self.config,
+                        appearance,
+                    );
+                }
+                if let Some(text) = &drawn.hud {
+                    draw_hud(scale, &mut pixmap, font, text, appearance);
+                }
+            }
+        }
+        // The pixels are premultiplied, so fading them scales every channel alike.
+        if drawn.opacity < 1.0 {
+            for byte in buffer.mmap.as_deref_mut().unwrap() {
+                *byte = (f32::from(*byte) * drawn.opacity).round() as u8;
+            }
+        }
+        if format == WL_SHM_FORMAT_ARGB8888 {
+            for pixel in buffer.mmap.as_deref_mut().unwrap().chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        if !surface.viewport.is_null() {
+            // The viewport scales the buffer back down to the surface size with fractional
+            // scaling, and may still be set from the single-pixel renderer otherwise.
+            conn.send(WpViewportRequest::SetDestination {
+                wp_viewport: surface.viewport,
+                width: i32::try_from(surface.width).unwrap(),
+                height: i32::try_from(surface.height).unwrap(),
+            })?;
+        }
+        let buffer_scale = match surface.preferred_scale {
+            Some(_) => 1,
+            None => integer_scale,
+        };
+        conn.send(WlSurfaceRequest::SetBufferScale {
+            wl_surface: surface.wl_surface,
+            scale: i32::try_from(buffer_scale).unwrap(),
+        })?;
+        conn.send(WlSurfaceRequest::Attach {
+            wl_surface: surface.wl_surface,
+            buffer: buffer.wl_buffer,
+            x: 0,
+            y: 0,
+        })?;
+        conn.send(WlSurfaceRequest::DamageBuffer {
+            wl_surface: surface.wl_surface,
+            x: 0,
+            y: 0,
+            width: i32::MAX,
+            height: i32::MAX,
+        })?;
+        Ok(())
+    }
+
+    /// Shows an output's overlay as rectangles of single-pixel buffers, over a transparent
+    /// single-pixel buffer stretched across the output.
+    fn draw_single_pixel(
+        &mut self,
+        conn: &mut WaylandConnection,
+        output_id: OutputId,
+        region: Region,
+        appearance: &Appearance,
+    ) -> Result<()> {
+        let transparent = self.single_pixel_buffer(conn, Color::TRANSPARENT)?;
+        let surface = self.outputs[output_id].surface.as_ref().unwrap();
+        let (width, height) = (surface.width as i32, surface.height as i32);
+        conn.send(WpViewportRequest::SetDestination {
+            wp_viewport: surface.viewport,
+            width,
+            height,
+        })?;
+        conn.send(WlSurfaceRequest::SetBufferScale {
+            wl_surface: surface.wl_surface,
+            scale: 1,
+        })?;
+        conn.send(WlSurfaceRequest::Attach {
+            wl_surface: surface.wl_surface,
+            buffer: transparent,
+            x: 0,
+            y: 0,
+        })?;
+        conn.send(WlSurfaceRequest::DamageBuffer {
+            wl_surface: surface.wl_surface,
+            x: 0,
+            y: 0,
+            width: i32::MAX,
+            height: i32::MAX,
+        })?;
+        let rects = overlay_rects(
+            region,
+            width,
+            height,
+            appearance,
+            appearance.guides.grid(self.config.grid),
+        );
+        self.place_rects(conn, output_id, &rects)
+    }
+
+    /// Shows `rects` on the subsurfaces of an output's surface, creating more subsurfaces as
+    /// needed and hiding the rest.
+    fn place_rects(
+        &mut self,
+        conn: &mut WaylandConnection,
+        output_id: OutputId,
+        rects: &[(Region, Color)],
+    ) -> Result<()> {
+        let buffers = rects
+            .iter()
+            .map(|&(_, color)| self.single_pixel_buffer(conn, color))
+            .collect::<Result<Vec<_>>>()?;
+        let globals = &self.globals;
+        let surface = self.outputs[output_id].surface.as_mut().unwrap();
+        while surface.rects.len() < rects.len() {
+            let wl_surface = conn.send_constructor(0, |id| WlCompositorRequest::CreateSurface {
+                wl_compositor: globals.wl_compositor,
+                id,
+            })?;
+            let subsurface =
+                conn.send_constructor(0, |id| WlSubcompositorRequest::GetSubsurface {
+                    wl_subcompositor: globals.subcompositor,
+                    id,
+                    surface: wl_surface,
+                    parent: surface.wl_surface,
+                })?;
+            let viewport = conn.send_constructor(0, |id| WpViewporterRequest::GetViewport {
+                wp_viewporter: globals.viewporter,
+                id,
+                surface: wl_surface,
+            })?;
+            let region = conn.send_constructor(0, |id| WlCompositorRequest::CreateRegion {
+                wl_compositor: globals.wl_compositor,
+                id,
+            })?;
+            conn.send(WlSurfaceRequest::SetInputRegion { wl_surface, region })?;
+            surface.rects.push(RectSurface {
+                wl_surface,
+                subsurface,
+                viewport,
+                visible: false,
+            });
+        }
+        for (i, rect_surface) in surface.rects.iter_mut().enumerate() {
+            let wl_surface = rect_surface.wl_surface;
+            match rects.get(i) {
+                Some(&(rect, _)) => {
+                    conn.send(WlSubsurfaceRequest::SetPosition {
+                        wl_subsurface: rect_surface.subsurface,
+                        x: rect.x,
+                        y: rect.y,
+                    })?;
+                    conn.send(WpViewportRequest::SetDestination {
+                        wp_viewport: rect_surface.viewport,
+                        width: rect.width,
+                        height: rect.height,
+                    })?;
+                    conn.send(WlSurfaceRequest::Attach {
+                        wl_surface,
+                        buffer: buffers[i],
+                        x: 0,
+                        y: 0,
+                    })?;
+                    conn.send(WlSurfaceRequest::DamageBuffer {
+                        wl_surface,
+                        x: 0,
+                        y: 0,
+                        width: 1,
+                        height: 1,
+                    })?;
+                    rect_surface.visible = true;
+                }
+                None if rect_surface.visible => {
+                    conn.send(WlSurfaceRequest::Attach {
+                        wl_surface,
+                        buffer: WlBuffer::default(),
+                        x: 0,
+                        y: 0,
+                    })?;
+                    rect_surface.visible = false;
+                }
+                None => continue,
+            }
+            conn.send(WlSurfaceRequest::Commit { wl_surface })?;
+        }
+        Ok(())
+    }
+
+    /// Returns a single-pixel buffer of `color`, creating it the first time it's needed.
+    fn single_pixel_buffer(
+        &mut self,
+        conn: &mut WaylandConnection,
+        color: Color,
+    ) -> Result<WlBuffer> {
+        let color = color.premultiply().to_color_u8();
+        let key = [color.red(), color.green(), color.blue(), color.alpha()];
+        if let Some(&buffer) = self.single_pixel_buffers.get(&key) {
+            return Ok(buffer);
+        }
+        // Channels are scaled from 0..=255 to 0..=u32::MAX.
+        let channel = |value: u8| u32::from(value) * 0x0101_0101;
+        let manager = self.globals.single_pixel_buffer_manager;
+        let buffer = conn.send_constructor(0, |id| {
+            WpSinglePixelBufferManagerV1Request::CreateU32RgbaBuffer {
+                wp_single_pixel_buffer_manager_v1: manager,
+                id,
+                r: channel(key[0]),
+                g: channel(key[1]),
+                b: channel(key[2]),
+                a: channel(key[3]),
+            }
+        })?;
+        self.single_pixel_buffers.insert(key, buffer);
+        Ok(buffer)
+    }
+
+    /// Returns the region drawn at `now`, which lags behind `region` during a transition.
+    fn shown_region(&self, now: Instant) -> Region {
+        match self.animation_progress(now) {
+            Some((from, t)) => from.lerp(
+                self.region,
+                self.config.appearance.animation_easing.apply(t),
+            ),
+            None => self.region,
+        }
+    }
+
+    /// Returns the region the current transition started from and the fraction of it that has
+    /// passed at `now`, or `None` if it's over.
+    fn animation_progress(&self, now: Instant) -> Option<(Region, f64)> {
+        let (from, start) = self.animation?;
+        let duration = Duration::from_millis(self.config.appearance.animation_duration.into());
+        let t = now.duration_since(start).as_secs_f64() / duration.as_secs_f64();
+        (t < 1.0).then_some((from, t))
+    }
+
+    /// Returns what an output's surface would be drawn with when `shown_region` is shown.
+    fn drawn_state(&self, output_id: OutputId, shown_region: Region) -> DrawnState {
+        // The border is drawn centered on the region's edges, so it and its glow can reach a
+        // little past them.
+        let output = &self.outputs[output_id];
+        let appearance = output
+            .appearance
+            .as_ref()
+            .unwrap_or(&self.config.appearance);
+        let margin = (appearance.border_thickness + appearance.glow).ceil() as i32 + 1;
+        let output_region = output.region();
+        let bounds = Region {
+            x: output_region.x - margin,
+            y: output_region.y - margin,
+            width: output_region.width + margin * 2,
+            height: output_region.height + margin * 2,
+        };
+        DrawnState {
+            region: bounds.intersects(&shown_region).then_some(shown_region),
+            sequence_pending: self.seats.iter().any(|seat| !seat.sequence.is_empty()),
+            hint_input: self.hint_input.clone(),
+            magnified: match self.magnifier.copied {
+                Some((copied_output_id, area))
+                    if copied_output_id == output_id && self.magnifier.image.is_some() =>
+                {
+                    Some(area)
+                }
+                _ => None,
+            },
+            hud: self
+                .hud_text()
+                .filter(|_| self.active_output() == Some(output_id)),
+            opacity: self.opacity(Instant::now()),
+        }
+    }
+
+    /// Returns how opaque the overlay is at `now`, which drops from 1 to 0 while it fades out.
+    fn opacity(&self, now: Instant) -> f32 {
+        let Some(start) = self.fading_out else {
+            return 1.0;
+        };
+        let duration = Duration::from_millis(self.config.appearance.fade_out.into());
+        let t = now.duration_since(start).as_secs_f32() / duration.as_secs_f32();
+        (1.0 - t).max(0.0)
+    }
+
+    /// Returns the output the center of the region is on.
+    fn active_output(&self) -> Option<OutputId> {
+        let center = self.region.center();
+        self.outputs
+            .iter_with_handles()
+            .find(|(_, output)| {
+                output.state.current.is_some() && output.region().contains(center.x, center.y)
+            })
+            .map(|(output_id, _)| output_id)
+    }
+
+    /// Returns the heads-up display text, listing the mode and any pending input, if it's
+    /// enabled.
+    fn hud_text(&self) -> Option<String> {
+        if !self.config.appearance.hud {
+            return None;
+        }
+        let mut parts = vec![format!("mode {}", self.config.mode_name(self.mode))];
+        for seat in self.seats.iter() {
+            let Some(xkb_state) = &seat.xkb_state else {
+                continue;
+            };
+            if !seat.sequence.is_empty() {
+                let keys = seat
+                    .sequence
+                    .iter()
+                    .map(|&(_, keycode)| xkb::keysym_get_name(xkb_state.key_get_one_sym(keycode)))
+                    .collect::<Vec<_>>();
+                parts.push(keys.join(" "));
+            }
+        }
+        if let Some(input) = &self.hint_input {
+            parts.push(format!("hint {input}"));
+        }
+        for button in [Button::Left, Button::Right, Button::Middle] {
+            let code = button.code();
+            if self
+                .seats
+                .iter()
+                .any(|seat| seat.buttons_down.contains(&code))
+            {
+                parts.push(format!("holding {}", button.name()));
+            }
+        }
+        Some(parts.join(" · "))
+    }
+
+    /// Returns whether an output's surface shows something other than `shown_region` would be
+    /// drawn with.
+    fn is_outdated(&self, output_id: OutputId, shown_region: Region) -> bool {
+        self.outputs[output_id]
+            .surface
+            .as_ref()
+            .is_some_and(|surface| {
+                surface.drawn.as_ref() != Some(&self.drawn_state(output_id, shown_region))
+            })
+    }
+
+    /// Redraws the outputs whose contents changed, except those still waiting for the compositor
+    /// to show their last frame, which are redrawn when it does.
+    fn draw_all(&mut self, conn: &mut WaylandConnection) -> Result<()> {
+        let shown_region = self.shown_region(Instant::now());
+        let output_ids = self
+            .outputs
+            .iter_with_handles()
+            .filter(|&(output_id, output)| {
+                output
+                    .surface
+                    .as_ref()
+                    .is_some_and(|surface| !surface.frame_pending)
+                    && self.is_outdated(output_id, shown_region)
+            })
+            .map(|(output_id, _)| output_id)
+            .collect::<Vec<_>>();
+        for output_id in output_ids {
+            self.draw(conn, output_id)?;
+        }
+        self.update_magnifier(conn)
+    }
+
+    /// Returns the output and output-local area the magnifier shows, if it's enabled.
+    fn magnifier_area(&self) -> Option<(OutputId, Region)> {
+        let appearance = &self.config.appearance;
+        if appearance.magnifier_zoom == 0 || self.globals.screencopy_manager.is_null() {
+            return None;
+        }
+        let center = self.region.center();
+        let output_id = self.active_output()?;
+        let output_region = self.outputs[output_id].region();
+        let size = (appearance.magnifier_size / appearance.magnifier_zoom as f32).ceil() as i32;
+        let width = size.clamp(1, output_region.width);
+        let height = size.clamp(1, output_region.height);
+        let area = Region {
+            x: (center.x - output_region.x - width / 2).clamp(0, output_region.width - width),
+            y: (center.y - output_region.y - height / 2).clamp(0, output_region.height - height),
+            width,
+            height,
+        };
+        Some((output_id, area))
+    }
+
+    /// Starts copying the screen for the magnifier if the area it shows changed.
+    fn update_magnifier(&mut self, conn: &mut WaylandConnection) -> Result<()> {
+        if !self.magnifier.frame.is_null() {
+            return Ok(());
+        }
+        let Some((output_id, area)) = self.magnifier_area() else {
+            return Ok(());
+        };
+        if self.magnifier.copied == Some((output_id, area)) {
+            return Ok(());
+        }
+        let wl_output = self.outputs[output_id].wl_output;
+        self.magnifier.frame = conn.send_constructor(0, |frame| {
+            ZwlrScreencopyManagerV1Request::CaptureOutputRegion {
+                zwlr_screencopy_manager_v1: self.globals.screencopy_manager,
+                frame,
+                overlay_cursor: 0,
+                output: wl_output,
+                x: area.x,
+                y: area.y,
+                width: area.width,
+                height: area.height,
+            }
+        })?;
+        self.magnifier.pending = Some((output_id, area));
+        self.magnifier.y_invert = false;
+        Ok(())
+    }
+
+    /// Copies the magnifier's frame into a buffer of the size and format the compositor asked
+    /// for, replacing the buffer of the last copy if that doesn't match.
+    fn copy_magnifier_frame(
+        &mut self,
+        conn: &mut WaylandConnection,
+        format: u32,
+        width: u32,
+        height: u32,
+        stride: u32,
+    ) -> Result<()> {
+        ensure!(
+            matches!(
+                format,
+                WL_SHM_FORMAT_ARGB8888
+                    | WL_SHM_FORMAT_XRGB8888
+                    | WL_SHM_FORMAT_ABGR8888
+                    | WL_SHM_FORMAT_XBGR8888
+            ),
+            "unsupported format {format:#x}"
+        );
+        let width = i32::try_from(width)?;
+        let height = i32::try_from(height)?;
+        let stride = i32::try_from(stride)?;
+        let magnifier = &mut self.magnifier;
+        let reusable = magnifier.buffer.filter(|&buffer_id| {
+            let buffer = &self.buffers[buffer_id];
+            (buffer.width, buffer.height) == (width, height)
+                && (magnifier.format, magnifier.stride) == (format, stride)
+        });
+        let buffer_id = match reusable {
+            Some(buffer_id) => buffer_id,
+            None => {
+                if let Some(buffer) = magnifier
+                    .buffer
+                    .take()
+                    .and_then(|id| self.buffers.remove(id))
+                {
+                    conn.send(WlShmPoolRequest::Destroy {
+                        wl_shm_pool: buffer.pool,
+                    })?;
+                    conn.send(WlBufferRequest::Destroy {
+                        wl_buffer: buffer.wl_buffer,
+                    })?;
+                }
+                let buffer_id = make_buffer(
+                    &self.globals,
+                    &mut self.buffers,
+                    conn,
+                    width,
+                    height,
+                    stride,
+                    format,
+                )?;
+                let buffer = &mut self.buffers[buffer_id];
+                buffer.width = width;
+                buffer.height = height;
+                buffer.busy = true;
+                magnifier.buffer = Some(buffer_id);
+                magnifier.format = format;
+                magnifier.stride = stride;
+                buffer_id
+            }
+        };
+        conn.send(ZwlrScreencopyFrameV1Request::Copy {
+            zwlr_screencopy_frame_v1: magnifier.frame,
+            buffer: self.buffers[buffer_id].wl_buffer,
+        })?;
+        Ok(())
+    }
+
+    /// Converts the screen copied into the magnifier's buffer into a pixmap.
+    fn magnifier_image(&self) -> Option<tiny_skia::Pixmap> {
+        let buffer = &self.buffers[self.magnifier.buffer?];
+        let data = buffer.mmap.as_deref()?;
+        let width = usize::try_from(buffer.width).ok()?;
+        let height = usize::try_from(buffer.height).ok()?;
+        let stride = usize::try_from(self.magnifier.stride).ok()?;
+        let mut pixmap = tiny_skia::Pixmap::new(width as u32, height as u32)?;
+        for (y, row) in pixmap.pixels_mut().chunks_exact_mut(width).enumerate() {
+            let src_y = if self.magnifier.y_invert {
+                height - 1 - y
+            } else {
+                y
+            };
+            let src = data.get(src_y * stride..)?.get(..width * 4)?;
+            for (pixel, src) in row.iter_mut().zip(src.chunks_exact(4)) {
+                // The formats are little-endian, so ARGB8888 is stored as B, G, R, A.
+                let [r, g, b, a] = match self.magnifier.format {
+                    WL_SHM_FORMAT_ARGB8888 => [src[2], src[1], src[0], src[3]],
+                    WL_SHM_FORMAT_XRGB8888 => [src[2], src[1], src[0], 0xff],
+                    WL_SHM_FORMAT_ABGR8888 => [src[0], src[1], src[2], src[3]],
+                    _ => [src[0], src[1], src[2], 0xff],
+                };
+                *pixel = PremultipliedColorU8::from_rgba(r, g, b, a)
+                    .unwrap_or(PremultipliedColorU8::TRANSPARENT);
+            }
+        }
+        Some(pixmap)
+    }
+
+    /// Ends the magnifier's copy in progress, showing `image` if it succeeded, and starts the
+    /// next one if the region moved in the meantime.
+    fn finish_magnifier_frame(
+        &mut self,
+        conn: &mut WaylandConnection,
+        image: Option<tiny_skia::Pixmap>,
+    ) -> Result<()> {
+        conn.send(ZwlrScreencopyFrameV1Request::Destroy {
+            zwlr_screencopy_frame_v1: self.magnifier.frame,
+        })?;
+        self.magnifier.frame = Default::default();
+        self.magnifier.copied = self.magnifier.pending.take();
+        self.magnifier.image = image;
+        self.draw_all(conn)
+    }
+
     fn handle_ei_event(&mut self, ei_conn: &mut LibeiConnection, event: ei_gen::Event) {
         match event {
             ei_gen::Event::EiHandshake(event) => match event {
@@ -1203,11 +3085,9 @@ impl App {
                     ei_handshake: _,
                     serial,
                     connection,
-                    version: _,
+                    version,
                 } => {
-                    ei_conn
-                        .interfaces
-                        .insert(connection.id(), ei_gen::Interface::EiConnection);
+                    ei_conn.insert(connection.id(), ei_gen::Interface::EiConnection, version);
                     self.ei_state.last_serial = serial;
                 }
             },
@@ -1215,66 +3095,91 @@ impl App {
                 EiCallbackEvent::Done { .. } => {}
             },
             ei_gen::Event::EiConnection(event) => match event {
-                EiConnectionEvent::Disconnected { .. } => {}
+                EiConnectionEvent::Disconnected {
+                    ei_connection: _,
+                    last_serial: _,
+                    reason,
+                    explanation,
+                } => {
+                    self.ei_state.disconnected = Some(ei::Disconnected {
+                        reason: ei::DisconnectReason::from_raw(reason),
+                        explanation: explanation.map(Cow::into_owned),
+                    });
+                }
                 EiConnectionEvent::Seat {
                     ei_connection: _,
                     seat,
-                    version: _,
+                    version,
                 } => {
-                    ei_conn
-                        .interfaces
-                        .insert(seat.id(), ei_gen::Interface::EiSeat);
+                    ei_conn.insert(seat.id(), ei_gen::Interface::EiSeat, version);
                     self.ei_state.seat_capabilities.insert(seat.id(), 0);
                 }
                 EiConnectionEvent::InvalidObject {
                     ei_connection: _,
-                    last_serial: _,
-                    invalid_id: _,
-                } => {}
-                EiConnectionEvent::Ping {
-                    ei_connection: _,
-                    ping,
-                    version: _,
+                    last_serial,
+                    invalid_id,
                 } => {
-                    ei_conn.send(EiPingpongRequest::Done {
-                        ei_pingpong: ping,
-                        callback_data: 0,
-                    });
+                    let interface = ei_conn.objects.get(&invalid_id);
+                    log::warn!(
+                        "the EIS implementation doesn't know object {invalid_id} ({}), which waypoint used after serial {last_serial}",
+                        interface.map_or("unknown interface", |(interface, _)| interface.name()),
+                    );
                 }
+                // Answered by the ei crate, so it never gets here.
+                EiConnectionEvent::Ping { .. } => {}
             },
             ei_gen::Event::EiDevice(event) => match event {
                 EiDeviceEvent::Destroyed { .. } => {}
                 EiDeviceEvent::Name { .. } => {}
                 EiDeviceEvent::DeviceType { .. } => {}
                 EiDeviceEvent::Dimensions { .. } => {}
-                EiDeviceEvent::Region { .. } => {}
+                EiDeviceEvent::Region {
+                    ei_device,
+                    offset_x,
+                    offset_y,
+                    width,
+                    hight,
+                    scale: _,
+                } => {
+                    let data = self.ei_state.devices.get_mut(&ei_device.id()).unwrap();
+                    data.regions.push(Region {
+                        x: offset_x as i32,
+                        y: offset_y as i32,
+                        width: width as i32,
+                        height: hight as i32,
+                    });
+                }
                 EiDeviceEvent::Interface {
                     ei_device,
                     object,
                     interface_name,
-                    version: _,
+                    version,
                 } => match interface_name.as_ref() {
                     "ei_pointer_absolute" => {
-                        ei_conn
-                            .interfaces
-                            .insert(object, ei_gen::Interface::EiPointerAbsolute);
+                        ei_conn.insert(object, ei_gen::Interface::EiPointerAbsolute, version);
                         let data = self.ei_state.devices.get_mut(&ei_device.id()).unwrap();
                         data.pointer_absolute = EiPointerAbsolute(object);
                     }
+                    "ei_pointer" => {
+                        ei_conn.insert(object, ei_gen::Interface::EiPointer, version);
+                        let data = self.ei_state.devices.get_mut(&ei_device.id()).unwrap();
+                        data.pointer = EiPointer(object);
+                    }
                     "ei_button" => {
-                        ei_conn
-                            .interfaces
-                            .insert(object, ei_gen::Interface::EiButton);
+                        ei_conn.insert(object, ei_gen::Interface::EiButton, version);
                         let data = self.ei_state.devices.get_mut(&ei_device.id()).unwrap();
                         data.button = EiButton(object);
                     }
                     "ei_scroll" => {
-                        ei_conn
-                            .interfaces
-                            .insert(object, ei_gen::Interface::EiScroll);
+                        ei_conn.insert(object, ei_gen::Interface::EiScroll, version);
                         let data = self.ei_state.devices.get_mut(&ei_device.id()).unwrap();
                         data.scroll = EiScroll(object);
                     }
+                    "ei_keyboard" => {
+                        ei_conn.insert(object, ei_gen::Interface::EiKeyboard, version);
+                        let data = self.ei_state.devices.get_mut(&ei_device.id()).unwrap();
+                        data.keyboard = EiKeyboard(object);
+                    }
                     _ => {
                         unreachable!();
                     }
@@ -1282,7 +3187,12 @@ impl App {
                 EiDeviceEvent::Done { .. } => {}
                 EiDeviceEvent::Resumed { .. } => {}
                 EiDeviceEvent::Paused { .. } => {}
-                EiDeviceEvent::RegionMappingId { .. } => {}
+                EiDeviceEvent::RegionMappingId {
+                    ei_device: _,
+                    mapping_id,
+                } => {
+                    log::debug!("ei region mapping id {mapping_id:?}");
+                }
             },
             ei_gen::Event::EiPingpong(event) => match event {},
             ei_gen::Event::EiSeat(event) => match event {
@@ -1293,7 +3203,11 @@ impl App {
                     mask,
                     interface,
                 } => match interface.as_ref() {
-                    "ei_pointer_absolute" | "ei_button" | "ei_scroll" => {
+                    "ei_pointer_absolute"
+                    | "ei_pointer"
+                    | "ei_button"
+                    | "ei_scroll"
+                    | "ei_keyboard" => {
                         let caps = self
                             .ei_state
                             .seat_capabilities
@@ -1318,15 +3232,14 @@ impl App {
                 EiSeatEvent::Device {
                     ei_seat: _,
                     device,
-                    version: _,
+                    version,
                 } => {
-                    ei_conn
-                        .interfaces
-                        .insert(device.id(), ei_gen::Interface::EiDevice);
+                    ei_conn.insert(device.id(), ei_gen::Interface::EiDevice, version);
                     self.ei_state.devices.insert(
                         device.id(),
                         EiDeviceInterfaces {
                             device,
+                            emulation: ei::Device::new(device.id()),
                             ..EiDeviceInterfaces::default()
                         },
                     );
@@ -1335,33 +3248,92 @@ impl App {
             ei_gen::Event::EiButton(event) => match event {
                 EiButtonEvent::Destroyed { .. } => {}
             },
+            ei_gen::Event::EiPointer(event) => match event {
+                EiPointerEvent::Destroyed { .. } => {}
+            },
             ei_gen::Event::EiPointerAbsolute(event) => match event {
                 EiPointerAbsoluteEvent::Destroyed { .. } => {}
             },
             ei_gen::Event::EiScroll(event) => match event {
                 EiScrollEvent::Destroyed { .. } => {}
             },
+            ei_gen::Event::EiKeyboard(event) => match event {
+                EiKeyboardEvent::Destroyed { .. } => {}
+                EiKeyboardEvent::Keymap {
+                    ei_keyboard,
+                    keymap_type,
+                    size,
+                    keymap,
+                } => {
+                    let Some(data) = self.ei_state.keyboard_device(ei_keyboard) else {
+                        return;
+                    };
+                    data.keymap = ei::keyboard::Keyboard::from_keymap(keymap_type, keymap, size);
+                    if data.keymap.is_none() {
+                        log::warn!("couldn't load the keymap of an ei keyboard");
+                    }
+                }
+                EiKeyboardEvent::Modifiers {
+                    ei_keyboard,
+                    serial,
+                    depressed,
+                    locked,
+                    latched,
+                    group,
+                } => {
+                    self.ei_state.last_serial = serial;
+                    if let Some(keymap) = self
+                        .ei_state
+                        .keyboard_device(ei_keyboard)
+                        .and_then(|data| data.keymap.as_mut())
+                    {
+                        keymap.update_modifiers(depressed, locked, latched, group);
+                    }
+                }
+            },
         }
     }
 
     fn handle_event(
         &mut self,
         conn: &mut WaylandConnection,
-        ei_conn: Option<&mut LibeiConnection>,
+        mut ei_conn: Option<&mut LibeiConnection>,
         event: Event,
-    ) {
+    ) -> Result<()> {
         match event {
             Event::WlSeat(event) => match event {
                 WlSeatEvent::Capabilities {
                     wl_seat,
                     capabilities,
                 } => {
-                    let seat_id = SeatId::from_raw(conn.ids.data_for(wl_seat.id()).data);
+                    let seat_id = SeatId::from_raw(conn.data_for(wl_seat.id()));
                     let seat = &mut self.seats[seat_id];
                     if capabilities & WL_SEAT_CAPABILITY_KEYBOARD != 0 {
                         seat.keyboard = conn.send_constructor(seat_id.into_raw(), |id| {
                             WlSeatRequest::GetKeyboard { wl_seat, id }
-                        });
+                        })?;
+                    }
+                    if capabilities & WL_SEAT_CAPABILITY_POINTER != 0
+                        && self.config.has_button_bindings()
+                    {
+                        seat.pointer = conn.send_constructor(seat_id.into_raw(), |id| {
+                            WlSeatRequest::GetPointer { wl_seat, id }
+                        })?;
+                        if self.config.appearance.cursor == Cursor::Crosshair
+                            && !self.globals.cursor_shape_manager.is_null()
+                        {
+                            let wl_pointer = seat.pointer;
+                            seat.cursor_shape_device =
+                                conn.send_constructor(seat_id.into_raw(), |id| {
+                                    WpCursorShapeManagerV1Request::GetPointer {
+                                        wp_cursor_shape_manager_v1: self
+                                            .globals
+                                            .cursor_shape_manager,
+                                        cursor_shape_device: id,
+                                        pointer: wl_pointer,
+                                    }
+                                })?;
+                        }
                     }
                 }
                 WlSeatEvent::Name { .. } => {}
@@ -1374,7 +3346,7 @@ impl App {
                     size,
                 } => {
                     if format == WL_KEYBOARD_KEYMAP_FORMAT_XKB_V1 {
-                        let seat_id = SeatId::from_raw(conn.ids.data_for(wl_keyboard.id()).data);
+                        let seat_id = SeatId::from_raw(conn.data_for(wl_keyboard.id()));
                         let seat = &mut self.seats[seat_id];
                         let keymap = unsafe {
                             xkb::Keymap::new_from_fd(
@@ -1388,6 +3360,13 @@ impl App {
                         .ok()
                         .flatten();
                         if let Some(keymap) = keymap.as_ref() {
+                            let untypeable = untypeable_hint_chars(keymap, &self.config);
+                            if !untypeable.is_empty() {
+                                log::warn!(
+                                    "hint characters {:?} can't be typed with the current keymap",
+                                    String::from_iter(untypeable),
+                                );
+                            }
                             seat.xkb_state = Some(xkb::State::new(keymap));
                             (seat.mod_indices, seat.specialized_bindings) =
                                 specialize_bindings(keymap, &self.config);
@@ -1403,7 +3382,7 @@ impl App {
                     key,
                     state,
                 } => {
-                    let seat_id = SeatId::from_raw(conn.ids.data_for(wl_keyboard.id()).data);
+                    let seat_id = SeatId::from_raw(conn.data_for(wl_keyboard.id()));
                     let seat = &mut self.seats[seat_id];
                     let key_repeat = seat.key_repeat;
                     let keycode = key + 8;
@@ -1414,23 +3393,41 @@ impl App {
                         .get_keymap()
                         .key_repeats(keycode);
                     let repeat_delay = seat.repeat_delay;
+                    let repeat_period = seat.repeat_period;
 
                     if state == WL_KEYBOARD_KEY_STATE_PRESSED
                         && (key_repeat.is_none() || key_repeat.is_some_and(|(_, it)| it != keycode))
                     {
-                        handle_key_pressed(self, time, key, seat_id, conn, ei_conn);
-                        if keycode_repeats {
-                            let seat_id =
-                                SeatId::from_raw(conn.ids.data_for(wl_keyboard.id()).data);
-                            let seat = &mut self.seats[seat_id];
+                        let chorded = handle_key_down(
+                            self,
+                            time,
+                            key,
+                            seat_id,
+                            conn,
+                            ei_conn.as_deref_mut(),
+                        )?;
+                        let seat_id = SeatId::from_raw(conn.data_for(wl_keyboard.id()));
+                        let seat = &mut self.seats[seat_id];
+                        if chorded {
+                            // Chords don't repeat, and neither does their first key.
+                            seat.key_repeat = None;
+                        } else if keycode_repeats && !repeat_period.is_zero() {
                             seat.key_repeat = Some((Instant::now() + repeat_delay, keycode));
                         }
                     }
 
+                    if state == WL_KEYBOARD_KEY_STATE_RELEASED
+                        && self.seats[seat_id]
+                            .pending_chord
+                            .is_some_and(|(_, it, _)| it == key)
+                    {
+                        flush_pending_chord(self, seat_id, conn, ei_conn)?;
+                    }
+
                     if state == WL_KEYBOARD_KEY_STATE_RELEASED
                         && key_repeat.is_some_and(|(_, it)| it == keycode)
                     {
-                        let seat_id = SeatId::from_raw(conn.ids.data_for(wl_keyboard.id()).data);
+                        let seat_id = SeatId::from_raw(conn.data_for(wl_keyboard.id()));
                         let seat = &mut self.seats[seat_id];
                         seat.key_repeat = None;
                     }
@@ -1443,7 +3440,7 @@ impl App {
                     mods_locked,
                     group,
                 } => {
-                    let seat_id = SeatId::from_raw(conn.ids.data_for(wl_keyboard.id()).data);
+                    let seat_id = SeatId::from_raw(conn.data_for(wl_keyboard.id()));
                     let seat = &mut self.seats[seat_id];
                     let state = seat.xkb_state.as_mut().unwrap();
                     state.update_mask(mods_depressed, mods_latched, mods_locked, 0, 0, group);
@@ -1453,22 +3450,27 @@ impl App {
                     rate,
                     delay,
                 } => {
-                    let seat_id = SeatId::from_raw(conn.ids.data_for(wl_keyboard.id()).data);
+                    let seat_id = SeatId::from_raw(conn.data_for(wl_keyboard.id()));
                     let seat = &mut self.seats[seat_id];
-                    seat.repeat_period = Duration::from_millis(1000 / rate as u64);
-                    seat.repeat_delay = Duration::from_millis(delay as u64);
+                    let rate = self.config.repeat_rate.unwrap_or(rate as u32);
+                    let delay = self.config.repeat_delay.unwrap_or(delay as u32);
+                    seat.repeat_period = match rate {
+                        0 => Duration::ZERO,
+                        rate => Duration::from_nanos(1_000_000_000 / u64::from(rate)),
+                    };
+                    seat.repeat_delay = Duration::from_millis(u64::from(delay));
                 }
             },
             Event::WlOutput(event) => match event {
                 WlOutputEvent::Geometry { .. } => {}
                 WlOutputEvent::Mode { .. } => {}
                 WlOutputEvent::Done { wl_output } => {
-                    let output_id = OutputId::from_raw(conn.ids.data_for(wl_output.id()).data);
+                    let output_id = OutputId::from_raw(conn.data_for(wl_output.id()));
                     let output = &mut self.outputs[output_id];
                     output.state.commit();
                 }
                 WlOutputEvent::Scale { wl_output, factor } => {
-                    let output_id = OutputId::from_raw(conn.ids.data_for(wl_output.id()).data);
+                    let output_id = OutputId::from_raw(conn.data_for(wl_output.id()));
                     let output = &mut self.outputs[output_id];
                     output.state.pending.integer_scale =
                         u32::try_from(factor).expect("negative scale factor");
@@ -1480,7 +3482,7 @@ impl App {
                     x,
                     y,
                 } => {
-                    let output_id = OutputId::from_raw(conn.ids.data_for(zxdg_output_v1.id()).data);
+                    let output_id = OutputId::from_raw(conn.data_for(zxdg_output_v1.id()));
                     let output = &mut self.outputs[output_id];
                     output.state.pending.logical_x = x;
                     output.state.pending.logical_y = y;
@@ -1490,19 +3492,96 @@ impl App {
                     width,
                     height,
                 } => {
-                    let output_id = OutputId::from_raw(conn.ids.data_for(zxdg_output_v1.id()).data);
+                    let output_id = OutputId::from_raw(conn.data_for(zxdg_output_v1.id()));
                     let output = &mut self.outputs[output_id];
                     output.state.pending.logical_width = width;
                     output.state.pending.logical_height = height;
                 }
                 ZxdgOutputV1Event::Done { .. } => {}
-                ZxdgOutputV1Event::Name { .. } => {}
+                ZxdgOutputV1Event::Name {
+                    zxdg_output_v1,
+                    name,
+                } => {
+                    let output_id = OutputId::from_raw(conn.data_for(zxdg_output_v1.id()));
+                    let output = &mut self.outputs[output_id];
+                    output.appearance = Some(self.config.output_appearance(&name));
+                    output.name = Some(name.into_owned());
+                }
                 ZxdgOutputV1Event::Description { .. } => {}
             },
 
             Event::WlSurface(event) => match event {
                 WlSurfaceEvent::Enter { .. } => {}
                 WlSurfaceEvent::Leave { .. } => {}
+                WlSurfaceEvent::PreferredBufferScale { wl_surface, factor } => {
+                    let Some((output_id, _)) =
+                        self.outputs.iter_with_handles().find(|(_, output)| {
+                            output
+                                .surface
+                                .as_ref()
+                                .is_some_and(|surface| surface.wl_surface == wl_surface)
+                        })
+                    else {
+                        return Ok(());
+                    };
+                    let surface = self.outputs[output_id].surface.as_mut().unwrap();
+                    let factor = u32::try_from(factor).ok().filter(|&factor| factor > 0);
+                    if surface.preferred_buffer_scale != factor {
+                        surface.preferred_buffer_scale = factor;
+                        // Before the first configure there's nothing to redraw yet.
+                        if surface.width != 0 {
+                            self.draw(conn, output_id)?;
+                        }
+                    }
+                }
+                // Buffers are drawn untransformed and the compositor transforms them, which only
+                // costs it a copy.
+                WlSurfaceEvent::PreferredBufferTransform { .. } => {}
+            },
+            Event::ZwlrScreencopyFrameV1(event) => match event {
+                ZwlrScreencopyFrameV1Event::Buffer {
+                    zwlr_screencopy_frame_v1: _,
+                    format,
+                    width,
+                    height,
+                    stride,
+                } => {
+                    if let Err(err) = self.copy_magnifier_frame(conn, format, width, height, stride)
+                    {
+                        log::warn!("can't copy the screen for the magnifier: {err:#}");
+                        self.finish_magnifier_frame(conn, None)?;
+                    }
+                }
+                ZwlrScreencopyFrameV1Event::Flags {
+                    zwlr_screencopy_frame_v1: _,
+                    flags,
+                } => {
+                    self.magnifier.y_invert = flags & ZWLR_SCREENCOPY_FRAME_V1_FLAGS_Y_INVERT != 0;
+                }
+                ZwlrScreencopyFrameV1Event::Ready { .. } => {
+                    let image = self.magnifier_image();
+                    self.finish_magnifier_frame(conn, image)?;
+                }
+                ZwlrScreencopyFrameV1Event::Failed { .. } => {
+                    log::debug!("copying the screen for the magnifier failed");
+                    self.finish_magnifier_frame(conn, None)?;
+                }
+            },
+            Event::WpFractionalScaleV1(event) => match event {
+                WpFractionalScaleV1Event::PreferredScale {
+                    wp_fractional_scale_v1,
+                    scale,
+                } => {
+                    let output_id = OutputId::from_raw(conn.data_for(wp_fractional_scale_v1.id()));
+                    let output = &mut self.outputs[output_id];
+                    if let Some(surface) = output.surface.as_mut() {
+                        surface.preferred_scale = Some(scale);
+                        // Before the first configure there's nothing to redraw yet.
+                        if surface.width != 0 {
+                            self.draw(conn, output_id)?;
+                        }
+                    }
+                }
             },
             Event::ZwlrLayerSurfaceV1(event) => match event {
                 ZwlrLayerSurfaceV1Event::Configure {
@@ -1511,69 +3590,113 @@ impl App {
                     width,
                     height,
                 } => {
-                    let output_id =
-                        OutputId::from_raw(conn.ids.data_for(zwlr_layer_surface_v1.id()).data);
+                    let output_id = OutputId::from_raw(conn.data_for(zwlr_layer_surface_v1.id()));
                     let output = &mut self.outputs[output_id];
                     let surface = output.surface.as_mut().unwrap();
                     conn.send(ZwlrLayerSurfaceV1Request::AckConfigure {
                         zwlr_layer_surface_v1,
                         serial,
-                    });
+                    })?;
                     conn.send(ZwlrLayerSurfaceV1Request::SetSize {
                         zwlr_layer_surface_v1,
                         width,
                         height,
-                    });
+                    })?;
                     surface.width = width;
                     surface.height = height;
-                    draw(
-                        &self.globals,
-                        &mut self.buffers,
-                        conn,
-                        output.state.current.as_ref().unwrap().integer_scale,
-                        surface,
-                        Region {
-                            x: self.region.x - output.state.current.unwrap().logical_x,
-                            y: self.region.y - output.state.current.unwrap().logical_y,
-                            ..self.region
-                        },
-                    )
-                    .unwrap();
+                    self.draw(conn, output_id)?;
                 }
                 ZwlrLayerSurfaceV1Event::Closed {
                     zwlr_layer_surface_v1,
                 } => {
-                    let output_id =
-                        OutputId::from_raw(conn.ids.data_for(zwlr_layer_surface_v1.id()).data);
+                    let output_id = OutputId::from_raw(conn.data_for(zwlr_layer_surface_v1.id()));
                     let output = &mut self.outputs[output_id];
                     output.surface = None;
                 }
             },
             Event::WlBuffer(event) => match event {
                 WlBufferEvent::Release { wl_buffer } => {
-                    let buffer_id = BufferId::from_raw(conn.ids.data_for(wl_buffer.id()).data);
-                    let buffer = &mut self.buffers[buffer_id];
-                    let wl_shm_pool = buffer.pool;
-                    conn.send(WlShmPoolRequest::Destroy { wl_shm_pool });
-                    conn.send(WlBufferRequest::Destroy { wl_buffer });
-                    self.buffers.remove(buffer_id);
+                    // Single-pixel buffers are never reused for anything else.
+                    if self
+                        .single_pixel_buffers
+                        .values()
+                        .any(|&it| it == wl_buffer)
+                    {
+                        return Ok(());
+                    }
+                    let buffer_id = BufferId::from_raw(conn.data_for(wl_buffer.id()));
+                    self.buffers[buffer_id].busy = false;
                 }
             },
             Event::WlShm(event) => match event {
-                WlShmEvent::Format { .. } => {}
+                WlShmEvent::Format { wl_shm: _, format } => {
+                    self.shm_formats.insert(format);
+                }
             },
             Event::WlCallback(event) => match event {
-                WlCallbackEvent::Done { .. } => {}
+                WlCallbackEvent::Done {
+                    wl_callback,
+                    callback_data: _,
+                } => {
+                    let output_id = OutputId::from_raw(conn.data_for(wl_callback.id()));
+                    if let Some(surface) = self.outputs[output_id].surface.as_mut() {
+                        surface.frame_pending = false;
+                        let now = Instant::now();
+                        if self.animation_progress(now).is_none() {
+                            self.animation = None;
+                        }
+                        // Changes made while the frame was pending are drawn now.
+                        if self.is_outdated(output_id, self.shown_region(now)) {
+                            self.draw(conn, output_id)?;
+                        }
+                    }
+                }
             },
             Event::WlDisplay(event) => match event {
+                // Errors are returned by `read_event` instead.
                 WlDisplayEvent::Error { .. } => {}
                 WlDisplayEvent::DeleteId { .. } => {}
             },
             Event::WlPointer(event) => match event {
-                WlPointerEvent::Enter { .. } => {}
+                WlPointerEvent::Enter {
+                    wl_pointer, serial, ..
+                } => {
+                    let seat_id = SeatId::from_raw(conn.data_for(wl_pointer.id()));
+                    let seat = &self.seats[seat_id];
+                    match self.config.appearance.cursor {
+                        Cursor::Default => {}
+                        Cursor::Crosshair => {
+                            if !seat.cursor_shape_device.is_null() {
+                                conn.send(WpCursorShapeDeviceV1Request::SetShape {
+                                    wp_cursor_shape_device_v1: seat.cursor_shape_device,
+                                    serial,
+                                    shape: WP_CURSOR_SHAPE_DEVICE_V1_SHAPE_CROSSHAIR,
+                                })?;
+                            }
+                        }
+                        Cursor::Hidden => conn.send(WlPointerRequest::SetCursor {
+                            wl_pointer,
+                            serial,
+                            surface: WlSurface::default(),
+                            hotspot_x: 0,
+                            hotspot_y: 0,
+                        })?,
+                    }
+                }
                 WlPointerEvent::Leave { .. } => {}
                 WlPointerEvent::Motion { .. } => {}
-                WlPointerEvent::Button { .. } => {}
+                WlPointerEvent::Button {
+                    wl_pointer,
+                    serial: _,
+                    time,
+                    button,
+                    state,
+                } => {
+                    if state == WL_POINTER_BUTTON_STATE_PRESSED {
+                        let seat_id = SeatId::from_raw(conn.data_for(wl_pointer.id()));
+                        handle_button_pressed(self, time, button, seat_id, conn, ei_conn)?;
+                    }
+                }
                 WlPointerEvent::Axis { .. } => {}
             },
             Event::WlRegistry(event) => match event {
@@ -1588,5 +3711,58 @@ impl App {
                 WlTouchEvent::Cancel { .. } => {}
             },
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointer_events() {
+        let mut buttons_down = HashSet::new();
+        let scroll = vec![(WL_POINTER_AXIS_VERTICAL_SCROLL, 10.0)];
+        assert_eq!(
+            pointer_events(Warp::Never, &mut buttons_down, None, None, scroll.clone()),
+            [],
+        );
+        assert_eq!(
+            pointer_events(
+                Warp::Never,
+                &mut buttons_down,
+                Some(272),
+                Some(272),
+                Vec::new()
+            ),
+            [],
+        );
+        assert!(buttons_down.is_empty());
+        assert_eq!(
+            pointer_events(Warp::Click, &mut buttons_down, None, None, scroll),
+            [
+                PointerEvent::Motion,
+                PointerEvent::Axis(WL_POINTER_AXIS_VERTICAL_SCROLL, 10.0)
+            ],
+        );
+        assert_eq!(
+            pointer_events(Warp::Click, &mut buttons_down, None, None, Vec::new()),
+            [],
+        );
+        assert_eq!(
+            pointer_events(Warp::Click, &mut buttons_down, Some(272), None, Vec::new()),
+            [PointerEvent::Motion, PointerEvent::Press(272)],
+        );
+        // The button is still down, so only the release is sent.
+        assert_eq!(
+            pointer_events(
+                Warp::Always,
+                &mut buttons_down,
+                Some(272),
+                Some(272),
+                Vec::new()
+            ),
+            [PointerEvent::Motion, PointerEvent::Release(272)],
+        );
     }
 }
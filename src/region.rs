@@ -4,7 +4,7 @@ pub(crate) struct Point {
     pub(crate) y: i32,
 }
 
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) struct Region {
     pub(crate) x: i32,
     pub(crate) y: i32,
@@ -13,7 +13,7 @@ pub(crate) struct Region {
 }
 
 impl Region {
-    fn contains(&self, x: i32, y: i32) -> bool {
+    pub(crate) fn contains(&self, x: i32, y: i32) -> bool {
         x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
     }
 
@@ -24,46 +24,53 @@ impl Region {
         }
     }
 
-    pub(crate) fn cut_up(mut self) -> Region {
-        self.height /= 2;
-        self
-    }
-
-    pub(crate) fn cut_down(mut self) -> Region {
-        self.height /= 2;
-        self.y += self.height;
-        self
-    }
+    /// Returns the cell at `col` and `row` when the region is divided into a grid of `cols` by
+    /// `rows` cells.
+    pub(crate) fn cell(self, cols: u32, rows: u32, col: u32, row: u32) -> Region {
+        fn split(start: i32, len: i32, n: u32, i: u32) -> (i32, i32) {
+            let at = |i: u32| start + (i64::from(len) * i64::from(i) / i64::from(n)) as i32;
+            (at(i), at(i + 1))
+        }
 
-    pub(crate) fn cut_left(mut self) -> Region {
-        self.width /= 2;
-        self
+        let (left, right) = split(self.x, self.width, cols, col);
+        let (top, bottom) = split(self.y, self.height, rows, row);
+        Region {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
     }
 
-    pub(crate) fn cut_right(mut self) -> Region {
-        self.width /= 2;
-        self.x += self.width;
+    pub(crate) fn move_up(mut self, distance: i32) -> Region {
+        self.y = self.y.saturating_sub(distance);
         self
     }
 
-    pub(crate) fn move_up(mut self) -> Region {
-        self.y = self.y.saturating_sub(self.height);
+    pub(crate) fn move_down(mut self, distance: i32) -> Region {
+        self.y = self.y.saturating_add(distance);
         self
     }
 
-    pub(crate) fn move_down(mut self) -> Region {
-        self.y = self.y.saturating_add(self.height);
+    pub(crate) fn move_left(mut self, distance: i32) -> Region {
+        self.x = self.x.saturating_sub(distance);
         self
     }
 
-    pub(crate) fn move_left(mut self) -> Region {
-        self.x = self.x.saturating_sub(self.width);
+    pub(crate) fn move_right(mut self, distance: i32) -> Region {
+        self.x = self.x.saturating_add(distance);
         self
     }
 
-    pub(crate) fn move_right(mut self) -> Region {
-        self.x = self.x.saturating_add(self.width);
-        self
+    /// Returns the region a fraction `t` of the way from this one to `to`.
+    pub(crate) fn lerp(self, to: Region, t: f64) -> Region {
+        let lerp = |from: i32, to: i32| from + (f64::from(to - from) * t).round() as i32;
+        Region {
+            x: lerp(self.x, to.x),
+            y: lerp(self.y, to.y),
+            width: lerp(self.width, to.width),
+            height: lerp(self.height, to.height),
+        }
     }
 
     pub(crate) fn contains_region(&self, other: &Region) -> bool {
@@ -71,15 +78,26 @@ impl Region {
             && self.contains(other.x + other.width - 1, other.y + other.height - 1)
     }
 
-    pub(crate) fn scale(&self, scale: u32) -> Region {
+    /// Scales the region by `scale`, rounding its edges to whole pixels.
+    pub(crate) fn scale(&self, scale: f64) -> Region {
+        let scale = |n: i32| (f64::from(n) * scale).round() as i32;
+        let left = scale(self.x);
+        let top = scale(self.y);
         Region {
-            x: self.x * scale as i32,
-            y: self.y * scale as i32,
-            width: self.width * scale as i32,
-            height: self.height * scale as i32,
+            x: left,
+            y: top,
+            width: scale(self.x + self.width) - left,
+            height: scale(self.y + self.height) - top,
         }
     }
 
+    pub(crate) fn intersects(&self, other: &Region) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
     pub(crate) fn union(&self, other: &Region) -> Region {
         let left = self.x.min(other.x);
         let top = self.y.min(other.y);
@@ -0,0 +1,110 @@
+use ab_glyph::{Font as _, FontVec, PxScale, ScaleFont as _};
+use anyhow::{ensure, Context as _, Result};
+use std::{path::PathBuf, process::Command};
+use tiny_skia::{Color, PremultipliedColorU8};
+
+/// A font for drawing labels on the overlay.
+pub(crate) struct Font {
+    font: FontVec,
+}
+
+impl Font {
+    /// Loads a font from a path, or finds one by its fontconfig pattern, like `monospace`.
+    pub(crate) fn load(name: &str) -> Result<Font> {
+        let path = if name.contains('/') {
+            PathBuf::from(name)
+        } else {
+            fc_match(name)?
+        };
+        let data = std::fs::read(&path)
+            .with_context(|| format!("failed to read font {}", path.display()))?;
+        let font = FontVec::try_from_vec(data)
+            .with_context(|| format!("failed to load font {}", path.display()))?;
+        Ok(Font { font })
+    }
+
+    /// Returns the width of `text` in pixels when drawn `size` pixels high.
+    pub(crate) fn measure(&self, text: &str, size: f32) -> f32 {
+        let font = self.font.as_scaled(PxScale::from(size));
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let glyph = font.glyph_id(c);
+            if let Some(previous) = previous {
+                width += font.kern(previous, glyph);
+            }
+            width += font.h_advance(glyph);
+            previous = Some(glyph);
+        }
+        width
+    }
+
+    /// Draws `text` `size` pixels high with the top left of its first line at `x` and `y`.
+    pub(crate) fn draw(
+        &self,
+        pixmap: &mut tiny_skia::PixmapMut<'_>,
+        text: &str,
+        size: f32,
+        x: f32,
+        y: f32,
+        color: Color,
+    ) {
+        let font = self.font.as_scaled(PxScale::from(size));
+        let width = pixmap.width() as i32;
+        let height = pixmap.height() as i32;
+        let pixels = pixmap.pixels_mut();
+        let mut caret = ab_glyph::point(x, y + font.ascent());
+        let mut previous = None;
+        for c in text.chars() {
+            let mut glyph = font.scaled_glyph(c);
+            if let Some(previous) = previous {
+                caret.x += font.kern(previous, glyph.id);
+            }
+            previous = Some(glyph.id);
+            glyph.position = caret;
+            caret.x += font.h_advance(glyph.id);
+
+            let Some(outline) = self.font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|glyph_x, glyph_y, coverage| {
+                let x = bounds.min.x as i32 + glyph_x as i32;
+                let y = bounds.min.y as i32 + glyph_y as i32;
+                if (0..width).contains(&x) && (0..height).contains(&y) {
+                    let pixel = &mut pixels[(y * width + x) as usize];
+                    *pixel = blend(*pixel, color, coverage);
+                }
+            });
+        }
+    }
+}
+
+/// Draws `color` over `dst` with the given coverage.
+fn blend(dst: PremultipliedColorU8, color: Color, coverage: f32) -> PremultipliedColorU8 {
+    let alpha = color.alpha() * coverage.clamp(0.0, 1.0);
+    let channel =
+        |src: f32, dst: u8| (src * alpha * 255.0 + f32::from(dst) * (1.0 - alpha)).round() as u8;
+    PremultipliedColorU8::from_rgba(
+        channel(color.red(), dst.red()),
+        channel(color.green(), dst.green()),
+        channel(color.blue(), dst.blue()),
+        channel(1.0, dst.alpha()),
+    )
+    .unwrap_or(dst)
+}
+
+/// Returns the path of the font fontconfig picks for `pattern`.
+fn fc_match(pattern: &str) -> Result<PathBuf> {
+    let output = Command::new("fc-match")
+        .arg("--format=%{file}")
+        .arg(pattern)
+        .output()
+        .context("failed to run fc-match")?;
+    ensure!(
+        output.status.success() && !output.stdout.is_empty(),
+        "no font found for {pattern:?}"
+    );
+    let path = String::from_utf8(output.stdout).context("invalid font path from fc-match")?;
+    Ok(PathBuf::from(path))
+}